@@ -0,0 +1,118 @@
+use std::error::Error;
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+/// Cidr `struct` represents a range of IP addresses, written as
+/// `address/prefix`, e.g. `192.168.1.0/24` or `fd00::/8`. A single address
+/// without prefix is also accepted.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Cidr {
+    /// First address of the range
+    pub address: IpAddr,
+    /// Count of leading bits shared by all the addresses of the range
+    pub prefix: u8,
+}
+
+impl Cidr {
+    /// Returns true if `ip` belongs to the range. IPv4-mapped IPv6 addresses
+    /// are matched as IPv4 ones.
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.address, ip.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = Box<dyn Error>;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (address, prefix) = match s.split_once('/') {
+            Some((address, prefix)) => (address.parse::<IpAddr>()?, Some(prefix.parse::<u8>()?)),
+            None => (s.parse::<IpAddr>()?, None),
+        };
+        let max = if address.is_ipv4() { 32 } else { 128 };
+        let prefix = prefix.unwrap_or(max);
+        if prefix > max {
+            return Err(format!("Invalid prefix length in {s}").into());
+        }
+
+        Ok(Cidr {
+            address: address.to_canonical(),
+            prefix,
+        })
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.address, self.prefix)
+    }
+}
+
+/// AccessList `struct` decides which clients the server answers to, from
+/// their IP address. A denied address is refused even when it is also
+/// allowed, and all addresses are allowed when the allow list is empty.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct AccessList {
+    /// Ranges of the addresses allowed to send requests
+    pub allow: Vec<Cidr>,
+    /// Ranges of the addresses refused, taking precedence over `allow`
+    pub deny: Vec<Cidr>,
+}
+
+impl AccessList {
+    /// Returns true if requests from `ip` should be answered.
+    pub fn permits(&self, ip: IpAddr) -> bool {
+        !self.deny.iter().any(|cidr| cidr.contains(ip))
+            && (self.allow.is_empty() || self.allow.iter().any(|cidr| cidr.contains(ip)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_cidr() {
+        let cidr: Cidr = "10.1.0.0/16".parse().unwrap();
+        assert_eq!(cidr.address, "10.1.0.0".parse::<IpAddr>().unwrap());
+        assert_eq!(cidr.prefix, 16);
+        assert_eq!("fd00::1".parse::<Cidr>().unwrap().prefix, 128);
+        assert_eq!("0.0.0.0/0".parse::<Cidr>().unwrap().prefix, 0);
+
+        assert!("10.1.0.0/33".parse::<Cidr>().is_err());
+        assert!("10.1.0/16".parse::<Cidr>().is_err());
+        assert!("fd00::/x".parse::<Cidr>().is_err());
+    }
+
+    #[test]
+    fn permits_allowed_addresses() {
+        let access = AccessList {
+            allow: vec!["10.1.0.0/16".parse().unwrap(), "fd00::/8".parse().unwrap()],
+            deny: vec!["10.1.2.0/24".parse().unwrap()],
+        };
+
+        assert!(access.permits("10.1.0.1".parse().unwrap()));
+        assert!(access.permits("::ffff:10.1.255.1".parse().unwrap()));
+        assert!(access.permits("fd12::1".parse().unwrap()));
+        assert!(!access.permits("10.1.2.3".parse().unwrap()));
+        assert!(!access.permits("10.2.0.1".parse().unwrap()));
+        assert!(!access.permits("fe80::1".parse().unwrap()));
+
+        assert!(AccessList::default().permits("192.0.2.1".parse().unwrap()));
+        assert!("0.0.0.0/0"
+            .parse::<Cidr>()
+            .unwrap()
+            .contains("192.0.2.1".parse().unwrap()));
+    }
+}
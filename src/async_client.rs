@@ -0,0 +1,674 @@
+use std::collections::VecDeque;
+use std::error::Error;
+use std::io;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use tokio::fs::{self, File, OpenOptions};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt, BufReader, BufWriter};
+use tokio::net::UdpSocket;
+use tokio::time::{self, Instant};
+
+use crate::options::OptionFmt;
+use crate::{log::*, Client, ClientConfig, ErrorCode, Mode, OptionsProtocol, Overwrite, Packet};
+use crate::{TftpError, TransferMode, TransferStats};
+
+// Largest block size (RFC 2348) and its header
+const MAX_PACKET_SIZE: usize = 65464 + 4;
+// Cap of the request backoff, as a multiple of the request timeout
+const MAX_HANDSHAKE_BACKOFF: u32 = 8;
+
+/// AsyncClient `struct` runs a TFTP transfer on the [tokio](https://tokio.rs)
+/// runtime, with a [`tokio::net::UdpSocket`] and asynchronous file I/O, so
+/// that no thread is blocked while waiting for the server.
+///
+/// It mirrors the upload and download flows of [`Client`], with the block
+/// size, window size, timeout and transfer size options. Other features of
+/// [`Client`] are refused by [`AsyncClient::new()`]. Dropping the future
+/// returned by [`AsyncClient::run()`] aborts the transfer.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use tftpd::{AsyncClient, ClientConfig};
+///
+/// async fn fetch() -> Result<(), Box<dyn std::error::Error>> {
+///     let args = ["firmware.bin", "-i", "192.168.1.10", "-d"].map(|s| s.to_string());
+///     let config = ClientConfig::new(args.into_iter())?;
+///     let stats = AsyncClient::new(&config)?.run().await?;
+///     println!("Received {} bytes", stats.bytes);
+///     Ok(())
+/// }
+/// ```
+pub struct AsyncClient {
+    remote_address: SocketAddr,
+    local_address: SocketAddr,
+    timeout_req: Duration,
+    max_handshake_retries: u32,
+    max_retries: usize,
+    clean_on_error: bool,
+    mkdir: bool,
+    mode: Mode,
+    file_local: PathBuf,
+    file_remote: String,
+    overwrite: Overwrite,
+    no_tsize: bool,
+    opt_request: OptionsProtocol,
+}
+
+impl AsyncClient {
+    /// Creates the async TFTP Client with the supplied [`ClientConfig`],
+    /// resolved as with [`Client::new()`].
+    pub fn new(config: &ClientConfig) -> Result<AsyncClient, Box<dyn Error>> {
+        let unsupported = [
+            (config.transfer_mode != TransferMode::Octet, "netascii mode"),
+            (!config.extra_files.is_empty(), "several files"),
+            (config.resume, "resume"),
+            (config.compression.is_some(), "compression"),
+            (config.multicast, "multicast"),
+            (config.dry_run, "dry run"),
+            (
+                config.hash.is_some() || config.manifest.is_some(),
+                "verification",
+            ),
+            (config.auto_blksize, "block size fallback"),
+        ];
+        if let Some((_, feature)) = unsupported.iter().find(|(used, _)| *used) {
+            return Err(format!("AsyncClient does not support {feature}").into());
+        }
+
+        let client = Client::new(config)?;
+        if client.output_path() == Path::new("-") {
+            return Err("AsyncClient does not support stdin or stdout".into());
+        }
+        let overwrite = match config.overwrite {
+            Overwrite::NoClobber if config.force => Overwrite::Replace,
+            Overwrite::NoClobber if config.mode == Mode::Upload => {
+                return Err("AsyncClient does not support checking the remote file".into())
+            }
+            overwrite => overwrite,
+        };
+
+        // Only the options of the windowed transfer itself are requested
+        let requested = client.requested_options();
+        let opt_request = OptionsProtocol {
+            block_size: requested.block_size,
+            window_size: requested.window_size,
+            timeout: requested.timeout,
+            ..Default::default()
+        };
+
+        Ok(AsyncClient {
+            remote_address: client.remote_address(),
+            local_address: client.local_address(),
+            timeout_req: config.timeout_req,
+            max_handshake_retries: config.max_handshake_retries,
+            max_retries: config.opt_local.max_retries,
+            clean_on_error: config.opt_local.clean_on_error,
+            mkdir: config.mkdir,
+            mode: config.mode,
+            file_local: client.output_path().to_path_buf(),
+            file_remote: client.remote_file().to_string(),
+            overwrite,
+            no_tsize: config.no_tsize,
+            opt_request,
+        })
+    }
+
+    /// Runs the transfer as configured and returns its statistics. A transfer
+    /// failing locally, e.g. timing out, resolves to a [`TftpError::Transfer`]
+    /// error, an error sent by the server to the matching [`TftpError`].
+    pub async fn run(self) -> Result<TransferStats, TftpError> {
+        let start = Instant::now();
+        let socket = UdpSocket::bind(self.local_address).await?;
+
+        let mut stats = match self.mode {
+            Mode::Upload => self.upload(socket, start).await?,
+            Mode::Download => self.download(socket, start).await?,
+        };
+        stats.duration = start.elapsed();
+
+        Ok(stats)
+    }
+
+    async fn upload(&self, socket: UdpSocket, start: Instant) -> Result<TransferStats, TftpError> {
+        let file = File::open(&self.file_local).await?;
+        let mut opt_common = self.opt_request.clone();
+        if !self.no_tsize {
+            opt_common.transfer_size = Some(file.metadata().await?.len());
+        }
+
+        let request = Packet::Wrq {
+            filename: self.file_remote.clone(),
+            mode: TransferMode::Octet.to_string(),
+            options: opt_common.prepare(),
+            extra: vec![],
+        };
+        log_dbg!("  Sending {request}");
+
+        let (packet, from) = self.send_request(&socket, &request).await?;
+        socket.connect(from).await?;
+        match packet {
+            Packet::Oack(options, extra) => {
+                opt_common.apply(&options, &extra)?;
+                log_dbg!("  Accepted options: {}", OptionFmt(&options));
+            }
+            Packet::Ack(0) => {
+                opt_common = OptionsProtocol {
+                    timeout: opt_common.timeout,
+                    ..Default::default()
+                };
+                log_dbg!("  Options not accepted, using default");
+            }
+            Packet::Error { code, msg } => return Err(TftpError::from_packet(code, msg)),
+            _ => return Err(unexpected(&packet)),
+        }
+
+        let mut stats = TransferStats {
+            block_size: opt_common.block_size,
+            ..Default::default()
+        };
+        let result = self
+            .send_file(&socket, BufReader::new(file), &opt_common, &mut stats)
+            .await;
+        abort_on_error(&socket, result, start).await?;
+
+        Ok(stats)
+    }
+
+    /// Sends the data, one window at a time, until the final block is
+    /// acknowledged. A window is sent again when no acknowledgement moves past
+    /// its first block before the timeout.
+    async fn send_file(
+        &self,
+        socket: &UdpSocket,
+        mut reader: impl AsyncRead + Unpin,
+        opt_common: &OptionsProtocol,
+        stats: &mut TransferStats,
+    ) -> Result<(), TftpError> {
+        let mut buf = vec![0; MAX_PACKET_SIZE];
+        let mut window: VecDeque<Vec<u8>> = VecDeque::new();
+        let mut first_block: u16 = 1;
+        let mut read_done = false;
+        let mut retry_cnt = 0;
+
+        loop {
+            while !read_done && window.len() < opt_common.window_size as usize {
+                let chunk = read_chunk(&mut reader, opt_common.block_size).await?;
+                read_done = chunk.len() < opt_common.block_size as usize;
+                window.push_back(chunk);
+            }
+            if window.is_empty() {
+                return Ok(());
+            }
+
+            for (index, chunk) in window.iter().enumerate() {
+                let packet = Packet::Data {
+                    block_num: first_block.wrapping_add(index as u16),
+                    data: chunk.clone(),
+                };
+                send(socket, &packet).await?;
+            }
+            if retry_cnt > 0 {
+                stats.retransmits += window.len() as u64;
+            }
+            log_trace!(
+                "  Window sent up to block {}",
+                first_block.wrapping_add(window.len() as u16 - 1)
+            );
+
+            let deadline = Instant::now() + opt_common.timeout;
+            let acked = loop {
+                let packet = match time::timeout_at(deadline, recv(socket, &mut buf)).await {
+                    Ok(packet) => packet?,
+                    Err(_) => break 0,
+                };
+                match packet {
+                    Packet::Ack(block_num) => {
+                        // Acknowledgements of previous windows are ignored
+                        let count = block_num.wrapping_sub(first_block).wrapping_add(1) as usize;
+                        if (1..=window.len()).contains(&count) {
+                            break count;
+                        }
+                    }
+                    Packet::Error { code, msg } => return Err(TftpError::from_packet(code, msg)),
+                    packet => log_warn!("  Ignoring unexpected packet: {packet}"),
+                }
+            };
+
+            if acked == 0 {
+                retry_cnt += 1;
+                log_info!("  Ack timeout {retry_cnt}/{}", self.max_retries);
+                if retry_cnt > self.max_retries {
+                    return Err(timeout_error("acknowledgement"));
+                }
+                continue;
+            }
+
+            retry_cnt = 0;
+            stats.windows += 1;
+            stats.blocks += acked as u64;
+            for chunk in window.drain(..acked) {
+                stats.bytes += chunk.len() as u64;
+            }
+            first_block = first_block.wrapping_add(acked as u16);
+        }
+    }
+
+    async fn download(
+        &self,
+        socket: UdpSocket,
+        start: Instant,
+    ) -> Result<TransferStats, TftpError> {
+        // Checked before the request, not to bother the server for nothing
+        if let Some(parent) = self.file_local.parent() {
+            if !parent.as_os_str().is_empty() && !fs::try_exists(parent).await? {
+                if !self.mkdir {
+                    return Err(local_error(format!(
+                        "Directory {} does not exist, use --mkdir to create it",
+                        parent.display()
+                    )));
+                }
+                log_info!("Creating directory {}", parent.display());
+                fs::create_dir_all(parent).await?;
+            }
+        }
+        if self.overwrite == Overwrite::NoClobber && fs::try_exists(&self.file_local).await? {
+            return Err(local_error(format!(
+                "Local file {} already exists, use --force to overwrite it",
+                self.file_local.display()
+            )));
+        }
+
+        let mut opt_common = self.opt_request.clone();
+        if !self.no_tsize {
+            opt_common.transfer_size = Some(0);
+        }
+
+        let request = Packet::Rrq {
+            filename: self.file_remote.clone(),
+            mode: TransferMode::Octet.to_string(),
+            options: opt_common.prepare(),
+            extra: vec![],
+        };
+        log_dbg!("  Sending {request}");
+
+        let (packet, from) = self.send_request(&socket, &request).await?;
+        socket.connect(from).await?;
+        let first_packet = match packet {
+            Packet::Oack(options, extra) => {
+                opt_common.apply(&options, &extra)?;
+                log_dbg!("  Accepted options: {}", OptionFmt(&options));
+                send(&socket, &Packet::Ack(0)).await?;
+                None
+            }
+            // Server without options (RFC 2347) sending the first block
+            Packet::Data { block_num: 1, .. } => {
+                opt_common = OptionsProtocol {
+                    timeout: opt_common.timeout,
+                    ..Default::default()
+                };
+                log_dbg!("  Options not accepted, using default");
+                Some(packet)
+            }
+            Packet::Error { code, msg } => return Err(TftpError::from_packet(code, msg)),
+            _ => return Err(unexpected(&packet)),
+        };
+
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .append(self.overwrite == Overwrite::Append)
+            .truncate(self.overwrite != Overwrite::Append)
+            .open(&self.file_local)
+            .await?;
+
+        let mut stats = TransferStats {
+            block_size: opt_common.block_size,
+            ..Default::default()
+        };
+        let result = self
+            .receive_file(
+                &socket,
+                BufWriter::new(file),
+                first_packet,
+                &opt_common,
+                (&mut stats, start),
+            )
+            .await;
+        if result.is_err() && self.clean_on_error && self.overwrite != Overwrite::Append {
+            let _ = fs::remove_file(&self.file_local).await;
+        }
+        abort_on_error(&socket, result, start).await?;
+
+        Ok(stats)
+    }
+
+    /// Receives the data until the final short block, acknowledging each
+    /// window, or the last block received in sequence when one is missing or
+    /// nothing comes before the timeout.
+    async fn receive_file(
+        &self,
+        socket: &UdpSocket,
+        mut writer: BufWriter<File>,
+        mut first_packet: Option<Packet>,
+        opt_common: &OptionsProtocol,
+        (stats, start): (&mut TransferStats, Instant),
+    ) -> Result<(), TftpError> {
+        let mut buf = vec![0; MAX_PACKET_SIZE];
+        let mut next_block: u16 = 1;
+        let mut in_window = 0;
+        let mut gap_acked = false;
+        let mut retry_cnt = 0;
+
+        loop {
+            let packet = match first_packet.take() {
+                Some(packet) => packet,
+                None => match time::timeout(opt_common.timeout, recv(socket, &mut buf)).await {
+                    Ok(packet) => packet?,
+                    Err(_) => {
+                        retry_cnt += 1;
+                        log_info!("  Data timeout {retry_cnt}/{}", self.max_retries);
+                        if retry_cnt > self.max_retries {
+                            return Err(timeout_error("data"));
+                        }
+                        send(socket, &Packet::Ack(next_block.wrapping_sub(1))).await?;
+                        stats.socket.retransmits += 1;
+                        in_window = 0;
+                        continue;
+                    }
+                },
+            };
+
+            match packet {
+                Packet::Data { block_num, data } if block_num == next_block => {
+                    if stats.time_to_first_data.is_none() {
+                        stats.time_to_first_data = Some(start.elapsed());
+                    }
+                    writer.write_all(&data).await?;
+                    stats.bytes += data.len() as u64;
+                    stats.blocks += 1;
+                    next_block = next_block.wrapping_add(1);
+                    in_window += 1;
+                    gap_acked = false;
+                    retry_cnt = 0;
+
+                    let last = data.len() < opt_common.block_size as usize;
+                    if last {
+                        writer.flush().await?;
+                        writer.get_ref().sync_all().await?;
+                    }
+                    if last || in_window == opt_common.window_size {
+                        send(socket, &Packet::Ack(block_num)).await?;
+                        log_trace!("  Window received up to block {block_num}");
+                        stats.windows += 1;
+                        in_window = 0;
+                    }
+                    if last {
+                        return Ok(());
+                    }
+                }
+                Packet::Data { .. } => {
+                    // Once per gap, the sender restarts the window after the
+                    // last block acknowledged
+                    stats.retransmits += 1;
+                    if !gap_acked {
+                        send(socket, &Packet::Ack(next_block.wrapping_sub(1))).await?;
+                        gap_acked = true;
+                        in_window = 0;
+                    }
+                }
+                Packet::Error { code, msg } => return Err(TftpError::from_packet(code, msg)),
+                packet => log_warn!("  Ignoring unexpected packet: {packet}"),
+            }
+        }
+    }
+
+    /// Sends a request and returns the first answer. The request is sent again
+    /// when no answer comes, with an exponential backoff from the request
+    /// timeout.
+    async fn send_request(
+        &self,
+        socket: &UdpSocket,
+        request: &Packet,
+    ) -> Result<(Packet, SocketAddr), TftpError> {
+        let mut buf = vec![0; MAX_PACKET_SIZE];
+        let mut wait = self.timeout_req;
+        let mut retry_cnt = 0;
+
+        loop {
+            socket
+                .send_to(&serialize(request)?, self.remote_address)
+                .await?;
+
+            match time::timeout(wait, socket.recv_from(&mut buf)).await {
+                Ok(answer) => {
+                    let (len, from) = answer?;
+                    return Ok((deserialize(&buf[..len])?, from));
+                }
+                Err(_) if retry_cnt < self.max_handshake_retries => {
+                    retry_cnt += 1;
+                    wait = (wait * 2).min(self.timeout_req * MAX_HANDSHAKE_BACKOFF);
+                    log_info!(
+                        "  No answer to the request, sending it again {retry_cnt}/{}",
+                        self.max_handshake_retries
+                    );
+                }
+                Err(_) => {
+                    return Err(TftpError::Transfer(Box::new(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        format!("No answer to the request from {}", self.remote_address),
+                    ))))
+                }
+            }
+        }
+    }
+}
+
+/// Reads a full chunk, or less at the end of the data.
+async fn read_chunk(reader: &mut (impl AsyncRead + Unpin), chunk_size: u16) -> io::Result<Vec<u8>> {
+    let mut chunk = Vec::with_capacity(chunk_size as usize);
+    reader
+        .take(chunk_size as u64)
+        .read_to_end(&mut chunk)
+        .await?;
+    Ok(chunk)
+}
+
+/// Tells the server about a local failure of the transfer, not to let it
+/// time out. A failure sent by the server is not answered.
+async fn abort_on_error(
+    socket: &UdpSocket,
+    result: Result<(), TftpError>,
+    start: Instant,
+) -> Result<(), TftpError> {
+    if let Err(TftpError::Transfer(err)) = &result {
+        log_dbg!("  Transfer failed after {:?}: {err}", start.elapsed());
+        let packet = Packet::Error {
+            code: ErrorCode::NotDefined,
+            msg: err.to_string(),
+        };
+        let _ = send(socket, &packet).await;
+    }
+    result
+}
+
+async fn send(socket: &UdpSocket, packet: &Packet) -> Result<(), TftpError> {
+    socket.send(&serialize(packet)?).await?;
+    Ok(())
+}
+
+async fn recv(socket: &UdpSocket, buf: &mut [u8]) -> Result<Packet, TftpError> {
+    let len = socket.recv(buf).await?;
+    deserialize(&buf[..len])
+}
+
+fn serialize(packet: &Packet) -> Result<Vec<u8>, TftpError> {
+    packet.serialize().map_err(local_error)
+}
+
+fn deserialize(buf: &[u8]) -> Result<Packet, TftpError> {
+    Packet::deserialize(buf).map_err(TftpError::from)
+}
+
+fn local_error(msg: impl Into<String>) -> TftpError {
+    TftpError::Transfer(msg.into().into())
+}
+
+fn timeout_error(waited: &str) -> TftpError {
+    TftpError::Transfer(Box::new(io::Error::new(
+        io::ErrorKind::TimedOut,
+        format!("Transfer timed out waiting for {waited}"),
+    )))
+}
+
+fn unexpected(packet: &Packet) -> TftpError {
+    local_error(format!(
+        "Client received unexpected packet from server: {packet}"
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::thread;
+
+    use crate::client::tests::TestServer;
+
+    const DIR_NAME: &str = "target/test/async_client";
+
+    fn serve(name: &str) -> (ClientConfig, PathBuf, TestServer) {
+        let dir = PathBuf::from(DIR_NAME).join(name);
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("client")).unwrap();
+        fs::create_dir_all(dir.join("server")).unwrap();
+
+        let server = TestServer::read_write(&dir.join("server"));
+        let config = ClientConfig {
+            remote_ip_address: server.addr.ip(),
+            port: server.addr.port(),
+            receive_directory: dir.join("client"),
+            ..Default::default()
+        };
+        (config, dir, server)
+    }
+
+    fn content(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    #[tokio::test]
+    async fn downloads_from_async_code() {
+        let (mut config, dir, _server) = serve("downloads_from_async_code");
+        fs::write(dir.join("server/file.bin"), content(1500)).unwrap();
+        config.file_path = PathBuf::from("file.bin");
+
+        let stats = AsyncClient::new(&config).unwrap().run().await.unwrap();
+        assert_eq!(stats.bytes, 1500);
+        assert_eq!(stats.blocks, 3);
+        assert_eq!(
+            fs::read(dir.join("client/file.bin")).unwrap(),
+            content(1500)
+        );
+    }
+
+    #[tokio::test]
+    async fn transfers_windows() {
+        let (mut config, dir, _server) = serve("transfers_windows");
+        fs::write(dir.join("server/down.bin"), content(10 * 512)).unwrap();
+        fs::write(dir.join("client/up.bin"), content(10 * 512 + 7)).unwrap();
+        config.opt_common.window_size = 4;
+
+        config.file_path = PathBuf::from("down.bin");
+        let stats = AsyncClient::new(&config).unwrap().run().await.unwrap();
+        assert_eq!(stats.blocks, 11);
+        assert_eq!(stats.windows, 3);
+        assert_eq!(
+            fs::read(dir.join("client/down.bin")).unwrap(),
+            content(10 * 512)
+        );
+
+        config.mode = Mode::Upload;
+        config.file_path = dir.join("client/up.bin");
+        let stats = AsyncClient::new(&config).unwrap().run().await.unwrap();
+        assert_eq!(stats.bytes, 10 * 512 + 7);
+        assert_eq!(stats.windows, 3);
+        assert_eq!(
+            fs::read(dir.join("server/up.bin")).unwrap(),
+            content(10 * 512 + 7)
+        );
+    }
+
+    #[tokio::test]
+    async fn reports_remote_error() {
+        let (mut config, dir, _server) = serve("reports_remote_error");
+        config.file_path = PathBuf::from("missing.bin");
+
+        let result = AsyncClient::new(&config).unwrap().run().await;
+        assert!(matches!(result, Err(TftpError::FileNotFound(_))));
+        assert!(!dir.join("client/missing.bin").exists());
+    }
+
+    #[tokio::test]
+    async fn acknowledges_last_block_before_gap() {
+        let dir = PathBuf::from(DIR_NAME).join("acknowledges_last_block_before_gap");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        // Server without options, losing block 2 once
+        let server = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let handle = thread::spawn(move || {
+            let mut buf = [0; 1024];
+            let (_, client) = server.recv_from(&mut buf).unwrap();
+            let mut acks = vec![];
+            for (block_num, len) in [(1, 512), (3, 10), (2, 512), (3, 10)] {
+                let data = Packet::Data {
+                    block_num,
+                    data: vec![block_num as u8; len],
+                };
+                server.send_to(&data.serialize().unwrap(), client).unwrap();
+                let len = server.recv(&mut buf).unwrap();
+                acks.push(Packet::deserialize(&buf[..len]).unwrap());
+            }
+            acks
+        });
+
+        let config = ClientConfig {
+            remote_ip_address: addr.ip(),
+            port: addr.port(),
+            file_path: PathBuf::from("file.bin"),
+            receive_directory: dir.clone(),
+            ..Default::default()
+        };
+        let stats = AsyncClient::new(&config).unwrap().run().await.unwrap();
+        assert_eq!(stats.retransmits, 1);
+        assert_eq!(
+            handle.join().unwrap(),
+            [
+                Packet::Ack(1),
+                Packet::Ack(1),
+                Packet::Ack(2),
+                Packet::Ack(3)
+            ]
+        );
+
+        let mut expected = vec![1; 512];
+        expected.extend([2; 512]);
+        expected.extend([3; 10]);
+        assert_eq!(fs::read(dir.join("file.bin")).unwrap(), expected);
+    }
+
+    #[test]
+    fn refuses_unsupported_features() {
+        let config = ClientConfig {
+            file_path: PathBuf::from("file.bin"),
+            multicast: true,
+            ..Default::default()
+        };
+        let err = AsyncClient::new(&config).err().unwrap();
+        assert_eq!(err.to_string(), "AsyncClient does not support multicast");
+    }
+}
@@ -0,0 +1,328 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+use std::str::FromStr;
+
+// CRC-32 (IEEE 802.3) reflected polynomial, as used by zip, gzip and Ethernet
+const POLYNOMIAL: u32 = 0xEDB8_8320;
+const TABLE: [u32; 256] = make_table();
+
+const fn make_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ POLYNOMIAL
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+/// Crc32 `struct` computes a CRC-32 incrementally, block after block.
+#[derive(Clone, Copy, Debug)]
+pub struct Crc32(u32);
+
+impl Crc32 {
+    /// Creates a new [`Crc32`] of empty data.
+    pub fn new() -> Crc32 {
+        Crc32(!0)
+    }
+
+    /// Adds `data` to the checksum.
+    pub fn update(&mut self, data: &[u8]) {
+        for byte in data {
+            self.0 = TABLE[((self.0 ^ *byte as u32) & 0xFF) as usize] ^ (self.0 >> 8);
+        }
+    }
+
+    /// Returns the checksum of the data added so far.
+    pub fn value(&self) -> u32 {
+        !self.0
+    }
+}
+
+impl Default for Crc32 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Returns the CRC-32 of the content of the file at `path`.
+pub fn crc32_file(path: &Path) -> io::Result<u32> {
+    crc32_read(File::open(path)?)
+}
+
+/// Returns the CRC-32 of all the data read from `reader`.
+pub fn crc32_read<R: Read>(mut reader: R) -> io::Result<u32> {
+    let mut crc = Crc32::new();
+    let mut buf = [0; 8192];
+
+    loop {
+        let len = reader.read(&mut buf)?;
+        if len == 0 {
+            return Ok(crc.value());
+        }
+        crc.update(&buf[..len]);
+    }
+}
+
+// SHA-256 round constants, FIPS 180-4 section 4.2.2
+const SHA256_K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+// SHA-256 initial hash value, FIPS 180-4 section 5.3.3
+const SHA256_H: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+];
+
+/// Sha256 `struct` computes a SHA-256 incrementally, block after block.
+#[derive(Clone, Debug)]
+pub struct Sha256 {
+    state: [u32; 8],
+    block: [u8; 64],
+    block_len: usize,
+    len: u64,
+}
+
+impl Sha256 {
+    /// Creates a new [`Sha256`] of empty data.
+    pub fn new() -> Sha256 {
+        Sha256 {
+            state: SHA256_H,
+            block: [0; 64],
+            block_len: 0,
+            len: 0,
+        }
+    }
+
+    /// Adds `data` to the hash.
+    pub fn update(&mut self, mut data: &[u8]) {
+        self.len += data.len() as u64;
+
+        while !data.is_empty() {
+            let len = data.len().min(64 - self.block_len);
+            self.block[self.block_len..self.block_len + len].copy_from_slice(&data[..len]);
+            self.block_len += len;
+            data = &data[len..];
+
+            if self.block_len == 64 {
+                self.compress();
+                self.block_len = 0;
+            }
+        }
+    }
+
+    /// Returns the hash of the data added so far.
+    pub fn value(&self) -> [u8; 32] {
+        let mut sha = self.clone();
+        let bit_len = self.len * 8;
+
+        sha.update(&[0x80]);
+        while sha.block_len != 56 {
+            sha.update(&[0]);
+        }
+        sha.update(&bit_len.to_be_bytes());
+
+        let mut hash = [0; 32];
+        for (bytes, word) in hash.chunks_mut(4).zip(sha.state) {
+            bytes.copy_from_slice(&word.to_be_bytes());
+        }
+        hash
+    }
+
+    fn compress(&mut self) {
+        let mut w = [0u32; 64];
+        for (word, bytes) in w.iter_mut().zip(self.block.chunks(4)) {
+            *word = u32::from_be_bytes(bytes.try_into().unwrap());
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(w[i - 7])
+                .wrapping_add(s1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = self.state;
+        for i in 0..64 {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ (!e & g);
+            let t1 = h
+                .wrapping_add(s1)
+                .wrapping_add(ch)
+                .wrapping_add(SHA256_K[i])
+                .wrapping_add(w[i]);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(maj);
+
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+
+        for (state, value) in self.state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+            *state = state.wrapping_add(value);
+        }
+    }
+}
+
+impl Default for Sha256 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// HashAlgorithm `enum` selects the digest computed over a received file.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HashAlgorithm {
+    /// CRC-32 (IEEE 802.3), 4 bytes
+    Crc32,
+    /// SHA-256, 32 bytes
+    Sha256,
+}
+
+impl HashAlgorithm {
+    /// Returns the hexadecimal digest of the content of the file at `path`,
+    /// read by blocks so that large files are not loaded in memory.
+    pub fn digest_file(&self, path: &Path) -> io::Result<String> {
+        let mut file = File::open(path)?;
+
+        let digest = match self {
+            HashAlgorithm::Crc32 => crc32_read(file)?.to_be_bytes().to_vec(),
+            HashAlgorithm::Sha256 => {
+                let mut sha = Sha256::new();
+                let mut buf = [0; 8192];
+                loop {
+                    let len = file.read(&mut buf)?;
+                    if len == 0 {
+                        break sha.value().to_vec();
+                    }
+                    sha.update(&buf[..len]);
+                }
+            }
+        };
+
+        Ok(to_hex(&digest))
+    }
+
+    /// Checks that `hex` is a digest of this algorithm, and returns it in
+    /// lowercase as returned by [`HashAlgorithm::digest_file()`].
+    pub fn parse_digest(&self, hex: &str) -> Result<String, Box<dyn Error>> {
+        let len = match self {
+            HashAlgorithm::Crc32 => 8,
+            HashAlgorithm::Sha256 => 64,
+        };
+        if hex.len() != len || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(format!("{self:?} digest must be {len} hexadecimal digits").into());
+        }
+
+        Ok(hex.to_lowercase())
+    }
+}
+
+impl FromStr for HashAlgorithm {
+    type Err = Box<dyn Error>;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "crc32" => Ok(HashAlgorithm::Crc32),
+            "sha256" => Ok(HashAlgorithm::Sha256),
+            _ => Err(format!("unknown hash algorithm {s}").into()),
+        }
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn computes_reference_values() {
+        assert_eq!(Crc32::new().value(), 0);
+
+        let mut crc = Crc32::new();
+        crc.update(b"123456789");
+        assert_eq!(crc.value(), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn computes_incrementally() {
+        let mut crc = Crc32::new();
+        crc.update(b"The quick brown fox ");
+        crc.update(b"jumps over the lazy dog");
+        assert_eq!(crc.value(), 0x414F_A339);
+    }
+
+    #[test]
+    fn computes_sha256_reference_values() {
+        let sha256 = |data: &[u8]| {
+            let mut sha = Sha256::new();
+            sha.update(data);
+            to_hex(&sha.value())
+        };
+
+        assert_eq!(
+            sha256(b""),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            sha256(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(
+            sha256(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"),
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+        );
+    }
+
+    #[test]
+    fn computes_sha256_incrementally() {
+        let data: Vec<u8> = (0..1000u32).map(|i| i as u8).collect();
+        let mut sha = Sha256::new();
+        for chunk in data.chunks(37) {
+            sha.update(chunk);
+        }
+
+        assert_eq!(
+            to_hex(&sha.value()),
+            "a8af099bf2e878609558dbf69d8f88f4a31040a8cf84b549a0cfa912f12ffc3f"
+        );
+    }
+
+    #[test]
+    fn parses_digest() {
+        let crc32 = HashAlgorithm::Crc32;
+        assert_eq!(crc32.parse_digest("74E3fb41").unwrap(), "74e3fb41");
+        assert!(crc32.parse_digest("74e3fb4").is_err());
+        assert!(crc32.parse_digest("74e3fbzz").is_err());
+        assert!(HashAlgorithm::Sha256.parse_digest("74e3fb41").is_err());
+    }
+}
@@ -1,15 +1,42 @@
 use std::cmp::PartialEq;
 use std::error::Error;
-use std::fs;
-use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
-use std::path::PathBuf;
-use std::sync::{atomic::AtomicBool, Arc};
-use std::time::Duration;
+use std::fmt;
+use std::fs::{self, File};
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6, UdpSocket};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant, SystemTime};
 
-#[cfg(debug_assertions)]
+use crate::checksum::{crc32_file, crc32_read};
+use crate::client_config::is_link_local;
 use crate::options::OptionFmt;
 use crate::options::{OptionsPrivate, OptionsProtocol};
-use crate::{log::*, ClientConfig, Packet, Socket, Worker};
+use crate::server::sanitize_path;
+use crate::socket::{buffer_size, set_buffer_size, Buffer};
+use crate::{log::*, ClientConfig, ErrorCode, Packet, Pipeline, Socket, TftpError, TransferStats};
+use crate::{Compression, HashAlgorithm, NetasciiEncoder, OptionType, TransferEvent, TransferMode};
+#[cfg(feature = "trace")]
+use crate::{Direction, Trace, TraceEntry, TracedSocket};
+use crate::{Meter, MeteredSocket, Multicast, MulticastSocket, RateLimitedSocket};
+use crate::{ProgressMeter, ProgressReport, StrictTidSocket, TransferOption, Worker};
+
+// Local path standing for stdin when uploading, stdout when downloading
+const STDIO: &str = "-";
+// Largest wait for the answer to a request, in request timeouts
+const MAX_HANDSHAKE_BACKOFF: u32 = 8;
+// Events buffered before the transfer waits for them to be consumed
+const EVENT_BUFFER_SIZE: usize = 16;
+// Block sizes tried in turn by the fallback: Ethernet MTU, then usual sizes
+const BLOCK_SIZE_LADDER: [u16; 3] = [1468, 1024, 512];
+// Headers around a block: IP, UDP (8 bytes) and TFTP data (4 bytes)
+const IPV4_OVERHEAD: u16 = 20 + 8 + 4;
+const IPV6_OVERHEAD: u16 = 40 + 8 + 4;
+
+type ProgressCallback = Box<dyn FnMut(u64, Option<u64>)>;
+type ProgressReportCallback = Box<dyn FnMut(&ProgressReport)>;
 
 /// Client `struct` is used for client sided TFTP requests.
 ///
@@ -28,16 +55,82 @@ use crate::{log::*, ClientConfig, Packet, Socket, Worker};
 /// ```
 pub struct Client {
     remote_address: SocketAddr,
+    remote_addresses: Vec<SocketAddr>,
+    local_address: Option<SocketAddr>,
     timeout_req: Duration,
+    max_handshake_retries: u32,
+    strict_source: bool,
+    strict_tid: bool,
+    mkdir: bool,
     mode: Mode,
     file_local: PathBuf,
     file_remote: String,
-    receive_directory: PathBuf,
+    extra_files: Vec<PathBuf>,
+    transfer_mode: TransferMode,
     opt_local: OptionsPrivate,
+    opt_request: OptionsProtocol,
     opt_common: OptionsProtocol,
+    min_block_size: u16,
+    auto_block_size: bool,
+    overwrite: Overwrite,
+    stdio: bool,
+    resume: bool,
+    resume_offset: u64,
+    source: Option<(u64, Option<SystemTime>)>,
+    hash: Option<HashAlgorithm>,
+    expected_digest: Option<String>,
+    manifest: Option<Vec<u32>>,
+    compression: Option<Compression>,
+    dry_run: bool,
+    no_tsize: bool,
+    multicast: bool,
+    recv_buffer: Option<usize>,
+    send_buffer: Option<usize>,
+    pipeline: Option<Box<dyn Fn() -> Pipeline + Send>>,
+    progress: Option<ProgressCallback>,
+    progress_report: Option<ProgressReportCallback>,
+    events: Option<mpsc::SyncSender<TransferEvent>>,
+    meter: Meter,
+    #[cfg(feature = "trace")]
+    trace: Option<Trace>,
+    start: Instant,
+    stats: TransferStats,
+    all_stats: Vec<TransferStats>,
+    abort: Arc<AtomicBool>,
+}
+
+/// CancelToken `struct` aborts the transfers of a [`Client`] from another
+/// thread, e.g. from a signal handler or a user interface. The worker then
+/// sends an error to the server, and removes the partial file of a download
+/// unless it should be kept on error.
+///
+/// # Example
+///
+/// ```rust
+/// use tftpd::{Client, ClientConfig};
+///
+/// let args = ["test.file", "-u"].iter().map(|s| s.to_string());
+/// let client = Client::new(&ClientConfig::new(args).unwrap()).unwrap();
+/// let token = client.cancel_token();
+/// std::thread::spawn(move || token.cancel());
+/// ```
+#[derive(Clone, Debug)]
+pub struct CancelToken {
     abort: Arc<AtomicBool>,
 }
 
+impl CancelToken {
+    /// Requests the transfer to stop as soon as possible.
+    pub fn cancel(&self) {
+        self.abort.store(true, Ordering::Relaxed);
+    }
+
+    /// Returns `true` once [`CancelToken::cancel()`] was called.
+    pub fn is_cancelled(&self) -> bool {
+        self.abort.load(Ordering::Relaxed)
+    }
+}
+
 /// Enum used to set the client either in Download Mode or Upload Mode
 #[derive(PartialEq, Clone, Copy, Debug)]
 pub enum Mode {
@@ -47,31 +140,346 @@ pub enum Mode {
     Download,
 }
 
+/// Overwrite `enum` tells what to do when the destination file of a transfer
+/// already exists.
+#[derive(PartialEq, Clone, Copy, Debug, Default)]
+pub enum Overwrite {
+    /// Replace the existing file (default)
+    #[default]
+    Replace,
+    /// Refuse the transfer, before sending the request
+    NoClobber,
+    /// Append the downloaded data to the existing local file, for downloads only
+    Append,
+}
+
+/// IpFamily `enum` selects the IP version used to reach the server, for the
+/// sockets bound and the addresses of the server hostname.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum IpFamily {
+    /// IPv4 only
+    V4,
+    /// IPv6 only
+    V6,
+}
+
+impl IpFamily {
+    /// Returns `true` if `ip` can be reached over this family, IPv4-mapped
+    /// IPv6 addresses being reachable over both.
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        match self {
+            IpFamily::V4 => ip.to_canonical().is_ipv4(),
+            IpFamily::V6 => ip.is_ipv6(),
+        }
+    }
+}
+
+impl fmt::Display for IpFamily {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IpFamily::V4 => write!(f, "IPv4"),
+            IpFamily::V6 => write!(f, "IPv6"),
+        }
+    }
+}
+
 impl Client {
     /// Creates the TFTP Client with the supplied [`ClientConfig`].
     pub fn new(config: &ClientConfig) -> Result<Client, Box<dyn Error>> {
+        let mut file_local = config.file_path.clone();
+        let mut file_remote = config.file_remote.clone();
+        let stdio = file_local.as_os_str() == STDIO || file_remote == STDIO;
+
+        match config.mode {
+            _ if stdio => {
+                // The other path is the remote one, whatever the order
+                if file_remote == STDIO {
+                    file_remote = file_local.display().to_string();
+                }
+                if file_remote.is_empty() || file_remote == STDIO {
+                    return Err("Remote file is required when using stdin or stdout".into());
+                }
+                file_local = PathBuf::from(STDIO);
+            }
+            Mode::Upload => {
+                if file_remote.is_empty() {
+                    // 1 path provided: use filename as remote
+                    file_remote = remote_name(&file_local)?;
+                }
+            }
+            Mode::Download => {
+                if file_remote.is_empty() {
+                    // 1 path provided: use it as remote and use rxdir + filename as local
+                    file_remote = file_local.display().to_string();
+                    file_local = sanitize_path(
+                        &config.receive_directory,
+                        Path::new(file_local.file_name().ok_or("Invalid filename")?),
+                    )?
+                } else if config.flatten {
+                    // 2 paths provided, flattened: use rxdir + local filename and remote as is
+                    file_local = sanitize_path(
+                        &config.receive_directory,
+                        Path::new(file_local.file_name().ok_or("Invalid filename")?),
+                    )?
+                } else {
+                    // 2 paths provided: prefix the local one with rxdir and use remote as is
+                    file_local = sanitize_path(&config.receive_directory, &file_local)?;
+                }
+            }
+        }
+
+        if !config.extra_files.is_empty() {
+            if config.mode != Mode::Upload || stdio {
+                return Err("Several files can only be uploaded from the file system".into());
+            }
+            if !config.file_remote.is_empty() {
+                return Err("A remote path cannot be given when uploading several files".into());
+            }
+            for file in &config.extra_files {
+                remote_name(file)?;
+            }
+        }
+
+        // IPv4-mapped addresses are reached over IPv4, unless IPv6 is forced,
+        // as IPv6 sockets may not accept them
+        let mut remote_ips: Vec<IpAddr> = std::iter::once(config.remote_ip_address)
+            .chain(config.remote_alternatives.iter().copied())
+            .map(|ip| match config.ip_family {
+                Some(IpFamily::V6) => ip,
+                _ => ip.to_canonical(),
+            })
+            .collect();
+        if let Some(family) = config.ip_family {
+            let remote_ip = remote_ips[0];
+            remote_ips.retain(|ip| family.contains(ip));
+            if remote_ips.is_empty() {
+                return Err(format!(
+                    "Remote address {remote_ip} is not an {family} address, as forced by -{}",
+                    if family == IpFamily::V4 { 4 } else { 6 }
+                )
+                .into());
+            }
+        }
+        let mut remote_addresses: Vec<SocketAddr> = remote_ips
+            .into_iter()
+            .map(|ip| match ip {
+                IpAddr::V6(ip) if is_link_local(&ip) => {
+                    SocketAddrV6::new(ip, config.port, 0, config.scope_id).into()
+                }
+                ip => SocketAddr::new(ip, config.port),
+            })
+            .collect();
+        if let Some(addr) = config.local_address {
+            let remote_address = remote_addresses[0];
+            remote_addresses.retain(|remote| remote.is_ipv4() == addr.is_ipv4());
+            if remote_addresses.is_empty() {
+                return Err(format!(
+                    "Local address {addr} and remote address {remote_address} are not of the same IP family"
+                )
+                .into());
+            }
+        }
+
+        let mut opt_request = config.opt_common.clone();
+        if let Some(max_memory) = config.opt_local.max_memory {
+            opt_request.clamp_memory(max_memory);
+        }
+        if let Some(mtu) = config.mtu {
+            let overhead = match remote_addresses[0] {
+                SocketAddr::V4(_) => IPV4_OVERHEAD,
+                SocketAddr::V6(_) => IPV6_OVERHEAD,
+            };
+            let max_block_size = mtu.saturating_sub(overhead).max(1);
+            if opt_request.block_size > max_block_size {
+                log_info!(
+                    "Blocksize {} exceeds MTU {mtu}. Changed to {max_block_size}.",
+                    opt_request.block_size
+                );
+                opt_request.block_size = max_block_size;
+            }
+        }
+
         Ok(Client {
-            remote_address: SocketAddr::from((config.remote_ip_address, config.port)),
+            remote_address: remote_addresses[0],
+            remote_addresses,
+            local_address: config.local_address,
             timeout_req: config.timeout_req,
+            max_handshake_retries: config.max_handshake_retries,
+            strict_source: config.strict_source,
+            strict_tid: config.strict_tid,
+            mkdir: config.mkdir,
             mode: config.mode,
-            file_local: config.file_path.clone(),
-            file_remote: config.file_remote.clone(),
-            receive_directory: config.receive_directory.clone(),
+            file_local,
+            file_remote,
+            extra_files: config.extra_files.clone(),
+            transfer_mode: config.transfer_mode,
             opt_local: config.opt_local.clone(),
-            opt_common: config.opt_common.clone(),
+            opt_common: opt_request.clone(),
+            opt_request,
+            min_block_size: config.min_blksize,
+            auto_block_size: config.auto_blksize,
+            overwrite: match config.overwrite {
+                Overwrite::NoClobber if config.force => Overwrite::Replace,
+                overwrite => overwrite,
+            },
+            stdio,
+            resume: config.resume,
+            resume_offset: 0,
+            source: None,
+            hash: config.hash,
+            expected_digest: config.expected_digest.clone(),
+            manifest: config.manifest.clone(),
+            compression: config.compression,
+            dry_run: config.dry_run,
+            no_tsize: config.no_tsize,
+            multicast: config.multicast,
+            recv_buffer: config.recv_buffer,
+            send_buffer: config.send_buffer,
+            pipeline: None,
+            progress: None,
+            progress_report: None,
+            events: None,
+            meter: Meter::default(),
+            #[cfg(feature = "trace")]
+            trace: None,
+            start: Instant::now(),
+            stats: Default::default(),
+            all_stats: vec![],
             abort: Arc::new(AtomicBool::new(false)),
         })
     }
 
-    /// Run the Client depending on the [`Mode`] the client is in
+    /// Run the Client depending on the [`Mode`] the client is in.
+    ///
+    /// Returns `Ok(false)` when the transfer failed locally, e.g. timed out,
+    /// the error being logged. I/O errors on the local file, e.g. a read
+    /// error in the middle of an upload, are returned with the file name and
+    /// block number.
+    ///
+    /// When the automatic block size fallback is enabled, a transfer that
+    /// failed locally (e.g. timed out) or was refused by the server because of
+    /// its options is restarted from the handshake with the next smaller block
+    /// size, until the configured minimum block size is reached. Other errors
+    /// from the server are returned as is.
+    ///
+    /// When several files are uploaded, they are sent one after the other,
+    /// each with its own request, even if some of them fail. An error listing
+    /// the failed files is then returned.
     pub fn run(&mut self) -> Result<bool, Box<dyn Error>> {
-        let socket = if self.remote_address.is_ipv4() {
-            UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?
+        if self.extra_files.is_empty() {
+            return self.run_file();
+        }
+
+        let files: Vec<PathBuf> = std::iter::once(self.file_local.clone())
+            .chain(self.extra_files.clone())
+            .collect();
+        self.all_stats.clear();
+        let mut failures = vec![];
+        for file in &files {
+            if self.abort.load(Ordering::Relaxed) {
+                failures.push(format!("{}: aborted", file.display()));
+                continue;
+            }
+            self.file_remote = remote_name(file)?;
+            self.file_local = file.clone();
+            log_info!("Uploading {}", file.display());
+
+            let result = self.run_file();
+            self.all_stats.push(self.stats.clone());
+            match result {
+                Ok(true) => {}
+                Ok(false) => failures.push(format!("{}: transfer failed", file.display())),
+                Err(err) => failures.push(format!("{}: {err}", file.display())),
+            }
+        }
+
+        if failures.is_empty() {
+            Ok(true)
         } else {
-            UdpSocket::bind((Ipv6Addr::UNSPECIFIED, 0))?
-        };
+            Err(format!(
+                "{} of {} uploads failed: {}",
+                failures.len(),
+                files.len(),
+                failures.join(", ")
+            )
+            .into())
+        }
+    }
 
-        socket.set_read_timeout(Some(self.timeout_req))?;
+    /// Runs the transfer of the current file, falling back to smaller block
+    /// sizes when enabled
+    fn run_file(&mut self) -> Result<bool, Box<dyn Error>> {
+        loop {
+            self.opt_common = self.opt_request.clone();
+
+            let result = self.run_once();
+            let retry = match &result {
+                Ok(success) => !success,
+                Err(err) => matches!(
+                    err.downcast_ref::<TftpError>(),
+                    Some(TftpError::IllegalOperation(_)) | Some(TftpError::RefusedOption(_))
+                ),
+            };
+            if !retry || !self.auto_block_size {
+                return result;
+            }
+            if self.stdio {
+                log_warn!("Cannot retry a transfer from stdin or to stdout");
+                return result;
+            }
+
+            let block_size = fallback_block_size(self.opt_common.block_size, self.min_block_size)?;
+            log_warn!(
+                "Transfer failed with block size {}, retrying with {block_size}",
+                self.opt_common.block_size
+            );
+            self.opt_request.block_size = block_size;
+        }
+    }
+
+    /// Only performs the handshake with the server and returns the options it
+    /// accepted. The transfer is then aborted with an error packet, so no
+    /// local file is created or modified.
+    pub fn negotiate(&mut self) -> Result<OptionsProtocol, Box<dyn Error>> {
+        self.opt_common = self.opt_request.clone();
+        let dry_run = std::mem::replace(&mut self.dry_run, true);
+        let result = self.run_once();
+        self.dry_run = dry_run;
+        result?;
+
+        Ok(self.opt_common.clone())
+    }
+
+    /// Runs the transfer with each remote address in turn, until one answers
+    /// the request.
+    fn run_once(&mut self) -> Result<bool, Box<dyn Error>> {
+        let remote_addresses = self.remote_addresses.clone();
+        if remote_addresses.len() == 1 {
+            return self.run_with(remote_addresses[0]);
+        }
+
+        for &remote_address in &remote_addresses {
+            let result = self.run_with(remote_address);
+            match &result {
+                Err(err) if is_no_answer(err.as_ref()) => log_warn!("{err}, trying next address"),
+                _ => return result,
+            }
+        }
+
+        let attempted: Vec<String> = remote_addresses.iter().map(|a| a.to_string()).collect();
+        Err(format!(
+            "No answer to the request from any of {}",
+            attempted.join(", ")
+        )
+        .into())
+    }
+
+    fn run_with(&mut self, remote_address: SocketAddr) -> Result<bool, Box<dyn Error>> {
+        self.remote_address = remote_address;
+        let socket = self.bind(self.local_address())?;
+        self.size_buffers(&socket)?;
+        self.start = Instant::now();
+        self.stats = Default::default();
 
         match self.mode {
             Mode::Upload => self.upload(socket),
@@ -79,70 +487,322 @@ impl Client {
         }
     }
 
-    fn upload(&mut self, socket: UdpSocket) -> Result<bool, Box<dyn Error>> {
-        if self.mode != Mode::Upload {
-            return Err(Box::from("Client mode is set to Download"));
+    /// Sends a request and returns the first answer. The request is sent again
+    /// when no answer comes, with an exponential backoff from the request
+    /// timeout.
+    fn send_request(
+        &self,
+        socket: &UdpSocket,
+        request: &Packet,
+    ) -> Result<(Packet, SocketAddr), Box<dyn Error>> {
+        let mut wait = self.timeout_req;
+        let mut retry_cnt = 0;
+
+        loop {
+            Socket::send_to(socket, request, &self.remote_address)?;
+            #[cfg(feature = "trace")]
+            self.trace_packet(Direction::Sent, request, self.remote_address);
+
+            match self.recv_answer(socket, wait) {
+                Ok(answer) => return Ok(answer),
+                Err(_) if self.abort.load(Ordering::Relaxed) => {
+                    return Err("Transfer aborted by user".into())
+                }
+                Err(err)
+                    if is_no_answer(err.as_ref()) && retry_cnt < self.max_handshake_retries =>
+                {
+                    retry_cnt += 1;
+                    wait = (wait * 2).min(self.timeout_req * MAX_HANDSHAKE_BACKOFF);
+                    log_info!(
+                        "  No answer to the request, sending it again {retry_cnt}/{}",
+                        self.max_handshake_retries
+                    );
+                }
+                Err(err) => return Err(self.request_error(err)),
+            }
+        }
+    }
+
+    /// Waits up to `wait` for the answer to a request. With a strict source,
+    /// packets from another IP address than the remote one are dropped, as
+    /// anyone may answer to the request port before the peer is pinned.
+    fn recv_answer(
+        &self,
+        socket: &UdpSocket,
+        wait: Duration,
+    ) -> Result<(Packet, SocketAddr), Box<dyn Error>> {
+        let deadline = Instant::now() + wait;
+
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(io::Error::from(io::ErrorKind::TimedOut).into());
+            }
+            socket.set_read_timeout(Some(remaining))?;
+
+            let (packet, from) = Socket::recv_from(socket)?;
+            if self.strict_source && from.ip() != self.remote_address.ip() {
+                log_warn!(
+                    "  Ignoring answer from {from}, request sent to {}",
+                    self.remote_address
+                );
+                continue;
+            }
+            #[cfg(feature = "trace")]
+            self.trace_packet(Direction::Received, &packet, from);
+            return Ok((packet, from));
+        }
+    }
+
+    /// Discards the answers already queued once the transfer is pinned to the
+    /// port of the server at `from`: those from other ports, e.g. answering a
+    /// request sent again, and the OACKs following the `first` answer.
+    fn discard_stale_answers(
+        &self,
+        socket: &UdpSocket,
+        from: SocketAddr,
+        first: &Packet,
+    ) -> Result<(), Box<dyn Error>> {
+        // Enough to tell the packets apart, data blocks are truncated
+        let mut buf = [0; 512];
+        socket.set_nonblocking(true)?;
+
+        while let Ok((len, sender)) = socket.peek_from(&mut buf) {
+            let packet = Packet::deserialize(&buf[..len]);
+            if sender != from {
+                log_warn!("  Ignoring answer from {sender}, transfer pinned to {from}");
+            } else if let Ok(packet @ Packet::Oack(..)) = &packet {
+                if packet != first {
+                    log_warn!("  Ignoring OACK with other options: {packet}");
+                }
+            } else {
+                break;
+            }
+            // The peeked datagram is consumed
+            let _ = socket.recv_from(&mut buf);
+        }
+
+        socket.set_nonblocking(false)?;
+
+        Ok(())
+    }
+
+    /// Converts the error of the reception of the answer to a request, a
+    /// timeout meaning the remote did not answer
+    fn request_error(&self, err: Box<dyn Error>) -> Box<dyn Error> {
+        if is_no_answer(err.as_ref()) {
+            io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("No answer to the request from {}", self.remote_address),
+            )
+            .into()
+        } else {
+            format!("Unexpected Error: {err}").into()
+        }
+    }
+
+    /// Returns the configured local address, or any address of the family of
+    /// the remote one, forced or given by its canonical form
+    pub(crate) fn local_address(&self) -> SocketAddr {
+        match self.local_address {
+            Some(addr) => addr,
+            None if self.remote_address.is_ipv4() => (Ipv4Addr::UNSPECIFIED, 0).into(),
+            None => (Ipv6Addr::UNSPECIFIED, 0).into(),
         }
+    }
+
+    fn bind(&self, local_address: SocketAddr) -> Result<UdpSocket, Box<dyn Error>> {
+        let socket = UdpSocket::bind(local_address)?;
+
+        socket.set_read_timeout(Some(self.timeout_req))?;
+
+        Ok(socket)
+    }
 
-        if self.file_remote.is_empty() {
-            // 1 path provided: use filename as remote
-            self.file_remote = self
-                .file_local
-                .file_name()
-                .ok_or("Invalid filename")?
-                .to_str()
-                .ok_or("Filename is not valid UTF-8")?
-                .to_owned();
+    /// Sets the sizes of the socket buffers, by default large enough for a
+    /// whole window of data packets so that none is dropped before being read
+    fn size_buffers(&self, socket: &UdpSocket) -> Result<(), Box<dyn Error>> {
+        let window =
+            self.opt_request.window_size as usize * (self.opt_request.block_size as usize + 4);
+
+        for (buffer, name, size) in [
+            (Buffer::Receive, "receive", self.recv_buffer),
+            (Buffer::Send, "send", self.send_buffer),
+        ] {
+            let size = match size {
+                Some(size) => size,
+                None if buffer_size(socket, buffer).is_ok_and(|current| current < window) => window,
+                None => continue,
+            };
+            let applied = set_buffer_size(socket, buffer, size)?;
+            log_dbg!("  Socket {name} buffer of {applied} bytes, {size} requested");
         }
 
-        self.opt_common.transfer_size = Some(fs::metadata(self.file_local.clone())?.len());
+        Ok(())
+    }
+
+    /// Checks whether the remote file exists with a read request, which is
+    /// aborted as soon as the server answers. When the server does not give a
+    /// definitive answer, the file is considered missing.
+    fn remote_exists(&self) -> Result<bool, Box<dyn Error>> {
+        // The transfer socket is already bound to the local port
+        let socket = self.bind(SocketAddr::new(self.local_address().ip(), 0))?;
 
-        log_dbg!("  Sending Write request for {}", self.file_remote);
+        log_dbg!("  Probing remote file {}", self.file_remote);
         Socket::send_to(
             &socket,
-            &Packet::Wrq {
+            &Packet::Rrq {
                 filename: self.file_remote.clone(),
                 mode: "octet".into(),
-                options: self.opt_common.prepare(),
+                options: vec![TransferOption {
+                    option: OptionType::TransferSize,
+                    value: 0,
+                }],
+                extra: vec![],
             },
             &self.remote_address,
         )?;
 
         match Socket::recv_from(&socket) {
-            Ok((packet, from)) => {
-                socket.connect(from)?;
-                match packet {
-                    Packet::Oack(options) => {
-                        // Reset options before applying those from server
-                        self.opt_common = Default::default();
-                        self.opt_common.apply(&options)?;
-                        log_dbg!("  Accepted options: {}", OptionFmt(&options));
-                    }
+            Ok((Packet::Oack(..) | Packet::Data { .. }, from)) => {
+                Socket::send_to(
+                    &socket,
+                    &Packet::Error {
+                        code: ErrorCode::NotDefined,
+                        msg: "probe only".to_string(),
+                    },
+                    &from,
+                )?;
+                Ok(true)
+            }
+            Ok((
+                Packet::Error {
+                    code: ErrorCode::FileNotFound,
+                    ..
+                },
+                _,
+            )) => Ok(false),
+            Ok((Packet::Error { code, msg }, _)) => {
+                log_warn!("Cannot probe remote file ({code}: {msg}), uploading anyway");
+                Ok(false)
+            }
+            Ok((packet, _)) => {
+                log_warn!("Unexpected answer to remote file probe ({packet}), uploading anyway");
+                Ok(false)
+            }
+            Err(err) => {
+                log_warn!("No answer to remote file probe ({err}), uploading anyway");
+                Ok(false)
+            }
+        }
+    }
 
-                    Packet::Ack(_) => {
-                        self.opt_common = Default::default();
-                        log_dbg!("  Options not accepted, using default");
-                    }
+    fn upload(&mut self, socket: UdpSocket) -> Result<bool, Box<dyn Error>> {
+        if self.mode != Mode::Upload {
+            return Err(Box::from("Client mode is set to Download"));
+        }
 
-                    Packet::Error { code, msg } => {
-                        return Err(Box::from(format!(
-                            "Client received error from server: {code}: {msg}"
-                        )))
-                    }
+        if self.overwrite == Overwrite::NoClobber && self.remote_exists()? {
+            return Err(format!(
+                "Remote file {} already exists, use --force to overwrite it",
+                self.file_remote
+            )
+            .into());
+        }
 
-                    _ => {
-                        return Err(Box::from(format!(
-                            "Client received unexpected packet from server: {packet:#?}"
-                        )))
-                    }
+        // The file is checked against this snapshot while sent
+        self.source = match fs::metadata(&self.file_local) {
+            Ok(metadata) if !self.stdio => Some((metadata.len(), metadata.modified().ok())),
+            _ => None,
+        };
+
+        // Sizes and checksums are those of the data as sent
+        match self.transfer_mode {
+            _ if self.stdio => {
+                self.opt_common.transfer_size = None;
+                if self.opt_common.checksum.take().is_some() {
+                    log_warn!("Cannot compute the checksum of stdin, skipping verification");
+                }
+            }
+            // Not known before the server accepts or refuses the compression
+            _ if self.compression.is_some() => {
+                self.opt_common.transfer_size = None;
+                if self.opt_common.checksum.take().is_some() {
+                    log_warn!(
+                        "Cannot announce the checksum of compressed data, skipping verification"
+                    );
+                }
+            }
+            TransferMode::Octet => {
+                if !self.no_tsize {
+                    self.opt_common.transfer_size = Some(fs::metadata(&self.file_local)?.len());
+                }
+                if self.opt_common.checksum.is_some() {
+                    self.opt_common.checksum = Some(crc32_file(&self.file_local)?);
+                }
+            }
+            TransferMode::Netascii => {
+                if !self.no_tsize {
+                    let mut encoder = NetasciiEncoder::new(File::open(&self.file_local)?);
+                    self.opt_common.transfer_size = Some(io::copy(&mut encoder, &mut io::sink())?);
+                }
+                if self.opt_common.checksum.is_some() {
+                    let encoder = NetasciiEncoder::new(File::open(&self.file_local)?);
+                    self.opt_common.checksum = Some(crc32_read(encoder)?);
+                }
+            }
+        }
+
+        if let Some(compression) = self.compression {
+            self.opt_common.extra.push(compression.option());
+        }
+
+        let request = Packet::Wrq {
+            filename: self.file_remote.clone(),
+            mode: self.transfer_mode.to_string(),
+            options: self.opt_common.prepare(),
+            extra: self.opt_common.extra.clone(),
+        };
+        log_dbg!("  Sending {request}");
+
+        let (packet, from) = self.send_request(&socket, &request)?;
+        if !self.strict_tid {
+            socket.connect(from)?;
+        }
+        self.discard_stale_answers(&socket, from, &packet)?;
+        match packet {
+            Packet::Oack(options, extra) => {
+                self.opt_common.apply(&options, &extra)?;
+                log_dbg!("  Accepted options: {}", OptionFmt(&options));
+                if let Some(max_memory) = self.opt_local.max_memory {
+                    self.opt_common.clamp_window(max_memory);
                 }
+            }
+
+            Packet::Ack(_) => {
+                self.opt_common = Default::default();
+                log_dbg!("  Options not accepted, using default");
+            }
+
+            Packet::Error { code, msg } => return Err(TftpError::from_packet(code, msg).into()),
 
-                let worker = self.configure_worker(socket)?;
-                let join_handle = worker.send(false)?;
-                Ok(join_handle.join().unwrap())
+            _ => {
+                return Err(Box::from(format!(
+                    "Client received unexpected packet from server: {packet}"
+                )))
             }
-            Err(err) => Err(Box::from(format!("Unexpected Error: {err}"))),
         }
+
+        let socket = self.pin_socket(socket, from);
+        self.handshake_complete();
+        if self.dry_run {
+            return self.abort_dry_run(&socket);
+        }
+
+        let mut worker = self.configure_worker(socket)?;
+        let progress = self.progress_channel(&mut worker);
+        let stats = worker.stats();
+        self.finish(worker.send(false)?, stats, progress)
     }
 
     fn download(&mut self, socket: UdpSocket) -> Result<bool, Box<dyn Error>> {
@@ -150,80 +810,2503 @@ impl Client {
             return Err(Box::from("Client mode is set to Upload"));
         }
 
-        if self.file_remote.is_empty() {
-            // 1 path provided: use it as remote and use rxdir + filename as local
-            self.file_remote = self.file_local.display().to_string();
-            self.file_local = self
-                .receive_directory
-                .join(self.file_local.file_name().ok_or("Invalid filename")?)
-        } else {
-            // 2 paths provided: prefix the local one with rxdir and use remote as is
-            self.file_local = self.receive_directory.join(self.file_local.clone());
+        // Checked before the request, not to bother the server for nothing
+        if let Some(parent) = self.missing_directory() {
+            if !self.mkdir {
+                return Err(format!(
+                    "Directory {} does not exist, use --mkdir to create it or --flatten to save {} in the receive directory",
+                    parent.display(),
+                    self.file_local.file_name().unwrap_or_default().to_string_lossy()
+                )
+                .into());
+            }
+            // The local path was sanitized, it stays in the receive directory
+            if !self.dry_run {
+                log_info!("Creating directory {}", parent.display());
+                fs::create_dir_all(parent)?;
+            }
+        }
+        if self.overwrite == Overwrite::NoClobber
+            && !self.resume
+            && !self.stdio
+            && self.file_local.exists()
+        {
+            return Err(format!(
+                "Local file {} already exists, use --force to overwrite it",
+                self.file_local.display()
+            )
+            .into());
         }
 
-        log_dbg!("  Sending Read request for {}", self.file_remote);
-        Socket::send_to(
-            &socket,
-            &Packet::Rrq {
-                filename: self.file_remote.clone(),
-                mode: "octet".into(),
-                options: self.opt_common.prepare(),
+        self.resume_offset = match fs::metadata(&self.file_local) {
+            Ok(metadata) if self.resume && !self.stdio => metadata.len(),
+            _ => 0,
+        };
+        if self.resume_offset > 0 {
+            log_info!(
+                "Resuming download of {} after {} bytes, which are received again",
+                self.file_remote,
+                self.resume_offset
+            );
+        }
+
+        // Asks the server for the file size (RFC 2349)
+        if !self.no_tsize {
+            self.opt_common.transfer_size = Some(0);
+        }
+        if self.multicast {
+            let multicast = (Multicast::OPTION.to_string(), String::new());
+            self.opt_common.extra.push(multicast);
+        }
+        if let Some(compression) = self.compression {
+            self.opt_common.extra.push(compression.option());
+        }
+
+        let request = Packet::Rrq {
+            filename: self.file_remote.clone(),
+            mode: self.transfer_mode.to_string(),
+            options: self.opt_common.prepare(),
+            extra: self.opt_common.extra.clone(),
+        };
+        log_dbg!("  Sending {request}");
+
+        let (packet, from) = self.send_request(&socket, &request)?;
+        if !self.strict_tid {
+            socket.connect(from)?;
+        }
+        self.discard_stale_answers(&socket, from, &packet)?;
+        match packet {
+            Packet::Oack(options, extra) => {
+                self.opt_common.apply(&options, &extra)?;
+                log_dbg!("  Accepted options: {}", OptionFmt(&options));
+                if let Some(max_memory) = self.opt_local.max_memory {
+                    self.opt_common.clamp_window(max_memory);
+                }
+                if self.opt_request.checksum.is_some() && self.opt_common.checksum.is_none() {
+                    log_warn!("Server does not support checksum, skipping verification");
+                }
+                if let Some(size) = self.opt_common.transfer_size {
+                    if size < self.resume_offset {
+                        let msg = format!(
+                            "Remote file ({size} bytes) is smaller than the local partial file ({} bytes)",
+                            self.resume_offset
+                        );
+                        Socket::send_to(
+                            &socket,
+                            &Packet::Error {
+                                code: ErrorCode::NotDefined,
+                                msg: "resume aborted".to_string(),
+                            },
+                            &from,
+                        )?;
+                        return Err(msg.into());
+                    }
+                }
+                if self.multicast {
+                    let socket = self.join_multicast(socket)?;
+                    Socket::send(&socket, &Packet::Ack(0))?;
+                    return self.receive(socket, None);
+                }
+                Socket::send_to(&socket, &Packet::Ack(0), &from)?;
+                #[cfg(feature = "trace")]
+                self.trace_packet(Direction::Sent, &Packet::Ack(0), from);
+                self.receive(self.pin_socket(socket, from), None)
+            }
+
+            Packet::Data { .. } if self.multicast => Err(multicast_declined(&socket)),
+
+            // Server without options (RFC 2347) sending the first block
+            Packet::Data { block_num: 1, .. } => {
+                // The timeout was not negotiated but only rules our own wait
+                self.opt_common = OptionsProtocol {
+                    timeout: self.opt_common.timeout,
+                    ..Default::default()
+                };
+                log_dbg!("  Options not accepted, using default");
+                self.receive(self.pin_socket(socket, from), Some(packet))
+            }
+
+            Packet::Error { code, msg } => Err(TftpError::from_packet(code, msg).into()),
+
+            _ => Err(Box::from(format!(
+                "Client received unexpected packet from server: {packet}"
+            ))),
+        }
+    }
+
+    /// Receives the file once the request was answered, `first_packet` being
+    /// the data already received
+    fn receive(
+        &mut self,
+        socket: impl Socket,
+        first_packet: Option<Packet>,
+    ) -> Result<bool, Box<dyn Error>> {
+        self.handshake_complete();
+        if self.dry_run {
+            return self.abort_dry_run(&socket);
+        }
+
+        let mut worker = self.configure_worker(socket)?;
+        if let Some(new_pipeline) = &self.pipeline {
+            worker.set_pipeline(new_pipeline());
+        }
+        if let Some(packet) = first_packet {
+            worker.set_first_packet(packet);
+        }
+        let progress = self.progress_channel(&mut worker);
+        let stats = worker.stats();
+        self.finish(worker.receive()?, stats, progress)
+    }
+
+    /// Ends a dry run once the options are negotiated, telling the server
+    /// instead of letting it time out
+    fn abort_dry_run(&self, socket: &impl Socket) -> Result<bool, Box<dyn Error>> {
+        log_dbg!("  Dry run, aborting the transfer");
+        Socket::send(
+            socket,
+            &Packet::Error {
+                code: ErrorCode::NotDefined,
+                msg: "dry run".to_string(),
             },
-            &self.remote_address,
         )?;
 
-        match Socket::recv_from(&socket) {
-            Ok((packet, from)) => {
-                socket.connect(from)?;
-                match packet {
-                    Packet::Oack(options) => {
-                        // Reset options before applying those from server
-                        self.opt_common = Default::default();
-                        self.opt_common.apply(&options)?;
-                        log_dbg!("  Accepted options: {}", OptionFmt(&options));
-                        Socket::send_to(&socket, &Packet::Ack(0), &from)?;
-                        let worker = self.configure_worker(socket)?;
-                        let join_handle = worker.receive()?;
-                        Ok(join_handle.join().unwrap())
-                    }
+        Ok(true)
+    }
 
-                    // We could implement this by forwarding Option<packet::Data> to worker.receive()
-                    Packet::Data { .. } => Err(
-                        "Client received data instead of o-ack. This implementation \
-                        does not support servers without options (RFC 2347)"
-                            .into(),
-                    ),
+    /// Returns the directory of the downloaded file when it does not exist
+    fn missing_directory(&self) -> Option<&Path> {
+        if self.stdio {
+            return None;
+        }
+        self.file_local
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty() && !parent.is_dir())
+    }
 
-                    Packet::Error { code, msg } => Err(Box::from(format!(
-                        "Client received error from server: {code}: {msg}"
-                    ))),
+    /// Returns the socket of the transfer with the server at `from`, which
+    /// checks the source of the datagrams itself in strict TID mode, the
+    /// socket being connected otherwise
+    fn pin_socket(&self, socket: UdpSocket, from: SocketAddr) -> Box<dyn Socket> {
+        if self.strict_tid {
+            Box::new(StrictTidSocket::new(socket, from))
+        } else {
+            Box::new(socket)
+        }
+    }
 
-                    _ => Err(Box::from(format!(
-                        "Client received unexpected packet from server: {packet:#?}"
-                    ))),
-                }
-            }
-            Err(err) => Err(Box::from(format!("Unexpected Error: {err}"))),
+    /// Joins the multicast group answered by the server in its OACK
+    fn join_multicast(&self, socket: UdpSocket) -> Result<MulticastSocket, Box<dyn Error>> {
+        let Some(multicast) = Multicast::find(&self.opt_common.extra)? else {
+            return Err(multicast_declined(&socket));
+        };
+        let (Some(address), Some(port)) = (multicast.address, multicast.port) else {
+            return Err("Server did not give the multicast group".into());
+        };
+
+        log_info!(
+            "Joining multicast group {}{}",
+            SocketAddr::new(address, port),
+            if multicast.master { " as master" } else { "" }
+        );
+        MulticastSocket::join(socket, SocketAddr::new(address, port), multicast.master)
+    }
+
+    /// Returns the channel the events of the following transfers are sent
+    /// to, replacing the previous one. The channel is bounded: the transfer
+    /// waits while it is full, so the events must be consumed from another
+    /// thread. Once the receiver is dropped, the events are discarded.
+    pub fn events(&mut self) -> mpsc::Receiver<TransferEvent> {
+        let (sender, receiver) = mpsc::sync_channel(EVENT_BUFFER_SIZE);
+        self.events = Some(sender);
+        receiver
+    }
+
+    fn handshake_complete(&self) {
+        self.report_event(TransferEvent::HandshakeComplete {
+            options: self.opt_common.clone(),
+        });
+    }
+
+    fn report_event(&self, event: TransferEvent) {
+        if let Some(events) = &self.events {
+            let _ = events.send(event);
         }
     }
 
-    fn configure_worker(&self, socket: UdpSocket) -> Result<Worker<dyn Socket>, Box<dyn Error>> {
+    /// Sets the function building the [`Pipeline`] the downloaded data goes
+    /// through before being written. A new [`Pipeline`] is built for each
+    /// download attempt.
+    pub fn set_pipeline<F: Fn() -> Pipeline + Send + 'static>(&mut self, new_pipeline: F) {
+        self.pipeline = Some(Box::new(new_pipeline));
+    }
+
+    /// Sets the callback reporting the progress of the transfers, called with
+    /// the number of bytes transferred so far and the total size when known
+    /// from the transfer size option. It is called once per completed window,
+    /// and a final time when the transfer succeeds.
+    pub fn set_progress_callback(&mut self, progress: Box<dyn FnMut(u64, Option<u64>)>) {
+        self.progress = Some(progress);
+    }
+
+    /// Sets the callback reporting the progress of the transfers with their
+    /// rates, time left and percentage, see [`ProgressReport`]. It is called
+    /// as the one of [`Client::set_progress_callback()`].
+    pub fn set_progress_report_callback(&mut self, progress: Box<dyn FnMut(&ProgressReport)>) {
+        self.progress_report = Some(progress);
+    }
+
+    fn configure_worker(
+        &mut self,
+        socket: impl Socket,
+    ) -> Result<Worker<dyn Socket>, Box<dyn Error>> {
+        let socket = MeteredSocket::new(socket);
+        self.meter = socket.meter();
         let mut socket: Box<dyn Socket> = Box::new(socket);
+        if let Some(max_rate) = self.opt_local.max_rate {
+            socket = Box::new(RateLimitedSocket::new(socket, max_rate));
+        }
+        #[cfg(feature = "trace")]
+        if let Some(trace) = &self.trace {
+            socket = Box::new(TracedSocket::new(socket, trace.clone()));
+        }
 
         socket.set_read_timeout(self.opt_common.timeout)?;
         socket.set_write_timeout(self.opt_common.timeout)?;
 
-        Ok(Worker::new(
+        let mut worker = Worker::new(
             socket,
             self.file_local.clone(),
             self.opt_local.clone(),
             self.opt_common.clone(),
             self.abort.clone(),
-        ))
+        );
+        worker.set_start(self.start);
+        worker.set_mode(self.transfer_mode);
+        if let Some(events) = &self.events {
+            worker.set_events(events.clone());
+        }
+        match self.mode {
+            Mode::Upload if self.stdio => worker.set_reader(Box::new(io::stdin())),
+            Mode::Download if self.stdio => worker.set_writer(Box::new(io::stdout())),
+            Mode::Upload => {
+                if let Some((len, modified)) = self.source {
+                    worker.set_source_snapshot(len, modified);
+                }
+            }
+            Mode::Download => {
+                worker.set_resume(self.resume_offset);
+                worker.set_append(self.overwrite == Overwrite::Append);
+            }
+        }
+        if let Some(manifest) = &self.manifest {
+            worker.set_manifest(manifest.clone());
+        }
+        if self.compression.is_some() {
+            let compression = Compression::find(&self.opt_common.extra);
+            if compression.is_none() {
+                log_warn!("Server does not support compression, transferring uncompressed data");
+            }
+            worker.set_compression(compression);
+        }
+
+        Ok(worker)
     }
 
-    /// Retrieve a ref to the abort flag
-    pub fn get_abort_flag(&self) -> Arc<AtomicBool> {
-        self.abort.clone()
+    /// Makes the worker report its progress when a callback is set
+    fn progress_channel(&self, worker: &mut Worker<dyn Socket>) -> Option<mpsc::Receiver<u64>> {
+        if self.progress.is_none() && self.progress_report.is_none() {
+            return None;
+        }
+        let (sender, receiver) = mpsc::channel();
+        worker.set_progress(sender);
+        Some(receiver)
+    }
+
+    fn report_progress(&mut self, meter: &mut ProgressMeter, bytes: u64) {
+        if let Some(callback) = &mut self.progress {
+            callback(bytes, self.opt_common.transfer_size);
+        }
+        if let Some(callback) = &mut self.progress_report {
+            callback(&meter.update(Instant::now(), bytes));
+        }
+    }
+
+    fn finish(
+        &mut self,
+        join_handle: JoinHandle<Result<(), TftpError>>,
+        stats: Arc<Mutex<TransferStats>>,
+        progress: Option<mpsc::Receiver<u64>>,
+    ) -> Result<bool, Box<dyn Error>> {
+        let total = self.opt_common.transfer_size;
+        let mut meter = ProgressMeter::new(self.start, total);
+        if let Some(progress) = progress {
+            // Ends when the worker thread drops its sender
+            for bytes in progress {
+                self.report_progress(&mut meter, bytes);
+            }
+        }
+
+        let result = join_handle.join().map_err(|_| "Transfer thread panicked")?;
+        self.stats = stats.lock().unwrap().clone();
+        self.stats.socket = self.meter.snapshot();
+        self.stats.block_size = self.opt_common.block_size;
+        if result.is_ok() {
+            self.report_progress(&mut meter, self.stats.bytes);
+        }
+
+        log_info!(
+            "Transferred {} bytes in {} blocks ({} retransmitted, {} windows)",
+            self.stats.bytes,
+            self.stats.blocks,
+            self.stats.retransmits,
+            self.stats.windows
+        );
+        if self.stats.windows_with_retransmits() > 0 {
+            log_dbg!(
+                "  Windows with retransmits: {}, windows by count of retransmits: {:?}",
+                self.stats.windows_with_retransmits(),
+                self.stats.window_retransmits
+            );
+        }
+        log_dbg!(
+            "  Socket: {} datagrams sent ({} bytes), {} received ({} bytes), {} retransmitted",
+            self.stats.socket.datagrams_sent,
+            self.stats.socket.bytes_sent,
+            self.stats.socket.datagrams_received,
+            self.stats.socket.bytes_received,
+            self.stats.socket.retransmits
+        );
+        if let Some(time) = self.stats.time_to_first_data {
+            log_info!(
+                "Time to first data: {:.3}s, total duration: {:.3}s",
+                time.as_secs_f32(),
+                self.stats.duration.as_secs_f32()
+            );
+        }
+
+        if result.is_ok() {
+            self.verify_digest()?;
+            self.report_event(TransferEvent::Completed {
+                stats: self.stats.clone(),
+            });
+        }
+
+        match result {
+            Ok(()) => Ok(true),
+            // Local failures were already reported by the worker, except the
+            // I/O errors, e.g. on the file, returned with their context
+            Err(TftpError::Transfer(err)) if !err.is::<io::Error>() => Ok(false),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Hashes the downloaded file with the selected algorithm and compares it
+    /// to the expected digest, if any. The file is removed on mismatch unless
+    /// it should be kept on error.
+    fn verify_digest(&mut self) -> Result<(), Box<dyn Error>> {
+        let Some(algorithm) = self.hash else {
+            return Ok(());
+        };
+        if self.mode != Mode::Download || self.stdio {
+            return Ok(());
+        }
+
+        let digest = algorithm.digest_file(&self.file_local)?;
+        log_info!("{algorithm:?} of {}: {digest}", self.file_local.display());
+        self.stats.digest = Some(digest.clone());
+
+        match &self.expected_digest {
+            Some(expected) if *expected != digest => {
+                if self.opt_local.clean_on_error && fs::remove_file(&self.file_local).is_err() {
+                    log_err!("Error while cleaning {}", self.file_local.display());
+                }
+                Err(
+                    format!("{algorithm:?} mismatch, expected: {expected}, computed: {digest}")
+                        .into(),
+                )
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns the size of the file of the last transfer, when known: the
+    /// local file size on upload, the size reported by the server on download.
+    pub fn transfer_size(&self) -> Option<u64> {
+        self.opt_common.transfer_size
+    }
+
+    /// Returns the options of the last transfer, as accepted by the server in
+    /// its OACK, or the default ones when it answered without options.
+    pub fn negotiated_options(&self) -> &OptionsProtocol {
+        &self.opt_common
+    }
+
+    /// Returns the path of the local file of the transfer, i.e. the file
+    /// written by a download, in the receive directory, once it succeeded.
+    /// `-` stands for stdin or stdout. With several uploads, it is the last
+    /// file sent.
+    pub fn output_path(&self) -> &Path {
+        &self.file_local
+    }
+
+    /// Returns the first address of the server, the one the request goes to
+    #[cfg(feature = "tokio")]
+    pub(crate) fn remote_address(&self) -> SocketAddr {
+        self.remote_address
+    }
+
+    /// Returns the path of the file on the server
+    #[cfg(feature = "tokio")]
+    pub(crate) fn remote_file(&self) -> &str {
+        &self.file_remote
+    }
+
+    /// Returns the options of the request, clamped to the memory budget and MTU
+    #[cfg(feature = "tokio")]
+    pub(crate) fn requested_options(&self) -> &OptionsProtocol {
+        &self.opt_request
+    }
+
+    /// Returns the statistics of the last transfer
+    pub fn stats(&self) -> &TransferStats {
+        &self.stats
+    }
+
+    /// Returns the statistics of each file uploaded by the last run, in
+    /// order, failed ones included. Empty unless several files are uploaded.
+    pub fn all_stats(&self) -> &[TransferStats] {
+        &self.all_stats
+    }
+
+    /// Retrieve a ref to the abort flag
+    pub fn get_abort_flag(&self) -> Arc<AtomicBool> {
+        self.abort.clone()
+    }
+
+    /// Returns a token to cancel the transfers of the client from another
+    /// thread.
+    pub fn cancel_token(&self) -> CancelToken {
+        CancelToken {
+            abort: self.abort.clone(),
+        }
+    }
+
+    /// Records the last `capacity` packets exchanged with the server, to be
+    /// retrieved with [`Client::take_trace()`].
+    #[cfg(feature = "trace")]
+    pub fn enable_trace(&mut self, capacity: usize) {
+        self.trace = Some(Trace::new(capacity));
+    }
+
+    /// Returns the packets recorded since the trace was enabled or last
+    /// taken, oldest first.
+    #[cfg(feature = "trace")]
+    pub fn take_trace(&mut self) -> Vec<TraceEntry> {
+        self.trace.as_ref().map(Trace::take).unwrap_or_default()
+    }
+
+    /// Records a packet exchanged outside of the socket of the worker
+    #[cfg(feature = "trace")]
+    fn trace_packet(&self, direction: Direction, packet: &Packet, peer: SocketAddr) {
+        if let Some(trace) = &self.trace {
+            trace.record(direction, packet, Some(peer));
+        }
+    }
+}
+
+/// Returns `true` if the error is a timeout while waiting for an answer
+fn is_no_answer(err: &(dyn Error + 'static)) -> bool {
+    matches!(
+        err.downcast_ref::<io::Error>().map(|err| err.kind()),
+        Some(io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut)
+    )
+}
+
+/// Returns the file name of `path`, used as remote path for the uploads
+fn remote_name(path: &Path) -> Result<String, Box<dyn Error>> {
+    Ok(path
+        .file_name()
+        .ok_or("Invalid filename")?
+        .to_str()
+        .ok_or("Filename is not valid UTF-8")?
+        .to_owned())
+}
+
+/// Returns the next block size to try after a transfer failed with `current`,
+/// or an error when it would fall below `floor`.
+fn fallback_block_size(current: u16, floor: u16) -> Result<u16, Box<dyn Error>> {
+    let next = BLOCK_SIZE_LADDER
+        .iter()
+        .copied()
+        .find(|&size| size < current)
+        .unwrap_or(current / 2);
+
+    if next == 0 || next < floor {
+        Err(format!("Block size fallback cannot go below {floor} (failed with {current})").into())
+    } else {
+        Ok(next)
+    }
+}
+
+/// Tells the server its answer to a multicast request lacks the multicast
+/// option, and returns the error of the client
+fn multicast_declined(socket: &UdpSocket) -> Box<dyn Error> {
+    let _ = Socket::send(
+        socket,
+        &Packet::Error {
+            code: ErrorCode::RefusedOption,
+            msg: "multicast declined".to_string(),
+        },
+    );
+
+    "Server declined multicast".into()
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::{Config, Server};
+    use std::path::Path;
+    use std::thread;
+
+    const DIR_NAME: &str = "target/test/client";
+
+    /// Running server, stopped when dropped
+    pub(crate) struct TestServer {
+        pub(crate) addr: SocketAddr,
+        abort: Arc<AtomicBool>,
+    }
+
+    impl TestServer {
+        fn serve_dir(dir: &Path) -> TestServer {
+            TestServer::start(Server::serve_dir("127.0.0.1:0".parse().unwrap(), dir).unwrap())
+        }
+
+        /// Serves `dir` for both downloads and uploads
+        pub(crate) fn read_write(dir: &Path) -> TestServer {
+            TestServer::read_write_with(dir, Config::default())
+        }
+
+        /// Serves `dir` for both downloads and uploads, with the other
+        /// settings of `config`
+        fn read_write_with(dir: &Path, config: Config) -> TestServer {
+            TestServer::start(
+                Server::new(&Config {
+                    ip_address: Ipv4Addr::LOCALHOST.into(),
+                    port: 0,
+                    receive_directory: dir.to_path_buf(),
+                    send_directory: dir.to_path_buf(),
+                    ..config
+                })
+                .unwrap(),
+            )
+        }
+
+        fn start(mut server: Server) -> TestServer {
+            let addr = server.local_addr().unwrap();
+            let abort = server.get_abort_flag();
+            thread::spawn(move || server.run());
+
+            TestServer { addr, abort }
+        }
+    }
+
+    impl Drop for TestServer {
+        fn drop(&mut self) {
+            self.abort.store(true, Ordering::Relaxed);
+        }
+    }
+
+    fn test_dirs(name: &str) -> (PathBuf, PathBuf) {
+        let server_dir = PathBuf::from(DIR_NAME).join(name).join("server");
+        let client_dir = PathBuf::from(DIR_NAME).join(name).join("client");
+        let _ = fs::remove_dir_all(PathBuf::from(DIR_NAME).join(name));
+        fs::create_dir_all(&server_dir).unwrap();
+        fs::create_dir_all(&client_dir).unwrap();
+
+        (server_dir, client_dir)
+    }
+
+    fn download_config(server: &TestServer, file: &str, client_dir: &Path) -> ClientConfig {
+        ClientConfig {
+            remote_ip_address: server.addr.ip(),
+            port: server.addr.port(),
+            file_path: PathBuf::from(file),
+            receive_directory: client_dir.to_path_buf(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn keeps_or_flattens_download_directories() {
+        let (server_dir, client_dir) = test_dirs("keeps_or_flattens_download_directories");
+        fs::create_dir_all(server_dir.join("a/b")).unwrap();
+        fs::write(server_dir.join("a/b/c.bin"), b"nested").unwrap();
+        let server = TestServer::serve_dir(&server_dir);
+        let mut config = download_config(&server, "a/b/c.bin", &client_dir);
+        config.file_remote = "a/b/c.bin".to_string();
+
+        // The directories of the local path are kept, and must exist
+        let err = Client::new(&config).unwrap().run().unwrap_err();
+        assert!(err.to_string().contains("does not exist"), "{err}");
+        assert!(!client_dir.join("a").exists());
+
+        fs::create_dir_all(client_dir.join("a/b")).unwrap();
+        assert!(Client::new(&config).unwrap().run().unwrap());
+        assert_eq!(fs::read(client_dir.join("a/b/c.bin")).unwrap(), b"nested");
+
+        config.flatten = true;
+        assert!(Client::new(&config).unwrap().run().unwrap());
+        assert_eq!(fs::read(client_dir.join("c.bin")).unwrap(), b"nested");
+
+        // A single path is always saved under its file name
+        let config = download_config(&server, "a/b/c.bin", &client_dir);
+        fs::remove_file(client_dir.join("c.bin")).unwrap();
+        assert!(Client::new(&config).unwrap().run().unwrap());
+        assert_eq!(fs::read(client_dir.join("c.bin")).unwrap(), b"nested");
+    }
+
+    #[test]
+    fn creates_download_directories() {
+        let (server_dir, client_dir) = test_dirs("creates_download_directories");
+        fs::create_dir_all(server_dir.join("configs/site1")).unwrap();
+        fs::write(server_dir.join("configs/site1/app.conf"), b"conf").unwrap();
+        let server = TestServer::serve_dir(&server_dir);
+        let mut config = download_config(&server, "configs/site1/app.conf", &client_dir);
+        config.file_remote = "configs/site1/app.conf".to_string();
+        config.mkdir = true;
+
+        assert!(Client::new(&config).unwrap().run().unwrap());
+        assert_eq!(
+            fs::read(client_dir.join("configs/site1/app.conf")).unwrap(),
+            b"conf"
+        );
+
+        // Nothing is created outside of the receive directory
+        config.file_path = PathBuf::from("../escaped/app.conf");
+        assert!(Client::new(&config).is_err());
+        assert!(!client_dir.join("../escaped").exists());
+    }
+
+    #[test]
+    fn applies_overwrite_policy_to_downloads() {
+        let (server_dir, client_dir) = test_dirs("applies_overwrite_policy_to_downloads");
+        fs::write(server_dir.join("file.txt"), b"remote").unwrap();
+        let server = TestServer::serve_dir(&server_dir);
+        let mut config = download_config(&server, "file.txt", &client_dir);
+
+        fs::write(client_dir.join("file.txt"), b"local ").unwrap();
+        // Refused before sending any request to the silent peer
+        let silent = UdpSocket::bind("127.0.0.1:0").unwrap();
+        config.port = silent.local_addr().unwrap().port();
+        config.overwrite = Overwrite::NoClobber;
+        let err = Client::new(&config).unwrap().run().unwrap_err();
+        assert!(err.to_string().contains("already exists"));
+        silent.set_nonblocking(true).unwrap();
+        assert!(silent.recv(&mut [0; 512]).is_err());
+        assert_eq!(fs::read(client_dir.join("file.txt")).unwrap(), b"local ");
+
+        config.port = server.addr.port();
+        config.overwrite = Overwrite::Append;
+        assert!(Client::new(&config).unwrap().run().unwrap());
+        assert_eq!(
+            fs::read(client_dir.join("file.txt")).unwrap(),
+            b"local remote"
+        );
+
+        config.overwrite = Overwrite::Replace;
+        assert!(Client::new(&config).unwrap().run().unwrap());
+        assert_eq!(fs::read(client_dir.join("file.txt")).unwrap(), b"remote");
+    }
+
+    #[test]
+    fn downloads_from_served_dir() {
+        let (server_dir, client_dir) = test_dirs("downloads_from_served_dir");
+        let content: Vec<u8> = (0..5000u32).map(|i| i as u8).collect();
+        fs::write(server_dir.join("file.bin"), &content).unwrap();
+
+        let server = TestServer::serve_dir(&server_dir);
+        let mut client = Client::new(&download_config(&server, "file.bin", &client_dir)).unwrap();
+
+        assert!(client.run().unwrap());
+        assert_eq!(fs::read(client_dir.join("file.bin")).unwrap(), content);
+    }
+
+    #[test]
+    fn refuses_local_path_outside_receive_dir() {
+        let (server_dir, client_dir) = test_dirs("refuses_local_path_outside_receive_dir");
+        let server = TestServer::serve_dir(&server_dir);
+        let mut config = download_config(&server, "../escape.bin", &client_dir);
+        config.file_remote = "file.bin".to_string();
+        assert!(Client::new(&config).is_err());
+
+        config.file_path = PathBuf::from("/tmp/escape.bin");
+        assert!(Client::new(&config).is_err());
+    }
+
+    #[cfg(feature = "trace")]
+    #[test]
+    fn traces_exchanged_packets() {
+        let (server_dir, client_dir) = test_dirs("traces_exchanged_packets");
+        fs::write(server_dir.join("file.bin"), [0; 600]).unwrap();
+
+        let server = TestServer::serve_dir(&server_dir);
+        let mut config = download_config(&server, "file.bin", &client_dir);
+        config.no_tsize = true;
+        let mut client = Client::new(&config).unwrap();
+        client.enable_trace(16);
+        assert!(client.run().unwrap());
+
+        let trace: Vec<(Direction, String)> = client
+            .take_trace()
+            .into_iter()
+            .map(|entry| (entry.direction, entry.packet))
+            .collect();
+        assert_eq!(trace[0].0, Direction::Sent);
+        assert!(trace[0].1.starts_with("RRQ \"file.bin\" octet"));
+        assert_eq!(
+            trace[1..],
+            [
+                (
+                    Direction::Received,
+                    "OACK [blksize=512,windowsize=1,timeout=5]".to_string()
+                ),
+                (Direction::Sent, "ACK #0".to_string()),
+                (Direction::Received, "DATA #1 (512 bytes)".to_string()),
+                (Direction::Sent, "ACK #1".to_string()),
+                (Direction::Received, "DATA #2 (88 bytes)".to_string()),
+                (Direction::Sent, "ACK #2".to_string()),
+            ]
+        );
+        assert!(client.take_trace().is_empty());
+    }
+
+    #[test]
+    fn negotiates_without_transfer() {
+        let (server_dir, client_dir) = test_dirs("negotiates_without_transfer");
+        fs::write(server_dir.join("file.bin"), [0; 3000]).unwrap();
+
+        let server = TestServer::serve_dir(&server_dir);
+        let mut config = download_config(&server, "file.bin", &client_dir);
+        config.opt_common.block_size = 1024;
+        config.opt_common.window_size = 4;
+        let mut client = Client::new(&config).unwrap();
+
+        let options = client.negotiate().unwrap();
+        assert_eq!(options.block_size, 1024);
+        assert_eq!(options.window_size, 4);
+        assert_eq!(options.transfer_size, Some(3000));
+        assert!(!client_dir.join("file.bin").exists());
+
+        // The client still transfers afterwards
+        assert!(client.run().unwrap());
+        assert_eq!(fs::read(client_dir.join("file.bin")).unwrap(), [0; 3000]);
+    }
+
+    #[test]
+    fn negotiates_selective_acknowledgements() {
+        let (server_dir, client_dir) = test_dirs("negotiates_selective_acknowledgements");
+        let content: Vec<u8> = (0..5000u32).map(|i| i as u8).collect();
+        fs::write(server_dir.join("file.bin"), &content).unwrap();
+
+        let server = TestServer::serve_dir(&server_dir);
+        let mut config = download_config(&server, "file.bin", &client_dir);
+        config.opt_common.window_size = 4;
+        config.opt_common.sack = true;
+        let mut client = Client::new(&config).unwrap();
+
+        assert!(client.negotiate().unwrap().sack);
+        assert!(client.run().unwrap());
+        assert_eq!(fs::read(client_dir.join("file.bin")).unwrap(), content);
+    }
+
+    #[test]
+    fn streams_transfer_events() {
+        let (server_dir, client_dir) = test_dirs("streams_transfer_events");
+        fs::write(server_dir.join("file.bin"), [7; 5000]).unwrap();
+
+        let server = TestServer::serve_dir(&server_dir);
+        let mut config = download_config(&server, "file.bin", &client_dir);
+        config.opt_common.block_size = 1024;
+        config.opt_common.window_size = 2;
+        let mut client = Client::new(&config).unwrap();
+        let events = client.events();
+        let consumer = thread::spawn(move || events.iter().collect::<Vec<_>>());
+
+        assert!(client.run().unwrap());
+        drop(client);
+        let events = consumer.join().unwrap();
+
+        assert!(matches!(
+            &events[0],
+            TransferEvent::HandshakeComplete { options } if options.block_size == 1024
+        ));
+        let windows: Vec<(u16, u64)> = events
+            .iter()
+            .filter_map(|event| match event {
+                TransferEvent::WindowReceived { block, bytes } => Some((*block, *bytes)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(windows, [(2, 2048), (4, 2048), (5, 904)]);
+        assert!(matches!(
+            events.last(),
+            Some(TransferEvent::Completed { stats }) if stats.bytes == 5000
+        ));
+    }
+
+    #[test]
+    fn reports_output_path() {
+        let (server_dir, client_dir) = test_dirs("reports_output_path");
+        fs::write(server_dir.join("file.bin"), [3; 700]).unwrap();
+        let server = TestServer::serve_dir(&server_dir);
+
+        // 1 path: the remote file name in the receive directory
+        let mut client = Client::new(&download_config(&server, "file.bin", &client_dir)).unwrap();
+        assert!(client.run().unwrap());
+        assert_eq!(client.output_path(), client_dir.join("file.bin"));
+
+        // 2 paths: the local one in the receive directory
+        let mut config = download_config(&server, "copy.bin", &client_dir);
+        config.file_remote = "file.bin".to_string();
+        let mut client = Client::new(&config).unwrap();
+        assert!(client.run().unwrap());
+        assert_eq!(client.output_path(), client_dir.join("copy.bin"));
+        assert_eq!(fs::read(client.output_path()).unwrap(), [3; 700]);
+    }
+
+    #[test]
+    fn ignores_dropped_events() {
+        let (server_dir, client_dir) = test_dirs("ignores_dropped_events");
+        fs::write(server_dir.join("file.bin"), [7; 5000]).unwrap();
+
+        let server = TestServer::serve_dir(&server_dir);
+        let mut client = Client::new(&download_config(&server, "file.bin", &client_dir)).unwrap();
+        drop(client.events());
+
+        assert!(client.run().unwrap());
+        assert_eq!(fs::read(client_dir.join("file.bin")).unwrap(), [7; 5000]);
+    }
+
+    #[test]
+    fn applies_pipeline_to_each_download() {
+        let (server_dir, client_dir) = test_dirs("applies_pipeline_to_each_download");
+        fs::write(server_dir.join("file.txt"), "hello").unwrap();
+
+        let server = TestServer::serve_dir(&server_dir);
+        let mut config = download_config(&server, "file.txt", &client_dir);
+        config.opt_common.checksum = Some(0);
+        let mut client = Client::new(&config).unwrap();
+        client.set_pipeline(|| {
+            let mut pipeline = Pipeline::new();
+            pipeline.push(|data: &[u8]| data.to_ascii_uppercase());
+            pipeline
+        });
+
+        for _ in 0..2 {
+            assert!(client.run().unwrap());
+            assert_eq!(fs::read(client_dir.join("file.txt")).unwrap(), b"HELLO");
+            fs::remove_file(client_dir.join("file.txt")).unwrap();
+        }
+    }
+
+    #[test]
+    fn refuses_upload_over_existing_file() {
+        let (server_dir, client_dir) = test_dirs("refuses_upload_over_existing_file");
+        fs::write(server_dir.join("file.txt"), "remote").unwrap();
+        fs::write(client_dir.join("file.txt"), "local").unwrap();
+
+        let server = TestServer::read_write_with(
+            &server_dir,
+            Config {
+                overwrite: true,
+                ..Default::default()
+            },
+        );
+        let mut config = ClientConfig {
+            remote_ip_address: server.addr.ip(),
+            port: server.addr.port(),
+            mode: Mode::Upload,
+            file_path: client_dir.join("file.txt"),
+            overwrite: Overwrite::NoClobber,
+            ..Default::default()
+        };
+
+        let err = Client::new(&config).unwrap().run().unwrap_err();
+        assert!(err.to_string().contains("already exists"));
+        assert_eq!(fs::read(server_dir.join("file.txt")).unwrap(), b"remote");
+
+        config.force = true;
+        assert!(Client::new(&config).unwrap().run().unwrap());
+        assert_eq!(fs::read(server_dir.join("file.txt")).unwrap(), b"local");
+    }
+
+    #[test]
+    fn reports_illegal_operation() {
+        let err = run_against_error(ErrorCode::IllegalOperation);
+        assert_eq!(
+            err.downcast_ref::<TftpError>(),
+            Some(&TftpError::IllegalOperation("refused".to_string()))
+        );
+    }
+
+    #[test]
+    fn reports_no_such_user() {
+        let err = run_against_error(ErrorCode::NoSuchUser);
+        assert_eq!(
+            err.downcast_ref::<TftpError>(),
+            Some(&TftpError::NoSuchUser("refused".to_string()))
+        );
+    }
+
+    /// Runs a download against a fake server answering the request with an error
+    fn run_against_error(code: ErrorCode) -> Box<dyn Error> {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || {
+            let (_, from) = Socket::recv_from(&server).unwrap();
+            let error = Packet::Error {
+                code,
+                msg: "refused".to_string(),
+            };
+            Socket::send_to(&server, &error, &from).unwrap();
+        });
+
+        let config = ClientConfig {
+            remote_ip_address: addr.ip(),
+            port: addr.port(),
+            file_path: PathBuf::from("file.bin"),
+            ..Default::default()
+        };
+        Client::new(&config).unwrap().run().unwrap_err()
+    }
+
+    #[test]
+    fn falls_back_on_refused_options() {
+        let (block_sizes, err) = run_fallback_against_error(ErrorCode::RefusedOption);
+        assert_eq!(block_sizes, [1468, 1024, 512]);
+        assert!(err.to_string().contains("cannot go below 512"));
+    }
+
+    #[test]
+    fn does_not_fall_back_on_other_errors() {
+        let (block_sizes, err) = run_fallback_against_error(ErrorCode::FileNotFound);
+        assert_eq!(block_sizes, [1468]);
+        assert_eq!(
+            err.downcast_ref::<TftpError>(),
+            Some(&TftpError::FileNotFound("refused".to_string()))
+        );
+    }
+
+    #[test]
+    fn falls_back_when_large_blocks_are_dropped() {
+        let (_, client_dir) = test_dirs("falls_back_when_large_blocks_are_dropped");
+
+        // Fake server whose data blocks larger than 512 bytes are lost
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let handle = thread::spawn(move || {
+            let mut block_sizes = vec![];
+            let mut lost = vec![];
+            loop {
+                let (Packet::Rrq { options, .. }, from) = Socket::recv_from(&server).unwrap()
+                else {
+                    panic!("expected read request");
+                };
+                let accepted: Vec<TransferOption> = options
+                    .into_iter()
+                    .filter(|option| {
+                        matches!(option.option, OptionType::BlockSize | OptionType::UTimeout)
+                    })
+                    .collect();
+                let block_size = accepted[0].value;
+                block_sizes.push(block_size);
+
+                let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+                socket.connect(from).unwrap();
+                Socket::send(&socket, &Packet::Oack(accepted, vec![])).unwrap();
+                assert_eq!(Socket::recv(&socket).unwrap(), Packet::Ack(0));
+                if block_size > 512 {
+                    // Kept open so that the acknowledgements are not refused
+                    lost.push(socket);
+                    continue;
+                }
+
+                for (block_num, len) in [(1, 512), (2, 100)] {
+                    let data = Packet::Data {
+                        block_num,
+                        data: vec![0x42; len],
+                    };
+                    Socket::send(&socket, &data).unwrap();
+                    assert_eq!(Socket::recv(&socket).unwrap(), Packet::Ack(block_num));
+                }
+                break block_sizes;
+            }
+        });
+
+        let mut config = ClientConfig {
+            remote_ip_address: addr.ip(),
+            port: addr.port(),
+            file_path: PathBuf::from("file.bin"),
+            receive_directory: client_dir.clone(),
+            auto_blksize: true,
+            min_blksize: 512,
+            ..Default::default()
+        };
+        config.opt_common.block_size = 1468;
+        config.opt_common.timeout = Duration::from_millis(100);
+        config.opt_local.max_retries = 2;
+        let mut client = Client::new(&config).unwrap();
+
+        assert!(client.run().unwrap());
+        assert_eq!(handle.join().unwrap(), [1468, 1024, 512]);
+        assert_eq!(client.stats().block_size, 512);
+        assert_eq!(fs::read(client_dir.join("file.bin")).unwrap(), [0x42; 612]);
+    }
+
+    /// Runs a download with the block size fallback against a fake server
+    /// answering each request with an error, returns the requested block sizes
+    fn run_fallback_against_error(code: ErrorCode) -> (Vec<u64>, Box<dyn Error>) {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        server
+            .set_read_timeout(Some(Duration::from_millis(500)))
+            .unwrap();
+        let addr = server.local_addr().unwrap();
+        let handle = thread::spawn(move || {
+            let mut block_sizes = vec![];
+            while let Ok((Packet::Rrq { options, .. }, from)) = Socket::recv_from(&server) {
+                block_sizes.extend(
+                    options
+                        .iter()
+                        .filter(|option| option.option == OptionType::BlockSize)
+                        .map(|option| option.value),
+                );
+                let error = Packet::Error {
+                    code,
+                    msg: "refused".to_string(),
+                };
+                Socket::send_to(&server, &error, &from).unwrap();
+            }
+            block_sizes
+        });
+
+        let mut config = ClientConfig {
+            remote_ip_address: addr.ip(),
+            port: addr.port(),
+            file_path: PathBuf::from("file.bin"),
+            auto_blksize: true,
+            min_blksize: 512,
+            ..Default::default()
+        };
+        config.opt_common.block_size = 1468;
+        let err = Client::new(&config).unwrap().run().unwrap_err();
+
+        (handle.join().unwrap(), err)
+    }
+
+    #[test]
+    fn clamps_windows_to_memory_budget() {
+        let (server_dir, client_dir) = test_dirs("clamps_windows_to_memory_budget");
+        let content: Vec<u8> = (0..4000u32).map(|i| (i % 251) as u8).collect();
+        fs::write(server_dir.join("file.bin"), &content).unwrap();
+
+        let server = TestServer::read_write_with(
+            &server_dir,
+            Config {
+                opt_local: OptionsPrivate {
+                    max_memory: Some(1000),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+        );
+        let mut config = download_config(&server, "file.bin", &client_dir);
+        config.opt_common.block_size = 1024;
+        config.opt_common.window_size = 4;
+        config.opt_local.max_memory = Some(2048);
+        let mut client = Client::new(&config).unwrap();
+
+        // Client budget clamps the request, server budget clamps the answer
+        assert_eq!(client.opt_request.block_size, 1024);
+        assert_eq!(client.opt_request.window_size, 2);
+        assert!(client.run().unwrap());
+        assert_eq!(client.opt_common.block_size, 1000);
+        assert_eq!(client.opt_common.window_size, 1);
+        assert_eq!(fs::read(client_dir.join("file.bin")).unwrap(), content);
+    }
+
+    #[test]
+    fn clamps_accepted_window_to_memory_budget() {
+        let (_, client_dir) = test_dirs("clamps_accepted_window_to_memory_budget");
+        let content: Vec<u8> = (0..700u32).map(|i| i as u8).collect();
+
+        // Fake server accepting a larger window than requested
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let blocks = content.clone();
+        let handle = thread::spawn(move || {
+            let (request, from) = Socket::recv_from(&server).unwrap();
+            server.connect(from).unwrap();
+            let options = vec![
+                TransferOption {
+                    option: OptionType::BlockSize,
+                    value: 512,
+                },
+                TransferOption {
+                    option: OptionType::WindowSize,
+                    value: 64,
+                },
+                TransferOption {
+                    option: OptionType::Timeout,
+                    value: 1,
+                },
+            ];
+            Socket::send(&server, &Packet::Oack(options, vec![])).unwrap();
+            assert_eq!(Socket::recv(&server).unwrap(), Packet::Ack(0));
+            for (block_num, data) in (1..).zip(blocks.chunks(512)) {
+                let data = Packet::Data {
+                    block_num,
+                    data: data.to_vec(),
+                };
+                Socket::send(&server, &data).unwrap();
+            }
+            assert_eq!(Socket::recv(&server).unwrap(), Packet::Ack(2));
+            request
+        });
+
+        let mut config = ClientConfig {
+            remote_ip_address: addr.ip(),
+            port: addr.port(),
+            file_path: PathBuf::from("file.bin"),
+            receive_directory: client_dir.clone(),
+            ..Default::default()
+        };
+        config.opt_common.window_size = 64;
+        config.opt_local.max_memory = Some(8 * 1024);
+        let mut client = Client::new(&config).unwrap();
+        assert!(client.run().unwrap());
+
+        let Packet::Rrq { options, .. } = handle.join().unwrap() else {
+            panic!("unexpected request");
+        };
+        assert!(options.contains(&TransferOption {
+            option: OptionType::WindowSize,
+            value: 16,
+        }));
+        assert_eq!(client.opt_common.window_size, 16);
+        assert_eq!(fs::read(client_dir.join("file.bin")).unwrap(), content);
+    }
+
+    #[test]
+    fn aborts_transfer_past_deadline() {
+        let (_, client_dir) = test_dirs("aborts_transfer_past_deadline");
+
+        // Fake server sending a block just before each timeout, forever
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        server
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        let addr = server.local_addr().unwrap();
+        let handle = thread::spawn(move || {
+            let (_, from) = Socket::recv_from(&server).unwrap();
+            server.connect(from).unwrap();
+            for block_num in 1.. {
+                thread::sleep(Duration::from_millis(200));
+                let data = Packet::Data {
+                    block_num,
+                    data: vec![0x42; 512],
+                };
+                Socket::send(&server, &data).unwrap();
+                match Socket::recv(&server).unwrap() {
+                    Packet::Ack(ack) => assert_eq!(ack, block_num),
+                    error => return (block_num, error),
+                }
+            }
+            unreachable!()
+        });
+
+        let mut config = ClientConfig {
+            remote_ip_address: addr.ip(),
+            port: addr.port(),
+            file_path: PathBuf::from("file.bin"),
+            receive_directory: client_dir.clone(),
+            ..Default::default()
+        };
+        config.opt_common.timeout = Duration::from_millis(300);
+        config.opt_local.deadline = Some(Duration::from_secs(1));
+        let mut client = Client::new(&config).unwrap();
+        let start = Instant::now();
+
+        assert!(!client.run().unwrap());
+        assert!(start.elapsed() < Duration::from_secs(2));
+        let (block_num, error) = handle.join().unwrap();
+        assert!(block_num > 2);
+        assert_eq!(
+            error,
+            Packet::Error {
+                code: ErrorCode::NotDefined,
+                msg: "transfer deadline exceeded".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn refuses_data_from_other_server_port() {
+        let (_, client_dir) = test_dirs("refuses_data_from_other_server_port");
+        let content: Vec<u8> = (0..600u32).map(|i| (i * 5) as u8).collect();
+
+        // Fake server, and another port sending a forged block in between
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        server
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        let addr = server.local_addr().unwrap();
+        let blocks = content.clone();
+        let handle = thread::spawn(move || {
+            let (_, from) = Socket::recv_from(&server).unwrap();
+            server.connect(from).unwrap();
+            let intruder = UdpSocket::bind("127.0.0.1:0").unwrap();
+            intruder
+                .set_read_timeout(Some(Duration::from_secs(2)))
+                .unwrap();
+
+            for (block_num, data) in (1..).zip(blocks.chunks(512)) {
+                if block_num == 2 {
+                    let forged = Packet::Data {
+                        block_num,
+                        data: vec![0xFF; 10],
+                    };
+                    Socket::send_to(&intruder, &forged, &from).unwrap();
+                    thread::sleep(Duration::from_millis(50));
+                }
+                let data = Packet::Data {
+                    block_num,
+                    data: data.to_vec(),
+                };
+                Socket::send(&server, &data).unwrap();
+                assert_eq!(Socket::recv(&server).unwrap(), Packet::Ack(block_num));
+            }
+            Socket::recv(&intruder).unwrap()
+        });
+
+        let mut config = ClientConfig {
+            remote_ip_address: addr.ip(),
+            port: addr.port(),
+            file_path: PathBuf::from("file.bin"),
+            receive_directory: client_dir.clone(),
+            strict_tid: true,
+            ..Default::default()
+        };
+        config.opt_common.timeout = Duration::from_secs(1);
+        let mut client = Client::new(&config).unwrap();
+        assert!(client.run().unwrap());
+
+        assert_eq!(
+            handle.join().unwrap(),
+            Packet::Error {
+                code: ErrorCode::UnknownId,
+                msg: "unknown transfer ID".to_string(),
+            }
+        );
+        assert_eq!(fs::read(client_dir.join("file.bin")).unwrap(), content);
+    }
+
+    #[test]
+    fn measures_time_to_first_data() {
+        let (server_dir, client_dir) = test_dirs("measures_time_to_first_data");
+        fs::write(server_dir.join("file.bin"), vec![0x5A; 2000]).unwrap();
+
+        let server = TestServer::serve_dir(&server_dir);
+        let mut client = Client::new(&download_config(&server, "file.bin", &client_dir)).unwrap();
+
+        assert!(client.run().unwrap());
+        let stats = client.stats();
+        let time_to_first_data = stats.time_to_first_data.unwrap();
+        assert!(!time_to_first_data.is_zero());
+        assert!(time_to_first_data <= stats.duration);
+    }
+
+    #[test]
+    fn transfers_empty_file() {
+        let (server_dir, client_dir) = test_dirs("transfers_empty_file");
+        fs::write(client_dir.join("empty.bin"), []).unwrap();
+
+        let server = TestServer::read_write(&server_dir);
+        let mut config = ClientConfig {
+            remote_ip_address: server.addr.ip(),
+            port: server.addr.port(),
+            mode: Mode::Upload,
+            file_path: client_dir.join("empty.bin"),
+            ..Default::default()
+        };
+        let mut client = Client::new(&config).unwrap();
+        assert!(client.run().unwrap());
+        assert_eq!(client.stats().blocks, 1);
+        assert_eq!(client.stats().bytes, 0);
+        assert_eq!(fs::read(server_dir.join("empty.bin")).unwrap(), b"");
+
+        config.mode = Mode::Download;
+        config.file_path = PathBuf::from("empty.bin");
+        config.receive_directory = client_dir.join("down");
+        fs::create_dir(&config.receive_directory).unwrap();
+        config.opt_common.timeout = Duration::from_secs(1);
+        let mut client = Client::new(&config).unwrap();
+        assert!(client.run().unwrap());
+        assert_eq!(client.stats().blocks, 1);
+        assert_eq!(fs::read(client_dir.join("down/empty.bin")).unwrap(), b"");
+    }
+
+    #[test]
+    fn returns_upload_read_error() {
+        let (server_dir, client_dir) = test_dirs("returns_upload_read_error");
+        let server = TestServer::read_write(&server_dir);
+        // A directory opens, but fails to be read
+        let config = ClientConfig {
+            remote_ip_address: server.addr.ip(),
+            port: server.addr.port(),
+            mode: Mode::Upload,
+            file_path: client_dir.clone(),
+            file_remote: "dir.bin".to_string(),
+            ..Default::default()
+        };
+
+        let err = Client::new(&config).unwrap().run().unwrap_err();
+        assert!(
+            err.to_string()
+                .starts_with("read error on 'client' at block 1:"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn uploads_several_files() {
+        let (server_dir, client_dir) = test_dirs("uploads_several_files");
+        for (name, len) in [("a.cfg", 100), ("b.cfg", 600), ("c.cfg", 1024)] {
+            fs::write(client_dir.join(name), vec![len as u8; len]).unwrap();
+        }
+
+        let server = TestServer::read_write(&server_dir);
+        let mut config = ClientConfig {
+            remote_ip_address: server.addr.ip(),
+            port: server.addr.port(),
+            mode: Mode::Upload,
+            file_path: client_dir.join("a.cfg"),
+            extra_files: vec![client_dir.join("b.cfg"), client_dir.join("c.cfg")],
+            ..Default::default()
+        };
+        let mut client = Client::new(&config).unwrap();
+        assert!(client.run().unwrap());
+        let bytes: Vec<u64> = client.all_stats().iter().map(|s| s.bytes).collect();
+        assert_eq!(bytes, [100, 600, 1024]);
+        for (name, len) in [("a.cfg", 100), ("b.cfg", 600), ("c.cfg", 1024)] {
+            assert_eq!(
+                fs::read(server_dir.join(name)).unwrap(),
+                vec![len as u8; len]
+            );
+        }
+
+        // The other files are still uploaded after a failure
+        fs::write(client_dir.join("d.cfg"), b"d").unwrap();
+        config.file_path = client_dir.join("missing.cfg");
+        config.extra_files = vec![client_dir.join("d.cfg")];
+        let mut client = Client::new(&config).unwrap();
+        let err = client.run().unwrap_err().to_string();
+        assert!(err.starts_with("1 of 2 uploads failed"), "{err}");
+        assert!(err.contains("missing.cfg"), "{err}");
+        assert_eq!(client.all_stats().len(), 2);
+        assert_eq!(fs::read(server_dir.join("d.cfg")).unwrap(), b"d");
+    }
+
+    #[test]
+    fn round_trips_netascii() {
+        let (server_dir, client_dir) = test_dirs("round_trips_netascii");
+        // Mixed line endings, a CR at the end of the first block once encoded,
+        // a NUL, and a lone CR at the end
+        let text = b"dos\r\nunix\nmac\rnul\0aaaaaaaaaaaaaaaaa\r\rz\r";
+        fs::write(client_dir.join("text.txt"), text).unwrap();
+
+        let server = TestServer::read_write(&server_dir);
+        let mut config = ClientConfig {
+            remote_ip_address: server.addr.ip(),
+            port: server.addr.port(),
+            mode: Mode::Upload,
+            file_path: client_dir.join("text.txt"),
+            transfer_mode: TransferMode::Netascii,
+            ..Default::default()
+        };
+        config.opt_common.block_size = 40;
+        config.opt_common.checksum = Some(0);
+        assert!(Client::new(&config).unwrap().run().unwrap());
+        assert_eq!(
+            fs::read(server_dir.join("text.txt")).unwrap(),
+            b"dos\r\0\r\nunix\r\nmac\r\0nul\0aaaaaaaaaaaaaaaaa\r\0\r\0z\r\0"
+        );
+
+        config.mode = Mode::Download;
+        config.file_path = PathBuf::from("text.txt");
+        config.receive_directory = client_dir.join("down");
+        fs::create_dir(&config.receive_directory).unwrap();
+        config.opt_common.timeout = Duration::from_secs(1);
+        assert!(Client::new(&config).unwrap().run().unwrap());
+        assert_eq!(fs::read(client_dir.join("down/text.txt")).unwrap(), text);
+    }
+
+    #[test]
+    fn round_trips_compressed_data() {
+        let (server_dir, client_dir) = test_dirs("round_trips_compressed_data");
+        let text = "TFTP is a simple protocol to transfer files.\n".repeat(1000);
+        fs::write(client_dir.join("text.txt"), &text).unwrap();
+        // Blocks of the uncompressed text, the final short one included
+        let plain_blocks = text.len() as u64 / 512 + 1;
+
+        let server = TestServer::read_write(&server_dir);
+        let mut config = ClientConfig {
+            remote_ip_address: server.addr.ip(),
+            port: server.addr.port(),
+            mode: Mode::Upload,
+            file_path: client_dir.join("text.txt"),
+            compression: Some(Compression::Gzip),
+            ..Default::default()
+        };
+        let mut client = Client::new(&config).unwrap();
+        assert!(client.run().unwrap());
+        assert!(client.stats().blocks < plain_blocks / 10);
+        assert_eq!(
+            fs::read(server_dir.join("text.txt")).unwrap(),
+            text.as_bytes()
+        );
+
+        // Size and checksum are those of the compressed data
+        config.mode = Mode::Download;
+        config.file_path = PathBuf::from("text.txt");
+        config.receive_directory = client_dir.join("down");
+        config.compression = Some(Compression::Deflate);
+        config.opt_common.checksum = Some(0);
+        config.opt_common.timeout = Duration::from_secs(1);
+        fs::create_dir(&config.receive_directory).unwrap();
+        let mut client = Client::new(&config).unwrap();
+        assert!(client.run().unwrap());
+        assert!(client.stats().blocks < plain_blocks / 10);
+        assert_eq!(client.transfer_size(), Some(client.stats().bytes));
+        assert_eq!(
+            fs::read(client_dir.join("down/text.txt")).unwrap(),
+            text.as_bytes()
+        );
+    }
+
+    #[test]
+    fn falls_back_to_uncompressed_data() {
+        let (_, client_dir) = test_dirs("falls_back_to_uncompressed_data");
+        let content: Vec<u8> = (0..700u32).map(|i| i as u8).collect();
+
+        // Fake server ignoring the compression option
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let blocks = content.clone();
+        let handle = thread::spawn(move || {
+            let (request, from) = Socket::recv_from(&server).unwrap();
+            server.connect(from).unwrap();
+            let Packet::Rrq { extra, .. } = request else {
+                panic!("unexpected request {request}");
+            };
+            let options = vec![TransferOption {
+                option: OptionType::Timeout,
+                value: 1,
+            }];
+            Socket::send(&server, &Packet::Oack(options, vec![])).unwrap();
+            assert_eq!(Socket::recv(&server).unwrap(), Packet::Ack(0));
+            for (block_num, data) in (1..).zip(blocks.chunks(512)) {
+                let data = Packet::Data {
+                    block_num,
+                    data: data.to_vec(),
+                };
+                Socket::send(&server, &data).unwrap();
+                assert_eq!(Socket::recv(&server).unwrap(), Packet::Ack(block_num));
+            }
+            extra
+        });
+
+        let mut config = ClientConfig {
+            remote_ip_address: addr.ip(),
+            port: addr.port(),
+            file_path: PathBuf::from("file.bin"),
+            receive_directory: client_dir.clone(),
+            compression: Some(Compression::Gzip),
+            ..Default::default()
+        };
+        config.opt_common.timeout = Duration::from_millis(200);
+        assert!(Client::new(&config).unwrap().run().unwrap());
+
+        assert_eq!(handle.join().unwrap(), [Compression::Gzip.option()]);
+        assert_eq!(fs::read(client_dir.join("file.bin")).unwrap(), content);
+    }
+
+    #[test]
+    fn resumes_download() {
+        let (server_dir, client_dir) = test_dirs("resumes_download");
+        let content: Vec<u8> = (0..5000u32).map(|i| (i % 253) as u8).collect();
+        fs::write(server_dir.join("file.bin"), &content).unwrap();
+        fs::write(client_dir.join("file.bin"), &content[..2100]).unwrap();
+
+        let server = TestServer::serve_dir(&server_dir);
+        let mut config = download_config(&server, "file.bin", &client_dir);
+        config.resume = true;
+        let mut client = Client::new(&config).unwrap();
+
+        assert!(client.run().unwrap());
+        assert_eq!(client.stats().bytes, 5000);
+        assert_eq!(fs::read(client_dir.join("file.bin")).unwrap(), content);
+    }
+
+    #[test]
+    fn refuses_to_resume_past_remote_file() {
+        let (server_dir, client_dir) = test_dirs("refuses_to_resume_past_remote_file");
+        fs::write(server_dir.join("file.bin"), vec![0x42; 1000]).unwrap();
+        fs::write(client_dir.join("file.bin"), vec![0x42; 1500]).unwrap();
+
+        let server = TestServer::serve_dir(&server_dir);
+        let mut config = download_config(&server, "file.bin", &client_dir);
+        config.resume = true;
+        let err = Client::new(&config).unwrap().run().unwrap_err();
+
+        assert!(err
+            .to_string()
+            .contains("smaller than the local partial file"));
+        assert_eq!(fs::read(client_dir.join("file.bin")).unwrap().len(), 1500);
+    }
+
+    #[test]
+    fn downloads_from_server_without_options() {
+        let (_, client_dir) = test_dirs("downloads_from_server_without_options");
+        let content: Vec<u8> = (0..700u32).map(|i| i as u8).collect();
+
+        // Fake RFC 1350 server answering the request with the first block
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let blocks = content.clone();
+        let handle = thread::spawn(move || {
+            let (_, from) = Socket::recv_from(&server).unwrap();
+            server.connect(from).unwrap();
+            for (block_num, data) in (1..).zip(blocks.chunks(512)) {
+                let data = Packet::Data {
+                    block_num,
+                    data: data.to_vec(),
+                };
+                Socket::send(&server, &data).unwrap();
+                assert_eq!(Socket::recv(&server).unwrap(), Packet::Ack(block_num));
+            }
+        });
+
+        let mut config = ClientConfig {
+            remote_ip_address: addr.ip(),
+            port: addr.port(),
+            file_path: PathBuf::from("file.bin"),
+            receive_directory: client_dir.clone(),
+            ..Default::default()
+        };
+        config.opt_common.timeout = Duration::from_millis(200);
+        config.opt_common.block_size = 1024;
+        let mut client = Client::new(&config).unwrap();
+
+        assert!(client.run().unwrap());
+        handle.join().unwrap();
+        assert_eq!(client.opt_common.block_size, 512);
+        assert_eq!(fs::read(client_dir.join("file.bin")).unwrap(), content);
+    }
+
+    #[test]
+    fn counts_transferred_blocks() {
+        let (server_dir, client_dir) = test_dirs("counts_transferred_blocks");
+        fs::write(server_dir.join("down.bin"), vec![0x42; 5000]).unwrap();
+        fs::write(client_dir.join("up.bin"), vec![0x24; 1024]).unwrap();
+
+        let server = TestServer::read_write(&server_dir);
+        let mut config = download_config(&server, "down.bin", &client_dir);
+        config.opt_common.window_size = 4;
+        let mut client = Client::new(&config).unwrap();
+
+        // 9 full blocks and a short one, acknowledged by windows of 4
+        assert!(client.run().unwrap());
+        let stats = client.stats();
+        assert_eq!(stats.bytes, 5000);
+        assert_eq!(stats.blocks, 10);
+        assert_eq!(stats.windows, 3);
+        assert_eq!(stats.retransmits, 0);
+        assert_eq!(stats.socket.datagrams_received, 10);
+        assert_eq!(stats.socket.bytes_received, 5000 + 10 * 4);
+        assert_eq!(stats.socket.datagrams_sent, 3);
+        assert_eq!(stats.socket.retransmits, 0);
+
+        config.mode = Mode::Upload;
+        config.file_path = client_dir.join("up.bin");
+        let mut client = Client::new(&config).unwrap();
+
+        // 2 full blocks and an empty one, sent in a single window
+        assert!(client.run().unwrap());
+        let stats = client.stats();
+        assert_eq!(stats.bytes, 1024);
+        assert_eq!(stats.blocks, 3);
+        assert_eq!(stats.windows, 1);
+        assert_eq!(stats.retransmits, 0);
+        assert_eq!(stats.socket.datagrams_sent, 3);
+        assert_eq!(stats.socket.bytes_sent, 1024 + 3 * 4);
+    }
+
+    #[test]
+    fn reports_progress_with_transfer_size() {
+        let (server_dir, client_dir) = test_dirs("reports_progress_with_transfer_size");
+        fs::write(server_dir.join("file.bin"), vec![0x42; 5000]).unwrap();
+
+        let server = TestServer::serve_dir(&server_dir);
+        let mut config = download_config(&server, "file.bin", &client_dir);
+        config.opt_common.window_size = 4;
+        let mut client = Client::new(&config).unwrap();
+        let calls = Arc::new(Mutex::new(vec![]));
+        let calls_cb = calls.clone();
+        client.set_progress_callback(Box::new(move |bytes, total| {
+            calls_cb.lock().unwrap().push((bytes, total))
+        }));
+
+        // One call per window, then a final one
+        assert!(client.run().unwrap());
+        assert_eq!(
+            *calls.lock().unwrap(),
+            [
+                (2048, Some(5000)),
+                (4096, Some(5000)),
+                (5000, Some(5000)),
+                (5000, Some(5000))
+            ]
+        );
+        assert_eq!(client.transfer_size(), Some(5000));
+    }
+
+    #[test]
+    fn reports_progress_with_rates() {
+        let (server_dir, client_dir) = test_dirs("reports_progress_with_rates");
+        fs::write(server_dir.join("file.bin"), vec![0x42; 5000]).unwrap();
+
+        let server = TestServer::serve_dir(&server_dir);
+        let mut config = download_config(&server, "file.bin", &client_dir);
+        config.opt_common.window_size = 4;
+        let mut client = Client::new(&config).unwrap();
+        let reports = Arc::new(Mutex::new(vec![]));
+        let reports_cb = reports.clone();
+        client.set_progress_report_callback(Box::new(move |report| {
+            reports_cb.lock().unwrap().push(report.clone())
+        }));
+
+        assert!(client.run().unwrap());
+        let reports = reports.lock().unwrap();
+        let bytes: Vec<_> = reports.iter().map(|report| report.bytes).collect();
+        assert_eq!(bytes, [2048, 4096, 5000, 5000]);
+        assert!(reports.windows(2).all(|w| w[0].elapsed <= w[1].elapsed));
+        assert!(reports.iter().all(|report| report.average_rate > 0.0));
+        let last = reports.last().unwrap();
+        assert_eq!(last.total, Some(5000));
+        assert_eq!(last.percent, Some(100.0));
+        assert_eq!(last.eta, Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn reports_progress_without_transfer_size() {
+        let (_, client_dir) = test_dirs("reports_progress_without_transfer_size");
+
+        // Fake server ignoring the transfer size option
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || {
+            let (request, from) = Socket::recv_from(&server).unwrap();
+            let Packet::Rrq { options, .. } = request else {
+                panic!("expected read request");
+            };
+            assert!(options.contains(&TransferOption {
+                option: OptionType::TransferSize,
+                value: 0
+            }));
+            server.connect(from).unwrap();
+            let oack = Packet::Oack(
+                vec![TransferOption {
+                    option: OptionType::BlockSize,
+                    value: 512,
+                }],
+                vec![],
+            );
+            Socket::send(&server, &oack).unwrap();
+            assert_eq!(Socket::recv(&server).unwrap(), Packet::Ack(0));
+            let data = Packet::Data {
+                block_num: 1,
+                data: vec![0x42; 100],
+            };
+            Socket::send(&server, &data).unwrap();
+            assert_eq!(Socket::recv(&server).unwrap(), Packet::Ack(1));
+        });
+
+        let mut config = ClientConfig {
+            remote_ip_address: addr.ip(),
+            port: addr.port(),
+            file_path: PathBuf::from("file.bin"),
+            receive_directory: client_dir.clone(),
+            ..Default::default()
+        };
+        config.opt_common.timeout = Duration::from_millis(200);
+        let mut client = Client::new(&config).unwrap();
+        let calls = Arc::new(Mutex::new(vec![]));
+        let calls_cb = calls.clone();
+        client.set_progress_callback(Box::new(move |bytes, total| {
+            calls_cb.lock().unwrap().push((bytes, total))
+        }));
+
+        assert!(client.run().unwrap());
+        assert_eq!(*calls.lock().unwrap(), [(100, None), (100, None)]);
+        assert_eq!(client.transfer_size(), None);
+    }
+
+    #[test]
+    fn downloads_with_checksum() {
+        let (server_dir, client_dir) = test_dirs("downloads_with_checksum");
+        let content: Vec<u8> = (0..3000u32).map(|i| (i * 7) as u8).collect();
+        fs::write(server_dir.join("file.bin"), &content).unwrap();
+
+        let server = TestServer::serve_dir(&server_dir);
+        let mut config = download_config(&server, "file.bin", &client_dir);
+        config.opt_common.checksum = Some(0);
+        let mut client = Client::new(&config).unwrap();
+
+        assert!(client.run().unwrap());
+        assert_eq!(
+            client.opt_common.checksum,
+            Some(crc32_file(&server_dir.join("file.bin")).unwrap())
+        );
+        assert_eq!(fs::read(client_dir.join("file.bin")).unwrap(), content);
+    }
+
+    #[test]
+    fn maps_stdio_paths() {
+        let config = ClientConfig {
+            mode: Mode::Upload,
+            file_path: PathBuf::from("-"),
+            file_remote: "remote.bin".to_string(),
+            ..Default::default()
+        };
+        let client = Client::new(&config).unwrap();
+        assert!(client.stdio);
+        assert_eq!(client.file_remote, "remote.bin");
+
+        let config = ClientConfig {
+            file_path: PathBuf::from("remote.bin"),
+            file_remote: "-".to_string(),
+            receive_directory: PathBuf::from("rx"),
+            ..Default::default()
+        };
+        let client = Client::new(&config).unwrap();
+        assert!(client.stdio);
+        assert_eq!(client.file_local, PathBuf::from("-"));
+        assert_eq!(client.file_remote, "remote.bin");
+
+        let config = ClientConfig {
+            file_path: PathBuf::from("-"),
+            ..Default::default()
+        };
+        assert!(Client::new(&config).is_err());
+    }
+
+    #[test]
+    fn verifies_downloaded_digest() {
+        let (server_dir, client_dir) = test_dirs("verifies_downloaded_digest");
+        fs::write(server_dir.join("file.txt"), "abc").unwrap();
+        let sha256 = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+
+        let server = TestServer::serve_dir(&server_dir);
+        let mut config = download_config(&server, "file.txt", &client_dir);
+        config.hash = Some(HashAlgorithm::Sha256);
+        let mut client = Client::new(&config).unwrap();
+        assert!(client.run().unwrap());
+        assert_eq!(client.stats().digest.as_deref(), Some(sha256));
+
+        config.expected_digest = Some(sha256.to_string());
+        assert!(Client::new(&config).unwrap().run().unwrap());
+
+        config.hash = Some(HashAlgorithm::Crc32);
+        config.expected_digest = Some("00000000".to_string());
+        let err = Client::new(&config).unwrap().run().unwrap_err();
+        assert!(err.to_string().contains("Crc32 mismatch"));
+        assert!(err.to_string().contains("352441c2"));
+        assert!(!client_dir.join("file.txt").exists());
+    }
+
+    #[test]
+    fn binds_to_local_address() {
+        let (server_dir, client_dir) = test_dirs("binds_to_local_address");
+        fs::write(server_dir.join("file.txt"), "bound").unwrap();
+
+        let server = TestServer::read_write(&server_dir);
+        let mut config = download_config(&server, "file.txt", &client_dir);
+        // Free port, for the transfer socket and not the remote file probe
+        let port = UdpSocket::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        config.local_address = Some(SocketAddr::from((Ipv4Addr::LOCALHOST, port)));
+        assert!(Client::new(&config).unwrap().run().unwrap());
+        assert_eq!(fs::read(client_dir.join("file.txt")).unwrap(), b"bound");
+
+        config.mode = Mode::Upload;
+        config.file_path = client_dir.join("file.txt");
+        config.file_remote = "copy.txt".to_string();
+        config.overwrite = Overwrite::NoClobber;
+        assert!(Client::new(&config).unwrap().run().unwrap());
+        assert_eq!(fs::read(server_dir.join("copy.txt")).unwrap(), b"bound");
+
+        config.local_address = Some("[::1]:0".parse().unwrap());
+        let err = Client::new(&config).err().unwrap();
+        assert!(err.to_string().contains("not of the same IP family"));
+    }
+
+    #[test]
+    fn selects_ip_family() {
+        let mut config = ClientConfig {
+            file_path: PathBuf::from("file.txt"),
+            remote_ip_address: "::ffff:127.0.0.1".parse().unwrap(),
+            ..Default::default()
+        };
+        // IPv4-mapped remote reached over IPv4
+        let client = Client::new(&config).unwrap();
+        assert_eq!(
+            client.remote_address,
+            SocketAddr::from((Ipv4Addr::LOCALHOST, 69))
+        );
+        assert!(client.local_address().is_ipv4());
+
+        config.ip_family = Some(IpFamily::V6);
+        let client = Client::new(&config).unwrap();
+        assert!(client.remote_address.is_ipv6());
+        assert!(client.local_address().is_ipv6());
+
+        config.remote_ip_address = Ipv6Addr::LOCALHOST.into();
+        config.ip_family = None;
+        assert!(Client::new(&config).unwrap().local_address().is_ipv6());
+
+        config.ip_family = Some(IpFamily::V4);
+        let err = Client::new(&config).err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "Remote address ::1 is not an IPv4 address, as forced by -4"
+        );
+
+        // Alternatives of the other family are skipped
+        config.remote_alternatives = vec![Ipv4Addr::LOCALHOST.into()];
+        let client = Client::new(&config).unwrap();
+        assert_eq!(
+            client.remote_address,
+            SocketAddr::from((Ipv4Addr::LOCALHOST, 69))
+        );
+    }
+
+    #[test]
+    fn caps_block_size_to_mtu() {
+        let mut config = ClientConfig {
+            file_path: PathBuf::from("file.txt"),
+            mtu: Some(1400),
+            ..Default::default()
+        };
+        config.opt_common.block_size = 1468;
+        assert_eq!(Client::new(&config).unwrap().opt_request.block_size, 1368);
+
+        config.remote_ip_address = Ipv6Addr::LOCALHOST.into();
+        assert_eq!(Client::new(&config).unwrap().opt_request.block_size, 1348);
+
+        config.opt_common.block_size = 512;
+        assert_eq!(Client::new(&config).unwrap().opt_request.block_size, 512);
+    }
+
+    #[test]
+    fn keeps_scope_of_link_local_address() {
+        let config = ClientConfig {
+            remote_ip_address: "fe80::1".parse().unwrap(),
+            remote_alternatives: vec!["2001:db8::1".parse().unwrap()],
+            scope_id: 1,
+            file_path: PathBuf::from("file.txt"),
+            ..Default::default()
+        };
+
+        let client = Client::new(&config).unwrap();
+        assert_eq!(client.remote_addresses[0].to_string(), "[fe80::1%1]:69");
+        assert_eq!(client.remote_addresses[1].to_string(), "[2001:db8::1]:69");
+    }
+
+    #[test]
+    fn tries_remote_addresses_in_turn() {
+        let (server_dir, client_dir) = test_dirs("tries_remote_addresses_in_turn");
+        fs::write(server_dir.join("file.txt"), "answered").unwrap();
+
+        let server = TestServer::serve_dir(&server_dir);
+        let mut config = download_config(&server, "file.txt", &client_dir);
+        config.timeout_req = Duration::from_millis(200);
+        config.max_handshake_retries = 1;
+        config.remote_ip_address = Ipv4Addr::new(127, 0, 0, 2).into();
+        config.remote_alternatives = vec![Ipv4Addr::LOCALHOST.into()];
+        assert!(Client::new(&config).unwrap().run().unwrap());
+        assert_eq!(fs::read(client_dir.join("file.txt")).unwrap(), b"answered");
+
+        config.remote_alternatives = vec![Ipv4Addr::new(127, 0, 0, 3).into()];
+        let err = Client::new(&config).unwrap().run().unwrap_err();
+        let port = server.addr.port();
+        assert_eq!(
+            err.to_string(),
+            format!("No answer to the request from any of 127.0.0.2:{port}, 127.0.0.3:{port}")
+        );
+    }
+
+    #[test]
+    fn omits_transfer_size_when_disabled() {
+        let (_, client_dir) = test_dirs("omits_transfer_size_when_disabled");
+        fs::write(client_dir.join("file.txt"), "sized").unwrap();
+
+        for no_tsize in [false, true] {
+            // Fake server refusing the request after recording it
+            let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+            let addr = server.local_addr().unwrap();
+            let handle = thread::spawn(move || {
+                let (request, from) = Socket::recv_from(&server).unwrap();
+                let error = Packet::Error {
+                    code: ErrorCode::AccessViolation,
+                    msg: "recorded".to_string(),
+                };
+                Socket::send_to(&server, &error, &from).unwrap();
+                request
+            });
+
+            let config = ClientConfig {
+                remote_ip_address: addr.ip(),
+                port: addr.port(),
+                mode: Mode::Upload,
+                file_path: client_dir.join("file.txt"),
+                no_tsize,
+                ..Default::default()
+            };
+            assert!(Client::new(&config).unwrap().run().is_err());
+
+            let Packet::Wrq { options, .. } = handle.join().unwrap() else {
+                panic!("expected a write request")
+            };
+            let tsize = options
+                .iter()
+                .find(|option| option.option == OptionType::TransferSize);
+            if no_tsize {
+                assert_eq!(tsize, None);
+            } else {
+                assert_eq!(tsize.map(|option| option.value), Some(5));
+            }
+        }
+    }
+
+    #[test]
+    fn passes_extra_options_through() {
+        let (_, client_dir) = test_dirs("passes_extra_options_through");
+        let vendor = ("x-vendor".to_string(), "on".to_string());
+
+        // Fake server echoing the options it does not know
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let handle = thread::spawn(move || {
+            let (request, from) = Socket::recv_from(&server).unwrap();
+            let Packet::Rrq { extra, .. } = request else {
+                panic!("expected read request");
+            };
+            server.connect(from).unwrap();
+            Socket::send(&server, &Packet::Oack(vec![], extra.clone())).unwrap();
+            Socket::recv(&server).unwrap();
+            extra
+        });
+
+        let mut config = ClientConfig {
+            remote_ip_address: addr.ip(),
+            port: addr.port(),
+            file_path: PathBuf::from("file.bin"),
+            receive_directory: client_dir,
+            ..Default::default()
+        };
+        config.opt_common.extra = vec![vendor.clone()];
+
+        let options = Client::new(&config).unwrap().negotiate().unwrap();
+        assert_eq!(handle.join().unwrap(), options.extra);
+        assert_eq!(options.extra, [vendor]);
+    }
+
+    #[test]
+    fn downloads_from_multicast_group() {
+        let (_, client_dir) = test_dirs("downloads_from_multicast_group");
+        let group_port = UdpSocket::bind("0.0.0.0:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let group = SocketAddr::from((Ipv4Addr::new(239, 255, 69, 1), group_port));
+
+        // Fake server sending the data to the group
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || {
+            let (request, from) = Socket::recv_from(&server).unwrap();
+            let Packet::Rrq { extra, .. } = request else {
+                panic!("expected read request");
+            };
+            assert_eq!(extra, [("multicast".to_string(), String::new())]);
+            server.connect(from).unwrap();
+            let multicast = (
+                "multicast".to_string(),
+                format!("{group},1").replace(':', ","),
+            );
+            Socket::send(&server, &Packet::Oack(vec![], vec![multicast])).unwrap();
+            assert_eq!(Socket::recv(&server).unwrap(), Packet::Ack(0));
+            let data = Packet::Data {
+                block_num: 1,
+                data: b"grouped".to_vec(),
+            };
+            // Sent from any address, as the group is joined on the default interface
+            let sender = UdpSocket::bind("0.0.0.0:0").unwrap();
+            Socket::send_to(&sender, &data, &group).unwrap();
+            assert_eq!(Socket::recv(&server).unwrap(), Packet::Ack(1));
+        });
+
+        let mut config = ClientConfig {
+            remote_ip_address: addr.ip(),
+            port: addr.port(),
+            file_path: PathBuf::from("file.txt"),
+            receive_directory: client_dir.clone(),
+            multicast: true,
+            ..Default::default()
+        };
+        config.opt_common.timeout = Duration::from_millis(500);
+
+        assert!(Client::new(&config).unwrap().run().unwrap());
+        assert_eq!(fs::read(client_dir.join("file.txt")).unwrap(), b"grouped");
+    }
+
+    #[test]
+    fn fails_when_multicast_declined() {
+        let (server_dir, client_dir) = test_dirs("fails_when_multicast_declined");
+        fs::write(server_dir.join("file.txt"), "unicast").unwrap();
+
+        let server = TestServer::serve_dir(&server_dir);
+        let mut config = download_config(&server, "file.txt", &client_dir);
+        config.multicast = true;
+
+        let err = Client::new(&config).unwrap().run().unwrap_err();
+        assert_eq!(err.to_string(), "Server declined multicast");
+        assert!(!client_dir.join("file.txt").exists());
+    }
+
+    #[test]
+    fn retries_unanswered_request() {
+        let (_, client_dir) = test_dirs("retries_unanswered_request");
+
+        // Fake server losing the first two requests
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let handle = thread::spawn(move || {
+            let mut received = vec![];
+            let from = loop {
+                let (_, from) = Socket::recv_from(&server).unwrap();
+                received.push(Instant::now());
+                if received.len() == 3 {
+                    break from;
+                }
+            };
+            let data = Packet::Data {
+                block_num: 1,
+                data: b"third".to_vec(),
+            };
+            Socket::send_to(&server, &data, &from).unwrap();
+            received
+        });
+
+        let mut config = ClientConfig {
+            remote_ip_address: addr.ip(),
+            port: addr.port(),
+            file_path: PathBuf::from("file.txt"),
+            receive_directory: client_dir.clone(),
+            timeout_req: Duration::from_millis(100),
+            max_handshake_retries: 2,
+            ..Default::default()
+        };
+        config.opt_common.timeout = Duration::from_millis(200);
+
+        assert!(Client::new(&config).unwrap().run().unwrap());
+        assert_eq!(fs::read(client_dir.join("file.txt")).unwrap(), b"third");
+        let received = handle.join().unwrap();
+        assert!(received[1] - received[0] >= Duration::from_millis(100));
+        assert!(received[2] - received[1] >= Duration::from_millis(200));
+    }
+
+    #[test]
+    fn uses_request_timeout_then_data_timeout() {
+        let (_, client_dir) = test_dirs("uses_request_timeout_then_data_timeout");
+
+        // Fake server slow to answer, then losing the first acknowledgement
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let handle = thread::spawn(move || {
+            let (_, from) = Socket::recv_from(&server).unwrap();
+            thread::sleep(Duration::from_millis(300));
+            server.set_nonblocking(true).unwrap();
+            let requests = 1 + std::iter::from_fn(|| Socket::recv_from(&server).ok()).count();
+            server.set_nonblocking(false).unwrap();
+
+            let data = Packet::Data {
+                block_num: 1,
+                data: vec![0; 512],
+            };
+            Socket::send_to(&server, &data, &from).unwrap();
+            let mut acks = vec![];
+            while acks.len() < 2 {
+                if Socket::recv_from(&server).unwrap().0 == Packet::Ack(1) {
+                    acks.push(Instant::now());
+                }
+            }
+            let data = Packet::Data {
+                block_num: 2,
+                data: b"end".to_vec(),
+            };
+            Socket::send_to(&server, &data, &from).unwrap();
+            (requests, acks[1] - acks[0])
+        });
+
+        let mut config = ClientConfig {
+            remote_ip_address: addr.ip(),
+            port: addr.port(),
+            file_path: PathBuf::from("file.txt"),
+            receive_directory: client_dir.clone(),
+            timeout_req: Duration::from_secs(1),
+            no_tsize: true,
+            ..Default::default()
+        };
+        config.opt_common.timeout = Duration::from_millis(100);
+
+        assert!(Client::new(&config).unwrap().run().unwrap());
+        let (requests, ack_interval) = handle.join().unwrap();
+        assert_eq!(requests, 1);
+        assert!(ack_interval >= Duration::from_millis(100));
+        assert!(ack_interval < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn reports_negotiated_options() {
+        let (_, client_dir) = test_dirs("reports_negotiated_options");
+
+        // Fake server accepting some of the options of the download request
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || {
+            let (_, from) = Socket::recv_from(&server).unwrap();
+            server.connect(from).unwrap();
+            let option = |option, value| TransferOption { option, value };
+            let oack = Packet::Oack(
+                vec![
+                    option(OptionType::BlockSize, 1024),
+                    option(OptionType::WindowSize, 2),
+                    option(OptionType::Timeout, 1),
+                    option(OptionType::TransferSize, 10),
+                ],
+                vec![],
+            );
+            Socket::send(&server, &oack).unwrap();
+            assert_eq!(Socket::recv(&server).unwrap(), Packet::Ack(0));
+            let data = Packet::Data {
+                block_num: 1,
+                data: vec![0x42; 10],
+            };
+            Socket::send(&server, &data).unwrap();
+            assert_eq!(Socket::recv(&server).unwrap(), Packet::Ack(1));
+        });
+
+        let mut config = ClientConfig {
+            remote_ip_address: addr.ip(),
+            port: addr.port(),
+            file_path: PathBuf::from("file.bin"),
+            receive_directory: client_dir.clone(),
+            ..Default::default()
+        };
+        config.opt_common.block_size = 1428;
+        config.opt_common.window_size = 2;
+        config.opt_common.timeout = Duration::from_secs(1);
+        config.opt_common.sack = true;
+        let mut client = Client::new(&config).unwrap();
+        assert!(client.run().unwrap());
+        assert_eq!(
+            *client.negotiated_options(),
+            OptionsProtocol {
+                block_size: 1024,
+                window_size: 2,
+                timeout: Duration::from_secs(1),
+                transfer_size: Some(10),
+                ..Default::default()
+            }
+        );
+
+        // Fake server ignoring the options of the upload request
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || {
+            let (_, from) = Socket::recv_from(&server).unwrap();
+            server.connect(from).unwrap();
+            Socket::send(&server, &Packet::Ack(0)).unwrap();
+            let Packet::Data { block_num: 1, .. } = Socket::recv(&server).unwrap() else {
+                panic!("expected first block");
+            };
+            Socket::send(&server, &Packet::Ack(1)).unwrap();
+        });
+
+        config.port = addr.port();
+        config.mode = Mode::Upload;
+        config.file_path = client_dir.join("file.bin");
+        let mut client = Client::new(&config).unwrap();
+        assert!(client.run().unwrap());
+        assert_eq!(*client.negotiated_options(), OptionsProtocol::default());
+    }
+
+    #[test]
+    fn uses_block_size_from_oack() {
+        let (_, client_dir) = test_dirs("uses_block_size_from_oack");
+        let content: Vec<u8> = (0..1300u32).map(|i| i as u8).collect();
+        fs::write(client_dir.join("up.bin"), &content).unwrap();
+
+        // Fake server lowering the block size of the requests to 512
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let expected = content.clone();
+        let handle = thread::spawn(move || {
+            let accept = |server: &UdpSocket| {
+                let (request, from) = Socket::recv_from(server).unwrap();
+                let (Packet::Wrq { options, .. } | Packet::Rrq { options, .. }) = &request else {
+                    panic!("expected request");
+                };
+                assert!(options.contains(&TransferOption {
+                    option: OptionType::BlockSize,
+                    value: 4096
+                }));
+                let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+                socket.connect(from).unwrap();
+                let option = |option, value| TransferOption { option, value };
+                let oack = Packet::Oack(
+                    vec![
+                        option(OptionType::BlockSize, 512),
+                        option(OptionType::UTimeout, 200_000),
+                    ],
+                    vec![],
+                );
+                Socket::send(&socket, &oack).unwrap();
+                socket
+            };
+
+            let socket = accept(&server);
+            let mut lengths = vec![];
+            let mut received = vec![];
+            while lengths.last().is_none_or(|&len| len == 512) {
+                let Packet::Data { block_num, data } = Socket::recv(&socket).unwrap() else {
+                    panic!("expected data");
+                };
+                lengths.push(data.len());
+                received.extend(data);
+                Socket::send(&socket, &Packet::Ack(block_num)).unwrap();
+            }
+            assert_eq!(lengths, [512, 512, 276]);
+            assert_eq!(received, expected);
+
+            let socket = accept(&server);
+            assert_eq!(Socket::recv(&socket).unwrap(), Packet::Ack(0));
+            for (block_num, chunk) in expected.chunks(512).enumerate() {
+                let block_num = block_num as u16 + 1;
+                let data = Packet::Data {
+                    block_num,
+                    data: chunk.to_vec(),
+                };
+                Socket::send(&socket, &data).unwrap();
+                assert_eq!(Socket::recv(&socket).unwrap(), Packet::Ack(block_num));
+            }
+        });
+
+        let mut config = ClientConfig {
+            remote_ip_address: addr.ip(),
+            port: addr.port(),
+            mode: Mode::Upload,
+            file_path: client_dir.join("up.bin"),
+            file_remote: "file.bin".to_string(),
+            receive_directory: client_dir.clone(),
+            ..Default::default()
+        };
+        config.opt_common.block_size = 4096;
+        let mut client = Client::new(&config).unwrap();
+        assert!(client.run().unwrap());
+        assert_eq!(client.negotiated_options().block_size, 512);
+
+        config.mode = Mode::Download;
+        config.file_path = PathBuf::from("down.bin");
+        config.file_remote = "file.bin".to_string();
+        let mut client = Client::new(&config).unwrap();
+        assert!(client.run().unwrap());
+        assert_eq!(client.stats().blocks, 3);
+        handle.join().unwrap();
+        assert_eq!(fs::read(client_dir.join("down.bin")).unwrap(), content);
+    }
+
+    #[test]
+    fn cancels_download() {
+        let (_, client_dir) = test_dirs("cancels_download");
+
+        // Fake server sending one block, then cancelling the transfer
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let (token_tx, token_rx) = mpsc::channel::<CancelToken>();
+        let handle = thread::spawn(move || {
+            let (_, from) = Socket::recv_from(&server).unwrap();
+            server.connect(from).unwrap();
+            let data = Packet::Data {
+                block_num: 1,
+                data: vec![0x42; 512],
+            };
+            Socket::send(&server, &data).unwrap();
+            assert_eq!(Socket::recv(&server).unwrap(), Packet::Ack(1));
+
+            token_rx.recv().unwrap().cancel();
+            Socket::recv(&server).unwrap()
+        });
+
+        let mut config = ClientConfig {
+            remote_ip_address: addr.ip(),
+            port: addr.port(),
+            file_path: PathBuf::from("file.bin"),
+            receive_directory: client_dir.clone(),
+            no_tsize: true,
+            ..Default::default()
+        };
+        config.opt_common.timeout = Duration::from_millis(100);
+        let mut client = Client::new(&config).unwrap();
+        token_tx.send(client.cancel_token()).unwrap();
+
+        assert!(!client.run().unwrap());
+        assert!(client.cancel_token().is_cancelled());
+        assert!(matches!(
+            handle.join().unwrap(),
+            Packet::Error {
+                code: ErrorCode::NotDefined,
+                ..
+            }
+        ));
+        assert!(!client_dir.join("file.bin").exists());
+    }
+
+    #[test]
+    fn ignores_stale_answers() {
+        let (_, client_dir) = test_dirs("ignores_stale_answers");
+
+        // Fake server answering twice, with a worker on another port also
+        // answering a previous request
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let stale = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let handle = thread::spawn(move || {
+            let (_, from) = Socket::recv_from(&server).unwrap();
+            server.connect(from).unwrap();
+            let oack = |value| {
+                let option = |option, value| TransferOption { option, value };
+                Packet::Oack(
+                    vec![
+                        option(OptionType::BlockSize, value),
+                        option(OptionType::UTimeout, 200_000),
+                    ],
+                    vec![],
+                )
+            };
+            Socket::send(&server, &oack(1024)).unwrap();
+            Socket::send(&server, &oack(512)).unwrap();
+            Socket::send_to(&stale, &oack(768), &from).unwrap();
+            let data = Packet::Data {
+                block_num: 1,
+                data: b"stale".to_vec(),
+            };
+            Socket::send_to(&stale, &data, &from).unwrap();
+
+            assert_eq!(Socket::recv(&server).unwrap(), Packet::Ack(0));
+            let data = Packet::Data {
+                block_num: 1,
+                data: b"genuine".to_vec(),
+            };
+            Socket::send(&server, &data).unwrap();
+            assert_eq!(Socket::recv(&server).unwrap(), Packet::Ack(1));
+        });
+
+        let mut config = ClientConfig {
+            remote_ip_address: addr.ip(),
+            port: addr.port(),
+            file_path: PathBuf::from("file.txt"),
+            receive_directory: client_dir.clone(),
+            ..Default::default()
+        };
+        config.opt_common.timeout = Duration::from_millis(200);
+        let mut client = Client::new(&config).unwrap();
+
+        assert!(client.run().unwrap());
+        handle.join().unwrap();
+        assert_eq!(client.negotiated_options().block_size, 1024);
+        assert_eq!(fs::read(client_dir.join("file.txt")).unwrap(), b"genuine");
+    }
+
+    #[test]
+    fn ignores_spoofed_answer() {
+        let (_, client_dir) = test_dirs("ignores_spoofed_answer");
+
+        // Fake server whose answer is preceded by one from another IP address
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let spoofer = UdpSocket::bind("127.0.0.2:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let handle = thread::spawn(move || {
+            let (_, from) = Socket::recv_from(&server).unwrap();
+            let spoofed = Packet::Data {
+                block_num: 1,
+                data: b"spoofed".to_vec(),
+            };
+            Socket::send_to(&spoofer, &spoofed, &from).unwrap();
+            thread::sleep(Duration::from_millis(50));
+            let data = Packet::Data {
+                block_num: 1,
+                data: b"genuine".to_vec(),
+            };
+            Socket::send_to(&server, &data, &from).unwrap();
+        });
+
+        let config = ClientConfig {
+            remote_ip_address: addr.ip(),
+            port: addr.port(),
+            file_path: PathBuf::from("file.txt"),
+            receive_directory: client_dir.clone(),
+            timeout_req: Duration::from_secs(1),
+            strict_source: true,
+            ..Default::default()
+        };
+
+        assert!(Client::new(&config).unwrap().run().unwrap());
+        assert_eq!(fs::read(client_dir.join("file.txt")).unwrap(), b"genuine");
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn follows_block_size_ladder() {
+        assert_eq!(fallback_block_size(8192, 512).unwrap(), 1468);
+        assert_eq!(fallback_block_size(1468, 512).unwrap(), 1024);
+        assert_eq!(fallback_block_size(1200, 512).unwrap(), 1024);
+        assert_eq!(fallback_block_size(1024, 512).unwrap(), 512);
+        assert_eq!(fallback_block_size(512, 128).unwrap(), 256);
+        assert_eq!(fallback_block_size(256, 128).unwrap(), 128);
+    }
+
+    #[test]
+    fn stops_block_size_fallback_at_floor() {
+        assert!(fallback_block_size(512, 512).is_err());
+        assert!(fallback_block_size(1024, 1024).is_err());
+        assert!(fallback_block_size(128, 128).is_err());
+        assert!(fallback_block_size(1, 1).is_err());
     }
 }
@@ -1,17 +1,26 @@
 use std::error::Error;
-use std::net::{IpAddr, Ipv4Addr};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs};
 use std::path::{Path, PathBuf, MAIN_SEPARATOR};
-use std::process;
+use std::str::FromStr;
 use std::time::Duration;
+use std::{env, fs, process};
 
-use crate::client::Mode;
-use crate::config;
+use crate::client::{IpFamily, Mode, Overwrite};
 use crate::log::*;
-use crate::options::{OptionsPrivate, OptionsProtocol, DEFAULT_TIMEOUT};
+use crate::options::{
+    OptionsPrivate, OptionsProtocol, DEFAULT_BLOCK_SIZE, DEFAULT_TIMEOUT, DEFAULT_WINDOW_SIZE,
+    DEFAULT_WINDOW_WAIT,
+};
+use crate::{config, config_file};
+use crate::{Compression, HashAlgorithm, OptionType, TransferMode};
 
 #[cfg(feature = "debug_drop")]
 use crate::drop::drop_set;
 
+const DEFAULT_HANDSHAKE_RETRIES: u32 = 3;
+// RFC 791 smallest MTU every IPv4 host must handle
+const MIN_MTU: u16 = 68;
+
 /// Configuration `struct` used for parsing TFTP Client options from user
 /// input.
 ///
@@ -31,40 +40,175 @@ use crate::drop::drop_set;
 pub struct ClientConfig {
     /// Local IP address of the TFTP Client. (default: 127.0.0.1)
     pub remote_ip_address: IpAddr,
+    /// Other addresses of the remote host, tried in turn when the previous
+    /// one does not answer the request. (default: none)
+    pub remote_alternatives: Vec<IpAddr>,
+    /// Scope (zone) of the link-local IPv6 remote addresses, given after a
+    /// `%` like in `fe80::1%eth0`. (default: 0, none)
+    pub scope_id: u32,
     /// Local Port number of the TFTP Client. (default: 69)
     pub port: u16,
-    /// Timeout to use after request. (default: 5s)
+    /// Local address and port the TFTP Client binds to, of the same IP
+    /// family as the remote one. (default: any address, port chosen by the OS)
+    pub local_address: Option<SocketAddr>,
+    /// IP family used to reach the server, the remote addresses of the other
+    /// one being refused. (default: the family of the remote address, IPv4
+    /// for an IPv4-mapped one)
+    pub ip_family: Option<IpFamily>,
+    /// Timeout to use after request, until the first answer of the server.
+    /// The data packets then use the timeout of `opt_common`, as negotiated
+    /// with the server. (default: 5s)
     pub timeout_req: Duration,
+    /// Times the request is sent again when not answered, doubling the
+    /// timeout each time up to 8 request timeouts. (default: 3)
+    pub max_handshake_retries: u32,
+    /// Ignore answers to the request coming from another IP address than the
+    /// one it was sent to, their port may still differ. (default: false)
+    pub strict_source: bool,
+    /// Check the port of the server on each datagram of the transfer instead
+    /// of leaving it to the OS, refusing with a warning the datagrams from
+    /// another one. (default: false)
+    pub strict_tid: bool,
     /// Upload or Download a file. (default: Download)
     pub mode: Mode,
     /// Download directory of the TFTP Client. (default: current working directory)
     pub receive_directory: PathBuf,
-    /// File to Upload or Download.
+    /// File to Upload or Download. A download given this path only is saved
+    /// in the receive directory under its file name, and given a remote path
+    /// too, under this path relative to the receive directory.
     pub file_path: PathBuf,
     /// Optional file path to send to server.
     pub file_remote: String,
+    /// Save a download in the receive directory under the file name of its
+    /// local path only, e.g. `a/b/c.bin` as `c.bin`, instead of keeping the
+    /// directories of the path. (default: false)
+    pub flatten: bool,
+    /// Create the missing directories of the local path of a download, which
+    /// cannot lead outside of the receive directory. (default: false)
+    pub mkdir: bool,
+    /// Other files to upload after `file_path`, each with its own request
+    /// and under its file name. Filled in Upload mode when more than two
+    /// paths, or paths with `*` or `?` wildcards, are given. (default: none)
+    pub extra_files: Vec<PathBuf>,
+    /// Transfer mode, netascii translating line endings. (default: octet)
+    pub transfer_mode: TransferMode,
     /// Local options for client
     pub opt_local: OptionsPrivate,
     /// Common options for client
     pub opt_common: OptionsProtocol,
+    /// Retry failed transfers with smaller block sizes. (default: false)
+    pub auto_blksize: bool,
+    /// Smallest block size the fallback may use. (default: 512)
+    pub min_blksize: u16,
+    /// What to do when the destination file already exists: the remote file
+    /// is checked before uploading, and the local one before downloading.
+    /// (default: replace it)
+    pub overwrite: Overwrite,
+    /// Transfer even if the destination file exists, overrides
+    /// [`Overwrite::NoClobber`]. (default: false)
+    pub force: bool,
+    /// Resume a download into an existing local file. As TFTP cannot seek,
+    /// the data already present is received again and discarded, so resuming
+    /// only saves the disk writes, not the bandwidth. (default: false)
+    pub resume: bool,
+    /// Hash algorithm of the digest computed over a downloaded file. (default: none)
+    pub hash: Option<HashAlgorithm>,
+    /// Expected digest of a downloaded file, in lowercase hexadecimal. (default: none)
+    pub expected_digest: Option<String>,
+    /// CRC-32 of each window of a download, read by `--verify-manifest` from
+    /// a file listing one CRC-32 per line in hexadecimal. Each window is a
+    /// run of windowsize x blocksize bytes of the file as negotiated, the
+    /// last one being possibly shorter. (default: none)
+    pub manifest: Option<Vec<u32>>,
+    /// Compression of the data requested with the private `compress` option,
+    /// the transfer falling back to uncompressed data when the server does
+    /// not accept it. (default: none)
+    pub compression: Option<Compression>,
+    /// Only negotiate the options with the server, then abort the transfer
+    /// without touching any file. (default: false)
+    pub dry_run: bool,
+    /// Never request the transfer size option, for servers rejecting it.
+    /// (default: false)
+    pub no_tsize: bool,
+    /// Request a multicast download (RFC 2090), receiving the data sent to
+    /// a group. (default: false)
+    pub multicast: bool,
+    /// MTU of the path to the server, capping the requested blocksize so that
+    /// each datagram fits in one IP packet. (default: none)
+    pub mtu: Option<u16>,
+    /// Size of the receive buffer of the socket, which the OS may clamp.
+    /// (default: the size of a window when larger than the OS one)
+    pub recv_buffer: Option<usize>,
+    /// Size of the send buffer of the socket, which the OS may clamp.
+    /// (default: the size of a window when larger than the OS one)
+    pub send_buffer: Option<usize>,
 }
 
 impl Default for ClientConfig {
     fn default() -> Self {
         Self {
             remote_ip_address: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            remote_alternatives: vec![],
+            scope_id: 0,
             port: 69,
+            local_address: None,
+            ip_family: None,
             timeout_req: DEFAULT_TIMEOUT,
+            max_handshake_retries: DEFAULT_HANDSHAKE_RETRIES,
+            strict_source: false,
+            strict_tid: false,
             mode: Mode::Download,
             receive_directory: Default::default(),
             file_path: Default::default(),
             file_remote: Default::default(),
+            flatten: false,
+            mkdir: false,
+            extra_files: vec![],
+            transfer_mode: TransferMode::Octet,
             opt_local: Default::default(),
             opt_common: Default::default(),
+            auto_blksize: false,
+            min_blksize: DEFAULT_BLOCK_SIZE,
+            overwrite: Overwrite::Replace,
+            force: false,
+            resume: false,
+            hash: None,
+            expected_digest: None,
+            manifest: None,
+            compression: None,
+            dry_run: false,
+            no_tsize: false,
+            multicast: false,
+            mtu: None,
+            recv_buffer: None,
+            send_buffer: None,
         }
     }
 }
 
+/// Reads a manifest of window CRC-32, one per line in hexadecimal, empty
+/// lines being ignored
+fn read_manifest(path: &Path) -> Result<Vec<u32>, Box<dyn Error>> {
+    let text = fs::read_to_string(path)
+        .map_err(|err| format!("Cannot read manifest {}: {err}", path.display()))?;
+
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(line_num, line)| {
+            u32::from_str_radix(line.trim(), 16).map_err(|_| {
+                format!(
+                    "{}: line {}: invalid CRC-32 {}",
+                    path.display(),
+                    line_num + 1,
+                    line.trim()
+                )
+                .into()
+            })
+        })
+        .collect()
+}
+
 fn parse_duration<T: Iterator<Item = String>>(args: &mut T) -> Result<Duration, Box<dyn Error>> {
     if let Some(dur_str) = args.next() {
         let dur = Duration::from_secs_f32(dur_str.parse::<f32>()?);
@@ -80,6 +224,66 @@ fn parse_duration<T: Iterator<Item = String>>(args: &mut T) -> Result<Duration,
     }
 }
 
+fn parse_extra_option(option_str: &str) -> Result<(String, String), Box<dyn Error>> {
+    let (key, value) = option_str
+        .split_once('=')
+        .ok_or_else(|| format!("Invalid option {option_str}, expected key=value"))?;
+    if key.is_empty() || key.contains('\0') || value.contains('\0') {
+        return Err(format!("Invalid option {option_str}").into());
+    }
+    if OptionType::from_str(&key.to_lowercase()).is_ok()
+        || key.eq_ignore_ascii_case(Compression::OPTION)
+    {
+        return Err(format!("Option {key} is managed by the client, use its flag instead").into());
+    }
+
+    Ok((key.to_string(), value.to_string()))
+}
+
+/// Returns whether `ip` is an IPv6 unicast link-local address (fe80::/10),
+/// only reachable through the interface given by its scope
+pub(crate) fn is_link_local(ip: &Ipv6Addr) -> bool {
+    ip.segments()[0] & 0xffc0 == 0xfe80
+}
+
+/// Converts the zone of an IPv6 address, an interface name or number, to
+/// its scope id
+fn parse_scope_id(zone: &str) -> Result<u32, Box<dyn Error>> {
+    if let Ok(scope_id) = zone.parse::<u32>() {
+        return Ok(scope_id);
+    }
+
+    #[cfg(unix)]
+    {
+        let name = std::ffi::CString::new(zone).map_err(|_| "Invalid interface name")?;
+        match unsafe { libc::if_nametoindex(name.as_ptr()) } {
+            0 => Err(format!("Unknown network interface {zone}").into()),
+            scope_id => Ok(scope_id),
+        }
+    }
+    #[cfg(not(unix))]
+    Err(format!("Interface names are only supported on Unix, use the number of {zone}").into())
+}
+
+/// Environment variables with the flags they stand for
+const ENV_FLAGS: [(&str, &str); 5] = [
+    ("TFTP_REMOTE", "--ip-address"),
+    ("TFTP_PORT", "--port"),
+    ("TFTP_BLKSIZE", "--blocksize"),
+    ("TFTP_WINDOWSIZE", "--windowsize"),
+    ("TFTP_TIMEOUT", "--timeout"),
+];
+
+/// Converts the environment variables which are set to arguments, to be
+/// placed before the ones of the command line so that the latter override them
+fn env_args(env_var: impl Fn(&str) -> Option<String>) -> Vec<String> {
+    ENV_FLAGS
+        .iter()
+        .filter_map(|(name, flag)| env_var(name).map(|value| [flag.to_string(), value]))
+        .flatten()
+        .collect()
+}
+
 fn print_version_exit() {
     println!("rs-tftp client version {}", env!("CARGO_PKG_VERSION"));
     #[cfg(debug_assertions)]
@@ -92,20 +296,51 @@ fn print_version_exit() {
 impl ClientConfig {
     /// Creates a new configuration by parsing the supplied arguments. It is
     /// intended for use with [`env::args()`].
-    pub fn new<T: Iterator<Item = String>>(mut args: T) -> Result<ClientConfig, Box<dyn Error>> {
+    ///
+    /// The settings are taken, by increasing precedence, from the `--config`
+    /// file (see [`ClientConfig::from_file()`]), from the `TFTP_REMOTE`,
+    /// `TFTP_PORT`, `TFTP_BLKSIZE`, `TFTP_WINDOWSIZE` and `TFTP_TIMEOUT`
+    /// environment variables, then from the flags.
+    pub fn new<T: Iterator<Item = String>>(args: T) -> Result<ClientConfig, Box<dyn Error>> {
+        ClientConfig::parse(args, |name| env::var(name).ok())
+    }
+
+    fn parse<T: Iterator<Item = String>>(
+        args: T,
+        env_var: impl Fn(&str) -> Option<String>,
+    ) -> Result<ClientConfig, Box<dyn Error>> {
+        let args = env_args(env_var).into_iter().chain(args);
+        let mut args = config_file::expand_args(args, 0)?;
         let mut config = ClientConfig::default();
         let mut verbosity: isize = 1;
+        let mut remote_host: Option<String> = None;
 
         while let Some(arg) = args.next() {
             match arg.as_str() {
                 "-i" | "--ip-address" => {
                     if let Some(ip_str) = args.next() {
-                        let ip_addr: IpAddr = ip_str.parse()?;
-                        config.remote_ip_address = ip_addr;
+                        if let Some((ip_str, zone)) = ip_str.split_once('%') {
+                            let ip_addr = ip_str
+                                .parse::<Ipv6Addr>()
+                                .map_err(|_| format!("{ip_str} is not an IPv6 address"))?;
+                            config.remote_ip_address = ip_addr.into();
+                            config.scope_id = parse_scope_id(zone)?;
+                            remote_host = None;
+                        } else {
+                            match ip_str.parse::<IpAddr>() {
+                                Ok(ip_addr) => {
+                                    config.remote_ip_address = ip_addr;
+                                    remote_host = None;
+                                }
+                                Err(_) => remote_host = Some(ip_str),
+                            }
+                        }
                     } else {
                         return Err("Missing ip address after flag".into());
                     }
                 }
+                "-4" => config.ip_family = Some(IpFamily::V4),
+                "-6" => config.ip_family = Some(IpFamily::V6),
                 "-p" | "--port" => {
                     if let Some(port_str) = args.next() {
                         config.port = port_str.parse::<u16>()?;
@@ -113,6 +348,13 @@ impl ClientConfig {
                         return Err("Missing port number after flag".into());
                     }
                 }
+                "-l" | "--local-address" => {
+                    if let Some(addr_str) = args.next() {
+                        config.local_address = Some(addr_str.parse()?);
+                    } else {
+                        return Err("Missing local address after flag".into());
+                    }
+                }
                 "-b" | "--blocksize" => {
                     if let Some(blocksize_str) = args.next() {
                         config.opt_common.block_size = blocksize_str.parse::<u16>()?;
@@ -127,6 +369,66 @@ impl ClientConfig {
                         return Err("Missing windowsize after flag".into());
                     }
                 }
+                "--auto-blksize" => {
+                    config.auto_blksize = true;
+                }
+                "--mtu" => {
+                    if let Some(mtu_str) = args.next() {
+                        let mtu = mtu_str.parse::<u16>()?;
+                        if mtu < MIN_MTU {
+                            return Err(format!("MTU cannot be smaller than {MIN_MTU}").into());
+                        }
+                        config.mtu = Some(mtu);
+                    } else {
+                        return Err("Missing MTU after flag".into());
+                    }
+                }
+                "--recv-buffer" => {
+                    if let Some(size_str) = args.next() {
+                        config.recv_buffer = Some(size_str.parse::<usize>()?);
+                    } else {
+                        return Err("Missing receive buffer size after flag".into());
+                    }
+                }
+                "--send-buffer" => {
+                    if let Some(size_str) = args.next() {
+                        config.send_buffer = Some(size_str.parse::<usize>()?);
+                    } else {
+                        return Err("Missing send buffer size after flag".into());
+                    }
+                }
+                "--min-blksize" => {
+                    if let Some(blocksize_str) = args.next() {
+                        config.min_blksize = blocksize_str.parse::<u16>()?;
+                    } else {
+                        return Err("Missing minimum blocksize after flag".into());
+                    }
+                }
+                "--checksum" => {
+                    // Placeholder, the actual value is set by the sender
+                    config.opt_common.checksum = Some(0);
+                }
+                "--sack" => {
+                    config.opt_common.sack = true;
+                }
+                "--request-rollover" => {
+                    config.opt_common.rollover = match args.next().as_deref() {
+                        Some("0") => Some(0),
+                        Some("1") => Some(1),
+                        Some(_) => return Err("Invalid rollover value: use 0 or 1".into()),
+                        None => return Err("Missing rollover value after flag".into()),
+                    };
+                }
+                "--opt" => {
+                    if let Some(option_str) = args.next() {
+                        config
+                            .opt_common
+                            .extra
+                            .push(parse_extra_option(&option_str)?);
+                    } else {
+                        return Err("Missing option after flag".into());
+                    }
+                }
                 "-W" | "--windowwait" => {
                     config.opt_common.window_wait = parse_duration(&mut args)?;
                 }
@@ -136,6 +438,19 @@ impl ClientConfig {
                 "-T" | "--timeout-req" => {
                     config.timeout_req = parse_duration(&mut args)?;
                 }
+                "--handshake-retries" => {
+                    if let Some(retries_str) = args.next() {
+                        config.max_handshake_retries = retries_str.parse::<u32>()?;
+                    } else {
+                        return Err("Missing handshake retries after flag".into());
+                    }
+                }
+                "--strict-source" => {
+                    config.strict_source = true;
+                }
+                "--strict-tid" => {
+                    config.strict_tid = true;
+                }
                 "-rd" | "--receive-directory" => {
                     if let Some(dir_str) = args.next() {
                         if !Path::new(&dir_str).exists() {
@@ -152,20 +467,125 @@ impl ClientConfig {
                 "-d" | "--download" => {
                     config.mode = Mode::Download;
                 }
+                "--netascii" => {
+                    config.transfer_mode = TransferMode::Netascii;
+                }
+                "--no-clobber" => {
+                    config.overwrite = Overwrite::NoClobber;
+                }
+                "--overwrite" => {
+                    config.overwrite = Overwrite::Replace;
+                }
+                "--append" => {
+                    config.overwrite = Overwrite::Append;
+                }
+                "--force" => {
+                    config.force = true;
+                }
+                "--resume" => {
+                    config.resume = true;
+                }
+                "--dry-run" => {
+                    config.dry_run = true;
+                }
+                "--no-tsize" => {
+                    config.no_tsize = true;
+                }
+                "--flatten" => {
+                    config.flatten = true;
+                }
+                "--mkdir" => {
+                    config.mkdir = true;
+                }
+                "--multicast" => {
+                    config.multicast = true;
+                }
+                "--hash" => {
+                    if let Some(algorithm_str) = args.next() {
+                        config.hash = Some(algorithm_str.parse()?);
+                    } else {
+                        return Err("Missing hash algorithm after flag".into());
+                    }
+                }
+                "--crc32" | "--sha256" => {
+                    let algorithm: HashAlgorithm = arg[2..].parse()?;
+                    if let Some(digest_str) = args.next() {
+                        config.expected_digest = Some(algorithm.parse_digest(&digest_str)?);
+                        config.hash = Some(algorithm);
+                    } else {
+                        return Err("Missing digest after flag".into());
+                    }
+                }
+                "--verify-manifest" => {
+                    if let Some(path_str) = args.next() {
+                        config.manifest = Some(read_manifest(Path::new(&path_str))?);
+                    } else {
+                        return Err("Missing manifest file after flag".into());
+                    }
+                }
+                "--compress" => {
+                    if let Some(compression_str) = args.next() {
+                        config.compression = Some(compression_str.parse()?);
+                    } else {
+                        return Err("Missing compression after flag".into());
+                    }
+                }
                 "-h" | "--help" => {
                     println!("TFTP Client\n");
                     println!("Usage: tftpd client [options] <file> [remote file] \n");
+                    println!("Upload several files with -u and more than two paths, or wildcards (*, ?) in the file names.");
+                    println!("Use '-' as file to upload from stdin or download to stdout.\n");
                     println!("Options:");
-                    println!("  -i, --ip-address <IP ADDRESS>\t\tIP address of the server (default: 127.0.0.1)");
+                    println!("  -i, --ip-address <IP ADDRESS|HOST>\tIP address or hostname of the server, with %zone for IPv6 link-local (default: 127.0.0.1)");
+                    println!("  -4, -6\t\t\t\t\tuse only IPv4 or IPv6 to reach the server (default: the family of its address)");
                     println!("  -p, --port <PORT>\t\t\tUDP port of the server (default: 69)");
-                    println!("  -b, --blocksize <number>\t\tset the blocksize (default: 512)");
-                    println!("  -w, --windowsize <number>\t\tset the windowsize (default: 1)");
-                    println!("  -W, --windowwait <seconds>\t\t inter-packet wait time in seconds for windows (default: 0)");
-                    println!("  -t, --timeout <seconds>\t\tset the timeout for data in seconds (default: 5, can be float)");
-                    println!("  -T, --timeout-req <seconds>\t\tset the timeout after request in seconds (default: 5, can be float)");
+                    println!("  -l, --local-address <IP:PORT>\t\tlocal address and port to bind to (default: any)");
+                    println!("  -b, --blocksize <number>\t\tset the blocksize (default: {DEFAULT_BLOCK_SIZE})");
+                    println!(
+                        "  --auto-blksize\t\t\t\tretry failed transfers with smaller blocksizes"
+                    );
+                    println!("  --mtu <number>\t\t\t\tcap the blocksize so that packets fit in this path MTU");
+                    println!("  --recv-buffer <bytes>\t\t\tsize of the socket receive buffer (default: a window when larger than the OS one)");
+                    println!("  --send-buffer <bytes>\t\t\tsize of the socket send buffer (default: a window when larger than the OS one)");
+                    println!("  --min-blksize <number>\t\tsmallest blocksize tried by --auto-blksize (default: {DEFAULT_BLOCK_SIZE})");
+                    println!("  -w, --windowsize <number>\t\tset the windowsize (default: {DEFAULT_WINDOW_SIZE})");
+                    println!("  -W, --windowwait <seconds>\t\t inter-packet wait time in seconds for windows (default: {})", DEFAULT_WINDOW_WAIT.as_secs_f32());
+                    println!("  --sack\t\t\t\t\tsend again only the blocks of a window the server missed, if supported");
+                    println!("  --request-rollover <0|1>\t\tnegotiate the value the block counter rolls over to, if supported");
+                    println!("  --no-tsize\t\t\t\tnever send the transfer size option");
+                    println!("  --flatten\t\t\t\tsave a download in the receive directory under its file name only");
+                    println!("  --mkdir\t\t\t\tcreate the missing directories of a download in the receive directory");
+                    println!("  --opt <key=value>\t\t\tsend a non standard option as is, can be repeated");
+                    println!("  --checksum\t\t\t\tverify the file CRC-32 after transfer, if server supports it");
+                    println!("  -t, --timeout <seconds>\t\tset the timeout for data in seconds (default: {}, can be float)", DEFAULT_TIMEOUT.as_secs_f32());
+                    println!("  -T, --timeout-req <seconds>\t\tset the timeout after request in seconds (default: {}, can be float)", DEFAULT_TIMEOUT.as_secs_f32());
+                    println!("  --handshake-retries <number>\t\tresend an unanswered request, doubling the timeout (default: {DEFAULT_HANDSHAKE_RETRIES})");
+                    println!(
+                        "  --strict-source			ignore answers to the request from another IP address"
+                    );
+                    println!("  --strict-tid\t\t\t\twarn about and refuse the datagrams from another server port");
                     println!("  -u, --upload\t\t\t\tselect upload mode, ignores previous flags");
                     println!("  -d, --download\t\t\tselect download mode, ignores previous flags");
+                    println!("  --netascii\t\t\t\ttransfer text, translating line endings");
+                    println!("  --no-clobber\t\t\t\trefuse to transfer over an existing destination file");
+                    println!("  --overwrite\t\t\t\treplace an existing destination file (default)");
+                    println!(
+                        "  --append\t\t\t\tappend the downloaded data to an existing local file"
+                    );
+                    println!("  --force\t\t\t\ttransfer even if the destination file exists, overrides --no-clobber");
+                    println!(
+                        "  --hash <crc32|sha256>\t\t\tcompute the digest of the downloaded file"
+                    );
+                    println!(
+                        "  --crc32, --sha256 <hex>\t\tverify the digest of the downloaded file"
+                    );
+                    println!("  --verify-manifest <FILE>\t\tverify the CRC-32 of each downloaded window, one per line in FILE");
+                    println!("  --resume\t\t\t\tappend to an existing local file, receiving its content again");
+                    println!("  --compress <gzip|deflate>\t\tcompress the data on the wire, if the server supports it");
+                    println!("  --multicast\t\t\t\tdownload from a multicast group, if the server supports it");
+                    println!("  --dry-run\t\t\t\tonly negotiate and print the options accepted by the server");
                     println!("  -rd, --receive-directory <DIR>\tdirectory to receive files when in Download mode (default: current)");
+                    println!("  --config <FILE>\t\t\tread default options from a TOML file, overridden by flags");
                     config::print_opt_local_help();
                     println!(
                         "  -v, --verbose\t\t\t\tIncrease log verbosity (can be repeated, e.g. -vv)"
@@ -173,6 +593,10 @@ impl ClientConfig {
                     println!("  -q, --quiet\t\t\t\tDecrease log verbosity (can be repeated)");
                     println!("  -h, --help\t\t\t\tprint help information");
                     println!("  -V, --version\t\t\t\tprint version");
+                    println!("\nEnvironment, overridden by flags:");
+                    println!(
+                        "  TFTP_REMOTE, TFTP_PORT, TFTP_BLKSIZE, TFTP_WINDOWSIZE, TFTP_TIMEOUT"
+                    );
                     process::exit(0);
                 }
                 "-q" | "--quiet" => verbosity -= 1,
@@ -180,6 +604,7 @@ impl ClientConfig {
                 "-V" | "--version" => print_version_exit(),
                 #[cfg(feature = "debug_drop")]
                 "-D" => drop_set(args.next())?,
+                "-" => config.set_paths(&arg)?,
                 "--" => {
                     for arg in args.by_ref() {
                         config.set_paths(&arg)?;
@@ -203,6 +628,19 @@ impl ClientConfig {
             return Err("missing filename".into());
         }
 
+        if let Some(host) = remote_host {
+            config.set_remote_host(&host)?;
+        }
+
+        if let IpAddr::V6(ip) = config.remote_ip_address {
+            if is_link_local(&ip) && config.scope_id == 0 {
+                return Err(format!(
+                    "Link-local address {ip} needs a scope, e.g. {ip}%eth0 or {ip}%2"
+                )
+                .into());
+            }
+        }
+
         if config.opt_common.timeout <= config.opt_common.window_wait {
             return Err("Inter-packet wait time cannot be exceed timeout".into());
         }
@@ -211,21 +649,189 @@ impl ClientConfig {
             return Err("Inter-packet wait time needs window size > 1".into());
         }
 
+        if config.mode == Mode::Upload {
+            config.expand_uploads()?;
+        } else if !config.extra_files.is_empty() {
+            return Err("too many arguments, only uploads accept several files".into());
+        }
+
+        if config.overwrite == Overwrite::Append {
+            if config.mode == Mode::Upload {
+                return Err("Appending is only supported for downloads".into());
+            }
+            if config.resume {
+                return Err("Cannot both append to and resume a download".into());
+            }
+            if config.hash.is_some() {
+                return Err("Cannot verify the digest of an appended download".into());
+            }
+        }
+
+        if config.compression.is_some() && config.resume {
+            // The size of the remote file is only known compressed
+            return Err("Cannot resume a compressed download".into());
+        }
+
+        if config.manifest.is_some() && config.mode == Mode::Upload {
+            return Err("Manifests are only verified on downloads".into());
+        }
+
+        if config.multicast && config.mode == Mode::Upload {
+            return Err("Multicast is only supported for downloads".into());
+        }
+
+        if config.multicast && config.strict_tid {
+            // The data comes from the group, not from the server port
+            return Err("Cannot check the server port of a multicast download".into());
+        }
+
+        if config.mode == Mode::Download
+            && (config.file_path.as_os_str() == "-" || config.file_remote == "-")
+        {
+            // Only errors are logged, on stderr, not to mix logs with the data
+            verbosity = verbosity.min(0);
+        }
+
         verbosity_set(verbosity);
 
         Ok(config)
     }
 
+    /// Creates a new configuration from a TOML file, whose keys are the long
+    /// flag names of [`ClientConfig::new()`], e.g. `blocksize = 1428`, and
+    /// `file` for the file paths.
+    pub fn from_file(path: &Path) -> Result<ClientConfig, Box<dyn Error>> {
+        ClientConfig::new(config_file::file_args(path)?.into_iter())
+    }
+
+    /// Resolves the remote hostname, keeping only the addresses of the forced
+    /// IP family, if any
+    fn set_remote_host(&mut self, host: &str) -> Result<(), Box<dyn Error>> {
+        let mut addresses: Vec<IpAddr> = (host, self.port)
+            .to_socket_addrs()
+            .map_err(|err| format!("Cannot resolve {host}: {err}"))?
+            .map(|addr| {
+                if let SocketAddr::V6(addr) = addr {
+                    if addr.scope_id() != 0 {
+                        self.scope_id = addr.scope_id();
+                    }
+                }
+                addr.ip()
+            })
+            .collect();
+        addresses.dedup();
+        if addresses.is_empty() {
+            return Err(format!("Cannot resolve {host}: no address").into());
+        }
+        if let Some(family) = self.ip_family {
+            addresses.retain(|addr| family.contains(addr));
+            if addresses.is_empty() {
+                return Err(format!("Cannot resolve {host}: no {family} address").into());
+            }
+        }
+        self.remote_ip_address = addresses.remove(0);
+        self.remote_alternatives = addresses;
+
+        Ok(())
+    }
+
     fn set_paths(&mut self, arg: &str) -> Result<(), Box<dyn Error>> {
         if self.file_path.as_os_str().is_empty() {
             self.file_path = convert_file_path_abs(arg);
         } else if self.file_remote.is_empty() {
             self.file_remote = arg.to_string();
         } else {
-            return Err("too many arguments".into());
+            self.extra_files.push(convert_file_path_abs(arg));
         }
         Ok(())
     }
+
+    /// Turns the paths into a list of files to upload when more than two
+    /// were given or some have wildcards, the remote path being then a local
+    /// one as well
+    fn expand_uploads(&mut self) -> Result<(), Box<dyn Error>> {
+        let has_wildcard = |path: &str| path.contains(['*', '?']);
+        if self.extra_files.is_empty()
+            && !has_wildcard(&self.file_path.to_string_lossy())
+            && !has_wildcard(&self.file_remote)
+        {
+            return Ok(());
+        }
+
+        let mut paths = vec![std::mem::take(&mut self.file_path)];
+        if !self.file_remote.is_empty() {
+            paths.push(convert_file_path_abs(&std::mem::take(
+                &mut self.file_remote,
+            )));
+        }
+        paths.append(&mut self.extra_files);
+
+        let mut files = vec![];
+        for path in paths {
+            if has_wildcard(&path.to_string_lossy()) {
+                files.extend(expand_wildcards(&path)?);
+            } else {
+                files.push(path);
+            }
+        }
+        self.file_path = files.remove(0);
+        self.extra_files = files;
+
+        Ok(())
+    }
+}
+
+/// Lists the files whose name matches the last component of `pattern`, in
+/// alphabetical order. Wildcards are not supported in the directories.
+fn expand_wildcards(pattern: &Path) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let name = pattern
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| format!("Invalid pattern {}", pattern.display()))?;
+    let dir = match pattern.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    if dir.to_string_lossy().contains(['*', '?']) {
+        return Err(format!(
+            "Wildcards are only supported in file names: {}",
+            pattern.display()
+        )
+        .into());
+    }
+
+    let mut files = vec![];
+    for entry in fs::read_dir(dir).map_err(|err| format!("Cannot list {}: {err}", dir.display()))? {
+        let entry = entry?;
+        let matches = entry
+            .file_name()
+            .to_str()
+            .is_some_and(|file_name| wildcard_match(name.as_bytes(), file_name.as_bytes()));
+        if matches && entry.file_type()?.is_file() {
+            files.push(pattern.with_file_name(entry.file_name()));
+        }
+    }
+    if files.is_empty() {
+        return Err(format!("No file matches {}", pattern.display()).into());
+    }
+    files.sort();
+
+    Ok(files)
+}
+
+/// Matches `name` against `pattern`, where `*` stands for any sequence of
+/// characters and `?` for any single one
+fn wildcard_match(pattern: &[u8], name: &[u8]) -> bool {
+    match (pattern.first(), name.first()) {
+        (None, None) => true,
+        (Some(b'*'), _) => {
+            wildcard_match(&pattern[1..], name)
+                || (!name.is_empty() && wildcard_match(pattern, &name[1..]))
+        }
+        (Some(b'?'), Some(_)) => wildcard_match(&pattern[1..], &name[1..]),
+        (Some(p), Some(n)) if p == n => wildcard_match(&pattern[1..], &name[1..]),
+        _ => false,
+    }
 }
 
 pub fn convert_file_path_abs(filename: &str) -> PathBuf {
@@ -242,6 +848,61 @@ pub fn convert_file_path_abs(filename: &str) -> PathBuf {
 mod tests {
     use super::*;
 
+    #[test]
+    fn falls_back_to_environment() {
+        let env_var = |name: &str| match name {
+            "TFTP_REMOTE" => Some("10.0.0.1".to_string()),
+            "TFTP_PORT" => Some("6969".to_string()),
+            "TFTP_BLKSIZE" => Some("1428".to_string()),
+            "TFTP_WINDOWSIZE" => Some("8".to_string()),
+            "TFTP_TIMEOUT" => Some("2".to_string()),
+            _ => None,
+        };
+
+        let config = ClientConfig::parse(["test.file".to_string()].into_iter(), env_var).unwrap();
+        assert_eq!(config.remote_ip_address, Ipv4Addr::new(10, 0, 0, 1));
+        assert_eq!(config.port, 6969);
+        assert_eq!(config.opt_common.block_size, 1428);
+        assert_eq!(config.opt_common.window_size, 8);
+        assert_eq!(config.opt_common.timeout, Duration::from_secs(2));
+
+        let args = ["test.file", "-p", "69", "-b", "512", "--timeout", "4"];
+        let config = ClientConfig::parse(args.map(|s| s.to_string()).into_iter(), env_var).unwrap();
+        assert_eq!(config.remote_ip_address, Ipv4Addr::new(10, 0, 0, 1));
+        assert_eq!(config.port, 69);
+        assert_eq!(config.opt_common.block_size, 512);
+        assert_eq!(config.opt_common.window_size, 8);
+        assert_eq!(config.opt_common.timeout, Duration::from_secs(4));
+
+        let env_var = |name: &str| (name == "TFTP_PORT").then(|| "port".to_string());
+        assert!(ClientConfig::parse(["test.file".to_string()].into_iter(), env_var).is_err());
+    }
+
+    #[test]
+    fn overrides_config_file_with_flags() {
+        let path = Path::new("target/test/client_config.toml");
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(
+            path,
+            "file = \"firmware.bin\"\nport = 6969\nblocksize = 1428\nwindowsize = 8\nsack = true\n",
+        )
+        .unwrap();
+
+        let config = ClientConfig::from_file(path).unwrap();
+        assert_eq!(config.file_path, PathBuf::from("firmware.bin"));
+        assert_eq!(config.port, 6969);
+        assert_eq!(config.opt_common.block_size, 1428);
+        assert!(config.opt_common.sack);
+
+        let args = ["-w", "4", "--config", "target/test/client_config.toml"];
+        let config = ClientConfig::new(args.map(|s| s.to_string()).into_iter()).unwrap();
+        assert_eq!(config.opt_common.window_size, 4);
+        assert_eq!(config.opt_common.block_size, 1428);
+
+        std::fs::write(path, "file = \"firmware.bin\"\nblksize = 1428\n").unwrap();
+        assert!(ClientConfig::from_file(path).is_err());
+    }
+
     #[test]
     fn parses_full_config() {
         let config = ClientConfig::new(
@@ -251,6 +912,8 @@ mod tests {
                 "0.0.0.0",
                 "-p",
                 "1234",
+                "-l",
+                "0.0.0.0:6969",
                 "-rd",
                 "/",
                 "-d",
@@ -263,7 +926,36 @@ mod tests {
                 "0.02",
                 "-t",
                 "4",
+                "--handshake-retries",
+                "5",
+                "--strict-source",
+                "--strict-tid",
                 "--keep-on-error",
+                "--no-sync",
+                "--auto-blksize",
+                "--min-blksize",
+                "256",
+                "--mtu",
+                "1400",
+                "--recv-buffer",
+                "1048576",
+                "--send-buffer",
+                "65536",
+                "--checksum",
+                "--sack",
+                "--request-rollover",
+                "1",
+                "--opt",
+                "x-vendor=on",
+                "--netascii",
+                "--no-clobber",
+                "--resume",
+                "--dry-run",
+                "--no-tsize",
+                "--flatten",
+                "--mkdir",
+                "--sha256",
+                "BA7816BF8F01CFEA414140DE5DAE2223B00361A396177A9CB410FF61F20015AD",
             ]
             .iter()
             .map(|s| s.to_string()),
@@ -272,14 +964,44 @@ mod tests {
 
         assert_eq!(config.remote_ip_address, Ipv4Addr::new(0, 0, 0, 0));
         assert_eq!(config.port, 1234);
+        assert_eq!(config.local_address, Some("0.0.0.0:6969".parse().unwrap()));
         assert_eq!(config.receive_directory, PathBuf::from("/"));
         assert_eq!(config.file_path, PathBuf::from("test.file"));
         assert_eq!(config.opt_common.window_size, 2);
         assert_eq!(config.opt_common.window_wait, Duration::from_millis(20));
         assert_eq!(config.opt_common.block_size, 1024);
         assert_eq!(config.mode, Mode::Upload);
+        assert_eq!(config.transfer_mode, TransferMode::Netascii);
         assert_eq!(config.opt_common.timeout, Duration::from_secs(4));
+        assert_eq!(config.max_handshake_retries, 5);
+        assert!(config.strict_source);
+        assert!(config.strict_tid);
         assert!(!config.opt_local.clean_on_error);
+        assert!(!config.opt_local.sync);
+        assert!(config.auto_blksize);
+        assert_eq!(config.min_blksize, 256);
+        assert_eq!(config.mtu, Some(1400));
+        assert_eq!(config.recv_buffer, Some(1048576));
+        assert_eq!(config.send_buffer, Some(65536));
+        assert!(config.opt_common.checksum.is_some());
+        assert!(config.opt_common.sack);
+        assert_eq!(config.opt_common.rollover, Some(1));
+        assert_eq!(
+            config.opt_common.extra,
+            [("x-vendor".to_string(), "on".to_string())]
+        );
+        assert_eq!(config.overwrite, Overwrite::NoClobber);
+        assert!(!config.force);
+        assert!(config.resume);
+        assert!(config.dry_run);
+        assert!(config.no_tsize);
+        assert!(config.flatten);
+        assert!(config.mkdir);
+        assert_eq!(config.hash, Some(HashAlgorithm::Sha256));
+        assert_eq!(
+            config.expected_digest.as_deref(),
+            Some("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad")
+        );
     }
 
     #[test]
@@ -297,6 +1019,37 @@ mod tests {
         assert_eq!(config.mode, Mode::Download);
     }
 
+    #[test]
+    fn parses_stdio_path() {
+        let config =
+            ClientConfig::new(["-", "-u", "remote.bin"].iter().map(|s| s.to_string())).unwrap();
+
+        assert_eq!(config.file_path, PathBuf::from("-"));
+        assert_eq!(config.file_remote, "remote.bin");
+    }
+
+    #[test]
+    fn resolves_hostname() {
+        let config = ClientConfig::new(
+            ["test.file", "-i", "localhost", "-4"]
+                .iter()
+                .map(|s| s.to_string()),
+        )
+        .unwrap();
+
+        assert_eq!(config.remote_ip_address, Ipv4Addr::LOCALHOST);
+        assert_eq!(config.ip_family, Some(IpFamily::V4));
+        assert!(config.remote_alternatives.iter().all(|addr| addr.is_ipv4()));
+
+        let err = ClientConfig::new(
+            ["test.file", "-i", "unknown.invalid"]
+                .iter()
+                .map(|s| s.to_string()),
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("Cannot resolve unknown.invalid"));
+    }
+
     #[test]
     fn parses_file_paths() {
         let config = ClientConfig::new(["test/test.file"].iter().map(|s| s.to_string())).unwrap();
@@ -318,6 +1071,133 @@ mod tests {
         assert_eq!(config.file_path, path);
     }
 
+    #[test]
+    fn parses_several_uploads() {
+        let dir = PathBuf::from("target/test/client_config/parses_several_uploads");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("sub.cfg")).unwrap();
+        for name in ["b.cfg", "a.cfg", "c.txt"] {
+            fs::write(dir.join(name), name).unwrap();
+        }
+        let args = |paths: &[&str], mode: &str| {
+            paths
+                .iter()
+                .map(|s| s.to_string())
+                .chain([mode.to_string()])
+                .collect::<Vec<_>>()
+        };
+
+        let config = ClientConfig::new(args(&["a", "b", "c"], "-u").into_iter()).unwrap();
+        assert_eq!(config.file_path, PathBuf::from("a"));
+        assert_eq!(config.file_remote, "");
+        assert_eq!(config.extra_files, [PathBuf::from("b"), PathBuf::from("c")]);
+
+        let pattern = dir.join("*.cfg").display().to_string();
+        let config = ClientConfig::new(args(&[&pattern], "-u").into_iter()).unwrap();
+        assert_eq!(config.file_path, dir.join("a.cfg"));
+        assert_eq!(config.extra_files, [dir.join("b.cfg")]);
+
+        let config = ClientConfig::new(args(&["local", "remote"], "-u").into_iter()).unwrap();
+        assert_eq!(config.file_remote, "remote");
+        assert!(config.extra_files.is_empty());
+
+        let pattern = dir.join("*.bin").display().to_string();
+        assert!(ClientConfig::new(args(&[&pattern], "-u").into_iter()).is_err());
+        assert!(ClientConfig::new(args(&["a", "b", "c"], "-d").into_iter()).is_err());
+    }
+
+    #[test]
+    fn matches_wildcards() {
+        assert!(wildcard_match(b"*.cfg", b"a.cfg"));
+        assert!(wildcard_match(b"*", b""));
+        assert!(wildcard_match(b"file?.*", b"file1.tar.gz"));
+        assert!(!wildcard_match(b"file?.*", b"file.txt"));
+        assert!(!wildcard_match(b"*.cfg", b"a.cfg.bak"));
+    }
+
+    #[test]
+    fn parses_ipv6_scope() {
+        let args = |ip: &str| ["test.file", "-i", ip].map(|s| s.to_string()).into_iter();
+
+        let config = ClientConfig::new(args("fe80::1%3")).unwrap();
+        assert_eq!(
+            config.remote_ip_address,
+            "fe80::1".parse::<IpAddr>().unwrap()
+        );
+        assert_eq!(config.scope_id, 3);
+        assert_eq!(ClientConfig::new(args("fe80::1%lo")).unwrap().scope_id, 1);
+        assert_eq!(ClientConfig::new(args("2001:db8::1")).unwrap().scope_id, 0);
+
+        for ip in ["fe80::1", "fe80::1%nosuchif0", "127.0.0.1%1"] {
+            assert!(ClientConfig::new(args(ip)).is_err(), "{ip}");
+        }
+    }
+
+    #[test]
+    fn parses_multicast_download_only() {
+        let args = |mode: &str| ["test.file", "--multicast", mode].map(|s| s.to_string());
+        assert!(ClientConfig::new(args("-d").into_iter()).unwrap().multicast);
+        assert!(ClientConfig::new(args("-u").into_iter()).is_err());
+
+        let args = ["test.file", "--multicast", "--strict-tid"].map(|s| s.to_string());
+        assert!(ClientConfig::new(args.into_iter()).is_err());
+    }
+
+    #[test]
+    fn parses_append_download_only() {
+        let parse = |args: &[&str]| ClientConfig::new(args.iter().map(|s| s.to_string()));
+        let config = parse(&["test.file", "--no-clobber", "--append"]).unwrap();
+        assert_eq!(config.overwrite, Overwrite::Append);
+        assert!(parse(&["test.file", "--append", "-u"]).is_err());
+        assert!(parse(&["test.file", "--append", "--resume"]).is_err());
+        assert!(parse(&["test.file", "--append", "--hash", "crc32"]).is_err());
+    }
+
+    #[test]
+    fn parses_compression() {
+        let parse = |args: &[&str]| ClientConfig::new(args.iter().map(|s| s.to_string()));
+        let config = parse(&["test.file", "--compress", "GZIP"]).unwrap();
+        assert_eq!(config.compression, Some(Compression::Gzip));
+        let config = parse(&["test.file", "--compress", "deflate", "-u"]).unwrap();
+        assert_eq!(config.compression, Some(Compression::Deflate));
+        assert!(parse(&["test.file", "--compress", "zstd"]).is_err());
+        assert!(parse(&["test.file", "--compress"]).is_err());
+        assert!(parse(&["test.file", "--compress", "gzip", "--resume"]).is_err());
+        assert!(parse(&["test.file", "--opt", "compress=gzip"]).is_err());
+    }
+
+    #[test]
+    fn reads_window_manifest() {
+        let dir = Path::new("target/test/client_config_manifest");
+        fs::create_dir_all(dir).unwrap();
+        fs::write(dir.join("good.txt"), "cbf43926\n\n0000ABCD\n").unwrap();
+        fs::write(dir.join("bad.txt"), "cbf43926\nnot a crc\n").unwrap();
+        let parse = |args: &[&str]| ClientConfig::new(args.iter().map(|s| s.to_string()));
+
+        let manifest = "target/test/client_config_manifest/good.txt";
+        let config = parse(&["test.file", "--verify-manifest", manifest]).unwrap();
+        assert_eq!(config.manifest, Some(vec![0xCBF4_3926, 0xABCD]));
+
+        let err = parse(&[
+            "test.file",
+            "--verify-manifest",
+            "target/test/client_config_manifest/bad.txt",
+        ])
+        .unwrap_err();
+        assert!(err.to_string().contains("line 2"), "{err}");
+        assert!(parse(&["test.file", "--verify-manifest", manifest, "-u"]).is_err());
+    }
+
+    #[test]
+    fn rejects_managed_extra_option() {
+        for option in ["blksize=1024", "TSize=0", "rollover=0", "vendor", "=1"] {
+            let args = ["test.file", "--opt", option]
+                .map(|s| s.to_string())
+                .into_iter();
+            assert!(ClientConfig::new(args).is_err(), "{option}");
+        }
+    }
+
     #[test]
     fn converts_file_path_abs() {
         let path = convert_file_path_abs("test.file");
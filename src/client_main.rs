@@ -24,7 +24,13 @@ fn client<T: Iterator<Item = String>>(args: T) -> Result<bool, Box<dyn Error>> {
         process::exit(1)
     });
 
-    if config.mode == Mode::Upload {
+    if !config.extra_files.is_empty() {
+        log_info!(
+            "Starting TFTP Client, uploading {} files to {}",
+            config.extra_files.len() + 1,
+            SocketAddr::new(config.remote_ip_address, config.port),
+        );
+    } else if config.mode == Mode::Upload {
         log_info!(
             "Starting TFTP Client, uploading {} to {}",
             config.file_path.display(),
@@ -41,5 +47,19 @@ fn client<T: Iterator<Item = String>>(args: T) -> Result<bool, Box<dyn Error>> {
     // Catch Ctrl-C to exit cleanly by sending error msg
     signal_hook::flag::register(signal_hook::consts::SIGINT, client.get_abort_flag()).unwrap();
 
+    if config.dry_run {
+        let options = client.negotiate()?;
+        log_info!(
+            "Accepted options: blksize {}, windowsize {}, timeout {:.3}s, tsize {}",
+            options.block_size,
+            options.window_size,
+            options.timeout.as_secs_f32(),
+            options
+                .transfer_size
+                .map_or("not reported".to_string(), |size| size.to_string())
+        );
+        return Ok(true);
+    }
+
     client.run()
 }
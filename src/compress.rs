@@ -0,0 +1,1002 @@
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read};
+use std::str::FromStr;
+
+use crate::checksum::Crc32;
+use crate::options::DEFAULT_MAX_MEMORY;
+use crate::Transform;
+
+/// Input compressed in one block, each block ending on a byte boundary
+const CHUNK_SIZE: usize = 32 * 1024;
+/// Farthest distance of a match (RFC 1951)
+const WINDOW_SIZE: usize = 32 * 1024;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+/// Candidates checked for each match, trading ratio for speed
+const MAX_CHAIN: usize = 64;
+const HASH_BITS: u32 = 15;
+
+/// Header of a gzip member without name nor time, compressed on an unknown OS
+const GZIP_HEADER: [u8; 10] = [0x1F, 0x8B, 8, 0, 0, 0, 0, 0, 0, 0xFF];
+const FHCRC: u8 = 0x02;
+const FEXTRA: u8 = 0x04;
+const FNAME: u8 = 0x08;
+const FCOMMENT: u8 = 0x10;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+/// Order of the code length code lengths of dynamic blocks
+const CLEN_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+/// Compression `enum` represents the formats of the private `compress`
+/// option, the sender compressing the whole data before splitting it in
+/// blocks, and the receiver decompressing it before writing it.
+///
+/// # Example
+///
+/// ```rust
+/// use tftpd::Compression;
+///
+/// let extra = vec![("compress".to_string(), "gzip".to_string())];
+/// assert_eq!(Compression::find(&extra), Some(Compression::Gzip));
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Compression {
+    /// Deflate data in a gzip member (RFC 1952), checked by its CRC-32
+    Gzip,
+    /// Raw deflate data (RFC 1951)
+    Deflate,
+}
+
+impl Compression {
+    /// Name of the option, not modelled by [`OptionType`](crate::OptionType)
+    /// as its value is not a number.
+    pub const OPTION: &'static str = "compress";
+
+    /// Returns the name of the format, as sent in the option.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Compression::Gzip => "gzip",
+            Compression::Deflate => "deflate",
+        }
+    }
+
+    /// Returns the option requesting or accepting the format.
+    pub fn option(&self) -> (String, String) {
+        (Compression::OPTION.to_string(), self.as_str().to_string())
+    }
+
+    /// Finds the compression option among the `extra` options of a request
+    /// or OACK. Unsupported formats are ignored, as unknown options.
+    pub fn find(extra: &[(String, String)]) -> Option<Compression> {
+        extra
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(Compression::OPTION))
+            .and_then(|(_, value)| value.parse().ok())
+    }
+}
+
+impl FromStr for Compression {
+    type Err = Box<dyn Error>;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "gzip" => Ok(Compression::Gzip),
+            "deflate" => Ok(Compression::Deflate),
+            _ => Err(format!("unsupported compression {s}").into()),
+        }
+    }
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Compressor `struct` wraps a [`Read`] source to compress its data on the
+/// fly, chunk after chunk, with fixed Huffman codes or stored as is when
+/// not compressible.
+///
+/// # Example
+///
+/// ```rust
+/// use std::io::Read;
+/// use tftpd::{Compression, Compressor, Decompressor, Transform};
+///
+/// let text = "all work and no play ".repeat(100);
+/// let mut compressed = vec![];
+/// Compressor::new(text.as_bytes(), Compression::Gzip)
+///     .read_to_end(&mut compressed)
+///     .unwrap();
+/// assert!(compressed.len() < text.len() / 10);
+///
+/// let mut decompressor = Decompressor::new(Compression::Gzip);
+/// let mut decompressed = decompressor.transform(&compressed).unwrap();
+/// decompressed.extend(decompressor.finish().unwrap());
+/// assert_eq!(decompressed, text.as_bytes());
+/// ```
+pub struct Compressor<R: Read> {
+    reader: R,
+    compression: Compression,
+    /// Last input, where matches are searched
+    history: Vec<u8>,
+    crc: Crc32,
+    size: u32,
+    out: Vec<u8>,
+    pos: usize,
+    started: bool,
+    done: bool,
+}
+
+impl<R: Read> Compressor<R> {
+    /// Creates a new [`Compressor`] of the data of `reader`.
+    pub fn new(reader: R, compression: Compression) -> Compressor<R> {
+        Compressor {
+            reader,
+            compression,
+            history: vec![],
+            crc: Crc32::new(),
+            size: 0,
+            out: vec![],
+            pos: 0,
+            started: false,
+            done: false,
+        }
+    }
+
+    fn compress_chunk(&mut self) -> io::Result<()> {
+        self.out.clear();
+        self.pos = 0;
+        if !self.started && self.compression == Compression::Gzip {
+            self.out.extend(GZIP_HEADER);
+        }
+        self.started = true;
+
+        let mut chunk = vec![0; CHUNK_SIZE];
+        let mut len = 0;
+        while len < CHUNK_SIZE {
+            match self.reader.read(&mut chunk[len..]) {
+                Ok(0) => break,
+                Ok(read) => len += read,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            }
+        }
+        chunk.truncate(len);
+        // A full chunk may be followed by more data, the final block being empty then
+        let last = len < CHUNK_SIZE;
+
+        deflate_chunk(&self.history, &chunk, last, &mut self.out);
+        self.crc.update(&chunk);
+        self.size = self.size.wrapping_add(len as u32);
+        self.history.extend(chunk);
+        if self.history.len() > WINDOW_SIZE {
+            self.history.drain(..self.history.len() - WINDOW_SIZE);
+        }
+
+        if last {
+            self.done = true;
+            if self.compression == Compression::Gzip {
+                self.out.extend(self.crc.value().to_le_bytes());
+                self.out.extend(self.size.to_le_bytes());
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for Compressor<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.out.len() && !self.done {
+            self.compress_chunk()?;
+        }
+
+        let len = buf.len().min(self.out.len() - self.pos);
+        buf[..len].copy_from_slice(&self.out[self.pos..self.pos + len]);
+        self.pos += len;
+
+        Ok(len)
+    }
+}
+
+/// Decompressor `struct` is a [`Transform`] decompressing the data received,
+/// whatever the way it was split. The data is returned as soon as it is
+/// decoded, only the last 32 KiB being kept for the matches. The truncated
+/// and corrupted data are errors, as well as a chunk decompressing to more
+/// than the memory budget.
+pub struct Decompressor {
+    compression: Compression,
+    /// Received data not decoded yet
+    input: Vec<u8>,
+    /// Bits of the first byte of `input` already decoded
+    bit: usize,
+    history: History,
+    /// Largest output of a single chunk
+    max_output: usize,
+    state: State,
+}
+
+/// Position in the compressed stream
+enum State {
+    Header,
+    /// Before the header of a block
+    BlockStart,
+    /// In a stored block, with the count of bytes left
+    Stored {
+        remaining: usize,
+        last: bool,
+    },
+    /// In a compressed block, with its codes
+    Codes {
+        literals: Huffman,
+        distances: Huffman,
+        last: bool,
+    },
+    Trailer,
+    Done,
+}
+
+/// Data decoded so far, the last part of it being referenced by the matches
+struct History {
+    /// Last output, at least the last 32 KiB
+    window: Vec<u8>,
+    crc: Crc32,
+    size: u32,
+}
+
+impl History {
+    fn push(&mut self, data: &[u8], output: &mut Vec<u8>) {
+        self.trim();
+        self.window.extend_from_slice(data);
+        self.crc.update(data);
+        self.size = self.size.wrapping_add(data.len() as u32);
+        output.extend_from_slice(data);
+    }
+
+    /// Copies `len` bytes from `dist` bytes back
+    fn copy(&mut self, dist: usize, len: usize, output: &mut Vec<u8>) -> Result<(), Inflate> {
+        if dist > self.window.len() {
+            return Err(Inflate::Invalid("distance too far back"));
+        }
+        self.trim();
+        let start = self.window.len() - dist;
+        for i in start..start + len {
+            self.window.push(self.window[i]);
+        }
+        let data = &self.window[self.window.len() - len..];
+        self.crc.update(data);
+        self.size = self.size.wrapping_add(len as u32);
+        output.extend_from_slice(data);
+
+        Ok(())
+    }
+
+    /// Drops the data too far back for a match, once in a while
+    fn trim(&mut self) {
+        if self.window.len() > 2 * WINDOW_SIZE {
+            self.window.drain(..self.window.len() - WINDOW_SIZE);
+        }
+    }
+}
+
+impl Decompressor {
+    /// Creates a new [`Decompressor`] of the `compression` format, each chunk
+    /// decompressing to the default memory limit of 16 MiB at most. See
+    /// [`Decompressor::with_memory_limit()`].
+    pub fn new(compression: Compression) -> Decompressor {
+        Decompressor::with_memory_limit(compression, DEFAULT_MAX_MEMORY)
+    }
+
+    /// Creates a new [`Decompressor`] of the `compression` format, failing
+    /// when a chunk decompresses to more than `max_bytes`, e.g. with crafted
+    /// data expanding without limit.
+    pub fn with_memory_limit(compression: Compression, max_bytes: usize) -> Decompressor {
+        Decompressor {
+            compression,
+            input: vec![],
+            bit: 0,
+            history: History {
+                window: vec![],
+                crc: Crc32::new(),
+                size: 0,
+            },
+            max_output: max_bytes,
+            state: match compression {
+                Compression::Gzip => State::Header,
+                Compression::Deflate => State::BlockStart,
+            },
+        }
+    }
+
+    fn inflate(&mut self) -> Result<Vec<u8>, Box<dyn Error>> {
+        let input = std::mem::take(&mut self.input);
+        let mut reader = BitReader {
+            data: &input,
+            pos: self.bit,
+        };
+        let mut output = vec![];
+        let result = self.decode(&mut reader, &mut output);
+
+        let pos = reader.pos;
+        self.input = input;
+        self.input.drain(..pos / 8);
+        self.bit = pos % 8;
+        result?;
+
+        Ok(output)
+    }
+
+    /// Decodes the input as far as possible, appending the data to `output`
+    fn decode(
+        &mut self,
+        reader: &mut BitReader,
+        output: &mut Vec<u8>,
+    ) -> Result<(), Box<dyn Error>> {
+        loop {
+            // Decoded again from there once more data is received
+            let pos = reader.pos;
+            match self.step(reader, output) {
+                Ok(true) => {}
+                Ok(false) => return Ok(()),
+                Err(Inflate::Incomplete) => {
+                    reader.pos = pos;
+                    return Ok(());
+                }
+                Err(Inflate::Invalid(msg)) => return Err(invalid(msg)),
+            }
+            if output.len() > self.max_output {
+                return Err(format!(
+                    "Decompressed data exceeds the memory budget of {} bytes",
+                    self.max_output
+                )
+                .into());
+            }
+        }
+    }
+
+    /// Decodes the next item of the stream: a header, a symbol, or some
+    /// stored data. Returns `false` at the end of the stream.
+    fn step(&mut self, reader: &mut BitReader, output: &mut Vec<u8>) -> Result<bool, Inflate> {
+        match &mut self.state {
+            State::Header => {
+                let len = parse_gzip_header(reader.rest())?;
+                reader.pos += len * 8;
+                self.state = State::BlockStart;
+            }
+            State::BlockStart => self.state = block_header(reader)?,
+            State::Stored { remaining: 0, last } => {
+                let last = *last;
+                self.end_block(reader, last);
+            }
+            State::Stored { remaining, .. } => {
+                let rest = reader.rest();
+                if rest.is_empty() {
+                    return Err(Inflate::Incomplete);
+                }
+                let data = &rest[..rest.len().min(*remaining)];
+                *remaining -= data.len();
+                reader.pos += data.len() * 8;
+                self.history.push(data, output);
+            }
+            State::Codes {
+                literals,
+                distances,
+                last,
+            } => match literals.decode(reader)? as usize {
+                literal @ 0..=255 => self.history.push(&[literal as u8], output),
+                256 => {
+                    let last = *last;
+                    self.end_block(reader, last);
+                }
+                symbol @ 257..=285 => {
+                    let index = symbol - 257;
+                    let len =
+                        LENGTH_BASE[index] as usize + reader.bits(LENGTH_EXTRA[index])? as usize;
+                    let index = distances.decode(reader)? as usize;
+                    if index >= DIST_BASE.len() {
+                        return Err(Inflate::Invalid("distance symbol"));
+                    }
+                    let dist = DIST_BASE[index] as usize + reader.bits(DIST_EXTRA[index])? as usize;
+                    self.history.copy(dist, len, output)?;
+                }
+                _ => return Err(Inflate::Invalid("literal or length symbol")),
+            },
+            State::Trailer => {
+                let trailer = reader.rest().get(..8).ok_or(Inflate::Incomplete)?;
+                let crc = u32::from_le_bytes(trailer[..4].try_into().unwrap());
+                let size = u32::from_le_bytes(trailer[4..].try_into().unwrap());
+                if crc != self.history.crc.value() || size != self.history.size {
+                    return Err(Inflate::Invalid("gzip trailer mismatch"));
+                }
+                reader.pos += 64;
+                self.state = State::Done;
+            }
+            State::Done => {
+                if !reader.rest().is_empty() {
+                    return Err(Inflate::Invalid("data after end of stream"));
+                }
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+
+    fn end_block(&mut self, reader: &mut BitReader, last: bool) {
+        self.state = if !last {
+            State::BlockStart
+        } else {
+            // The trailer starts on the next byte
+            reader.align();
+            match self.compression {
+                Compression::Gzip => State::Trailer,
+                Compression::Deflate => State::Done,
+            }
+        };
+    }
+}
+
+impl Transform for Decompressor {
+    fn transform(&mut self, data: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+        self.input.extend_from_slice(data);
+        self.inflate()
+    }
+
+    fn finish(&mut self) -> Result<Vec<u8>, Box<dyn Error>> {
+        let output = self.inflate()?;
+        if !matches!(self.state, State::Done) {
+            return Err("Truncated compressed stream".into());
+        }
+
+        Ok(output)
+    }
+}
+
+/// Compresses a chunk, following the previous input in `history`, as
+/// complete blocks ending on a byte boundary.
+fn deflate_chunk(history: &[u8], chunk: &[u8], last: bool, out: &mut Vec<u8>) {
+    let data = [history, chunk].concat();
+    let mut writer = BitWriter::default();
+    writer.bits(last as u32, 1);
+    writer.bits(1, 2);
+
+    let mut chains = HashChains::new(&data);
+    for pos in 0..history.len() {
+        chains.insert(pos);
+    }
+
+    let mut pos = history.len();
+    while pos < data.len() {
+        let (len, dist) = chains.longest_match(pos);
+        if len >= MIN_MATCH {
+            let index = LENGTH_BASE
+                .iter()
+                .rposition(|&base| base as usize <= len)
+                .unwrap();
+            writer.fixed_code(257 + index as u16);
+            writer.bits(
+                (len - LENGTH_BASE[index] as usize) as u32,
+                LENGTH_EXTRA[index] as u32,
+            );
+            let index = DIST_BASE
+                .iter()
+                .rposition(|&base| base as usize <= dist)
+                .unwrap();
+            writer.code(index as u32, 5);
+            writer.bits(
+                (dist - DIST_BASE[index] as usize) as u32,
+                DIST_EXTRA[index] as u32,
+            );
+            for pos in pos..pos + len {
+                chains.insert(pos);
+            }
+            pos += len;
+        } else {
+            writer.fixed_code(data[pos] as u16);
+            chains.insert(pos);
+            pos += 1;
+        }
+    }
+    writer.fixed_code(256);
+
+    if writer.out.len() > chunk.len() + 5 {
+        // Not compressible, stored as is
+        writer = BitWriter::default();
+        writer.bits(last as u32, 1);
+        writer.bits(0, 2);
+        writer.align();
+        writer.out.extend((chunk.len() as u16).to_le_bytes());
+        writer.out.extend((!(chunk.len() as u16)).to_le_bytes());
+        writer.out.extend(chunk);
+    } else if !last {
+        // Empty stored block to end on a byte boundary
+        writer.bits(0, 3);
+        writer.align();
+        writer.out.extend([0, 0, 0xFF, 0xFF]);
+    }
+    writer.align();
+
+    out.extend(writer.out);
+}
+
+/// Positions of the data with the same hash of their first bytes, most recent first
+struct HashChains<'a> {
+    data: &'a [u8],
+    head: Vec<usize>,
+    prev: Vec<usize>,
+}
+
+impl HashChains<'_> {
+    fn new(data: &[u8]) -> HashChains<'_> {
+        HashChains {
+            data,
+            head: vec![usize::MAX; 1 << HASH_BITS],
+            prev: vec![usize::MAX; data.len()],
+        }
+    }
+
+    fn hash(&self, pos: usize) -> usize {
+        let value = u32::from_le_bytes([self.data[pos], self.data[pos + 1], self.data[pos + 2], 0]);
+        (value.wrapping_mul(2_654_435_761) >> (32 - HASH_BITS)) as usize
+    }
+
+    fn insert(&mut self, pos: usize) {
+        if pos + MIN_MATCH <= self.data.len() {
+            let hash = self.hash(pos);
+            self.prev[pos] = self.head[hash];
+            self.head[hash] = pos;
+        }
+    }
+
+    /// Returns the length and distance of the longest previous match of the data at `pos`
+    fn longest_match(&self, pos: usize) -> (usize, usize) {
+        if pos + MIN_MATCH > self.data.len() {
+            return (0, 0);
+        }
+        let max_len = MAX_MATCH.min(self.data.len() - pos);
+        let (mut best_len, mut best_dist) = (0, 0);
+        let mut candidate = self.head[self.hash(pos)];
+
+        for _ in 0..MAX_CHAIN {
+            if candidate == usize::MAX || pos - candidate > WINDOW_SIZE {
+                break;
+            }
+            let len = self.data[candidate..]
+                .iter()
+                .zip(&self.data[pos..pos + max_len])
+                .take_while(|(a, b)| a == b)
+                .count();
+            if len > best_len {
+                (best_len, best_dist) = (len, pos - candidate);
+                if len == max_len {
+                    break;
+                }
+            }
+            candidate = self.prev[candidate];
+        }
+
+        (best_len, best_dist)
+    }
+}
+
+/// Writes bits from the least significant one, as packed by deflate
+#[derive(Default)]
+struct BitWriter {
+    out: Vec<u8>,
+    acc: u64,
+    len: u32,
+}
+
+impl BitWriter {
+    fn bits(&mut self, value: u32, count: u32) {
+        self.acc |= (value as u64) << self.len;
+        self.len += count;
+        while self.len >= 8 {
+            self.out.push(self.acc as u8);
+            self.acc >>= 8;
+            self.len -= 8;
+        }
+    }
+
+    /// Writes a Huffman code, packed from its most significant bit
+    fn code(&mut self, code: u32, count: u32) {
+        self.bits(code.reverse_bits() >> (32 - count), count);
+    }
+
+    /// Writes a literal or length symbol with the fixed Huffman codes
+    fn fixed_code(&mut self, symbol: u16) {
+        let symbol = symbol as u32;
+        match symbol {
+            0..=143 => self.code(0x30 + symbol, 8),
+            144..=255 => self.code(0x190 + symbol - 144, 9),
+            256..=279 => self.code(symbol - 256, 7),
+            _ => self.code(0xC0 + symbol - 280, 8),
+        }
+    }
+
+    fn align(&mut self) {
+        if self.len > 0 {
+            self.bits(0, 8 - self.len);
+        }
+    }
+}
+
+/// Failure to decode compressed data
+enum Inflate {
+    /// More data is needed
+    Incomplete,
+    /// The data is corrupted
+    Invalid(&'static str),
+}
+
+fn invalid(msg: &str) -> Box<dyn Error> {
+    format!("Invalid compressed data: {msg}").into()
+}
+
+/// Reads bits from the least significant one, as packed by deflate
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn bits(&mut self, count: u8) -> Result<u32, Inflate> {
+        if self.pos + count as usize > self.data.len() * 8 {
+            return Err(Inflate::Incomplete);
+        }
+        let mut value = 0;
+        for i in 0..count {
+            let bit = (self.data[self.pos / 8] >> (self.pos % 8)) & 1;
+            value |= (bit as u32) << i;
+            self.pos += 1;
+        }
+
+        Ok(value)
+    }
+
+    fn align(&mut self) {
+        self.pos = self.pos.div_ceil(8) * 8;
+    }
+
+    /// Returns the bytes from the current one, to be read once aligned
+    fn rest(&self) -> &'a [u8] {
+        self.data.get(self.pos.div_ceil(8)..).unwrap_or_default()
+    }
+}
+
+/// Canonical Huffman code, decoded bit after bit
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Result<Huffman, Inflate> {
+        let mut counts = [0; 16];
+        for &len in lengths {
+            counts[len as usize] += 1;
+        }
+        let mut left: i32 = 1;
+        for &count in &counts[1..] {
+            left = (left << 1) - count as i32;
+            if left < 0 {
+                return Err(Inflate::Invalid("over-subscribed Huffman code"));
+            }
+        }
+
+        let mut offsets = [0; 16];
+        for len in 1..15 {
+            offsets[len + 1] = offsets[len] + counts[len] as usize;
+        }
+        let mut symbols = vec![0; offsets[15] + counts[15] as usize];
+        for (symbol, &len) in lengths.iter().enumerate() {
+            if len != 0 {
+                symbols[offsets[len as usize]] = symbol as u16;
+                offsets[len as usize] += 1;
+            }
+        }
+
+        Ok(Huffman { counts, symbols })
+    }
+
+    fn decode(&self, reader: &mut BitReader) -> Result<u16, Inflate> {
+        let (mut code, mut first, mut index) = (0, 0, 0);
+        for &count in &self.counts[1..] {
+            code |= reader.bits(1)? as i32;
+            if code - (count as i32) < first {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count as i32;
+            first = (first + count as i32) << 1;
+            code <<= 1;
+        }
+
+        Err(Inflate::Invalid("unused Huffman code"))
+    }
+}
+
+/// Reads the header of a block, and returns the state to decode its data
+fn block_header(reader: &mut BitReader) -> Result<State, Inflate> {
+    let last = reader.bits(1)? == 1;
+    match reader.bits(2)? {
+        0 => {
+            reader.align();
+            let len = reader.bits(16)?;
+            if reader.bits(16)? != !len & 0xFFFF {
+                return Err(Inflate::Invalid("stored block length"));
+            }
+            Ok(State::Stored {
+                remaining: len as usize,
+                last,
+            })
+        }
+        1 => {
+            let mut lengths = [0; 288];
+            lengths[..144].fill(8);
+            lengths[144..256].fill(9);
+            lengths[256..280].fill(7);
+            lengths[280..].fill(8);
+            Ok(State::Codes {
+                literals: Huffman::new(&lengths)?,
+                distances: Huffman::new(&[5; 30])?,
+                last,
+            })
+        }
+        2 => {
+            let (literals, distances) = dynamic_codes(reader)?;
+            Ok(State::Codes {
+                literals,
+                distances,
+                last,
+            })
+        }
+        _ => Err(Inflate::Invalid("block type")),
+    }
+}
+
+/// Reads the Huffman codes at the start of a dynamic block
+fn dynamic_codes(reader: &mut BitReader) -> Result<(Huffman, Huffman), Inflate> {
+    let literal_count = reader.bits(5)? as usize + 257;
+    let distance_count = reader.bits(5)? as usize + 1;
+    let length_count = reader.bits(4)? as usize + 4;
+
+    let mut lengths = [0; 19];
+    for &symbol in &CLEN_ORDER[..length_count] {
+        lengths[symbol] = reader.bits(3)? as u8;
+    }
+    let code_lengths = Huffman::new(&lengths)?;
+
+    let mut lengths = vec![];
+    while lengths.len() < literal_count + distance_count {
+        let (len, repeat) = match code_lengths.decode(reader)? {
+            len @ 0..=15 => (len as u8, 1),
+            16 => {
+                let prev = *lengths
+                    .last()
+                    .ok_or(Inflate::Invalid("repeated length without previous one"))?;
+                (prev, 3 + reader.bits(2)?)
+            }
+            17 => (0, 3 + reader.bits(3)?),
+            _ => (0, 11 + reader.bits(7)?),
+        };
+        lengths.extend(std::iter::repeat_n(len, repeat as usize));
+    }
+    if lengths.len() > literal_count + distance_count {
+        return Err(Inflate::Invalid("too many code lengths"));
+    }
+    if lengths[256] == 0 {
+        return Err(Inflate::Invalid("missing end of block code"));
+    }
+
+    Ok((
+        Huffman::new(&lengths[..literal_count])?,
+        Huffman::new(&lengths[literal_count..])?,
+    ))
+}
+
+/// Returns the length of the gzip header at the start of `input`
+fn parse_gzip_header(input: &[u8]) -> Result<usize, Inflate> {
+    if input.len() < GZIP_HEADER.len() {
+        return Err(Inflate::Incomplete);
+    }
+    if input[..3] != GZIP_HEADER[..3] {
+        return Err(Inflate::Invalid("gzip header"));
+    }
+    let flags = input[3];
+    let mut len = GZIP_HEADER.len();
+
+    if flags & FEXTRA != 0 {
+        let extra = input.get(len..len + 2).ok_or(Inflate::Incomplete)?;
+        len += 2 + u16::from_le_bytes([extra[0], extra[1]]) as usize;
+    }
+    for flag in [FNAME, FCOMMENT] {
+        if flags & flag != 0 {
+            // Zero terminated string
+            let end = input
+                .get(len..)
+                .and_then(|rest| rest.iter().position(|&byte| byte == 0))
+                .ok_or(Inflate::Incomplete)?;
+            len += end + 1;
+        }
+    }
+    if flags & FHCRC != 0 {
+        len += 2;
+    }
+    if input.len() < len {
+        return Err(Inflate::Incomplete);
+    }
+
+    Ok(len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Pseudo-random bytes, not compressible
+    fn noise(len: usize) -> Vec<u8> {
+        let mut state: u32 = 0x1234_5678;
+        (0..len)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                (state >> 16) as u8
+            })
+            .collect()
+    }
+
+    fn compress(data: &[u8], compression: Compression) -> Vec<u8> {
+        let mut compressed = vec![];
+        Compressor::new(data, compression)
+            .read_to_end(&mut compressed)
+            .unwrap();
+        compressed
+    }
+
+    /// Decompresses the data fed in chunks of `chunk_len` bytes
+    fn decompress(
+        data: &[u8],
+        compression: Compression,
+        chunk_len: usize,
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        let mut decompressor = Decompressor::new(compression);
+        let mut decompressed = vec![];
+        for chunk in data.chunks(chunk_len) {
+            decompressed.extend(decompressor.transform(chunk)?);
+        }
+        decompressed.extend(decompressor.finish()?);
+        Ok(decompressed)
+    }
+
+    #[test]
+    fn round_trips_data() {
+        let text = "The quick brown fox jumps over the lazy dog.\n".repeat(2000);
+        let mut mixed = noise(40_000);
+        mixed.extend(text.as_bytes());
+        mixed.extend(noise(CHUNK_SIZE));
+
+        for data in [
+            vec![],
+            b"a".to_vec(),
+            text.into_bytes(),
+            mixed,
+            noise(CHUNK_SIZE),
+        ] {
+            for compression in [Compression::Gzip, Compression::Deflate] {
+                let compressed = compress(&data, compression);
+                for chunk_len in [1, 512, 65464] {
+                    assert_eq!(
+                        decompress(&compressed, compression, chunk_len).unwrap(),
+                        data
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn compresses_repeated_data() {
+        let text = "0123456789".repeat(10_000);
+        assert!(compress(text.as_bytes(), Compression::Gzip).len() < 1000);
+        // Stored blocks add a few bytes only
+        assert!(compress(&noise(100_000), Compression::Deflate).len() < 100_100);
+    }
+
+    #[test]
+    fn decompresses_dynamic_blocks() {
+        // Python: gzip.compress((TEXT * 3).encode(), mtime=0), with a dynamic block
+        let compressed = [
+            0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x03, 0xed, 0x8e, 0xc1, 0x0d,
+            0x02, 0x31, 0x0c, 0x04, 0x5b, 0xd9, 0x0a, 0x28, 0x83, 0xf7, 0x3d, 0xd2, 0x40, 0x14,
+            0x39, 0x24, 0xc2, 0xc9, 0x46, 0xb6, 0xd1, 0xb5, 0x4f, 0xe0, 0x01, 0x12, 0x35, 0xf0,
+            0x9c, 0xd5, 0x8c, 0xb4, 0xa9, 0x09, 0xd2, 0x35, 0x1d, 0x58, 0xc6, 0x60, 0xa1, 0xa2,
+            0x3b, 0x32, 0xbc, 0x8f, 0xa5, 0x02, 0x65, 0xb9, 0x7b, 0xc8, 0x42, 0xed, 0x9b, 0xc2,
+            0xf2, 0xf4, 0x2a, 0xf6, 0x75, 0xcf, 0xd6, 0x4b, 0x43, 0x56, 0xe5, 0xf9, 0xaa, 0x8a,
+            0x76, 0x99, 0x81, 0x20, 0x6e, 0x12, 0x9b, 0xdf, 0x55, 0x35, 0x0e, 0x70, 0x47, 0x8f,
+            0xcf, 0xc4, 0xb9, 0x95, 0x0c, 0x93, 0xc1, 0x10, 0x34, 0x7a, 0x5c, 0x90, 0xfe, 0x47,
+            0x7e, 0x8e, 0x3c, 0x01, 0x45, 0x09, 0x31, 0x84, 0x9b, 0x01, 0x00, 0x00,
+        ];
+        let text = "The TFTP protocol is a simple lockstep file transfer protocol which allows \
+            a client to get a file from or put a file onto a remote host. "
+            .repeat(3);
+        assert_eq!(
+            decompress(&compressed, Compression::Gzip, 7).unwrap(),
+            text.as_bytes()
+        );
+    }
+
+    #[test]
+    fn rejects_corrupted_data() {
+        let text = "corrupted ".repeat(1000);
+        let compressed = compress(text.as_bytes(), Compression::Gzip);
+
+        let truncated = &compressed[..compressed.len() - 4];
+        assert!(decompress(truncated, Compression::Gzip, 512).is_err());
+
+        let mut altered = compressed.clone();
+        let len = altered.len();
+        altered[len - 8] ^= 1;
+        assert!(decompress(&altered, Compression::Gzip, 512).is_err());
+
+        let mut trailing = compressed;
+        trailing.push(0);
+        assert!(decompress(&trailing, Compression::Gzip, 512).is_err());
+
+        assert!(decompress(b"not gzip data", Compression::Gzip, 512).is_err());
+    }
+
+    #[test]
+    fn limits_output_of_each_chunk() {
+        let compressed = compress(&vec![0; 1 << 20], Compression::Deflate);
+
+        // Returned as decoded, only the end of the data being kept
+        let mut decompressor = Decompressor::with_memory_limit(Compression::Deflate, 64 * 1024);
+        let mut len = 0;
+        for chunk in compressed.chunks(64) {
+            len += decompressor.transform(chunk).unwrap().len();
+            assert!(decompressor.history.window.len() <= 2 * WINDOW_SIZE + MAX_MATCH);
+        }
+        len += decompressor.finish().unwrap().len();
+        assert_eq!(len, 1 << 20);
+
+        let mut decompressor = Decompressor::with_memory_limit(Compression::Deflate, 64 * 1024);
+        let err = decompressor.transform(&compressed).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Decompressed data exceeds the memory budget of 65536 bytes"
+        );
+    }
+
+    #[test]
+    fn finds_compression_option() {
+        let extra = |value: &str| vec![("COMPRESS".to_string(), value.to_string())];
+        assert_eq!(Compression::find(&extra("gzip")), Some(Compression::Gzip));
+        assert_eq!(
+            Compression::find(&extra("Deflate")),
+            Some(Compression::Deflate)
+        );
+        assert_eq!(Compression::find(&extra("zstd")), None);
+        assert_eq!(Compression::find(&[]), None);
+        assert_eq!(
+            Compression::Gzip.option(),
+            ("compress".to_string(), "gzip".to_string())
+        );
+    }
+}
@@ -1,10 +1,13 @@
 use std::error::Error;
 use std::net::{IpAddr, Ipv4Addr};
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 use std::{env, process};
 
+use crate::config_file;
 use crate::log::*;
-use crate::options::{OptionsPrivate, Rollover};
+use crate::options::{OptionsPrivate, Rollover, TrailingData, DEFAULT_MAX_RETRIES};
+use crate::AccessList;
 
 #[cfg(feature = "debug_drop")]
 use crate::drop::drop_set;
@@ -41,6 +44,16 @@ pub struct Config {
     pub read_only: bool,
     /// Overwrite existing files. (default: false)
     pub overwrite: bool,
+    /// Jail the server into its directory after binding, Unix only. (default: false)
+    pub chroot: bool,
+    /// User to run as after binding, Unix only. (default: unchanged)
+    pub user: Option<String>,
+    /// Group to run as after binding, Unix only. (default: primary group of user)
+    pub group: Option<String>,
+    /// Maximum count of simultaneous transfers, further requests are refused. (default: unlimited)
+    pub max_workers: Option<usize>,
+    /// Client addresses to answer or to ignore. (default: all answered)
+    pub access: AccessList,
     /// Local options for server
     pub opt_local: OptionsPrivate,
 }
@@ -56,6 +69,11 @@ impl Default for Config {
             single_port: Default::default(),
             read_only: Default::default(),
             overwrite: Default::default(),
+            chroot: Default::default(),
+            user: Default::default(),
+            group: Default::default(),
+            max_workers: Default::default(),
+            access: Default::default(),
             opt_local: Default::default(),
         }
     }
@@ -81,6 +99,9 @@ pub fn parse_local_args<T: Iterator<Item = String>>(
         "--keep-on-error" => {
             opt_local.clean_on_error = false;
         }
+        "--no-sync" => {
+            opt_local.sync = false;
+        }
         "-m" | "--maxretries" => {
             if let Some(retries_str) = args.next() {
                 opt_local.max_retries = retries_str.parse::<usize>()?;
@@ -101,18 +122,86 @@ pub fn parse_local_args<T: Iterator<Item = String>>(
                 return Err("Rollover policy value missing: use n, 0, 1, x".into());
             }
         }
+        "--trailing-data" => {
+            if let Some(arg_str) = args.next() {
+                opt_local.trailing_data = match arg_str.as_str() {
+                    "ignore" => TrailingData::Ignore,
+                    "abort" => TrailingData::Abort,
+                    _ => return Err("Invalid trailing data policy: use ignore, abort".into()),
+                }
+            } else {
+                return Err("Trailing data policy missing: use ignore, abort".into());
+            }
+        }
+        "--adaptive-window" => {
+            opt_local.adaptive_window = true;
+        }
+        "--max-memory" => {
+            if let Some(max_str) = args.next() {
+                let max_memory = max_str.parse::<usize>()?;
+                if max_memory == 0 {
+                    return Err("Max memory should be at least 1 byte".into());
+                }
+                opt_local.max_memory = Some(max_memory);
+            } else {
+                return Err("Missing max memory after flag".into());
+            }
+        }
+        "--max-rate" => {
+            if let Some(rate_str) = args.next() {
+                let max_rate = rate_str.parse::<u64>()?;
+                if max_rate == 0 {
+                    return Err("Max rate should be at least 1 byte per second".into());
+                }
+                opt_local.max_rate = Some(max_rate);
+            } else {
+                return Err("Missing max rate after flag".into());
+            }
+        }
+        "--pad-final-block" => {
+            opt_local.pad_final_block = true;
+        }
+        "--ack-per-block" => {
+            opt_local.ack_per_block = true;
+        }
+        "--deadline" => {
+            if let Some(deadline_str) = args.next() {
+                opt_local.deadline = Some(
+                    Duration::try_from_secs_f64(deadline_str.parse::<f64>()?)
+                        .ok()
+                        .filter(|deadline| !deadline.is_zero())
+                        .ok_or("Deadline should be a positive number of seconds")?,
+                );
+            } else {
+                return Err("Missing deadline after flag".into());
+            }
+        }
         _ => return Ok(false),
     }
     Ok(true)
 }
 
 pub fn print_opt_local_help() {
-    println!("  -m, --maxretries <cnt>\t\tSets the max retries count (default: 6)");
+    println!("  -m, --maxretries <cnt>\t\tSets the max retries count per block (default: {DEFAULT_MAX_RETRIES})");
     println!("  -R, --rollover <policy>\t\tsets the rollover policy: 0, 1, n (forbidden), x (don't care) (default: 0)");
+    println!(
+        "  --trailing-data <policy>\t\tdata after the final block: ignore, abort (default: abort)"
+    );
+    println!("  --adaptive-window\t\t\tshrink the sent window on losses, up to the windowsize");
+    println!(
+        "  --max-memory <BYTES>\t\t\tclamp windowsize x blocksize to this budget (default: unlimited)"
+    );
+    println!(
+        "  --pad-final-block\t\t\tpad the final block sent with zeros, then send an empty block"
+    );
+    println!("  --ack-per-block\t\t\tacknowledge each block received instead of each window");
+    println!("  --deadline <seconds>\t\t\tabort each transfer lasting longer (default: unlimited, can be float)");
+    println!("  --max-rate <BYTES>\t\t\tcap the bytes sent per second by each transfer (default: unlimited)");
     println!(
         "  --duplicate-packets <NUM>\t\tDuplicate all packets sent from the server (default: 0)"
     );
     println!("  --keep-on-error\t\t\tPrevent daemon from deleting files after receiving errors");
+    println!("  --no-sync\t\t\t\tDo not wait for received files to reach the disk");
 }
 
 fn print_version_exit() {
@@ -127,7 +216,10 @@ fn print_version_exit() {
 impl Config {
     /// Creates a new configuration by parsing the supplied arguments. It is
     /// intended for use with [`env::args()`].
-    pub fn new<T: Iterator<Item = String>>(mut args: T) -> Result<Config, Box<dyn Error>> {
+    /// The arguments of a `--config` file are parsed first, see
+    /// [`Config::from_file()`].
+    pub fn new<T: Iterator<Item = String>>(args: T) -> Result<Config, Box<dyn Error>> {
+        let mut args = config_file::expand_args(args, 1)?;
         let mut config = Config::default();
         let mut verbosity: isize = 1;
 
@@ -199,6 +291,13 @@ impl Config {
                     println!("  -s, --single-port\t\t\tUse a single port for both sending and receiving (default: false)");
                     println!("  -r, --read-only\t\t\tRefuse all write requests, making the server read-only (default: false)");
                     println!("  --overwrite\t\t\t\tOverwrite existing files (default: false)");
+                    println!("  --max-workers <NUM>\t\t\tRefuse requests beyond this count of simultaneous transfers (default: unlimited)");
+                    println!("  --allow <CIDR>\t\t\t\tOnly answer clients in this range, can be repeated (default: all)");
+                    println!("  --deny <CIDR>\t\t\t\tIgnore clients in this range, can be repeated (default: none)");
+                    println!("  --config <FILE>\t\t\tRead default options from a TOML file, overridden by flags");
+                    println!("  --chroot\t\t\t\tJail the server into the directory after binding (Unix only)");
+                    println!("  --user <USER>\t\t\t\tRun as this user after binding (Unix only)");
+                    println!("  --group <GROUP>\t\t\tRun as this group after binding (default: primary group of user)");
                     print_opt_local_help();
                     println!(
                         "  -v, --verbose\t\t\t\tIncrease log verbosity (can be repeated, e.g. -vv)"
@@ -211,6 +310,48 @@ impl Config {
                 "--overwrite" => {
                     config.overwrite = true;
                 }
+                "--max-workers" => {
+                    if let Some(max_str) = args.next() {
+                        let max_workers = max_str.parse::<usize>()?;
+                        if max_workers == 0 {
+                            return Err("Max workers should be at least 1".into());
+                        }
+                        config.max_workers = Some(max_workers);
+                    } else {
+                        return Err("Missing max workers after flag".into());
+                    }
+                }
+                "--allow" => {
+                    if let Some(cidr_str) = args.next() {
+                        config.access.allow.push(cidr_str.parse()?);
+                    } else {
+                        return Err("Missing address range after flag".into());
+                    }
+                }
+                "--deny" => {
+                    if let Some(cidr_str) = args.next() {
+                        config.access.deny.push(cidr_str.parse()?);
+                    } else {
+                        return Err("Missing address range after flag".into());
+                    }
+                }
+                "--chroot" => {
+                    config.chroot = true;
+                }
+                "--user" => {
+                    if let Some(user_str) = args.next() {
+                        config.user = Some(user_str);
+                    } else {
+                        return Err("Missing user after flag".into());
+                    }
+                }
+                "--group" => {
+                    if let Some(group_str) = args.next() {
+                        config.group = Some(group_str);
+                    } else {
+                        return Err("Missing group after flag".into());
+                    }
+                }
                 "-q" | "--quiet" => verbosity -= 1,
                 "-v" | "--verbose" => verbosity += 1,
                 "-V" | "--version" => print_version_exit(),
@@ -235,6 +376,13 @@ impl Config {
 
         Ok(config)
     }
+
+    /// Creates a new configuration from a TOML file, whose keys are the long
+    /// flag names of [`Config::new()`], e.g. `port = 6969` or `read_only = true`.
+    pub fn from_file(path: &Path) -> Result<Config, Box<dyn Error>> {
+        let args = config_file::file_args(path)?;
+        Config::new(std::iter::once(String::new()).chain(args))
+    }
 }
 
 #[cfg(test)]
@@ -243,6 +391,26 @@ mod tests {
 
     use super::*;
 
+    #[test]
+    fn parses_config_file() {
+        let path = Path::new("target/test/server_config.toml");
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(
+            path,
+            "ip_address = \"0.0.0.0\"\nport = 6969\nread-only = true\nallow = [\"10.0.0.0/8\"]\n",
+        )
+        .unwrap();
+
+        let config = Config::from_file(path).unwrap();
+        assert_eq!(config.ip_address, Ipv4Addr::UNSPECIFIED);
+        assert_eq!(config.port, 6969);
+        assert!(config.read_only);
+        assert_eq!(config.access.allow.len(), 1);
+
+        std::fs::write(path, "prot = 6969\n").unwrap();
+        assert!(Config::from_file(path).is_err());
+    }
+
     #[test]
     fn parses_full_config() {
         let config = Config::new(
@@ -261,6 +429,31 @@ mod tests {
                 "-s",
                 "-r",
                 "--keep-on-error",
+                "--no-sync",
+                "--chroot",
+                "--trailing-data",
+                "ignore",
+                "--user",
+                "nobody",
+                "--group",
+                "nogroup",
+                "--max-workers",
+                "8",
+                "--allow",
+                "10.0.0.0/8",
+                "--allow",
+                "fd00::/8",
+                "--deny",
+                "10.0.0.1",
+                "--max-memory",
+                "4096",
+                "--adaptive-window",
+                "--max-rate",
+                "1000000",
+                "--pad-final-block",
+                "--ack-per-block",
+                "--deadline",
+                "90.5",
             ]
             .iter()
             .map(|s| s.to_string()),
@@ -273,8 +466,26 @@ mod tests {
         assert_eq!(config.receive_directory, PathBuf::from("/"));
         assert_eq!(config.send_directory, PathBuf::from("/"));
         assert!(!config.opt_local.clean_on_error);
+        assert!(!config.opt_local.sync);
         assert!(config.single_port);
         assert!(config.read_only);
+        assert!(config.chroot);
+        assert_eq!(config.opt_local.trailing_data, TrailingData::Ignore);
+        assert_eq!(config.user.as_deref(), Some("nobody"));
+        assert_eq!(config.group.as_deref(), Some("nogroup"));
+        assert_eq!(config.max_workers, Some(8));
+        assert_eq!(config.access.allow.len(), 2);
+        assert!(config.access.permits("10.1.2.3".parse().unwrap()));
+        assert!(!config.access.permits("10.0.0.1".parse().unwrap()));
+        assert_eq!(config.opt_local.max_memory, Some(4096));
+        assert!(config.opt_local.adaptive_window);
+        assert_eq!(config.opt_local.max_rate, Some(1_000_000));
+        assert!(config.opt_local.pad_final_block);
+        assert!(config.opt_local.ack_per_block);
+        assert_eq!(
+            config.opt_local.deadline,
+            Some(Duration::from_secs_f64(90.5))
+        );
     }
 
     #[test]
@@ -383,6 +594,15 @@ mod tests {
         .is_err());
     }
 
+    #[test]
+    fn returns_error_on_invalid_deadline() {
+        for deadline in ["0", "-1", "soon"] {
+            assert!(
+                Config::new(["/", "--deadline", deadline].iter().map(|s| s.to_string())).is_err()
+            );
+        }
+    }
+
     #[test]
     fn initializes_duplicate_packets_as_zero() {
         let config = Config::new(["/"].iter().map(|s| s.to_string())).unwrap();
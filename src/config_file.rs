@@ -0,0 +1,246 @@
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+/// Flag giving the path of a configuration file on the command line
+pub const CONFIG_FLAG: &str = "--config";
+
+/// Value `enum` represents the values supported in configuration files
+#[derive(Debug, PartialEq)]
+enum Value {
+    Text(String),
+    Bool(bool),
+    List(Vec<Value>),
+}
+
+/// Reads the configuration file at `path` and converts it to the equivalent
+/// command line arguments.
+///
+/// The file is a flat TOML document of `key = value` lines, where the keys are
+/// the long flag names, with `-` or `_` as separator. Booleans enable the
+/// flags without value, arrays repeat the flag for each item, and the special
+/// `file` key gives the file paths of the client. Unknown keys are reported
+/// as invalid flags by the parsers.
+///
+/// ```toml
+/// # Client defaults
+/// ip_address = "192.168.1.10"
+/// blocksize = 1428
+/// windowsize = 16
+/// receive_directory = "/srv/firmware"
+/// sack = true
+/// ```
+pub fn file_args(path: &Path) -> Result<Vec<String>, Box<dyn Error>> {
+    let text = fs::read_to_string(path)
+        .map_err(|err| format!("Cannot read config file {}: {err}", path.display()))?;
+
+    parse(&text).map_err(|err| format!("{}: {err}", path.display()).into())
+}
+
+/// Returns `args` with the arguments from the file of the `--config` flag
+/// inserted after the `skip` first ones, so that the flags of the command
+/// line override the values of the file.
+pub fn expand_args<T: Iterator<Item = String>>(
+    args: T,
+    skip: usize,
+) -> Result<std::vec::IntoIter<String>, Box<dyn Error>> {
+    let mut args: Vec<String> = args.collect();
+    // Arguments after "--" are file paths
+    let end = args
+        .iter()
+        .position(|arg| arg == "--")
+        .unwrap_or(args.len());
+
+    if let Some(pos) = args[..end].iter().position(|arg| arg == CONFIG_FLAG) {
+        let path = args
+            .get(pos + 1)
+            .filter(|_| pos + 1 < end)
+            .ok_or("Missing config file after flag")?
+            .clone();
+        args.drain(pos..pos + 2);
+        let file_args = file_args(Path::new(&path))?;
+        let skip = skip.min(args.len());
+        args.splice(skip..skip, file_args);
+    }
+
+    Ok(args.into_iter())
+}
+
+fn parse(text: &str) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut args = vec![];
+    let mut keys: Vec<String> = vec![];
+
+    for (line_num, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let err = |msg: &str| format!("line {}: {msg}", line_num + 1);
+        if line.starts_with('[') {
+            return Err(err("tables are not supported").into());
+        }
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| err("expected key = value"))?;
+        let key = key.trim().replace('_', "-");
+        if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+            return Err(err("invalid key").into());
+        }
+        if keys.contains(&key) {
+            return Err(err(&format!("duplicate key {key}")).into());
+        }
+
+        let (value, rest) = parse_value(value.trim()).map_err(|msg| err(&msg))?;
+        let rest = rest.trim();
+        if !rest.is_empty() && !rest.starts_with('#') {
+            return Err(err(&format!("unexpected {rest}")).into());
+        }
+
+        let values = match value {
+            Value::List(values) => values,
+            value => vec![value],
+        };
+        for value in values {
+            match value {
+                Value::Text(text) if key == "file" => args.push(text),
+                Value::Text(text) => args.extend([format!("--{key}"), text]),
+                Value::Bool(true) => args.push(format!("--{key}")),
+                Value::Bool(false) => {}
+                Value::List(_) => return Err(err("nested arrays are not supported").into()),
+            }
+        }
+        keys.push(key);
+    }
+
+    Ok(args)
+}
+
+/// Parses the value at the start of `text`, returning it with the remaining text
+fn parse_value(text: &str) -> Result<(Value, &str), String> {
+    if let Some(text) = text.strip_prefix('"') {
+        let mut value = String::new();
+        let mut chars = text.char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '"' => return Ok((Value::Text(value), &text[i + 1..])),
+                '\\' => match chars.next() {
+                    Some((_, '"')) => value.push('"'),
+                    Some((_, '\\')) => value.push('\\'),
+                    Some((_, 'n')) => value.push('\n'),
+                    Some((_, 't')) => value.push('\t'),
+                    _ => return Err("invalid escape sequence".into()),
+                },
+                c => value.push(c),
+            }
+        }
+        Err("unterminated string".into())
+    } else if let Some(text) = text.strip_prefix('\'') {
+        let (value, rest) = text.split_once('\'').ok_or("unterminated string")?;
+        Ok((Value::Text(value.to_string()), rest))
+    } else if let Some(mut text) = text.strip_prefix('[') {
+        let mut values = vec![];
+        loop {
+            text = text.trim_start();
+            if let Some(rest) = text.strip_prefix(']') {
+                return Ok((Value::List(values), rest));
+            }
+            let (value, rest) = parse_value(text)?;
+            values.push(value);
+            let rest = rest.trim_start();
+            text = match rest.strip_prefix(',') {
+                Some(rest) => rest,
+                None if rest.starts_with(']') => rest,
+                None => return Err("expected , or ] in array".into()),
+            };
+        }
+    } else {
+        // Bare numbers and booleans, checked by the flag parsers
+        let end = text
+            .find(|c: char| c.is_whitespace() || c == ',' || c == ']' || c == '#')
+            .unwrap_or(text.len());
+        let (value, rest) = text.split_at(end);
+        let value = match value {
+            "" => return Err("missing value".into()),
+            "true" => Value::Bool(true),
+            "false" => Value::Bool(false),
+            other => match other.replace('_', "") {
+                number if number.parse::<f64>().is_ok() => Value::Text(number),
+                _ => return Err(format!("invalid value {other}")),
+            },
+        };
+        Ok((value, rest))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_file_to_args() {
+        let text = r#"
+            # Defaults
+            ip_address = "192.168.1.10"  # remote
+            blocksize = 1_428
+            sack = true
+            strict-source = false
+            allow = ['10.0.0.0/8', "fd00::/8"]
+            file = "firmware.bin"
+        "#;
+
+        assert_eq!(
+            parse(text).unwrap(),
+            [
+                "--ip-address",
+                "192.168.1.10",
+                "--blocksize",
+                "1428",
+                "--sack",
+                "--allow",
+                "10.0.0.0/8",
+                "--allow",
+                "fd00::/8",
+                "firmware.bin",
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_file() {
+        assert!(parse("port").is_err());
+        assert!(parse("port = 69\nport = 70").is_err());
+        assert!(parse("[client]").is_err());
+        assert!(parse("ip_address = \"127.0.0.1").is_err());
+        assert!(parse("blocksize = 1428 512").is_err());
+        assert!(parse("blocksize = big").is_err());
+        assert!(parse("allow = [[\"10.0.0.0/8\"]]").is_err());
+    }
+
+    #[test]
+    fn expands_config_flag_before_command_line() {
+        let path = Path::new("target/test/config_file.toml");
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, "port = 6969\n").unwrap();
+
+        let args = [
+            "tftpd",
+            "-p",
+            "69",
+            "--config",
+            "target/test/config_file.toml",
+        ];
+        let args: Vec<String> = expand_args(args.map(|s| s.to_string()).into_iter(), 1)
+            .unwrap()
+            .collect();
+        assert_eq!(args, ["tftpd", "--port", "6969", "-p", "69"]);
+
+        let args = ["file", "--", "--config", "x"];
+        let args: Vec<String> = expand_args(args.map(|s| s.to_string()).into_iter(), 0)
+            .unwrap()
+            .collect();
+        assert_eq!(args, ["file", "--", "--config", "x"]);
+
+        assert!(expand_args(["--config".to_string()].into_iter(), 0).is_err());
+    }
+}
@@ -8,6 +8,7 @@ use std::error::Error;
 /// use tftpd::Convert;
 ///
 /// assert_eq!(Convert::to_u16(&[0x01, 0x02]).unwrap(), 0x0102);
+/// assert_eq!(Convert::from_u16(0x0102), [0x01, 0x02]);
 ///
 /// let (result, index) = Convert::to_string(b"hello world\0", 0).unwrap();
 /// assert_eq!(result, "hello world");
@@ -16,15 +17,22 @@ use std::error::Error;
 pub struct Convert;
 
 impl Convert {
-    /// Converts a [`u8`] slice to a [`u16`].
+    /// Converts a big-endian [`u8`] slice to a [`u16`], as used on the wire for
+    /// opcodes, block numbers and error codes.
     pub fn to_u16(buf: &[u8]) -> Result<u16, &'static str> {
         if buf.len() < 2 {
             Err("Error when converting to u16")
         } else {
-            Ok(((buf[0] as u16) << 8) + buf[1] as u16)
+            Ok(u16::from_be_bytes([buf[0], buf[1]]))
         }
     }
 
+    /// Converts a [`u16`] to a big-endian [`u8`] array, as used on the wire for
+    /// opcodes, block numbers and error codes.
+    pub const fn from_u16(val: u16) -> [u8; 2] {
+        val.to_be_bytes()
+    }
+
     /// Converts a zero-terminated [`u8`] slice to a [`String`], and returns the
     /// size of the [`String`]. Useful for TFTP packet conversions.
     pub fn to_string(buf: &[u8], start: usize) -> Result<(String, usize), Box<dyn Error>> {
@@ -49,6 +57,22 @@ mod tests {
         assert_eq!(Convert::to_u16(&[0xfe, 0xdc, 0xba]).unwrap(), 0xfedc);
     }
 
+    #[test]
+    fn converts_from_u16() {
+        assert_eq!(Convert::from_u16(0x0102), [0x01, 0x02]);
+        assert_eq!(Convert::from_u16(0x0002), [0x00, 0x02]);
+        assert_eq!(Convert::from_u16(0xfedc), [0xfe, 0xdc]);
+    }
+
+    #[test]
+    fn converts_u16_both_ways() {
+        for val in [0x0000, 0x0001, 0x0100, 0x0102, 0x7fff, 0x8000, 0xffff] {
+            assert_eq!(Convert::to_u16(&Convert::from_u16(val)).unwrap(), val);
+        }
+        // Would fail with a native-endian conversion on little-endian targets
+        assert_ne!(Convert::from_u16(0x0102), 0x0102u16.to_le_bytes());
+    }
+
     #[test]
     fn returns_error_on_short_array() {
         assert!(Convert::to_u16(&[0x01]).is_err());
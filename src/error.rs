@@ -0,0 +1,221 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+use crate::ErrorCode;
+
+/// TftpError `enum` represents the reasons a transfer can fail.
+///
+/// Each error code defined by the RFCs gets its own variant holding the
+/// message of the peer, so that callers can react to them specifically.
+///
+/// # Example
+///
+/// ```rust
+/// use tftpd::{ErrorCode, TftpError};
+///
+/// let error = TftpError::from_packet(ErrorCode::FileNotFound, "no such file".to_string());
+/// assert_eq!(error, TftpError::FileNotFound("no such file".to_string()));
+/// assert_eq!(error.to_string(), "File Not Found (1): no such file");
+/// ```
+#[derive(Debug)]
+pub enum TftpError {
+    /// Peer did not find the file (error code 1)
+    FileNotFound(String),
+    /// Peer refused to access the file (error code 2)
+    AccessViolation(String),
+    /// Peer ran out of space (error code 3)
+    DiskFull(String),
+    /// Peer reported an illegal TFTP operation (error code 4)
+    IllegalOperation(String),
+    /// Peer does not know the transfer ID (error code 5)
+    UnknownId(String),
+    /// Peer already has the file (error code 6)
+    FileExists(String),
+    /// Peer reported an unknown user (error code 7)
+    NoSuchUser(String),
+    /// Peer refused the requested options (error code 8)
+    RefusedOption(String),
+    /// Peer reported an undefined error (error code 0), or a code unknown
+    /// to the RFCs
+    Remote {
+        /// Error code sent by the peer
+        code: ErrorCode,
+        /// Error message sent by the peer
+        msg: String,
+    },
+    /// Transfer failed locally, keeping the original error when it is an
+    /// [`io::Error`] and its message otherwise
+    Transfer(Box<dyn Error + Send + Sync>),
+}
+
+impl TftpError {
+    /// Converts an error packet received from the peer to a [`TftpError`].
+    pub fn from_packet(code: ErrorCode, msg: String) -> TftpError {
+        match code {
+            ErrorCode::FileNotFound => TftpError::FileNotFound(msg),
+            ErrorCode::AccessViolation => TftpError::AccessViolation(msg),
+            ErrorCode::DiskFull => TftpError::DiskFull(msg),
+            ErrorCode::IllegalOperation => TftpError::IllegalOperation(msg),
+            ErrorCode::UnknownId => TftpError::UnknownId(msg),
+            ErrorCode::FileExists => TftpError::FileExists(msg),
+            ErrorCode::NoSuchUser => TftpError::NoSuchUser(msg),
+            ErrorCode::RefusedOption => TftpError::RefusedOption(msg),
+            code => TftpError::Remote { code, msg },
+        }
+    }
+
+    /// Returns the error code and message sent by the peer, or `None` for a
+    /// local failure.
+    pub fn remote(&self) -> Option<(ErrorCode, &str)> {
+        match self {
+            TftpError::FileNotFound(msg) => Some((ErrorCode::FileNotFound, msg)),
+            TftpError::AccessViolation(msg) => Some((ErrorCode::AccessViolation, msg)),
+            TftpError::DiskFull(msg) => Some((ErrorCode::DiskFull, msg)),
+            TftpError::IllegalOperation(msg) => Some((ErrorCode::IllegalOperation, msg)),
+            TftpError::UnknownId(msg) => Some((ErrorCode::UnknownId, msg)),
+            TftpError::FileExists(msg) => Some((ErrorCode::FileExists, msg)),
+            TftpError::NoSuchUser(msg) => Some((ErrorCode::NoSuchUser, msg)),
+            TftpError::RefusedOption(msg) => Some((ErrorCode::RefusedOption, msg)),
+            TftpError::Remote { code, msg } => Some((*code, msg)),
+            TftpError::Transfer(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for TftpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let TftpError::Transfer(err) = self {
+            return write!(f, "{err}");
+        }
+        if let Some((code, msg)) = self.remote() {
+            write!(f, "{code} ({}): {msg}", u16::from(code))?;
+        }
+        match self {
+            TftpError::IllegalOperation(_) => write!(f, " (check the requested options and mode)"),
+            TftpError::NoSuchUser(_) => write!(f, " (check the user expected by the server)"),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Error for TftpError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            TftpError::Transfer(err) => err.source(),
+            _ => None,
+        }
+    }
+}
+
+impl PartialEq for TftpError {
+    /// Compares the variants and their content, local errors by their message.
+    fn eq(&self, other: &TftpError) -> bool {
+        match (self, other) {
+            (TftpError::Transfer(a), TftpError::Transfer(b)) => a.to_string() == b.to_string(),
+            _ => self.remote().is_some() && self.remote() == other.remote(),
+        }
+    }
+}
+
+impl From<Box<dyn Error>> for TftpError {
+    /// Keeps a [`TftpError`] or an [`io::Error`] as is, and wraps any other
+    /// error message as a local one.
+    fn from(err: Box<dyn Error>) -> TftpError {
+        let err = match err.downcast::<TftpError>() {
+            Ok(err) => return *err,
+            Err(err) => err,
+        };
+        match err.downcast::<io::Error>() {
+            Ok(err) => TftpError::Transfer(err),
+            Err(err) => TftpError::Transfer(err.to_string().into()),
+        }
+    }
+}
+
+impl From<io::Error> for TftpError {
+    /// Keeps the [`io::Error`] as the local failure of the transfer.
+    fn from(err: io::Error) -> TftpError {
+        TftpError::Transfer(Box::new(err))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_error_codes() {
+        assert_eq!(
+            TftpError::from_packet(ErrorCode::IllegalOperation, "bad".to_string()),
+            TftpError::IllegalOperation("bad".to_string())
+        );
+        assert_eq!(
+            TftpError::from_packet(ErrorCode::NoSuchUser, "who".to_string()),
+            TftpError::NoSuchUser("who".to_string())
+        );
+        assert_eq!(
+            TftpError::from_packet(ErrorCode::DiskFull, "full".to_string()),
+            TftpError::DiskFull("full".to_string())
+        );
+        assert_eq!(
+            TftpError::from_packet(ErrorCode::Other(42), "odd".to_string()),
+            TftpError::Remote {
+                code: ErrorCode::Other(42),
+                msg: "odd".to_string()
+            }
+        );
+        assert_ne!(
+            TftpError::FileExists("same".to_string()),
+            TftpError::AccessViolation("same".to_string())
+        );
+    }
+
+    #[test]
+    fn displays_code_and_message() {
+        let error = TftpError::from_packet(
+            ErrorCode::FileNotFound,
+            "no such file bootx64.efi".to_string(),
+        );
+        assert_eq!(
+            error.to_string(),
+            "File Not Found (1): no such file bootx64.efi"
+        );
+        assert_eq!(
+            error.remote(),
+            Some((ErrorCode::FileNotFound, "no such file bootx64.efi"))
+        );
+        assert_eq!(
+            TftpError::from_packet(ErrorCode::NotDefined, "aborted".to_string()).to_string(),
+            "Not Defined (0): aborted"
+        );
+        assert_eq!(TftpError::Transfer("timed out".into()).remote(), None);
+    }
+
+    #[test]
+    fn keeps_variant_through_boxing() {
+        let boxed: Box<dyn Error> = TftpError::NoSuchUser("who".to_string()).into();
+        assert_eq!(
+            TftpError::from(boxed),
+            TftpError::NoSuchUser("who".to_string())
+        );
+
+        let boxed: Box<dyn Error> = "timed out".into();
+        assert_eq!(
+            TftpError::from(boxed),
+            TftpError::Transfer("timed out".into())
+        );
+    }
+
+    #[test]
+    fn keeps_io_error_kind() {
+        let boxed: Box<dyn Error> = io::Error::from(io::ErrorKind::PermissionDenied).into();
+        let TftpError::Transfer(err) = TftpError::from(boxed) else {
+            panic!("not a local error");
+        };
+        assert_eq!(
+            err.downcast_ref::<io::Error>().map(io::Error::kind),
+            Some(io::ErrorKind::PermissionDenied)
+        );
+    }
+}
@@ -15,41 +15,105 @@
 //! Since TFTP servers do not offer any type of login or access control mechanisms, this server only allows
 //! transfer and receiving inside a chosen folder, and disallows external file access.
 
+mod access;
+#[cfg(feature = "tokio")]
+mod async_client;
 #[cfg(feature = "client")]
 mod client;
 
+mod checksum;
 #[cfg(feature = "client")]
 mod client_config;
+mod compress;
 mod config;
+mod config_file;
 mod convert;
+mod error;
 mod log;
+#[cfg(unix)]
+mod mmap;
+mod netascii;
 mod options;
 mod packet;
+#[cfg(unix)]
+mod privilege;
 mod server;
 mod socket;
+mod stats;
+#[cfg(feature = "trace")]
+mod trace;
+mod transform;
 mod window;
 mod worker;
 
 #[cfg(feature = "debug_drop")]
 mod drop;
 
+pub use access::AccessList;
+pub use access::Cidr;
+#[cfg(feature = "tokio")]
+pub use async_client::AsyncClient;
+pub use checksum::HashAlgorithm;
+#[cfg(feature = "client")]
+pub use client::CancelToken;
 #[cfg(feature = "client")]
 pub use client::Client;
 #[cfg(feature = "client")]
+pub use client::IpFamily;
+#[cfg(feature = "client")]
 pub use client::Mode;
 #[cfg(feature = "client")]
+pub use client::Overwrite;
+#[cfg(feature = "client")]
 pub use client_config::ClientConfig;
+pub use compress::Compression;
+pub use compress::Compressor;
+pub use compress::Decompressor;
 pub use config::Config;
 pub use convert::Convert;
+pub use error::TftpError;
+#[doc(hidden)]
+pub use log::has_logger;
+#[doc(hidden)]
+pub use log::log_message;
+pub use log::set_logger;
 pub use log::verbosity;
+pub use log::Level;
+pub use log::Logger;
+pub use netascii::NetasciiDecoder;
+pub use netascii::NetasciiEncoder;
+pub use netascii::TransferMode;
+pub use options::Multicast;
 pub use options::OptionType;
+pub use options::OptionsProtocol;
 pub use options::TransferOption;
 pub use packet::ErrorCode;
 pub use packet::Opcode;
 pub use packet::Packet;
 pub use server::Server;
+pub use socket::Meter;
+pub use socket::MeteredSocket;
+pub use socket::MulticastSocket;
+pub use socket::RateLimitedSocket;
 pub use socket::ServerSocket;
 pub use socket::Socket;
+pub use socket::StrictTidSocket;
+pub use stats::ProgressMeter;
+pub use stats::ProgressReport;
+pub use stats::SocketMetrics;
+pub use stats::TransferEvent;
+pub use stats::TransferStats;
+#[cfg(feature = "trace")]
+pub use trace::Direction;
+#[cfg(feature = "trace")]
+pub use trace::Trace;
+#[cfg(feature = "trace")]
+pub use trace::TraceEntry;
+#[cfg(feature = "trace")]
+pub use trace::TracedSocket;
+pub use transform::Pipeline;
+pub use transform::Transform;
+pub use window::Sink;
 pub use window::WindowRead;
 pub use window::WindowWrite;
 pub use worker::Worker;
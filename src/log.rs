@@ -1,9 +1,31 @@
 #![allow(unused_imports)]
 
 use std::cmp::max;
+use std::fmt;
 use std::sync::OnceLock;
 
 static VERBOSITY: OnceLock<usize> = OnceLock::new();
+static LOGGER: OnceLock<Logger> = OnceLock::new();
+
+/// Level `enum` represents the severity of the log messages, the verbosity
+/// needed to print them increasing from `Error` to `Debug`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    /// Failures, always printed on stderr
+    Error,
+    /// Unexpected events which do not stop the transfers
+    Warn,
+    /// Requests and transfer milestones
+    Info,
+    /// Details of the protocol, printed in debug builds only
+    Debug,
+    /// Progress of each window, printed in debug builds only from `-v -v -v`
+    Trace,
+}
+
+/// Logger function receiving the log messages instead of the standard
+/// outputs, see [`set_logger()`].
+pub type Logger = fn(Level, fmt::Arguments);
 
 /// Verbosity should be set once at program start.
 pub fn verbosity_set(verbosity: isize) {
@@ -15,41 +37,166 @@ pub fn verbosity() -> usize {
     *VERBOSITY.get().unwrap_or(&1)
 }
 
+/// Routes all the log messages to `logger` whatever the verbosity, instead
+/// of the standard outputs or the `log` crate facade with the `log` feature.
+/// Debug messages are also passed in release builds. The logger can only be
+/// set once, the rejected one is returned otherwise.
+///
+/// # Example
+///
+/// ```rust
+/// use tftpd::Level;
+///
+/// tftpd::set_logger(|level, args| {
+///     if level <= Level::Info {
+///         eprintln!("[tftp] {args}");
+///     }
+/// })
+/// .unwrap();
+/// ```
+pub fn set_logger(logger: Logger) -> Result<(), Logger> {
+    LOGGER.set(logger)
+}
+
+/// Returns true if a logger was set with [`set_logger()`], or if the
+/// messages go to the `log` crate facade.
+#[doc(hidden)]
+pub fn has_logger() -> bool {
+    LOGGER.get().is_some() || facade_enabled()
+}
+
+/// Passes a message to the logger if any, otherwise to the `log` crate
+/// facade with the `log` feature once a logger enabled it, or prints it when
+/// the verbosity allows it. Used by the log macros.
+#[doc(hidden)]
+pub fn log_message(level: Level, args: fmt::Arguments) {
+    route(LOGGER.get(), level, args)
+}
+
+fn route(logger: Option<&Logger>, level: Level, args: fmt::Arguments) {
+    match logger {
+        Some(logger) => logger(level, args),
+        #[cfg(feature = "log")]
+        None if facade_enabled() => ::log::log!(level.into(), "{args}"),
+        None if level == Level::Error => eprintln!("{args}"),
+        None if (level as usize) <= verbosity() => println!("{args}"),
+        None => {}
+    }
+}
+
+/// Returns true if the `log` crate facade is enabled, the executables
+/// without a logger printing the messages as without the `log` feature
+fn facade_enabled() -> bool {
+    #[cfg(feature = "log")]
+    return ::log::max_level() != ::log::LevelFilter::Off;
+    #[cfg(not(feature = "log"))]
+    false
+}
+
+#[cfg(feature = "log")]
+impl From<Level> for ::log::Level {
+    fn from(level: Level) -> ::log::Level {
+        match level {
+            Level::Error => ::log::Level::Error,
+            Level::Warn => ::log::Level::Warn,
+            Level::Info => ::log::Level::Info,
+            Level::Debug => ::log::Level::Debug,
+            Level::Trace => ::log::Level::Trace,
+        }
+    }
+}
+
 /// Report error logs
 #[macro_export]
 macro_rules! log_err {
-    ($($x:tt)*) => { eprintln!($($x)*) }
+    ($($x:tt)*) => { $crate::log_message($crate::Level::Error, format_args!($($x)*)) }
 }
 
 /// Report warning logs
 #[macro_export]
 macro_rules! log_warn {
-    ($($x:tt)*) => { if  0 < $crate::verbosity() { println!($($x)*)} }
+    ($($x:tt)*) => { $crate::log_message($crate::Level::Warn, format_args!($($x)*)) }
 }
 
 /// Report info logs
 #[macro_export]
 macro_rules! log_info {
-    ($($x:tt)*) => { if  1 < $crate::verbosity() { println!($($x)*)} }
+    ($($x:tt)*) => { $crate::log_message($crate::Level::Info, format_args!($($x)*)) }
 }
 
 /// Report debug logs
 #[macro_export]
 #[cfg(debug_assertions)]
 macro_rules! log_dbg {
-    ($($x:tt)*) => { if  2 < $crate::verbosity() { println!($($x)*)} }
+    ($($x:tt)*) => { $crate::log_message($crate::Level::Debug, format_args!($($x)*)) }
 }
 
-/// Do not compile debug logs with release target
+/// Debug logs are only passed to a logger with release target
 #[macro_export]
 #[cfg(not(debug_assertions))]
 macro_rules! log_dbg {
     ($($x:tt)*) => {
-        ()
+        if $crate::has_logger() {
+            $crate::log_message($crate::Level::Debug, format_args!($($x)*))
+        }
+    };
+}
+
+/// Report trace logs, one per window
+#[macro_export]
+#[cfg(debug_assertions)]
+macro_rules! log_trace {
+    ($($x:tt)*) => { $crate::log_message($crate::Level::Trace, format_args!($($x)*)) }
+}
+
+/// Trace logs are only passed to a logger with release target
+#[macro_export]
+#[cfg(not(debug_assertions))]
+macro_rules! log_trace {
+    ($($x:tt)*) => {
+        if $crate::has_logger() {
+            $crate::log_message($crate::Level::Trace, format_args!($($x)*))
+        }
     };
 }
 
 pub(crate) use log_dbg;
 pub(crate) use log_err;
 pub(crate) use log_info;
+pub(crate) use log_trace;
 pub(crate) use log_warn;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    static RECORDED: Mutex<Vec<(Level, String)>> = Mutex::new(vec![]);
+
+    fn record(level: Level, args: fmt::Arguments) {
+        RECORDED.lock().unwrap().push((level, args.to_string()));
+    }
+
+    /// The logger is passed directly, the global one staying unset for the
+    /// other tests
+    #[test]
+    fn routes_messages_to_logger() {
+        let logger: Logger = record;
+        for (level, msg) in [
+            (Level::Error, "error"),
+            (Level::Info, "info"),
+            (Level::Trace, "trace"),
+        ] {
+            route(Some(&logger), level, format_args!("hook: {msg}"));
+        }
+
+        assert_eq!(
+            *RECORDED.lock().unwrap(),
+            [
+                (Level::Error, "hook: error".to_string()),
+                (Level::Info, "hook: info".to_string()),
+                (Level::Trace, "hook: trace".to_string()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,137 @@
+use std::fs::File;
+use std::io::{self, ErrorKind, Read};
+use std::os::unix::fs::FileExt;
+use std::os::unix::io::AsRawFd;
+use std::ptr;
+
+/// Mmap `struct` is a read-only memory mapping of a whole file, read
+/// sequentially like the file itself.
+///
+/// Accessing the mapping beyond the end of a file truncated in the meantime
+/// raises SIGBUS, so the mapping is never dereferenced directly. On Linux,
+/// the kernel copies it with `process_vm_readv()`, which fails on such pages
+/// instead. Elsewhere, or if the call is not permitted, the file is read with
+/// `pread()`. In both cases a shrunk file is reported as an error.
+pub struct Mmap {
+    ptr: *mut libc::c_void,
+    len: usize,
+    pos: usize,
+    file: File,
+    pread: bool,
+}
+
+// SAFETY: the mapping is private and read-only, and only accessed through &mut self
+unsafe impl Send for Mmap {}
+
+impl Mmap {
+    /// Maps the whole `file` in memory.
+    pub fn new(file: File) -> io::Result<Mmap> {
+        let len = usize::try_from(file.metadata()?.len())
+            .map_err(|_| io::Error::new(ErrorKind::InvalidInput, "file too large to map"))?;
+
+        // Empty mappings are not allowed, nothing to read anyway
+        let ptr = if len == 0 {
+            ptr::null_mut()
+        } else {
+            // SAFETY: the file descriptor is valid for the call duration and
+            // the result is checked
+            let ptr = unsafe {
+                libc::mmap(
+                    ptr::null_mut(),
+                    len,
+                    libc::PROT_READ,
+                    libc::MAP_PRIVATE,
+                    file.as_raw_fd(),
+                    0,
+                )
+            };
+            if ptr == libc::MAP_FAILED {
+                return Err(io::Error::last_os_error());
+            }
+            ptr
+        };
+
+        Ok(Mmap {
+            ptr,
+            len,
+            pos: 0,
+            file,
+            pread: !cfg!(target_os = "linux"),
+        })
+    }
+
+    /// Copies `buf.len()` bytes from the mapping at the current position,
+    /// returning how many could be copied.
+    #[cfg(target_os = "linux")]
+    fn copy_mapped(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let local = libc::iovec {
+            iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+            iov_len: buf.len(),
+        };
+        let remote = libc::iovec {
+            // SAFETY: pos + buf.len() is within the mapping
+            iov_base: unsafe { (self.ptr as *mut u8).add(self.pos) } as *mut libc::c_void,
+            iov_len: buf.len(),
+        };
+
+        // SAFETY: both ranges are valid in this process, the kernel reports
+        // the pages beyond the end of the file as EFAULT
+        let size = unsafe { libc::process_vm_readv(libc::getpid(), &local, 1, &remote, 1, 0) };
+        if size >= 0 {
+            return Ok(size as usize);
+        }
+
+        let error = io::Error::last_os_error();
+        match error.raw_os_error() {
+            Some(libc::EFAULT) => Ok(0),
+            // Forbidden by a security policy, or not available
+            Some(libc::EPERM | libc::ENOSYS) => {
+                self.pread = true;
+                self.file.read_at(buf, self.pos as u64)
+            }
+            _ => Err(error),
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn copy_mapped(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.file.read_at(buf, self.pos as u64)
+    }
+}
+
+impl Read for Mmap {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let size = buf.len().min(self.len - self.pos);
+        if size == 0 {
+            return Ok(0);
+        }
+
+        let buf = &mut buf[..size];
+        let copied = if self.pread {
+            self.file.read_at(buf, self.pos as u64)?
+        } else {
+            self.copy_mapped(buf)?
+        };
+
+        // The end of the last page reads as zeros after a truncation, so the
+        // size is checked once the data is copied
+        if copied < size || self.file.metadata()?.len() < (self.pos + size) as u64 {
+            return Err(io::Error::new(
+                ErrorKind::UnexpectedEof,
+                "file was truncated during the transfer",
+            ));
+        }
+        self.pos += size;
+
+        Ok(size)
+    }
+}
+
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        if !self.ptr.is_null() {
+            // SAFETY: ptr and len describe a mapping created in new()
+            unsafe { libc::munmap(self.ptr, self.len) };
+        }
+    }
+}
@@ -0,0 +1,258 @@
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read};
+use std::str::FromStr;
+
+use crate::Transform;
+
+const CR: u8 = b'\r';
+const LF: u8 = b'\n';
+const NUL: u8 = 0;
+
+/// TransferMode `enum` represents the transfer modes of
+/// [RFC 1350](https://www.rfc-editor.org/rfc/rfc1350), except the obsolete mail mode.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum TransferMode {
+    /// Raw 8 bit bytes (default)
+    #[default]
+    Octet,
+    /// Text, with lines ending with CR LF on the wire
+    Netascii,
+}
+
+impl TransferMode {
+    /// Returns the name of the mode, as sent in requests.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TransferMode::Octet => "octet",
+            TransferMode::Netascii => "netascii",
+        }
+    }
+}
+
+impl FromStr for TransferMode {
+    type Err = Box<dyn Error>;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "octet" => Ok(TransferMode::Octet),
+            "netascii" => Ok(TransferMode::Netascii),
+            _ => Err(format!("unsupported transfer mode {s}").into()),
+        }
+    }
+}
+
+impl fmt::Display for TransferMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// NetasciiEncoder `struct` wraps a [`Read`] source to translate local text
+/// to netascii: LF becomes CR LF and CR becomes CR NUL.
+///
+/// # Example
+///
+/// ```rust
+/// use std::io::Read;
+/// use tftpd::NetasciiEncoder;
+///
+/// let mut encoded = vec![];
+/// NetasciiEncoder::new(&b"a\nb\r"[..]).read_to_end(&mut encoded).unwrap();
+/// assert_eq!(encoded, b"a\r\nb\r\0");
+/// ```
+pub struct NetasciiEncoder<R: Read> {
+    inner: R,
+    pending: Option<u8>,
+    raw: Vec<u8>,
+}
+
+impl<R: Read> NetasciiEncoder<R> {
+    /// Creates a new [`NetasciiEncoder`] reading from `inner`.
+    pub fn new(inner: R) -> NetasciiEncoder<R> {
+        NetasciiEncoder {
+            inner,
+            pending: None,
+            raw: vec![],
+        }
+    }
+}
+
+impl<R: Read> Read for NetasciiEncoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut len = 0;
+        if let Some(byte) = self.pending.take() {
+            match buf.first_mut() {
+                Some(first) => *first = byte,
+                None => {
+                    self.pending = Some(byte);
+                    return Ok(0);
+                }
+            }
+            len = 1;
+        }
+        if len == buf.len() {
+            return Ok(len);
+        }
+
+        // Each byte expands to 2 at most, the second one of the last byte
+        // is kept for the next read when it does not fit
+        self.raw.resize(((buf.len() - len) / 2).max(1), 0);
+        let raw_len = match self.inner.read(&mut self.raw) {
+            Ok(raw_len) => raw_len,
+            Err(err) if len == 0 => return Err(err),
+            Err(_) => return Ok(len),
+        };
+
+        for &byte in &self.raw[..raw_len] {
+            let (first, second) = match byte {
+                LF => (CR, Some(LF)),
+                CR => (CR, Some(NUL)),
+                _ => (byte, None),
+            };
+            buf[len] = first;
+            len += 1;
+            if let Some(second) = second {
+                if len < buf.len() {
+                    buf[len] = second;
+                    len += 1;
+                } else {
+                    self.pending = Some(second);
+                }
+            }
+        }
+
+        Ok(len)
+    }
+}
+
+/// NetasciiDecoder `struct` is a [`Transform`] translating netascii to local
+/// text: CR LF becomes LF and CR NUL becomes CR. A CR followed by any other
+/// byte is kept as is.
+///
+/// # Example
+///
+/// ```rust
+/// use tftpd::{NetasciiDecoder, Transform};
+///
+/// let mut decoder = NetasciiDecoder::new();
+/// assert_eq!(decoder.transform(b"a\r\nb\r").unwrap(), b"a\nb");
+/// assert_eq!(decoder.transform(b"\0c").unwrap(), b"\rc");
+/// ```
+#[derive(Default)]
+pub struct NetasciiDecoder {
+    cr: bool,
+}
+
+impl NetasciiDecoder {
+    /// Creates a new [`NetasciiDecoder`].
+    pub fn new() -> NetasciiDecoder {
+        NetasciiDecoder { cr: false }
+    }
+}
+
+impl Transform for NetasciiDecoder {
+    fn transform(&mut self, data: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+        let mut decoded = Vec::with_capacity(data.len() + 1);
+
+        for &byte in data {
+            if self.cr {
+                self.cr = false;
+                match byte {
+                    LF => {
+                        decoded.push(LF);
+                        continue;
+                    }
+                    NUL => {
+                        decoded.push(CR);
+                        continue;
+                    }
+                    _ => decoded.push(CR),
+                }
+            }
+
+            if byte == CR {
+                // Depends on the next byte, which may be in the next chunk
+                self.cr = true;
+            } else {
+                decoded.push(byte);
+            }
+        }
+
+        Ok(decoded)
+    }
+
+    fn finish(&mut self) -> Result<Vec<u8>, Box<dyn Error>> {
+        if self.cr {
+            self.cr = false;
+            Ok(vec![CR])
+        } else {
+            Ok(vec![])
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEXT: &[u8] = b"dos\r\nunix\nmac\rnul\0end\r";
+    const NETASCII: &[u8] = b"dos\r\0\r\nunix\r\nmac\r\0nul\0end\r\0";
+
+    #[test]
+    fn parses_mode_names() {
+        assert_eq!(
+            "octet".parse::<TransferMode>().unwrap(),
+            TransferMode::Octet
+        );
+        assert_eq!(
+            "OCTET".parse::<TransferMode>().unwrap(),
+            TransferMode::Octet
+        );
+        assert_eq!(
+            "NetAscii".parse::<TransferMode>().unwrap(),
+            TransferMode::Netascii
+        );
+        assert!("mail".parse::<TransferMode>().is_err());
+    }
+
+    /// Reads at most `len` bytes at a time
+    fn read_by(len: usize, mut reader: impl Read) -> Vec<u8> {
+        let mut data = vec![];
+        let mut buf = vec![0; len];
+        loop {
+            match reader.read(&mut buf).unwrap() {
+                0 => return data,
+                read => data.extend_from_slice(&buf[..read]),
+            }
+        }
+    }
+
+    #[test]
+    fn encodes_line_endings() {
+        for len in [1, 2, 3, 7, 64] {
+            assert_eq!(read_by(len, NetasciiEncoder::new(TEXT)), NETASCII);
+        }
+    }
+
+    #[test]
+    fn decodes_line_endings_across_chunks() {
+        for len in [1, 2, 3, 5, 64] {
+            let mut decoder = NetasciiDecoder::new();
+            let mut text = vec![];
+            for chunk in NETASCII.chunks(len) {
+                text.extend(decoder.transform(chunk).unwrap());
+            }
+            text.extend(decoder.finish().unwrap());
+            assert_eq!(text, TEXT);
+        }
+    }
+
+    #[test]
+    fn keeps_lone_cr() {
+        let mut decoder = NetasciiDecoder::new();
+        assert_eq!(decoder.transform(b"a\rb\r").unwrap(), b"a\rb");
+        assert_eq!(decoder.transform(b"\r").unwrap(), b"\r");
+        assert_eq!(decoder.finish().unwrap(), b"\r");
+    }
+}
@@ -1,16 +1,23 @@
 use std::error::Error;
 use std::fmt;
+use std::net::IpAddr;
 use std::str::FromStr;
 use std::time::Duration;
 
 use crate::{log::*, server::RequestType};
 
+// Defaults of the options, used by the `Default` implementations and the help
+// texts. The protocol ones are those of RFC 1350 and RFC 7440 when not negotiated.
 pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
 pub const DEFAULT_BLOCK_SIZE: u16 = 512;
 pub const DEFAULT_WINDOW_SIZE: u16 = 1;
 pub const DEFAULT_WINDOW_WAIT: Duration = Duration::from_millis(0);
 pub const DEFAULT_MAX_RETRIES: usize = 6;
 pub const DEFAULT_ROLLOVER: Rollover = Rollover::Enforce0;
+pub const DEFAULT_TRAILING_DATA: TrailingData = TrailingData::Abort;
+pub const DEFAULT_MAX_MEMORY: usize = 16 * 1024 * 1024;
+// RFC 2348 smallest block size, the memory budget cannot reduce it further
+const MIN_CLAMPED_BLOCK_SIZE: u16 = 8;
 
 /// Enum used to set the block counter roll-over policy
 #[derive(PartialEq, Clone, Copy, Debug)]
@@ -25,6 +32,15 @@ pub enum Rollover {
     DontCare,
 }
 
+/// Enum used to set the handling of data received after the final (short) block
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum TrailingData {
+    /// Log and ignore the extra data, the transfer succeeds
+    Ignore,
+    /// Send an error to the peer and fail the transfer
+    Abort,
+}
+
 /// Local options `struct` used for storing and passing options for client and server
 /// set directly from executable arguments. Though present on both sides of the
 /// transfer, they can differ and are independent.
@@ -34,10 +50,30 @@ pub struct OptionsPrivate {
     pub repeat_count: u8,
     /// Should clean (delete) files after receiving errors. (default: true)
     pub clean_on_error: bool,
-    /// Max count of retires (default: 6)
+    /// Max count of retries of a block before aborting (default: 6)
     pub max_retries: usize,
     /// Block counter roll-over policy  (default: Enforce0)
     pub rollover: Rollover,
+    /// Handling of data received after the final block (default: Abort)
+    pub trailing_data: TrailingData,
+    /// Budget for windowsize x blocksize, clamping negotiation (default: unlimited)
+    pub max_memory: Option<usize>,
+    /// Shrink the sent window on losses and grow it back on success (default: false)
+    pub adaptive_window: bool,
+    /// Wait for a received file to reach the disk before acknowledging the
+    /// final block (default: true)
+    pub sync: bool,
+    /// Bytes per second sent by each transfer at most (default: unlimited)
+    pub max_rate: Option<u64>,
+    /// Pad the final block sent with zeros to the blocksize, followed by an
+    /// empty block, for receivers expecting full blocks only (default: false)
+    pub pad_final_block: bool,
+    /// Acknowledge each block received instead of each window, for senders
+    /// misbehaving with RFC 7440 windows (default: false)
+    pub ack_per_block: bool,
+    /// Wall-clock limit of a whole transfer, aborted when exceeded, even if
+    /// the peer never lets a timeout expire (default: unlimited)
+    pub deadline: Option<Duration>,
 }
 
 impl Default for OptionsPrivate {
@@ -47,6 +83,14 @@ impl Default for OptionsPrivate {
             clean_on_error: true,
             max_retries: DEFAULT_MAX_RETRIES,
             rollover: DEFAULT_ROLLOVER,
+            trailing_data: DEFAULT_TRAILING_DATA,
+            max_memory: None,
+            adaptive_window: false,
+            sync: true,
+            max_rate: None,
+            pad_final_block: false,
+            ack_per_block: false,
+            deadline: None,
         }
     }
 }
@@ -68,24 +112,39 @@ pub struct OptionsProtocol {
     pub timeout: Duration,
     /// Size of the file to transfer (default: N/A)
     pub transfer_size: Option<u64>,
+    /// CRC-32 of the file to transfer, verified by the receiver (default: N/A)
+    pub checksum: Option<u32>,
+    /// Selective acknowledgements, the sender only sending again the blocks
+    /// of a window reported missing by the receiver (default: false)
+    pub sack: bool,
+    /// Value the block counter rolls over to after 65535, 0 or 1, overriding
+    /// the local roll-over policy (default: N/A)
+    pub rollover: Option<u16>,
+    /// Options not modelled by [`OptionType`], as key and value, sent as is
+    /// in the request and collected from the OACK (default: none)
+    pub extra: Vec<(String, String)>,
 }
 
 impl OptionsProtocol {
+    /// Builds the options of a request from the local ones.
     pub fn prepare(&self) -> Vec<TransferOption> {
-        let mut options = vec![
-            TransferOption {
-                option: OptionType::BlockSize,
-                value: self.block_size as u64,
-            },
-            TransferOption {
+        let mut options = vec![TransferOption {
+            option: OptionType::BlockSize,
+            value: self.block_size as u64,
+        }];
+
+        // Requested with 0 to learn the size from the server, omitted when unknown
+        if let Some(transfer_size) = self.transfer_size {
+            options.push(TransferOption {
                 option: OptionType::TransferSize,
-                value: self.transfer_size.unwrap_or(0),
-            },
-            TransferOption {
-                option: OptionType::WindowSize,
-                value: self.window_size as u64,
-            },
-        ];
+                value: transfer_size,
+            });
+        }
+
+        options.push(TransferOption {
+            option: OptionType::WindowSize,
+            value: self.window_size as u64,
+        });
 
         if self.window_wait.as_millis() != 0 {
             options.push(TransferOption {
@@ -94,21 +153,46 @@ impl OptionsProtocol {
             });
         }
 
-        options.push(if self.timeout.subsec_micros() == 0 {
-            TransferOption {
-                option: OptionType::Timeout,
-                value: self.timeout.as_secs(),
-            }
-        } else {
-            TransferOption {
-                option: OptionType::UTimeout,
-                value: self.timeout.as_micros() as u64,
-            }
-        });
+        if let Some(checksum) = self.checksum {
+            options.push(TransferOption {
+                option: OptionType::Checksum,
+                value: checksum as u64,
+            });
+        }
+
+        if self.sack {
+            options.push(TransferOption {
+                option: OptionType::Sack,
+                value: 1,
+            });
+        }
+
+        if let Some(rollover) = self.rollover {
+            options.push(TransferOption {
+                option: OptionType::Rollover,
+                value: rollover as u64,
+            });
+        }
+
+        // RFC 2349 timeout is in whole seconds, up to 255
+        options.push(
+            if self.timeout.subsec_micros() == 0 && self.timeout.as_secs() <= 255 {
+                TransferOption {
+                    option: OptionType::Timeout,
+                    value: self.timeout.as_secs(),
+                }
+            } else {
+                TransferOption {
+                    option: OptionType::UTimeout,
+                    value: self.timeout.as_micros() as u64,
+                }
+            },
+        );
 
         options
     }
 
+    /// Validates the options of a request and builds those of the transfer.
     pub fn parse(
         options: &mut [TransferOption],
         request_type: RequestType,
@@ -173,24 +257,133 @@ impl OptionsProtocol {
                 OptionType::WindowWait => {
                     opt_common.window_wait = Duration::from_millis(*value);
                 }
+                OptionType::Checksum => {
+                    // For read requests, the file value is only filled by the worker
+                    if *value > u32::MAX as u64 {
+                        return Err("Invalid checksum value");
+                    }
+                    opt_common.checksum = Some(*value as u32);
+                }
+                OptionType::Sack => {
+                    if *value > 1 {
+                        log_warn!("  Invalid sack value {}. Changed to 1.", *value);
+                        *value = 1;
+                    }
+                    opt_common.sack = *value == 1;
+                }
+                OptionType::Rollover => {
+                    if *value > 1 {
+                        log_warn!("  Invalid rollover value {}. Changed to 0.", *value);
+                        *value = 0;
+                    }
+                    opt_common.rollover = Some(*value as u16);
+                }
             }
         }
 
         Ok(opt_common)
     }
 
-    pub fn apply(&mut self, options: &Vec<TransferOption>) -> Result<(), Box<dyn Error>> {
+    /// Reduces the block size, then the window size, so that a window fits in
+    /// `max_memory` bytes. The block size is not reduced below 8 bytes
+    /// (RFC 2348), and the window keeps at least one block.
+    pub fn clamp_memory(&mut self, max_memory: usize) {
+        if max_memory < self.block_size as usize {
+            let block_size = max_memory.max(MIN_CLAMPED_BLOCK_SIZE as usize) as u16;
+            if block_size < self.block_size {
+                log_warn!(
+                    "  Block size {} exceeds memory budget. Changed to {block_size}.",
+                    self.block_size
+                );
+                self.block_size = block_size;
+            }
+        }
+
+        self.clamp_window(max_memory);
+    }
+
+    /// Reduces the window size so that a window of the current block size
+    /// fits in `max_memory` bytes, keeping at least one block. Used on the
+    /// options accepted by the peer, whose block size cannot be changed.
+    pub fn clamp_window(&mut self, max_memory: usize) {
+        let max_window = (max_memory / self.block_size as usize).clamp(1, u16::MAX as usize) as u16;
+        if max_window < self.window_size {
+            log_warn!(
+                "  Window size {} exceeds memory budget. Changed to {max_window}.",
+                self.window_size
+            );
+            self.window_size = max_window;
+        }
+    }
+
+    /// Applies the options accepted by the server in its OACK. Unlike the
+    /// request, whose values are corrected, an out of range value is an error.
+    /// The options not modelled by [`OptionType`] are only collected in `extra`.
+    ///
+    /// The options are first reset to their defaults, as those requested but
+    /// absent from the OACK were declined by the server (RFC 2347).
+    pub fn apply(
+        &mut self,
+        options: &Vec<TransferOption>,
+        extra: &[(String, String)],
+    ) -> Result<(), Box<dyn Error>> {
+        *self = OptionsProtocol::default();
+
         for option in options {
             match option.option {
-                OptionType::BlockSize => self.block_size = option.value as u16,
-                OptionType::WindowSize => self.window_size = option.value as u16,
+                OptionType::BlockSize => {
+                    if !(1..=65464).contains(&option.value) {
+                        return Err(format!("Invalid block size {}", option.value).into());
+                    }
+                    self.block_size = option.value as u16;
+                }
+                OptionType::WindowSize => {
+                    if !(1..=65535).contains(&option.value) {
+                        return Err(format!("Invalid window size {}", option.value).into());
+                    }
+                    self.window_size = option.value as u16;
+                }
                 OptionType::WindowWait => self.window_wait = Duration::from_millis(option.value),
-                OptionType::Timeout => self.timeout = Duration::from_secs(option.value),
-                OptionType::UTimeout => self.timeout = Duration::from_micros(option.value),
+                OptionType::Timeout => {
+                    if !(1..=255).contains(&option.value) {
+                        return Err(format!("Invalid timeout value {}", option.value).into());
+                    }
+                    self.timeout = Duration::from_secs(option.value);
+                }
+                OptionType::UTimeout => {
+                    if option.value == 0 {
+                        return Err("Invalid utimeout value 0".into());
+                    }
+                    self.timeout = Duration::from_micros(option.value);
+                }
                 OptionType::TransferSize => self.transfer_size = Some(option.value),
+                OptionType::Checksum => {
+                    if option.value > u32::MAX as u64 {
+                        return Err("Invalid checksum value".into());
+                    }
+                    self.checksum = Some(option.value as u32);
+                }
+                OptionType::Sack => {
+                    if option.value > 1 {
+                        return Err(format!("Invalid sack value {}", option.value).into());
+                    }
+                    self.sack = option.value == 1;
+                }
+                OptionType::Rollover => {
+                    if option.value > 1 {
+                        return Err(format!("Invalid rollover value {}", option.value).into());
+                    }
+                    self.rollover = Some(option.value as u16);
+                }
             }
         }
 
+        // RFC 2347: options which are not understood are ignored
+        if !extra.is_empty() {
+            log_dbg!("  Unknown options: {extra:?}");
+        }
+        self.extra = extra.to_vec();
+
         Ok(())
     }
 }
@@ -203,10 +396,85 @@ impl Default for OptionsProtocol {
             window_wait: DEFAULT_WINDOW_WAIT,
             timeout: DEFAULT_TIMEOUT,
             transfer_size: None,
+            checksum: None,
+            sack: false,
+            rollover: None,
+            extra: vec![],
         }
     }
 }
 
+/// Multicast `struct` represents the multicast option (RFC 2090) of an OACK,
+/// whose value is `addr,port,mc`. The address and port may be left empty by
+/// the OACKs which only change the master client.
+///
+/// # Example
+///
+/// ```rust
+/// use tftpd::Multicast;
+///
+/// let multicast: Multicast = "239.255.0.1,1758,1".parse().unwrap();
+/// assert_eq!(multicast.address, Some("239.255.0.1".parse().unwrap()));
+/// assert_eq!(multicast.port, Some(1758));
+/// assert!(multicast.master);
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Multicast {
+    /// Address of the multicast group
+    pub address: Option<IpAddr>,
+    /// Port of the multicast group
+    pub port: Option<u16>,
+    /// Whether the client is the master one, acknowledging the data
+    pub master: bool,
+}
+
+impl Multicast {
+    /// Name of the option, not modelled by [`OptionType`] as its value is
+    /// not a number.
+    pub const OPTION: &'static str = "multicast";
+
+    /// Finds the multicast option among the `extra` options of an OACK.
+    pub fn find(extra: &[(String, String)]) -> Result<Option<Multicast>, Box<dyn Error>> {
+        match extra
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(Multicast::OPTION))
+        {
+            Some((_, value)) => Ok(Some(value.parse()?)),
+            None => Ok(None),
+        }
+    }
+}
+
+impl FromStr for Multicast {
+    type Err = &'static str;
+
+    /// Converts an `addr,port,mc` [`str`] to a [`Multicast`].
+    fn from_str(value: &str) -> Result<Self, &'static str> {
+        let mut fields = value.split(',');
+        let (Some(address), Some(port), Some(master), None) =
+            (fields.next(), fields.next(), fields.next(), fields.next())
+        else {
+            return Err("Invalid multicast option");
+        };
+
+        Ok(Multicast {
+            address: match address {
+                "" => None,
+                address => Some(address.parse().map_err(|_| "Invalid multicast address")?),
+            },
+            port: match port {
+                "" => None,
+                port => Some(port.parse().map_err(|_| "Invalid multicast port")?),
+            },
+            master: match master {
+                "0" => false,
+                "1" => true,
+                _ => return Err("Invalid multicast master flag"),
+            },
+        })
+    }
+}
+
 /// TransferOption `struct` represents the TFTP transfer options.
 ///
 /// This `struct` has a function implementation for converting [`TransferOption`]s
@@ -243,8 +511,7 @@ impl TransferOption {
     }
 }
 
-/// Wrapper to print TransferOption slices (warning in release build)
-#[allow(dead_code)]
+/// Wrapper to print TransferOption slices
 pub struct OptionFmt<'a>(pub &'a [TransferOption]);
 impl fmt::Display for OptionFmt<'_> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -285,6 +552,12 @@ pub enum OptionType {
     WindowSize,
     /// Windowwait option type
     WindowWait,
+    /// File CRC-32 option type (non standard)
+    Checksum,
+    /// Selective acknowledgement option type (non standard)
+    Sack,
+    /// Block counter roll-over value option type (non standard)
+    Rollover,
 }
 
 impl OptionType {
@@ -297,6 +570,9 @@ impl OptionType {
             OptionType::UTimeout => "utimeout",
             OptionType::WindowSize => "windowsize",
             OptionType::WindowWait => "windowwait",
+            OptionType::Checksum => "x-checksum",
+            OptionType::Sack => "x-sack",
+            OptionType::Rollover => "rollover",
         }
     }
 }
@@ -313,7 +589,159 @@ impl FromStr for OptionType {
             "utimeout" => Ok(OptionType::UTimeout),
             "windowsize" => Ok(OptionType::WindowSize),
             "windowwait" => Ok(OptionType::WindowWait),
+            "x-checksum" => Ok(OptionType::Checksum),
+            "x-sack" => Ok(OptionType::Sack),
+            "rollover" => Ok(OptionType::Rollover),
             _ => Err("Invalid option type"),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Packet;
+
+    #[test]
+    fn defaults_to_rfc_values() {
+        let opt_common = OptionsProtocol::default();
+        // RFC 1350 blocks, RFC 7440 lockstep window
+        assert_eq!(opt_common.block_size, 512);
+        assert_eq!(opt_common.window_size, 1);
+        assert_eq!(opt_common.window_wait, Duration::ZERO);
+        assert_eq!(opt_common.timeout, Duration::from_secs(5));
+        assert_eq!(opt_common.transfer_size, None);
+
+        let opt_local = OptionsPrivate::default();
+        assert_eq!(opt_local.max_retries, DEFAULT_MAX_RETRIES);
+        assert_eq!(opt_local.rollover, Rollover::Enforce0);
+    }
+
+    #[test]
+    fn applies_oack_with_unknown_option() {
+        let oack = [&[0x00, 0x06][..], b"x-unknown\0yes\0windowsize\x008\0"].concat();
+        let Packet::Oack(options, extra) = Packet::deserialize(&oack).unwrap() else {
+            panic!("cannot parse oack")
+        };
+
+        let mut opt_common = OptionsProtocol::default();
+        opt_common.apply(&options, &extra).unwrap();
+        assert_eq!(opt_common.window_size, 8);
+        assert_eq!(
+            opt_common.extra,
+            [("x-unknown".to_string(), "yes".to_string())]
+        );
+    }
+
+    #[test]
+    fn resets_options_omitted_from_oack() {
+        let mut opt_common = OptionsProtocol {
+            block_size: 1024,
+            window_size: 8,
+            transfer_size: Some(0),
+            sack: true,
+            ..Default::default()
+        };
+        let options = vec![
+            TransferOption {
+                option: OptionType::BlockSize,
+                value: 1024,
+            },
+            TransferOption {
+                option: OptionType::TransferSize,
+                value: 4000,
+            },
+        ];
+
+        opt_common.apply(&options, &[]).unwrap();
+        assert_eq!(opt_common.block_size, 1024);
+        assert_eq!(opt_common.transfer_size, Some(4000));
+        assert_eq!(opt_common.window_size, 1);
+        assert!(!opt_common.sack);
+    }
+
+    #[test]
+    fn round_trips_rollover() {
+        let opt_common = OptionsProtocol {
+            rollover: Some(1),
+            ..Default::default()
+        };
+        let mut options = opt_common.prepare();
+        let parsed = OptionsProtocol::parse(&mut options, RequestType::Read(0)).unwrap();
+        assert_eq!(parsed.rollover, Some(1));
+
+        let mut applied = OptionsProtocol::default();
+        applied.apply(&options, &[]).unwrap();
+        assert_eq!(applied.rollover, Some(1));
+
+        let mut options = vec![TransferOption {
+            option: OptionType::Rollover,
+            value: 2,
+        }];
+        assert!(applied.apply(&options, &[]).is_err());
+        let parsed = OptionsProtocol::parse(&mut options, RequestType::Write).unwrap();
+        assert_eq!(parsed.rollover, Some(0));
+        assert_eq!(options[0].value, 0);
+    }
+
+    #[test]
+    fn round_trips_sub_second_timeout() {
+        for timeout in [
+            Duration::from_secs(3),
+            Duration::from_millis(80),
+            Duration::from_micros(1_500_250),
+            Duration::from_secs(300),
+        ] {
+            let opt_common = OptionsProtocol {
+                timeout,
+                ..Default::default()
+            };
+            let mut options = opt_common.prepare();
+            let parsed = OptionsProtocol::parse(&mut options, RequestType::Write).unwrap();
+            assert_eq!(parsed.timeout, timeout);
+
+            let mut applied = OptionsProtocol::default();
+            applied.apply(&options, &[]).unwrap();
+            assert_eq!(applied.timeout, timeout);
+        }
+
+        let opt_common = OptionsProtocol {
+            timeout: Duration::from_millis(80),
+            ..Default::default()
+        };
+        assert!(opt_common.prepare().contains(&TransferOption {
+            option: OptionType::UTimeout,
+            value: 80_000,
+        }));
+    }
+
+    #[test]
+    fn parses_multicast_option() {
+        let extra = [("MULTICAST".to_string(), ",,0".to_string())];
+        assert_eq!(
+            Multicast::find(&extra).unwrap(),
+            Some(Multicast {
+                address: None,
+                port: None,
+                master: false,
+            })
+        );
+        assert_eq!(Multicast::find(&[]).unwrap(), None);
+
+        for value in ["", "239.255.0.1,1758", "239.255.0.1,1758,2", "host,1758,1"] {
+            assert!(value.parse::<Multicast>().is_err(), "{value}");
+        }
+    }
+
+    #[test]
+    fn rejects_oack_with_invalid_window_size() {
+        let mut opt_common = OptionsProtocol::default();
+        let options = vec![TransferOption {
+            option: OptionType::WindowSize,
+            value: 0,
+        }];
+
+        assert!(opt_common.apply(&options, &[]).is_err());
+        assert_eq!(opt_common.window_size, DEFAULT_WINDOW_SIZE);
+    }
+}
@@ -1,5 +1,6 @@
 use std::error::Error;
 use std::fmt;
+use std::io::Write;
 use std::str::FromStr;
 
 use crate::{Convert, OptionType, TransferOption};
@@ -28,6 +29,8 @@ pub enum Packet {
         mode: String,
         /// Transfer options
         options: Vec<TransferOption>,
+        /// Options not modelled by [`OptionType`], as key and value
+        extra: Vec<(String, String)>,
     },
     /// Write Request `struct`
     Wrq {
@@ -37,6 +40,8 @@ pub enum Packet {
         mode: String,
         /// Transfer options
         options: Vec<TransferOption>,
+        /// Options not modelled by [`OptionType`], as key and value
+        extra: Vec<(String, String)>,
     },
     /// Data `struct`
     Data {
@@ -47,6 +52,15 @@ pub enum Packet {
     },
     /// Acknowledgement `tuple` with block number
     Ack(u16),
+    /// Selective acknowledgement `struct` (non standard), sent as an
+    /// acknowledgement followed by the missing block numbers when the
+    /// `x-sack` option was negotiated
+    Sack {
+        /// Block number of the last block received in order
+        block_num: u16,
+        /// Blocks of the window not received yet
+        missing: Vec<u16>,
+    },
     /// Error `struct`
     Error {
         /// Error code
@@ -54,8 +68,9 @@ pub enum Packet {
         /// Error message
         msg: String,
     },
-    /// Option acknowledgement `tuple` with transfer options
-    Oack(Vec<TransferOption>),
+    /// Option acknowledgement `tuple` with transfer options, then those not
+    /// modelled by [`OptionType`] as key and value
+    Oack(Vec<TransferOption>, Vec<(String, String)>),
 }
 
 impl Packet {
@@ -75,27 +90,111 @@ impl Packet {
         }
     }
 
+    /// Same as [`Packet::deserialize()`], taking the ownership of `buf`,
+    /// which becomes the data of a data packet without being copied.
+    pub fn deserialize_owned(mut buf: Vec<u8>) -> Result<Packet, Box<dyn Error>> {
+        if buf.len() < 4 || Opcode::from_u16(Convert::to_u16(&buf[0..=1])?)? != Opcode::Data {
+            return Packet::deserialize(&buf);
+        }
+        let block_num = Convert::to_u16(&buf[2..])?;
+        buf.drain(..4);
+
+        Ok(Packet::Data {
+            block_num,
+            data: buf,
+        })
+    }
+
     /// Serializes a [`Packet`] into a [`Vec<u8>`].
     pub fn serialize(&self) -> Result<Vec<u8>, &'static str> {
+        let mut buf = vec![];
+        self.serialize_into(&mut buf)?;
+
+        Ok(buf)
+    }
+
+    /// Serializes a [`Packet`] into `buf`, replacing its content, and returns
+    /// the size of the serialized [`Packet`]. Reusing the same `buf` for each
+    /// [`Packet`] avoids allocating.
+    pub fn serialize_into(&self, buf: &mut Vec<u8>) -> Result<usize, &'static str> {
+        buf.clear();
+        match self {
+            Packet::Rrq {
+                filename,
+                mode,
+                options,
+                extra,
+            } => serialize_rq(buf, Opcode::Rrq, filename, mode, options, extra),
+            Packet::Wrq {
+                filename,
+                mode,
+                options,
+                extra,
+            } => serialize_rq(buf, Opcode::Wrq, filename, mode, options, extra),
+            Packet::Data { block_num, data } => serialize_data(buf, *block_num, data),
+            Packet::Ack(block_num) => serialize_ack(buf, *block_num),
+            Packet::Sack { block_num, missing } => serialize_sack(buf, *block_num, missing),
+            Packet::Error { code, msg } => serialize_error(buf, *code, msg),
+            Packet::Oack(options, extra) => serialize_oack(buf, options, extra),
+        }
+
+        Ok(buf.len())
+    }
+}
+
+impl fmt::Display for Packet {
+    /// Formats a [`Packet`] on one line, e.g. `DATA #42 (512 bytes)`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             Packet::Rrq {
                 filename,
                 mode,
                 options,
-            } => Ok(serialize_rrq(filename, mode, options)),
+                extra,
+            } => {
+                write!(f, "RRQ {filename:?} {mode}")?;
+                fmt_options(f, options, extra, false)
+            }
             Packet::Wrq {
                 filename,
                 mode,
                 options,
-            } => Ok(serialize_wrq(filename, mode, options)),
-            Packet::Data { block_num, data } => Ok(serialize_data(block_num, data)),
-            Packet::Ack(block_num) => Ok(serialize_ack(block_num)),
-            Packet::Error { code, msg } => Ok(serialize_error(code, msg)),
-            Packet::Oack(options) => Ok(serialize_oack(options)),
+                extra,
+            } => {
+                write!(f, "WRQ {filename:?} {mode}")?;
+                fmt_options(f, options, extra, false)
+            }
+            Packet::Data { block_num, data } => {
+                write!(f, "DATA #{block_num} ({} bytes)", data.len())
+            }
+            Packet::Ack(block_num) => write!(f, "ACK #{block_num}"),
+            Packet::Sack { block_num, missing } => write!(f, "SACK #{block_num} {missing:?}"),
+            Packet::Error { code, msg } => write!(f, "ERROR {} {msg:?}", u16::from(*code)),
+            Packet::Oack(options, extra) => {
+                write!(f, "OACK")?;
+                fmt_options(f, options, extra, true)
+            }
         }
     }
 }
 
+fn fmt_options(
+    f: &mut fmt::Formatter,
+    options: &[TransferOption],
+    extra: &[(String, String)],
+    always: bool,
+) -> fmt::Result {
+    if options.is_empty() && extra.is_empty() && !always {
+        return Ok(());
+    }
+    let options = options
+        .iter()
+        .map(|option| format!("{}={}", option.option.as_str(), option.value))
+        .chain(extra.iter().map(|(key, value)| format!("{key}={value}")));
+
+    write!(f, " [{}]", options.collect::<Vec<_>>().join(","))
+}
+
 /// Opcode `enum` represents the opcodes used in the TFTP definition.
 ///
 /// This `enum` has function implementations for converting [`u16`]s to
@@ -142,66 +241,93 @@ impl Opcode {
 
     /// Converts a [`u16`] to a [`u8`] array with 2 elements.
     pub const fn as_bytes(self) -> [u8; 2] {
-        (self as u16).to_be_bytes()
+        Convert::from_u16(self as u16)
     }
 }
 
 /// ErrorCode `enum` represents the error codes used in the TFTP definition.
 ///
 /// This `enum` has function implementations for converting [`u16`]s to
-/// [`ErrorCode`]s and [`ErrorCode`]s to [`u8`] arrays.
+/// [`ErrorCode`]s and [`ErrorCode`]s to [`u8`] arrays. Codes not defined by
+/// the RFCs are kept as [`ErrorCode::Other`].
 ///
 /// # Example
 ///
 /// ```rust
 /// use tftpd::ErrorCode;
 ///
-/// assert_eq!(ErrorCode::from_u16(3).unwrap(), ErrorCode::DiskFull);
+/// assert_eq!(ErrorCode::from(3), ErrorCode::DiskFull);
+/// assert_eq!(ErrorCode::from(42), ErrorCode::Other(42));
+/// assert_eq!(u16::from(ErrorCode::FileExists), 6);
 /// assert_eq!(ErrorCode::FileExists.as_bytes(), [0x00, 0x06]);
 /// ```
-#[repr(u16)]
 #[derive(Clone, Copy, PartialEq, Debug)]
 pub enum ErrorCode {
-    /// Not Defined error code
-    NotDefined = 0,
-    /// File not found error code
-    FileNotFound = 1,
-    /// Access violation error code
-    AccessViolation = 2,
-    /// Disk full error code
-    DiskFull = 3,
-    /// Illegal operation error code
-    IllegalOperation = 4,
-    /// Unknown ID error code
-    UnknownId = 5,
-    /// File exists error code
-    FileExists = 6,
-    /// No such user error code
-    NoSuchUser = 7,
-    /// Refused option error code
-    RefusedOption = 8,
+    /// Not Defined error code (0)
+    NotDefined,
+    /// File not found error code (1)
+    FileNotFound,
+    /// Access violation error code (2)
+    AccessViolation,
+    /// Disk full error code (3)
+    DiskFull,
+    /// Illegal operation error code (4)
+    IllegalOperation,
+    /// Unknown ID error code (5)
+    UnknownId,
+    /// File exists error code (6)
+    FileExists,
+    /// No such user error code (7)
+    NoSuchUser,
+    /// Refused option error code (8)
+    RefusedOption,
+    /// Error code not defined by the RFCs
+    Other(u16),
 }
 
 impl ErrorCode {
-    /// Converts a [`u16`] to an [`ErrorCode`].
+    /// Converts a [`u16`] to an [`ErrorCode`], see also [`ErrorCode::from()`].
     pub fn from_u16(code: u16) -> Result<ErrorCode, &'static str> {
-        match code {
-            0 => Ok(ErrorCode::NotDefined),
-            1 => Ok(ErrorCode::FileNotFound),
-            2 => Ok(ErrorCode::AccessViolation),
-            3 => Ok(ErrorCode::DiskFull),
-            4 => Ok(ErrorCode::IllegalOperation),
-            5 => Ok(ErrorCode::UnknownId),
-            6 => Ok(ErrorCode::FileExists),
-            7 => Ok(ErrorCode::NoSuchUser),
-            8 => Ok(ErrorCode::RefusedOption),
-            _ => Err("Invalid error code"),
-        }
+        Ok(ErrorCode::from(code))
     }
 
     /// Converts an [`ErrorCode`] to a [`u8`] array with 2 elements.
     pub fn as_bytes(self) -> [u8; 2] {
-        (self as u16).to_be_bytes()
+        Convert::from_u16(self.into())
+    }
+}
+
+impl From<u16> for ErrorCode {
+    fn from(code: u16) -> Self {
+        match code {
+            0 => ErrorCode::NotDefined,
+            1 => ErrorCode::FileNotFound,
+            2 => ErrorCode::AccessViolation,
+            3 => ErrorCode::DiskFull,
+            4 => ErrorCode::IllegalOperation,
+            5 => ErrorCode::UnknownId,
+            6 => ErrorCode::FileExists,
+            7 => ErrorCode::NoSuchUser,
+            8 => ErrorCode::RefusedOption,
+            code => ErrorCode::Other(code),
+        }
+    }
+}
+
+impl From<ErrorCode> for u16 {
+    fn from(code: ErrorCode) -> Self {
+        match code {
+            ErrorCode::NotDefined => 0,
+            ErrorCode::FileNotFound => 1,
+            ErrorCode::AccessViolation => 2,
+            ErrorCode::DiskFull => 3,
+            ErrorCode::IllegalOperation => 4,
+            ErrorCode::UnknownId => 5,
+            ErrorCode::FileExists => 6,
+            ErrorCode::NoSuchUser => 7,
+            ErrorCode::RefusedOption => 8,
+            ErrorCode::Other(code) => code,
+        }
     }
 }
 
@@ -217,18 +343,22 @@ impl fmt::Display for ErrorCode {
             ErrorCode::FileExists => write!(f, "File Exists"),
             ErrorCode::NoSuchUser => write!(f, "No Such User"),
             ErrorCode::RefusedOption => write!(f, "Refused option"),
+            ErrorCode::Other(code) => write!(f, "Unknown Error {code}"),
         }
     }
 }
 
 fn parse_rq(buf: &[u8], opcode: Opcode) -> Result<Packet, Box<dyn Error>> {
     let mut options = vec![];
+    let mut extra = vec![];
     let filename: String;
     let mode: String;
     let mut zero_index: usize;
 
     (filename, zero_index) = Convert::to_string(buf, 2)?;
     (mode, zero_index) = Convert::to_string(buf, zero_index + 1)?;
+    // The mode is case-insensitive, as per RFC 1350
+    let mode = mode.to_lowercase();
 
     let mut value: String;
     let mut option;
@@ -236,11 +366,13 @@ fn parse_rq(buf: &[u8], opcode: Opcode) -> Result<Packet, Box<dyn Error>> {
         (option, zero_index) = Convert::to_string(buf, zero_index + 1)?;
         (value, zero_index) = Convert::to_string(buf, zero_index + 1)?;
 
-        if let Ok(option) = OptionType::from_str(option.to_lowercase().as_str()) {
+        if let Ok(option_type) = OptionType::from_str(option.to_lowercase().as_str()) {
             options.push(TransferOption {
-                option,
+                option: option_type,
                 value: value.parse()?,
             });
+        } else {
+            extra.push((option, value));
         }
     }
 
@@ -249,11 +381,13 @@ fn parse_rq(buf: &[u8], opcode: Opcode) -> Result<Packet, Box<dyn Error>> {
             filename,
             mode,
             options,
+            extra,
         }),
         Opcode::Wrq => Ok(Packet::Wrq {
             filename,
             mode,
             options,
+            extra,
         }),
         _ => Err("Non request opcode".into()),
     }
@@ -267,11 +401,21 @@ fn parse_data(buf: &[u8]) -> Result<Packet, Box<dyn Error>> {
 }
 
 fn parse_ack(buf: &[u8]) -> Result<Packet, Box<dyn Error>> {
-    Ok(Packet::Ack(Convert::to_u16(&buf[2..])?))
+    let block_num = Convert::to_u16(&buf[2..])?;
+    if buf.len() <= 4 {
+        return Ok(Packet::Ack(block_num));
+    }
+
+    let missing = buf[4..]
+        .chunks(2)
+        .map(Convert::to_u16)
+        .collect::<Result<_, _>>()?;
+    Ok(Packet::Sack { block_num, missing })
 }
 
 fn parse_oack(buf: &[u8]) -> Result<Packet, Box<dyn Error>> {
     let mut options = vec![];
+    let mut extra = vec![];
     let mut value: String;
     let mut option;
     let mut zero_index = 1usize;
@@ -279,19 +423,21 @@ fn parse_oack(buf: &[u8]) -> Result<Packet, Box<dyn Error>> {
     while zero_index < buf.len() - 1 {
         (option, zero_index) = Convert::to_string(buf, zero_index + 1)?;
         (value, zero_index) = Convert::to_string(buf, zero_index + 1)?;
-        if let Ok(option) = OptionType::from_str(option.to_lowercase().as_str()) {
+        if let Ok(option_type) = OptionType::from_str(option.to_lowercase().as_str()) {
             options.push(TransferOption {
-                option,
+                option: option_type,
                 value: value.parse()?,
             });
+        } else {
+            extra.push((option, value));
         }
     }
 
-    Ok(Packet::Oack(options))
+    Ok(Packet::Oack(options, extra))
 }
 
 fn parse_error(buf: &[u8]) -> Result<Packet, Box<dyn Error>> {
-    let code = ErrorCode::from_u16(Convert::to_u16(&buf[2..])?)?;
+    let code = ErrorCode::from(Convert::to_u16(&buf[2..])?);
     if let Ok((msg, _)) = Convert::to_string(buf, 4) {
         Ok(Packet::Error { code, msg })
     } else {
@@ -302,69 +448,66 @@ fn parse_error(buf: &[u8]) -> Result<Packet, Box<dyn Error>> {
     }
 }
 
-fn serialize_rrq(filename: &String, mode: &String, options: &Vec<TransferOption>) -> Vec<u8> {
-    let mut buf = [
-        &Opcode::Rrq.as_bytes(),
-        filename.as_bytes(),
-        &[0x00],
-        mode.as_bytes(),
-        &[0x00],
-    ]
-    .concat();
-
-    for option in options {
-        buf = [buf, option.as_bytes()].concat();
-    }
-    buf
+fn serialize_rq(
+    buf: &mut Vec<u8>,
+    opcode: Opcode,
+    filename: &str,
+    mode: &str,
+    options: &[TransferOption],
+    extra: &[(String, String)],
+) {
+    buf.extend_from_slice(&opcode.as_bytes());
+    buf.extend_from_slice(filename.as_bytes());
+    buf.push(0x00);
+    buf.extend_from_slice(mode.as_bytes());
+    buf.push(0x00);
+    serialize_options(buf, options, extra);
 }
 
-fn serialize_wrq(filename: &String, mode: &String, options: &Vec<TransferOption>) -> Vec<u8> {
-    let mut buf = [
-        &Opcode::Wrq.as_bytes(),
-        filename.as_bytes(),
-        &[0x00],
-        mode.as_bytes(),
-        &[0x00],
-    ]
-    .concat();
-
-    for option in options {
-        buf = [buf, option.as_bytes()].concat();
-    }
-    buf
+fn serialize_data(buf: &mut Vec<u8>, block_num: u16, data: &[u8]) {
+    buf.extend_from_slice(&Opcode::Data.as_bytes());
+    buf.extend_from_slice(&Convert::from_u16(block_num));
+    buf.extend_from_slice(data);
 }
 
-fn serialize_data(block_num: &u16, data: &Vec<u8>) -> Vec<u8> {
-    [
-        &Opcode::Data.as_bytes(),
-        &block_num.to_be_bytes(),
-        data.as_slice(),
-    ]
-    .concat()
+fn serialize_ack(buf: &mut Vec<u8>, block_num: u16) {
+    buf.extend_from_slice(&Opcode::Ack.as_bytes());
+    buf.extend_from_slice(&Convert::from_u16(block_num));
 }
 
-fn serialize_ack(block_num: &u16) -> Vec<u8> {
-    [Opcode::Ack.as_bytes(), block_num.to_be_bytes()].concat()
+fn serialize_sack(buf: &mut Vec<u8>, block_num: u16, missing: &[u16]) {
+    serialize_ack(buf, block_num);
+    for block in missing {
+        buf.extend_from_slice(&Convert::from_u16(*block));
+    }
 }
 
-fn serialize_error(code: &ErrorCode, msg: &String) -> Vec<u8> {
-    [
-        &Opcode::Error.as_bytes()[..],
-        &code.as_bytes()[..],
-        msg.as_bytes(),
-        &[0x00],
-    ]
-    .concat()
+fn serialize_error(buf: &mut Vec<u8>, code: ErrorCode, msg: &str) {
+    buf.extend_from_slice(&Opcode::Error.as_bytes());
+    buf.extend_from_slice(&code.as_bytes());
+    buf.extend_from_slice(msg.as_bytes());
+    buf.push(0x00);
 }
 
-fn serialize_oack(options: &Vec<TransferOption>) -> Vec<u8> {
-    let mut buf = Opcode::Oack.as_bytes().to_vec();
+fn serialize_oack(buf: &mut Vec<u8>, options: &[TransferOption], extra: &[(String, String)]) {
+    buf.extend_from_slice(&Opcode::Oack.as_bytes());
+    serialize_options(buf, options, extra);
+}
 
+fn serialize_options(buf: &mut Vec<u8>, options: &[TransferOption], extra: &[(String, String)]) {
     for option in options {
-        buf = [buf, option.as_bytes()].concat();
+        buf.extend_from_slice(option.option.as_str().as_bytes());
+        buf.push(0x00);
+        // Writing to a Vec cannot fail
+        let _ = write!(buf, "{}", option.value);
+        buf.push(0x00);
+    }
+    for (key, value) in extra {
+        buf.extend_from_slice(key.as_bytes());
+        buf.push(0x00);
+        buf.extend_from_slice(value.as_bytes());
+        buf.push(0x00);
     }
-
-    buf
 }
 
 #[cfg(test)]
@@ -386,6 +529,7 @@ mod tests {
             filename,
             mode,
             options,
+            ..
         }) = parse_rq(&buf, Opcode::Rrq)
         {
             assert_eq!(filename, "test.png");
@@ -423,6 +567,7 @@ mod tests {
             filename,
             mode,
             options,
+            ..
         }) = parse_rq(&buf, Opcode::Rrq)
         {
             assert_eq!(filename, "test.png");
@@ -469,6 +614,7 @@ mod tests {
             filename,
             mode,
             options,
+            ..
         }) = parse_rq(&buf, Opcode::Wrq)
         {
             assert_eq!(filename, "test.png");
@@ -479,6 +625,28 @@ mod tests {
         }
     }
 
+    #[test]
+    fn parses_mode_case_insensitively() {
+        let buf = [
+            &Opcode::Wrq.as_bytes()[..],
+            ("test.png".as_bytes()),
+            &[0x00],
+            ("OCTET".as_bytes()),
+            &[0x00],
+        ]
+        .concat();
+
+        assert_eq!(
+            parse_rq(&buf, Opcode::Wrq).unwrap(),
+            Packet::Wrq {
+                filename: "test.png".to_string(),
+                mode: "octet".to_string(),
+                options: vec![],
+                extra: vec![],
+            }
+        );
+    }
+
     #[test]
     fn parses_write_request_with_options() {
         let buf = [
@@ -502,6 +670,7 @@ mod tests {
             filename,
             mode,
             options,
+            ..
         }) = parse_rq(&buf, Opcode::Wrq)
         {
             assert_eq!(filename, "test.png");
@@ -548,6 +717,27 @@ mod tests {
         }
     }
 
+    #[test]
+    fn parses_owned_data_in_place() {
+        let mut buf = Vec::with_capacity(516);
+        buf.extend_from_slice(&Opcode::Data.as_bytes());
+        buf.extend_from_slice(&7u16.to_be_bytes());
+        buf.extend_from_slice(&[0x0A, 0x0B, 0x0C]);
+        let ptr = buf.as_ptr();
+
+        let Ok(Packet::Data { block_num, data }) = Packet::deserialize_owned(buf) else {
+            panic!("cannot parse data")
+        };
+        assert_eq!(block_num, 7);
+        assert_eq!(data, [0x0A, 0x0B, 0x0C]);
+        assert_eq!(data.as_ptr(), ptr);
+
+        // Other packets are parsed as usual
+        let buf = [&Opcode::Ack.as_bytes()[..], &12u16.to_be_bytes()].concat();
+        assert_eq!(Packet::deserialize_owned(buf).unwrap(), Packet::Ack(12));
+        assert!(Packet::deserialize_owned(vec![0x00, 0x03, 0x01]).is_err());
+    }
+
     #[test]
     fn parses_ack() {
         let buf = [&Opcode::Ack.as_bytes()[..], &12u16.to_be_bytes()].concat();
@@ -559,6 +749,20 @@ mod tests {
         }
     }
 
+    #[test]
+    fn parses_sack() {
+        let buf = [0x00, 0x04, 0x00, 0x01, 0x00, 0x02, 0x00, 0x04];
+
+        assert_eq!(
+            parse_ack(&buf).unwrap(),
+            Packet::Sack {
+                block_num: 1,
+                missing: vec![2, 4]
+            }
+        );
+        assert!(parse_ack(&buf[..7]).is_err());
+    }
+
     #[test]
     fn parses_oack() {
         let buf = [
@@ -578,7 +782,7 @@ mod tests {
         ]
         .concat();
 
-        if let Ok(Packet::Oack(options)) = parse_oack(&buf) {
+        if let Ok(Packet::Oack(options, _)) = parse_oack(&buf) {
             assert_eq!(options.len(), 3);
             assert_eq!(
                 options[0],
@@ -606,6 +810,28 @@ mod tests {
         }
     }
 
+    #[test]
+    fn parses_oack_with_unknown_option() {
+        let buf = [
+            &Opcode::Oack.as_bytes()[..],
+            b"x-unknown\0yes\0blksize\x001024\0",
+        ]
+        .concat();
+
+        if let Ok(Packet::Oack(options, extra)) = parse_oack(&buf) {
+            assert_eq!(
+                options,
+                vec![TransferOption {
+                    option: OptionType::BlockSize,
+                    value: 1024
+                }]
+            );
+            assert_eq!(extra, [("x-unknown".to_string(), "yes".to_string())]);
+        } else {
+            panic!("cannot parse oack")
+        }
+    }
+
     #[test]
     fn parses_error() {
         let buf = [
@@ -641,6 +867,26 @@ mod tests {
         }
     }
 
+    #[test]
+    fn parses_unknown_error_code() {
+        let buf = [
+            &Opcode::Error.as_bytes()[..],
+            &[0x00, 0x2a],
+            b"custom",
+            &[0x00],
+        ]
+        .concat();
+
+        if let Ok(Packet::Error { code, msg }) = parse_error(&buf) {
+            assert_eq!(code, ErrorCode::Other(42));
+            assert_eq!(code.to_string(), "Unknown Error 42");
+            assert_eq!(msg, "custom");
+            assert_eq!(Packet::Error { code, msg }.serialize().unwrap(), buf);
+        } else {
+            panic!("cannot parse error")
+        }
+    }
+
     #[test]
     fn serializes_rrq() {
         let serialized_data = vec![
@@ -648,7 +894,14 @@ mod tests {
         ];
 
         assert_eq!(
-            serialize_rrq(&"test".into(), &"octet".into(), &vec![]),
+            Packet::Rrq {
+                filename: "test".into(),
+                mode: "octet".into(),
+                options: vec![],
+                extra: vec![],
+            }
+            .serialize()
+            .unwrap(),
             serialized_data
         )
     }
@@ -663,10 +916,10 @@ mod tests {
         ];
 
         assert_eq!(
-            serialize_rrq(
-                &"test".into(),
-                &"octet".into(),
-                &vec![
+            Packet::Rrq {
+                filename: "test".into(),
+                mode: "octet".into(),
+                options: vec![
                     TransferOption {
                         option: OptionType::BlockSize,
                         value: 1468,
@@ -679,8 +932,11 @@ mod tests {
                         option: OptionType::Timeout,
                         value: 5,
                     }
-                ]
-            ),
+                ],
+                extra: vec![],
+            }
+            .serialize()
+            .unwrap(),
             serialized_data
         )
     }
@@ -692,7 +948,14 @@ mod tests {
         ];
 
         assert_eq!(
-            serialize_wrq(&"test".into(), &"octet".into(), &vec![]),
+            Packet::Wrq {
+                filename: "test".into(),
+                mode: "octet".into(),
+                options: vec![],
+                extra: vec![],
+            }
+            .serialize()
+            .unwrap(),
             serialized_data
         )
     }
@@ -707,10 +970,10 @@ mod tests {
         ];
 
         assert_eq!(
-            serialize_wrq(
-                &"test".into(),
-                &"octet".into(),
-                &vec![
+            Packet::Wrq {
+                filename: "test".into(),
+                mode: "octet".into(),
+                options: vec![
                     TransferOption {
                         option: OptionType::BlockSize,
                         value: 1468,
@@ -723,8 +986,11 @@ mod tests {
                         option: OptionType::Timeout,
                         value: 5,
                     }
-                ]
-            ),
+                ],
+                extra: vec![],
+            }
+            .serialize()
+            .unwrap(),
             serialized_data
         )
     }
@@ -734,7 +1000,12 @@ mod tests {
         let serialized_data = vec![0x00, 0x03, 0x00, 0x10, 0x01, 0x02, 0x03, 0x04];
 
         assert_eq!(
-            serialize_data(&16, &vec![0x01, 0x02, 0x03, 0x04]),
+            Packet::Data {
+                block_num: 16,
+                data: vec![0x01, 0x02, 0x03, 0x04]
+            }
+            .serialize()
+            .unwrap(),
             serialized_data
         );
     }
@@ -743,7 +1014,166 @@ mod tests {
     fn serializes_ack() {
         let serialized_ack = vec![0x00, 0x04, 0x04, 0xD2];
 
-        assert_eq!(serialize_ack(&1234), serialized_ack);
+        assert_eq!(Packet::Ack(1234).serialize().unwrap(), serialized_ack);
+    }
+
+    #[test]
+    fn serializes_sack() {
+        let serialized_sack = vec![0x00, 0x04, 0x04, 0xD2, 0x04, 0xD4];
+
+        let sack = Packet::Sack {
+            block_num: 1234,
+            missing: vec![1236],
+        };
+        assert_eq!(sack.serialize().unwrap(), serialized_sack);
+    }
+
+    #[test]
+    fn serializes_into_reused_buffer() {
+        let options = vec![
+            TransferOption {
+                option: OptionType::BlockSize,
+                value: 1432,
+            },
+            TransferOption {
+                option: OptionType::TransferSize,
+                value: 0,
+            },
+            TransferOption {
+                option: OptionType::WindowSize,
+                value: 16,
+            },
+        ];
+        let packets = [
+            Packet::Rrq {
+                filename: "test".into(),
+                mode: "octet".into(),
+                options: options.clone(),
+                extra: vec![],
+            },
+            Packet::Wrq {
+                filename: "test".into(),
+                mode: "netascii".into(),
+                options: vec![],
+                extra: vec![],
+            },
+            Packet::Data {
+                block_num: 16,
+                data: vec![0x01, 0x02, 0x03, 0x04],
+            },
+            Packet::Ack(1234),
+            Packet::Sack {
+                block_num: 1234,
+                missing: vec![1235, 1237],
+            },
+            Packet::Error {
+                code: ErrorCode::FileNotFound,
+                msg: "file not found".into(),
+            },
+            Packet::Oack(options, vec![]),
+        ];
+
+        let mut buf = vec![0xFF; 1024];
+        for packet in &packets {
+            let len = packet.serialize_into(&mut buf).unwrap();
+            assert_eq!(len, buf.len());
+            assert_eq!(buf, packet.serialize().unwrap());
+        }
+        assert_eq!(
+            buf,
+            [
+                &[0x00, 0x06][..],
+                b"blksize\x001432\x00tsize\x000\x00windowsize\x0016\x00"
+            ]
+            .concat()
+        );
+    }
+
+    #[test]
+    fn displays_packets_on_one_line() {
+        let options = vec![
+            TransferOption {
+                option: OptionType::BlockSize,
+                value: 1024,
+            },
+            TransferOption {
+                option: OptionType::WindowSize,
+                value: 8,
+            },
+        ];
+        let packets = [
+            (
+                Packet::Rrq {
+                    filename: "file".into(),
+                    mode: "octet".into(),
+                    options: options.clone(),
+                    extra: vec![],
+                },
+                r#"RRQ "file" octet [blksize=1024,windowsize=8]"#,
+            ),
+            (
+                Packet::Wrq {
+                    filename: "file".into(),
+                    mode: "netascii".into(),
+                    options: vec![],
+                    extra: vec![],
+                },
+                r#"WRQ "file" netascii"#,
+            ),
+            (
+                Packet::Data {
+                    block_num: 42,
+                    data: vec![0; 512],
+                },
+                "DATA #42 (512 bytes)",
+            ),
+            (Packet::Ack(42), "ACK #42"),
+            (
+                Packet::Sack {
+                    block_num: 42,
+                    missing: vec![44, 46],
+                },
+                "SACK #42 [44, 46]",
+            ),
+            (
+                Packet::Error {
+                    code: ErrorCode::FileNotFound,
+                    msg: "File not found".into(),
+                },
+                r#"ERROR 1 "File not found""#,
+            ),
+            (
+                Packet::Oack(options, vec![("rollover".into(), "0".into())]),
+                "OACK [blksize=1024,windowsize=8,rollover=0]",
+            ),
+            (Packet::Oack(vec![], vec![]), "OACK []"),
+        ];
+
+        for (packet, display) in packets {
+            assert_eq!(packet.to_string(), display);
+        }
+    }
+
+    #[test]
+    fn uses_big_endian_block_numbers() {
+        let data = Packet::Data {
+            block_num: 0x0102,
+            data: vec![0xFF],
+        };
+        assert_eq!(data.serialize().unwrap(), [0x00, 0x03, 0x01, 0x02, 0xFF]);
+        assert_eq!(
+            Packet::deserialize(&[0x00, 0x03, 0x01, 0x02, 0xFF]).unwrap(),
+            data
+        );
+
+        assert_eq!(
+            Packet::Ack(0x0102).serialize().unwrap(),
+            [0x00, 0x04, 0x01, 0x02]
+        );
+        assert_eq!(
+            Packet::deserialize(&[0x00, 0x04, 0x01, 0x02]).unwrap(),
+            Packet::Ack(0x0102)
+        );
     }
 
     #[test]
@@ -754,10 +1184,12 @@ mod tests {
         ];
 
         assert_eq!(
-            serialize_error(
-                &ErrorCode::IllegalOperation,
-                &"illegal operation".to_string()
-            ),
+            Packet::Error {
+                code: ErrorCode::IllegalOperation,
+                msg: "illegal operation".to_string()
+            }
+            .serialize()
+            .unwrap(),
             serialized_error
         );
     }
@@ -770,10 +1202,15 @@ mod tests {
         ];
 
         assert_eq!(
-            serialize_oack(&vec![TransferOption {
-                option: OptionType::BlockSize,
-                value: 1432
-            }]),
+            Packet::Oack(
+                vec![TransferOption {
+                    option: OptionType::BlockSize,
+                    value: 1432
+                }],
+                vec![]
+            )
+            .serialize()
+            .unwrap(),
             serialized_oack
         );
     }
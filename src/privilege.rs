@@ -0,0 +1,214 @@
+use std::error::Error;
+use std::ffi::CString;
+use std::fs;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+use crate::log::*;
+use crate::Config;
+
+/// Restriction `enum` represents the operations performed by the server to
+/// restrict itself once its socket is bound. They are listed in the order
+/// they must be applied.
+#[derive(Debug, PartialEq)]
+pub enum Restriction {
+    /// Change the root directory of the process to the served directory
+    Chroot(PathBuf),
+    /// Drop supplementary groups and change the group ID of the process
+    SetGid(libc::gid_t),
+    /// Change the user ID of the process
+    SetUid(libc::uid_t),
+}
+
+/// Builds the ordered list of restrictions requested by the [`Config`].
+/// User and group names are resolved here, as the user database is usually not
+/// reachable anymore once jailed.
+pub fn plan(config: &Config) -> Result<Vec<Restriction>, Box<dyn Error>> {
+    let mut restrictions = vec![];
+
+    if config.chroot {
+        restrictions.push(Restriction::Chroot(fs::canonicalize(&config.directory)?));
+    }
+
+    // Group must be changed first, as it is not permitted anymore once the user is changed
+    let user = config.user.as_deref().map(resolve_user).transpose()?;
+    match (config.group.as_deref(), user) {
+        (Some(group), _) => restrictions.push(Restriction::SetGid(resolve_group(group)?)),
+        (None, Some((_, gid))) => restrictions.push(Restriction::SetGid(gid)),
+        (None, None) => (),
+    }
+    if let Some((uid, _)) = user {
+        restrictions.push(Restriction::SetUid(uid));
+    }
+
+    Ok(restrictions)
+}
+
+/// Applies the restrictions, in order. This must be called after binding the
+/// server socket and before accepting any request.
+pub fn apply(restrictions: &[Restriction]) -> Result<(), Box<dyn Error>> {
+    for restriction in restrictions {
+        match restriction {
+            Restriction::Chroot(root) => {
+                let path = CString::new(root.as_os_str().as_bytes())?;
+                // SAFETY: path is a valid NUL terminated string
+                if unsafe { libc::chroot(path.as_ptr()) } != 0 {
+                    return Err(format!(
+                        "chroot to {}: {}",
+                        root.display(),
+                        io::Error::last_os_error()
+                    )
+                    .into());
+                }
+                std::env::set_current_dir("/")?;
+                log_info!("Jailed into {}", root.display());
+            }
+            Restriction::SetGid(gid) => {
+                // SAFETY: an empty group list is valid
+                if unsafe { libc::setgroups(0, std::ptr::null()) } != 0
+                    // SAFETY: setgid has no memory safety requirement
+                    || unsafe { libc::setgid(*gid) } != 0
+                {
+                    return Err(format!("setgid to {gid}: {}", io::Error::last_os_error()).into());
+                }
+                log_info!("Changed group to {gid}");
+            }
+            Restriction::SetUid(uid) => {
+                // SAFETY: setuid has no memory safety requirement
+                if unsafe { libc::setuid(*uid) } != 0 {
+                    return Err(format!("setuid to {uid}: {}", io::Error::last_os_error()).into());
+                }
+                log_info!("Changed user to {uid}");
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the user ID and primary group ID of a user name or numeric ID.
+fn resolve_user(user: &str) -> Result<(libc::uid_t, libc::gid_t), Box<dyn Error>> {
+    let passwd = if let Ok(uid) = user.parse::<libc::uid_t>() {
+        // SAFETY: getpwuid has no memory safety requirement
+        unsafe { libc::getpwuid(uid) }
+    } else {
+        let name = CString::new(user)?;
+        // SAFETY: name is a valid NUL terminated string
+        unsafe { libc::getpwnam(name.as_ptr()) }
+    };
+
+    if passwd.is_null() {
+        return Err(format!("unknown user {user}").into());
+    }
+
+    // SAFETY: passwd was checked to be a valid entry
+    unsafe { Ok(((*passwd).pw_uid, (*passwd).pw_gid)) }
+}
+
+/// Returns the group ID of a group name or numeric ID.
+fn resolve_group(group: &str) -> Result<libc::gid_t, Box<dyn Error>> {
+    if let Ok(gid) = group.parse::<libc::gid_t>() {
+        return Ok(gid);
+    }
+
+    let name = CString::new(group)?;
+    // SAFETY: name is a valid NUL terminated string
+    let entry = unsafe { libc::getgrnam(name.as_ptr()) };
+    if entry.is_null() {
+        return Err(format!("unknown group {group}").into());
+    }
+
+    // SAFETY: entry was checked to be a valid entry
+    unsafe { Ok((*entry).gr_gid) }
+}
+
+/// Converts a `path` located inside `root` to the path it will have once the
+/// process is jailed into `root`.
+pub fn jail_path(root: &Path, path: &Path) -> Result<PathBuf, Box<dyn Error>> {
+    let path = fs::canonicalize(path)?;
+    match path.strip_prefix(root) {
+        Ok(relative) => Ok(Path::new("/").join(relative)),
+        Err(_) => Err(format!(
+            "{} is outside of the chroot directory {}",
+            path.display(),
+            root.display()
+        )
+        .into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plans_chroot_only_when_requested() {
+        let mut config = Config {
+            directory: PathBuf::from("/"),
+            ..Default::default()
+        };
+        assert_eq!(plan(&config).unwrap(), vec![]);
+
+        config.chroot = true;
+        assert_eq!(
+            plan(&config).unwrap(),
+            vec![Restriction::Chroot(PathBuf::from("/"))]
+        );
+    }
+
+    #[test]
+    fn plans_group_before_user_after_chroot() {
+        let config = Config {
+            directory: PathBuf::from("/"),
+            chroot: true,
+            user: Some("0".to_string()),
+            group: Some("1234".to_string()),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            plan(&config).unwrap(),
+            vec![
+                Restriction::Chroot(PathBuf::from("/")),
+                Restriction::SetGid(1234),
+                Restriction::SetUid(0),
+            ]
+        );
+    }
+
+    #[test]
+    fn plans_primary_group_of_user() {
+        let config = Config {
+            user: Some("0".to_string()),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            plan(&config).unwrap(),
+            vec![Restriction::SetGid(0), Restriction::SetUid(0)]
+        );
+    }
+
+    #[test]
+    fn returns_error_on_unknown_user() {
+        let config = Config {
+            user: Some("no-such-user-for-tftpd".to_string()),
+            ..Default::default()
+        };
+
+        assert!(plan(&config).is_err());
+    }
+
+    #[test]
+    fn converts_paths_into_jail() {
+        let root = fs::canonicalize(env!("CARGO_MANIFEST_DIR")).unwrap();
+
+        assert_eq!(jail_path(&root, &root).unwrap(), PathBuf::from("/"));
+        assert_eq!(
+            jail_path(&root, &root.join("src")).unwrap(),
+            PathBuf::from("/src")
+        );
+        assert!(jail_path(&root.join("src"), &root).is_err());
+    }
+}
@@ -2,19 +2,20 @@ use std::cmp::max;
 use std::collections::HashMap;
 use std::error::Error;
 use std::net::{SocketAddr, UdpSocket};
-use std::path::{Path, PathBuf, MAIN_SEPARATOR};
+use std::path::{Component, Path, PathBuf, MAIN_SEPARATOR};
 use std::sync::mpsc::Sender;
 use std::sync::{atomic::AtomicBool, Arc};
+use std::thread::JoinHandle;
 use std::time::Duration;
 
-#[cfg(debug_assertions)]
 use crate::options::OptionFmt;
 use crate::options::{OptionsPrivate, OptionsProtocol, DEFAULT_BLOCK_SIZE};
-use crate::{log::*, ServerSocket, Socket, TransferOption, Worker};
-use crate::{Config, ErrorCode, Packet};
+#[cfg(unix)]
+use crate::privilege::{self, Restriction};
+use crate::{log::*, RateLimitedSocket, ServerSocket, Socket, TransferOption, Worker};
+use crate::{AccessList, Compression, Config, ErrorCode, OptionType, Packet, TftpError};
 
-#[cfg(test)]
-use crate::OptionType;
+type FilenameResolver = Box<dyn Fn(&str, SocketAddr) -> Option<PathBuf> + Send>;
 
 /// Server `struct` is used for handling incoming TFTP requests.
 ///
@@ -40,30 +41,100 @@ pub struct Server {
     overwrite: bool,
     largest_block_size: u16,
     clients: HashMap<SocketAddr, Sender<Packet>>,
+    workers: Vec<JoinHandle<Result<(), TftpError>>>,
+    max_workers: Option<usize>,
+    access: AccessList,
     opt_local: OptionsPrivate,
     abort: Arc<AtomicBool>,
+    filename_resolver: Option<FilenameResolver>,
 }
 
 impl Server {
     /// Creates the TFTP Server with the supplied [`Config`].
     pub fn new(config: &Config) -> Result<Server, Box<dyn Error>> {
         let socket = UdpSocket::bind(SocketAddr::from((config.ip_address, config.port)))?;
+        let (receive_directory, send_directory) = restrict(config)?;
         let server = Server {
             socket,
-            receive_directory: config.receive_directory.clone(),
-            send_directory: config.send_directory.clone(),
+            receive_directory,
+            send_directory,
             single_port: config.single_port,
             read_only: config.read_only,
             overwrite: config.overwrite,
             largest_block_size: DEFAULT_BLOCK_SIZE,
             clients: HashMap::new(),
+            workers: Vec::new(),
+            max_workers: config.max_workers,
+            access: config.access.clone(),
             opt_local: config.opt_local.clone(),
             abort: Arc::new(AtomicBool::new(false)),
+            filename_resolver: None,
         };
 
         Ok(server)
     }
 
+    /// Creates a read-only TFTP Server serving the files of `dir` on `addr`, with
+    /// default options. Use [`Server::run()`] to start serving.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use std::path::Path;
+    /// use tftpd::Server;
+    ///
+    /// let server = Server::serve_dir("127.0.0.1:0".parse().unwrap(), Path::new(".")).unwrap();
+    /// println!("Serving on {}", server.local_addr().unwrap());
+    /// ```
+    pub fn serve_dir(addr: SocketAddr, dir: &Path) -> Result<Server, Box<dyn Error>> {
+        Server::new(&Config {
+            ip_address: addr.ip(),
+            port: addr.port(),
+            directory: dir.to_path_buf(),
+            receive_directory: dir.to_path_buf(),
+            send_directory: dir.to_path_buf(),
+            read_only: true,
+            ..Default::default()
+        })
+    }
+
+    /// Serves requests until the abort flag is set. See [`Server::listen()`].
+    pub fn run(&mut self) {
+        self.listen()
+    }
+
+    /// Returns the [`SocketAddr`] the server is listening on.
+    pub fn local_addr(&self) -> Result<SocketAddr, Box<dyn Error>> {
+        Ok(self.socket.local_addr()?)
+    }
+
+    /// Returns the count of transfers currently running.
+    pub fn active_workers(&mut self) -> usize {
+        self.workers.retain(|handle| !handle.is_finished());
+        self.workers.len()
+    }
+
+    /// Sets a function choosing the file served for a read request, given the
+    /// requested filename and the client address. A relative path is taken in
+    /// the send directory, and `None` rejects the request with a file not found
+    /// error. The path returned is trusted, without the checks of the filenames
+    /// received.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use std::path::{Path, PathBuf};
+    /// use tftpd::Server;
+    ///
+    /// let mut server = Server::serve_dir("127.0.0.1:0".parse().unwrap(), Path::new(".")).unwrap();
+    /// server.set_filename_resolver(Box::new(|filename, client| {
+    ///     (filename == "boot.cfg").then(|| PathBuf::from(format!("by-ip/{}.cfg", client.ip())))
+    /// }));
+    /// ```
+    pub fn set_filename_resolver(&mut self, resolver: FilenameResolver) {
+        self.filename_resolver = Some(resolver);
+    }
+
     /// Starts listening for connections. Note that this function does not finish running until termination.
     pub fn listen(&mut self) {
         // To check abort flag every seconds
@@ -73,27 +144,47 @@ impl Server {
 
         loop {
             let received = if self.single_port {
-                self.socket
-                    .recv_from_with_size(self.largest_block_size as usize)
+                self.receive(self.largest_block_size)
             } else {
-                Socket::recv_from(&self.socket)
+                self.receive(DEFAULT_BLOCK_SIZE)
             };
 
             if let Ok((packet, from)) = received {
+                if matches!(packet, Packet::Rrq { .. } | Packet::Wrq { .. }) && self.is_busy() {
+                    if Socket::send_to(
+                        &self.socket,
+                        &Packet::Error {
+                            code: ErrorCode::NotDefined,
+                            msg: "server is busy, try again later".to_string(),
+                        },
+                        &from,
+                    )
+                    .is_err()
+                    {
+                        log_err!("Could not send error packet");
+                    };
+                    log_warn!("Refused request from {from}: too many transfers running");
+                    continue;
+                }
+
                 match packet {
                     Packet::Rrq {
                         filename,
                         mut options,
+                        extra,
                         ..
                     } => {
                         log_info!("Received Read request from {from}: {filename}");
-                        if let Err(err) = self.handle_rrq(filename.clone(), &mut options, &from) {
+                        if let Err(err) =
+                            self.handle_rrq(filename.clone(), &mut options, &extra, &from)
+                        {
                             log_err!("Error while sending file: {err}")
                         }
                     }
                     Packet::Wrq {
                         filename,
                         mut options,
+                        extra,
                         ..
                     } => {
                         if self.read_only {
@@ -101,7 +192,7 @@ impl Server {
                                 &self.socket,
                                 &Packet::Error {
                                     code: ErrorCode::AccessViolation,
-                                    msg: "server is read-only".to_string(),
+                                    msg: "upload disabled".to_string(),
                                 },
                                 &from,
                             )
@@ -109,11 +200,11 @@ impl Server {
                             {
                                 log_err!("Could not send error packet");
                             };
-                            log_warn!("Received write request while in read-only mode");
+                            log_warn!("Refused write request from {from}: {filename}, server is read-only");
                             continue;
                         }
                         log_info!("Received Write request from {from}: {filename}");
-                        if let Err(err) = self.handle_wrq(filename, &mut options, &from) {
+                        if let Err(err) = self.handle_wrq(filename, &mut options, &extra, &from) {
                             log_err!("Error while receiving file: {err}")
                         }
                     }
@@ -144,15 +235,64 @@ impl Server {
         }
     }
 
+    /// Receives the next packet, the datagrams from the clients refused by
+    /// the access list are dropped silently, before being parsed.
+    fn receive(&self, size: u16) -> Result<(Packet, SocketAddr), Box<dyn Error>> {
+        let mut buf = vec![0; size as usize + 4];
+        let (amt, from) = self.socket.recv_from(&mut buf)?;
+        if !self.access.permits(from.ip()) {
+            log_dbg!("Ignored datagram from {from}");
+            return Err("Client address refused".into());
+        }
+        if request_filename(&buf[..amt]).is_some_and(|filename| !validate_filename(filename)) {
+            if Socket::send_to(
+                &self.socket,
+                &Packet::Error {
+                    code: ErrorCode::NotDefined,
+                    msg: "invalid filename".to_string(),
+                },
+                &from,
+            )
+            .is_err()
+            {
+                log_err!("Could not send error packet");
+            };
+            log_warn!("Refused request from {from}: invalid filename");
+            return Err("Invalid filename".into());
+        }
+
+        Ok((Packet::deserialize(&buf[..amt])?, from))
+    }
+
     fn handle_rrq(
         &mut self,
         filename: String,
-        options: &mut [TransferOption],
+        options: &mut Vec<TransferOption>,
+        extra: &[(String, String)],
         to: &SocketAddr,
     ) -> Result<(), Box<dyn Error>> {
-        let file_path = convert_file_path(&filename);
-        let file_path = &self.send_directory.join(file_path);
-        match check_file_exists(file_path, &self.send_directory) {
+        let compression = Compression::find(extra);
+        let (file_path, status) = match &self.filename_resolver {
+            Some(resolver) => match resolver(&filename, *to) {
+                Some(path) => {
+                    let path = self.send_directory.join(path);
+                    let status = if path.exists() {
+                        ErrorCode::FileExists
+                    } else {
+                        ErrorCode::FileNotFound
+                    };
+                    (path, status)
+                }
+                None => (PathBuf::from(&filename), ErrorCode::FileNotFound),
+            },
+            None => {
+                let path = self.send_directory.join(convert_file_path(&filename));
+                let status = check_file_exists(&path, &self.send_directory);
+                (path, status)
+            }
+        };
+        let file_path = &file_path;
+        match status {
             ErrorCode::FileNotFound => {
                 log_warn!("Cannot find requested file: {}", file_path.display());
                 Socket::send_to(
@@ -176,10 +316,17 @@ impl Server {
                 )
             }
             ErrorCode::FileExists => {
-                let worker_options = OptionsProtocol::parse(
+                let mut worker_options = OptionsProtocol::parse(
                     options,
                     RequestType::Read(file_path.metadata()?.len()),
                 )?;
+                clamp_memory(
+                    &self.socket,
+                    self.opt_local.max_memory,
+                    &mut worker_options,
+                    options,
+                    to,
+                )?;
                 let mut socket: Box<dyn Socket>;
 
                 if self.single_port {
@@ -193,26 +340,44 @@ impl Server {
                 } else {
                     socket = Box::new(create_multi_socket(&self.socket.local_addr()?, to)?);
                 }
+                if let Some(max_rate) = self.opt_local.max_rate {
+                    socket = Box::new(RateLimitedSocket::new(socket, max_rate));
+                }
 
                 socket.set_read_timeout(worker_options.timeout)?;
                 socket.set_write_timeout(worker_options.timeout)?;
 
                 log_dbg!("  Accepted options: {}", OptionFmt(options));
 
-                accept_request(
-                    &socket,
-                    options,
-                    RequestType::Read(file_path.metadata()?.len()),
-                )?;
+                // Checksum is requested with a placeholder, the worker fills
+                // it with the file one so as not to block other requests, as
+                // the size of the compressed file
+                let checksum = options
+                    .iter()
+                    .any(|option| option.option == OptionType::Checksum);
+                let deferred = checksum || compression.is_some();
+                if !deferred {
+                    accept_request(
+                        &socket,
+                        options,
+                        &[],
+                        RequestType::Read(file_path.metadata()?.len()),
+                    )?;
+                }
 
-                let worker = Worker::new(
+                let mut worker = Worker::new(
                     socket,
                     file_path.clone(),
                     self.opt_local.clone(),
                     worker_options.clone(),
                     self.abort.clone(),
                 );
-                worker.send(!options.is_empty())?;
+                if deferred {
+                    worker.set_oack(options.to_vec());
+                }
+                worker.set_compression(compression);
+                self.workers
+                    .push(worker.send(!options.is_empty() || compression.is_some())?);
                 Ok(())
             }
             _ => Err("Unexpected error code when checking file".into()),
@@ -222,13 +387,22 @@ impl Server {
     fn handle_wrq(
         &mut self,
         filename: String,
-        options: &mut [TransferOption],
+        options: &mut Vec<TransferOption>,
+        extra: &[(String, String)],
         to: &SocketAddr,
     ) -> Result<(), Box<dyn Error>> {
+        let compression = Compression::find(extra);
         let file_path = convert_file_path(&filename);
         let file_path = &self.receive_directory.join(file_path);
         let initialize_write = &mut || -> Result<(), Box<dyn Error>> {
-            let worker_options = OptionsProtocol::parse(options, RequestType::Write)?;
+            let mut worker_options = OptionsProtocol::parse(options, RequestType::Write)?;
+            clamp_memory(
+                &self.socket,
+                self.opt_local.max_memory,
+                &mut worker_options,
+                options,
+                to,
+            )?;
             let mut socket: Box<dyn Socket>;
 
             if self.single_port {
@@ -240,21 +414,26 @@ impl Server {
             } else {
                 socket = Box::new(create_multi_socket(&self.socket.local_addr()?, to)?);
             }
+            if let Some(max_rate) = self.opt_local.max_rate {
+                socket = Box::new(RateLimitedSocket::new(socket, max_rate));
+            }
 
             socket.set_read_timeout(worker_options.timeout)?;
             socket.set_write_timeout(worker_options.timeout)?;
 
             log_dbg!("  Accepted options: {}", OptionFmt(options));
-            accept_request(&socket, options, RequestType::Write)?;
+            let accepted: Vec<_> = compression.iter().map(Compression::option).collect();
+            accept_request(&socket, options, &accepted, RequestType::Write)?;
 
-            let worker = Worker::new(
+            let mut worker = Worker::new(
                 socket,
                 file_path.clone(),
                 self.opt_local.clone(),
                 worker_options.clone(),
                 self.abort.clone(),
             );
-            worker.receive()?;
+            worker.set_compression(compression);
+            self.workers.push(worker.receive()?);
             Ok(())
         };
 
@@ -290,6 +469,13 @@ impl Server {
         }
     }
 
+    fn is_busy(&mut self) -> bool {
+        // Also prunes finished workers when unlimited
+        let active_workers = self.active_workers();
+        self.max_workers
+            .is_some_and(|max_workers| max_workers <= active_workers)
+    }
+
     fn route_packet(&self, packet: Packet, to: &SocketAddr) -> Result<(), Box<dyn Error>> {
         if self.clients.contains_key(to) {
             self.clients[to].send(packet)?;
@@ -305,6 +491,42 @@ impl Server {
     }
 }
 
+/// Applies the restrictions requested by the [`Config`] once the socket is bound,
+/// and returns the receive and send directories as seen from the restricted server.
+#[cfg(unix)]
+fn restrict(config: &Config) -> Result<(PathBuf, PathBuf), Box<dyn Error>> {
+    let restrictions = privilege::plan(config)?;
+    let mut directories = (
+        config.receive_directory.clone(),
+        config.send_directory.clone(),
+    );
+
+    for restriction in &restrictions {
+        if let Restriction::Chroot(root) = restriction {
+            directories = (
+                privilege::jail_path(root, &directories.0)?,
+                privilege::jail_path(root, &directories.1)?,
+            );
+        }
+    }
+
+    privilege::apply(&restrictions)?;
+
+    Ok(directories)
+}
+
+#[cfg(not(unix))]
+fn restrict(config: &Config) -> Result<(PathBuf, PathBuf), Box<dyn Error>> {
+    if config.chroot || config.user.is_some() || config.group.is_some() {
+        return Err("chroot and privilege dropping are only supported on Unix".into());
+    }
+
+    Ok((
+        config.receive_directory.clone(),
+        config.send_directory.clone(),
+    ))
+}
+
 #[derive(Debug, PartialEq)]
 pub enum RequestType {
     Read(u64),
@@ -329,6 +551,63 @@ pub fn convert_file_path(filename: &str) -> PathBuf {
     PathBuf::from(normalized_filename)
 }
 
+/// Joins the relative `path` to the `root` directory, refusing the paths
+/// which could lead outside of it: absolute ones, those with `..`
+/// components, and those going through a symbolic link out of `root`.
+pub fn sanitize_path(root: &Path, path: &Path) -> Result<PathBuf, Box<dyn Error>> {
+    let mut joined = root.to_path_buf();
+    for component in path.components() {
+        match component {
+            Component::Normal(name) => joined.push(name),
+            Component::CurDir => {}
+            _ => return Err(format!("Path {} escapes {}", path.display(), root.display()).into()),
+        }
+    }
+
+    // The deepest existing entry is resolved, a dangling link is refused too
+    if let Some(existing) = joined
+        .ancestors()
+        .find(|a| a.starts_with(root) && a.symlink_metadata().is_ok())
+    {
+        let real_root = match root.as_os_str().is_empty() {
+            true => Path::new(".").canonicalize()?,
+            false => root.canonicalize()?,
+        };
+        if !existing
+            .canonicalize()
+            .is_ok_and(|real| real.starts_with(real_root))
+        {
+            return Err(format!(
+                "Path {} links outside of {}",
+                path.display(),
+                root.display()
+            )
+            .into());
+        }
+    }
+
+    Ok(joined)
+}
+
+/// Returns the raw filename of a read or write request datagram, up to its
+/// terminating zero, so that it can be checked before being decoded.
+fn request_filename(buf: &[u8]) -> Option<&[u8]> {
+    if !matches!(buf, [0, 1, ..] | [0, 2, ..]) {
+        return None;
+    }
+    let filename = &buf[2..];
+    let end = filename.iter().position(|&b| b == 0)?;
+
+    Some(&filename[..end])
+}
+
+/// Checks that a requested filename is not empty, is valid UTF-8, and holds
+/// no control characters (NUL included).
+fn validate_filename(filename: &[u8]) -> bool {
+    std::str::from_utf8(filename)
+        .is_ok_and(|filename| !filename.is_empty() && !filename.chars().any(char::is_control))
+}
+
 fn create_single_socket(
     socket: &UdpSocket,
     remote: &SocketAddr,
@@ -349,13 +628,64 @@ fn create_multi_socket(
     Ok(socket)
 }
 
+/// Clamps the negotiated options to the memory budget, if any. A reduced block
+/// size must be acknowledged: it is added to the `options` when the peer
+/// requested some, otherwise the request is refused as the peer expects
+/// blocks of 512 bytes.
+fn clamp_memory<T: Socket>(
+    socket: &T,
+    max_memory: Option<usize>,
+    worker_options: &mut OptionsProtocol,
+    options: &mut Vec<TransferOption>,
+    to: &SocketAddr,
+) -> Result<(), Box<dyn Error>> {
+    let Some(max_memory) = max_memory else {
+        return Ok(());
+    };
+    let block_size = worker_options.block_size;
+    worker_options.clamp_memory(max_memory);
+
+    if worker_options.block_size < block_size
+        && !options
+            .iter()
+            .any(|option| option.option == OptionType::BlockSize)
+    {
+        if options.is_empty() {
+            Socket::send_to(
+                socket,
+                &Packet::Error {
+                    code: ErrorCode::NotDefined,
+                    msg: format!("blocks of {block_size} bytes exceed the memory budget"),
+                },
+                to,
+            )?;
+            return Err(format!("Block size {block_size} exceeds memory budget").into());
+        }
+        options.push(TransferOption {
+            option: OptionType::BlockSize,
+            value: worker_options.block_size as u64,
+        });
+    }
+
+    for option in options {
+        match option.option {
+            OptionType::BlockSize => option.value = worker_options.block_size as u64,
+            OptionType::WindowSize => option.value = worker_options.window_size as u64,
+            _ => (),
+        }
+    }
+
+    Ok(())
+}
+
 fn accept_request<T: Socket>(
     socket: &T,
     options: &[TransferOption],
+    extra: &[(String, String)],
     request_type: RequestType,
 ) -> Result<(), Box<dyn Error>> {
-    if !options.is_empty() {
-        socket.send(&Packet::Oack(options.to_vec()))?;
+    if !options.is_empty() || !extra.is_empty() {
+        socket.send(&Packet::Oack(options.to_vec(), extra.to_vec()))?;
     } else if request_type == RequestType::Write {
         socket.send(&Packet::Ack(0))?;
     }
@@ -376,12 +706,472 @@ fn check_file_exists(file: &Path, directory: &PathBuf) -> ErrorCode {
 }
 
 fn validate_file_path(file: &Path, directory: &PathBuf) -> bool {
-    !file.to_str().unwrap().contains("..") && file.ancestors().any(|a| a == directory)
+    file.strip_prefix(directory)
+        .is_ok_and(|relative| sanitize_path(directory, relative).is_ok())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::fs;
+    use std::net::Ipv4Addr;
+    use std::sync::atomic::Ordering;
+    use std::thread;
+
+    #[test]
+    fn refuses_requests_beyond_max_workers() {
+        let dir = PathBuf::from("target/test/server");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("max_workers.bin"), vec![0xA5; 1500]).unwrap();
+
+        let mut server = Server::new(&Config {
+            ip_address: Ipv4Addr::LOCALHOST.into(),
+            port: 0,
+            receive_directory: dir.clone(),
+            send_directory: dir.clone(),
+            max_workers: Some(1),
+            ..Default::default()
+        })
+        .unwrap();
+        let addr = server.local_addr().unwrap();
+        let abort = server.get_abort_flag();
+        let handle = thread::spawn(move || {
+            server.run();
+            server.active_workers()
+        });
+
+        // First request is served and kept running by not acknowledging
+        let first = request(&addr, "max_workers.bin");
+        let (packet, worker_addr) = Socket::recv_from(&first).unwrap();
+        assert!(matches!(packet, Packet::Data { block_num: 1, .. }));
+
+        let second = request(&addr, "max_workers.bin");
+        assert!(matches!(
+            Socket::recv_from(&second).unwrap().0,
+            Packet::Error {
+                code: ErrorCode::NotDefined,
+                ..
+            }
+        ));
+
+        // Once the first transfer completes, requests are accepted again
+        for block_num in 1..=3 {
+            Socket::send_to(&first, &Packet::Ack(block_num), &worker_addr).unwrap();
+            if block_num < 3 {
+                Socket::recv_from(&first).unwrap();
+            }
+        }
+        thread::sleep(Duration::from_millis(100));
+
+        let third = request(&addr, "max_workers.bin");
+        assert!(matches!(
+            Socket::recv_from(&third).unwrap().0,
+            Packet::Data { block_num: 1, .. }
+        ));
+
+        abort.store(true, Ordering::Relaxed);
+        assert!(handle.join().unwrap() <= 1);
+    }
+
+    #[test]
+    fn acknowledges_block_size_clamped_to_memory_budget() {
+        let dir = PathBuf::from("target/test/server");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("max_memory.bin"), vec![0x5A; 1000]).unwrap();
+
+        let mut server = Server::new(&Config {
+            ip_address: Ipv4Addr::LOCALHOST.into(),
+            port: 0,
+            receive_directory: dir.clone(),
+            send_directory: dir.clone(),
+            opt_local: OptionsPrivate {
+                max_memory: Some(256),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .unwrap();
+        let addr = server.local_addr().unwrap();
+        let abort = server.get_abort_flag();
+        thread::spawn(move || server.run());
+
+        // Without options, the peer cannot be told about smaller blocks
+        let plain = request(&addr, "max_memory.bin");
+        assert!(matches!(
+            Socket::recv_from(&plain).unwrap().0,
+            Packet::Error {
+                code: ErrorCode::NotDefined,
+                ..
+            }
+        ));
+
+        let tsize = TransferOption {
+            option: OptionType::TransferSize,
+            value: 0,
+        };
+        let with_options = request_with_options(&addr, "max_memory.bin", vec![tsize]);
+        let Packet::Oack(options, _) = Socket::recv_from(&with_options).unwrap().0 else {
+            panic!("expected an option acknowledgement");
+        };
+        assert!(options.contains(&TransferOption {
+            option: OptionType::BlockSize,
+            value: 256,
+        }));
+
+        abort.store(true, Ordering::Relaxed);
+    }
+
+    #[test]
+    fn serves_files_chosen_by_resolver() {
+        let dir = PathBuf::from("target/test/server_resolver");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("by-ip")).unwrap();
+        fs::write(dir.join("by-ip/127.0.0.1.cfg"), b"local").unwrap();
+        fs::write(dir.join("boot.cfg"), b"default").unwrap();
+        fs::write(dir.join("other.cfg"), b"other").unwrap();
+
+        let mut server = Server::serve_dir("127.0.0.1:0".parse().unwrap(), &dir).unwrap();
+        server.set_filename_resolver(Box::new(|filename, client| {
+            (filename == "boot.cfg").then(|| PathBuf::from(format!("by-ip/{}.cfg", client.ip())))
+        }));
+        let addr = server.local_addr().unwrap();
+        let abort = server.get_abort_flag();
+        thread::spawn(move || server.run());
+
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        let rrq = |filename: &str| Packet::Rrq {
+            filename: filename.to_string(),
+            mode: "octet".to_string(),
+            options: vec![],
+            extra: vec![],
+        };
+
+        Socket::send_to(&socket, &rrq("boot.cfg"), &addr).unwrap();
+        let (data, worker) = Socket::recv_from(&socket).unwrap();
+        assert_eq!(
+            data,
+            Packet::Data {
+                block_num: 1,
+                data: b"local".to_vec(),
+            }
+        );
+        Socket::send_to(&socket, &Packet::Ack(1), &worker).unwrap();
+
+        // Names unknown to the resolver are not served, even if present
+        Socket::send_to(&socket, &rrq("other.cfg"), &addr).unwrap();
+        assert!(matches!(
+            Socket::recv_from(&socket).unwrap(),
+            (
+                Packet::Error {
+                    code: ErrorCode::FileNotFound,
+                    ..
+                },
+                from
+            ) if from == addr
+        ));
+
+        abort.store(true, Ordering::Relaxed);
+    }
+
+    #[test]
+    fn refuses_write_requests_when_read_only() {
+        let dir = PathBuf::from("target/test/server_read_only");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let mut server = Server::serve_dir("127.0.0.1:0".parse().unwrap(), &dir).unwrap();
+        let addr = server.local_addr().unwrap();
+        let abort = server.get_abort_flag();
+        thread::spawn(move || server.run());
+
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        let wrq = Packet::Wrq {
+            filename: "upload.bin".to_string(),
+            mode: "octet".to_string(),
+            options: vec![],
+            extra: vec![],
+        };
+        Socket::send_to(&socket, &wrq, &addr).unwrap();
+
+        assert_eq!(
+            Socket::recv_from(&socket).unwrap(),
+            (
+                Packet::Error {
+                    code: ErrorCode::AccessViolation,
+                    msg: "upload disabled".to_string(),
+                },
+                addr
+            )
+        );
+        assert!(!dir.join("upload.bin").exists());
+
+        abort.store(true, Ordering::Relaxed);
+    }
+
+    #[test]
+    fn accepts_mode_in_any_case() {
+        let dir = PathBuf::from("target/test/server_mode_case");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let mut server = Server::new(&Config {
+            ip_address: Ipv4Addr::LOCALHOST.into(),
+            port: 0,
+            receive_directory: dir.clone(),
+            send_directory: dir.clone(),
+            ..Default::default()
+        })
+        .unwrap();
+        let addr = server.local_addr().unwrap();
+        let abort = server.get_abort_flag();
+        thread::spawn(move || server.run());
+
+        for mode in ["octet", "OCTET"] {
+            let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+            socket
+                .set_read_timeout(Some(Duration::from_secs(2)))
+                .unwrap();
+            let filename = format!("{mode}.bin");
+            let wrq = Packet::Wrq {
+                filename: filename.clone(),
+                mode: mode.to_string(),
+                options: vec![],
+                extra: vec![],
+            };
+            Socket::send_to(&socket, &wrq, &addr).unwrap();
+
+            let (ack, worker) = Socket::recv_from(&socket).unwrap();
+            assert_eq!(ack, Packet::Ack(0));
+            let data = Packet::Data {
+                block_num: 1,
+                data: vec![0x4F; 100],
+            };
+            Socket::send_to(&socket, &data, &worker).unwrap();
+            assert_eq!(
+                Socket::recv_from(&socket).unwrap(),
+                (Packet::Ack(1), worker)
+            );
+        }
+
+        // The final block is acknowledged once written
+        assert_eq!(fs::read(dir.join("octet.bin")).unwrap(), vec![0x4F; 100]);
+        assert_eq!(fs::read(dir.join("OCTET.bin")).unwrap(), vec![0x4F; 100]);
+
+        abort.store(true, Ordering::Relaxed);
+    }
+
+    #[test]
+    fn ignores_clients_outside_access_list() {
+        let dir = PathBuf::from("target/test/server_access");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let mut server = Server::new(&Config {
+            ip_address: Ipv4Addr::LOCALHOST.into(),
+            port: 0,
+            receive_directory: dir.clone(),
+            send_directory: dir.clone(),
+            access: AccessList {
+                allow: vec!["127.0.0.0/24".parse().unwrap()],
+                deny: vec!["127.0.0.1".parse().unwrap()],
+            },
+            ..Default::default()
+        })
+        .unwrap();
+        let addr = server.local_addr().unwrap();
+        let abort = server.get_abort_flag();
+        thread::spawn(move || server.run());
+
+        // No answer at all, not even an error
+        let denied = request(&addr, "missing.bin");
+        assert!(Socket::recv_from(&denied).is_err());
+
+        let allowed = UdpSocket::bind("127.0.0.2:0").unwrap();
+        allowed
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        let rrq = Packet::Rrq {
+            filename: "missing.bin".to_string(),
+            mode: "octet".to_string(),
+            options: vec![],
+            extra: vec![],
+        };
+        Socket::send_to(&allowed, &rrq, &addr).unwrap();
+        assert!(matches!(
+            Socket::recv_from(&allowed).unwrap().0,
+            Packet::Error {
+                code: ErrorCode::FileNotFound,
+                ..
+            }
+        ));
+
+        abort.store(true, Ordering::Relaxed);
+    }
+
+    #[test]
+    fn serves_simultaneous_clients_on_single_port() {
+        let dir = PathBuf::from("target/test/server_single_port");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let contents: Vec<Vec<u8>> = (0..2u8)
+            .map(|i| (0..50_000u32).map(|b| (b as u8) ^ i).collect())
+            .collect();
+        for (i, content) in contents.iter().enumerate() {
+            fs::write(dir.join(format!("file{i}.bin")), content).unwrap();
+        }
+
+        let mut server = Server::new(&Config {
+            ip_address: Ipv4Addr::LOCALHOST.into(),
+            port: 0,
+            receive_directory: dir.clone(),
+            send_directory: dir.clone(),
+            single_port: true,
+            ..Default::default()
+        })
+        .unwrap();
+        let addr = server.local_addr().unwrap();
+        let abort = server.get_abort_flag();
+        thread::spawn(move || server.run());
+
+        // Both requests are sent before any block is acknowledged
+        let sockets: Vec<UdpSocket> = (0..2)
+            .map(|i| request(&addr, &format!("file{i}.bin")))
+            .collect();
+        let downloads: Vec<_> = sockets
+            .into_iter()
+            .map(|socket| {
+                thread::spawn(move || {
+                    let mut data = vec![];
+                    loop {
+                        let (packet, from) = Socket::recv_from(&socket).unwrap();
+                        // The whole transfer stays on the listening port
+                        assert_eq!(from, addr);
+                        let Packet::Data {
+                            block_num,
+                            data: block,
+                        } = packet
+                        else {
+                            panic!("expected data, got {packet}");
+                        };
+                        data.extend_from_slice(&block);
+                        Socket::send_to(&socket, &Packet::Ack(block_num), &addr).unwrap();
+                        if block.len() < DEFAULT_BLOCK_SIZE as usize {
+                            return data;
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        for (download, content) in downloads.into_iter().zip(contents) {
+            assert_eq!(download.join().unwrap(), content);
+        }
+
+        abort.store(true, Ordering::Relaxed);
+    }
+
+    fn request(addr: &SocketAddr, filename: &str) -> UdpSocket {
+        request_with_options(addr, filename, vec![])
+    }
+
+    fn request_with_options(
+        addr: &SocketAddr,
+        filename: &str,
+        options: Vec<TransferOption>,
+    ) -> UdpSocket {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        Socket::send_to(
+            &socket,
+            &Packet::Rrq {
+                filename: filename.to_string(),
+                mode: "octet".to_string(),
+                options,
+                extra: vec![],
+            },
+            addr,
+        )
+        .unwrap();
+
+        socket
+    }
+
+    #[test]
+    fn refuses_invalid_filenames() {
+        let dir = PathBuf::from("target/test/server_invalid_filename");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let mut server = Server::serve_dir("127.0.0.1:0".parse().unwrap(), &dir).unwrap();
+        let addr = server.local_addr().unwrap();
+        let abort = server.get_abort_flag();
+        thread::spawn(move || server.run());
+
+        for filename in ["", "bad\x07name", "tab\tname"] {
+            let socket = request(&addr, filename);
+            assert_eq!(
+                Socket::recv_from(&socket).unwrap(),
+                (
+                    Packet::Error {
+                        code: ErrorCode::NotDefined,
+                        msg: "invalid filename".to_string(),
+                    },
+                    addr
+                )
+            );
+        }
+
+        // A filename that is not UTF-8 cannot be built as a packet
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        socket
+            .send_to(b"\x00\x01bad\xffname\x00octet\x00", addr)
+            .unwrap();
+        assert_eq!(
+            Socket::recv_from(&socket).unwrap(),
+            (
+                Packet::Error {
+                    code: ErrorCode::NotDefined,
+                    msg: "invalid filename".to_string(),
+                },
+                addr
+            )
+        );
+
+        abort.store(true, Ordering::Relaxed);
+    }
+
+    #[test]
+    fn validates_filename() {
+        assert!(validate_filename(b"file.bin"));
+        assert!(validate_filename("dir/fichier-é.bin".as_bytes()));
+        assert!(!validate_filename(b""));
+        assert!(!validate_filename(b"bad\0name"));
+        assert!(!validate_filename(b"bad\x1bname"));
+        assert!(!validate_filename(b"line\nbreak"));
+        assert!(!validate_filename(b"bad\xffname"));
+    }
+
+    #[test]
+    fn extracts_request_filename() {
+        assert_eq!(
+            request_filename(b"\x00\x01file\x00octet\x00"),
+            Some(&b"file"[..])
+        );
+        assert_eq!(request_filename(b"\x00\x02\x00octet\x00"), Some(&b""[..]));
+        assert_eq!(request_filename(b"\x00\x03\x00\x01data"), None);
+        assert_eq!(request_filename(b"\x00\x01unterminated"), None);
+    }
 
     #[test]
     fn converts_file_path() {
@@ -442,6 +1232,38 @@ mod tests {
         ));
     }
 
+    #[test]
+    fn sanitizes_escaping_paths() {
+        let dir = PathBuf::from("target/test/server_sanitize");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("sub")).unwrap();
+
+        assert_eq!(
+            sanitize_path(&dir, Path::new("sub/./file")).unwrap(),
+            dir.join("sub/file")
+        );
+        assert!(sanitize_path(&dir, Path::new("sub/../../file")).is_err());
+        assert!(sanitize_path(&dir, Path::new("/etc/passwd")).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn sanitizes_symlink_escapes() {
+        let dir = PathBuf::from("target/test/server_symlink");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        std::os::unix::fs::symlink("..", dir.join("out")).unwrap();
+        std::os::unix::fs::symlink("sub", dir.join("in")).unwrap();
+        std::os::unix::fs::symlink("../missing", dir.join("dangling")).unwrap();
+
+        assert!(sanitize_path(&dir, Path::new("out/file")).is_err());
+        assert!(sanitize_path(&dir, Path::new("dangling")).is_err());
+        assert_eq!(
+            sanitize_path(&dir, Path::new("in/file")).unwrap(),
+            dir.join("in/file")
+        );
+    }
+
     #[test]
     fn parses_write_options() {
         let mut options = vec![
@@ -1,16 +1,64 @@
-use crate::Packet;
+use crate::log::*;
+use crate::{ErrorCode, Multicast, Packet, SocketMetrics};
 use std::{
+    cell::RefCell,
     error::Error,
     io::{Error as IoError, ErrorKind},
-    net::{SocketAddr, UdpSocket},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket},
     sync::{
+        atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering},
         mpsc::{self, Receiver, Sender},
-        Mutex,
+        Arc, Mutex,
     },
-    time::Duration,
+    thread,
+    time::{Duration, Instant},
 };
 
 const MAX_REQUEST_PACKET_SIZE: usize = 512;
+// Wait for the group data before checking the packets from the server again
+const MULTICAST_POLL_INTERVAL: Duration = Duration::from_millis(10);
+// No block sent yet, out of the range of block numbers
+const NO_BLOCK: u32 = u32::MAX;
+
+/// Buffer `enum` selects one of the buffers of a socket in the kernel
+#[cfg(feature = "client")]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Buffer {
+    /// Datagrams received and not read yet (SO_RCVBUF)
+    Receive,
+    /// Datagrams written and not sent yet (SO_SNDBUF)
+    Send,
+}
+
+thread_local! {
+    // Packets are serialized into a buffer reused by all the sends of a thread
+    static SCRATCH: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Serializes `packet` into the scratch buffer of the thread and passes the
+/// bytes to `send`.
+fn with_serialized<T>(
+    packet: &Packet,
+    send: impl FnOnce(&[u8]) -> std::io::Result<T>,
+) -> Result<T, Box<dyn Error>> {
+    SCRATCH.with(|scratch| {
+        let mut buf = scratch.borrow_mut();
+        packet.serialize_into(&mut buf)?;
+        Ok(send(&buf)?)
+    })
+}
+
+/// Returns the size of `packet` once serialized, only serializing the
+/// requests and option acknowledgements.
+fn wire_len(packet: &Packet) -> Result<usize, Box<dyn Error>> {
+    match packet {
+        Packet::Data { data, .. } => Ok(4 + data.len()),
+        Packet::Ack(_) => Ok(4),
+        Packet::Sack { missing, .. } => Ok(4 + 2 * missing.len()),
+        Packet::Error { msg, .. } => Ok(5 + msg.len()),
+        _ => with_serialized(packet, |buf| Ok(buf.len())),
+    }
+}
 
 /// Socket `trait` is used to allow building custom sockets to be used for
 /// TFTP communication.
@@ -30,6 +78,13 @@ pub trait Socket: Send + Sync + 'static {
     /// parsed [`Packet`]. The received packet can actually be of any type, however,
     /// this function also allows supplying the buffer size for an incoming request.
     fn recv_with_size(&self, size: usize) -> Result<Packet, Box<dyn Error>>;
+    /// Same as [`Socket::recv_with_size()`], receiving the datagram in `buf`
+    /// when the socket supports it, so that the data of a data packet is
+    /// `buf` itself and no allocation is needed once `buf` is large enough.
+    fn recv_into(&self, buf: Vec<u8>, size: usize) -> Result<Packet, Box<dyn Error>> {
+        drop(buf);
+        self.recv_with_size(size)
+    }
     /// Receives a [`Packet`] from any remote [`Socket`] and returns the [`SocketAddr`]
     /// of the remote [`Socket`]. This function cannot handle large data packets
     /// due to the limited buffer size, so it is intended for only accepting incoming
@@ -55,13 +110,13 @@ pub trait Socket: Send + Sync + 'static {
 
 impl Socket for UdpSocket {
     fn send(&self, packet: &Packet) -> Result<(), Box<dyn Error>> {
-        self.send(&packet.serialize()?)?;
+        with_serialized(packet, |buf| self.send(buf))?;
 
         Ok(())
     }
 
     fn send_to(&self, packet: &Packet, to: &SocketAddr) -> Result<(), Box<dyn Error>> {
-        self.send_to(&packet.serialize()?, to)?;
+        with_serialized(packet, |buf| self.send_to(buf, to))?;
 
         Ok(())
     }
@@ -74,6 +129,14 @@ impl Socket for UdpSocket {
         Ok(packet)
     }
 
+    fn recv_into(&self, mut buf: Vec<u8>, size: usize) -> Result<Packet, Box<dyn Error>> {
+        buf.resize(size + 4, 0);
+        let amt = self.recv(&mut buf)?;
+        buf.truncate(amt);
+
+        Packet::deserialize_owned(buf)
+    }
+
     fn recv_from_with_size(&self, size: usize) -> Result<(Packet, SocketAddr), Box<dyn Error>> {
         let mut buf = vec![0; size + 4];
         let (amt, addr) = self.recv_from(&mut buf)?;
@@ -139,7 +202,7 @@ impl Socket for ServerSocket {
     }
 
     fn send_to(&self, packet: &Packet, to: &SocketAddr) -> Result<(), Box<dyn Error>> {
-        self.socket.send_to(&packet.serialize()?, to)?;
+        with_serialized(packet, |buf| self.socket.send_to(buf, to))?;
 
         Ok(())
     }
@@ -210,6 +273,574 @@ impl ServerSocket {
     }
 }
 
+/// RateLimitedSocket `struct` wraps a [`Socket`] to cap the bytes it sends
+/// per second. The packets go through a token bucket holding 100 ms worth of
+/// bytes, so short bursts are sent at once, and sending waits when it is empty.
+///
+/// # Example
+///
+/// ```rust
+/// use std::net::UdpSocket;
+/// use tftpd::{Packet, RateLimitedSocket, Socket};
+///
+/// let peer = UdpSocket::bind("127.0.0.1:0").unwrap();
+/// let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+/// socket.connect(peer.local_addr().unwrap()).unwrap();
+///
+/// // 1 MB/s
+/// let socket = RateLimitedSocket::new(socket, 1_000_000);
+/// socket.send(&Packet::Ack(1)).unwrap();
+/// ```
+pub struct RateLimitedSocket<S: Socket> {
+    inner: S,
+    rate: f64,
+    burst: f64,
+    bucket: Mutex<TokenBucket>,
+}
+
+struct TokenBucket {
+    tokens: f64,
+    last: Instant,
+}
+
+impl<S: Socket> RateLimitedSocket<S> {
+    /// Creates a new [`RateLimitedSocket`] sending at most `rate` bytes per
+    /// second through `inner`.
+    pub fn new(inner: S, rate: u64) -> Self {
+        let rate = rate.max(1) as f64;
+        let burst = rate / 10.0;
+        Self {
+            inner,
+            rate,
+            burst,
+            bucket: Mutex::new(TokenBucket {
+                tokens: burst,
+                last: Instant::now(),
+            }),
+        }
+    }
+
+    /// Takes `bytes` tokens from the bucket, waiting for them when it is empty.
+    /// Packets larger than the bucket are sent once it is full, the next ones
+    /// waiting for the excess.
+    fn throttle(&self, bytes: usize) {
+        let wait = {
+            let mut bucket = self.bucket.lock().unwrap();
+            let now = Instant::now();
+            let refill = now.duration_since(bucket.last).as_secs_f64() * self.rate;
+            bucket.tokens = (bucket.tokens + refill).min(self.burst);
+            bucket.last = now;
+            bucket.tokens -= bytes as f64;
+            Duration::from_secs_f64((-bucket.tokens).max(0.0) / self.rate)
+        };
+
+        if !wait.is_zero() {
+            thread::sleep(wait);
+        }
+    }
+}
+
+impl<S: Socket> Socket for RateLimitedSocket<S> {
+    fn send(&self, packet: &Packet) -> Result<(), Box<dyn Error>> {
+        self.throttle(with_serialized(packet, |buf| Ok(buf.len()))?);
+        self.inner.send(packet)
+    }
+
+    fn send_to(&self, packet: &Packet, to: &SocketAddr) -> Result<(), Box<dyn Error>> {
+        self.throttle(with_serialized(packet, |buf| Ok(buf.len()))?);
+        self.inner.send_to(packet, to)
+    }
+
+    fn recv_with_size(&self, size: usize) -> Result<Packet, Box<dyn Error>> {
+        self.inner.recv_with_size(size)
+    }
+
+    fn recv_into(&self, buf: Vec<u8>, size: usize) -> Result<Packet, Box<dyn Error>> {
+        self.inner.recv_into(buf, size)
+    }
+
+    fn recv_from_with_size(&self, size: usize) -> Result<(Packet, SocketAddr), Box<dyn Error>> {
+        self.inner.recv_from_with_size(size)
+    }
+
+    fn remote_addr(&self) -> Result<SocketAddr, Box<dyn Error>> {
+        self.inner.remote_addr()
+    }
+
+    fn set_read_timeout(&mut self, dur: Duration) -> Result<(), Box<dyn Error>> {
+        self.inner.set_read_timeout(dur)
+    }
+
+    fn set_write_timeout(&mut self, dur: Duration) -> Result<(), Box<dyn Error>> {
+        self.inner.set_write_timeout(dur)
+    }
+
+    fn set_nonblocking(&mut self, nonblocking: bool) -> Result<(), Box<dyn Error>> {
+        self.inner.set_nonblocking(nonblocking)
+    }
+}
+
+/// MulticastSocket `struct` is a client [`Socket`] receiving the data sent
+/// to a multicast group (RFC 2090). The acknowledgements go to the server
+/// through the unicast socket of the request, and only when the client is the
+/// master one. The server makes another client master by sending it an OACK
+/// with the `multicast` option, which is handled by the [`MulticastSocket`]:
+/// the new master then acknowledges the blocks it misses on its next timeout.
+/// The group is left when the [`MulticastSocket`] is dropped.
+pub struct MulticastSocket {
+    unicast: UdpSocket,
+    group: UdpSocket,
+    group_addr: IpAddr,
+    master: AtomicBool,
+    timeout: Option<Duration>,
+    nonblocking: bool,
+}
+
+impl MulticastSocket {
+    /// Joins the multicast `group`, `unicast` being the socket connected to
+    /// the server.
+    pub fn join(
+        unicast: UdpSocket,
+        group: SocketAddr,
+        master: bool,
+    ) -> Result<Self, Box<dyn Error>> {
+        let socket = match group.ip() {
+            IpAddr::V4(ip) if ip.is_multicast() => {
+                let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, group.port()))?;
+                socket.join_multicast_v4(&ip, &Ipv4Addr::UNSPECIFIED)?;
+                socket
+            }
+            IpAddr::V6(ip) if ip.is_multicast() => {
+                let socket = UdpSocket::bind((Ipv6Addr::UNSPECIFIED, group.port()))?;
+                socket.join_multicast_v6(&ip, 0)?;
+                socket
+            }
+            ip => return Err(format!("{ip} is not a multicast address").into()),
+        };
+        socket.set_read_timeout(Some(MULTICAST_POLL_INTERVAL))?;
+        unicast.set_nonblocking(true)?;
+
+        Ok(Self {
+            unicast,
+            group: socket,
+            group_addr: group.ip(),
+            master: AtomicBool::new(master),
+            timeout: None,
+            nonblocking: false,
+        })
+    }
+
+    /// Returns whether the client acknowledges the data for the group.
+    pub fn is_master(&self) -> bool {
+        self.master.load(Ordering::Relaxed)
+    }
+
+    fn recv_any(&self, size: usize) -> Result<(Packet, SocketAddr), Box<dyn Error>> {
+        let deadline = self.timeout.map(|timeout| Instant::now() + timeout);
+        let mut buf = vec![0; size + 4];
+        loop {
+            // Packets from the server first, errors and master changes
+            match self.unicast.recv_from(&mut buf) {
+                Ok((amt, from)) => {
+                    let packet = Packet::deserialize(&buf[..amt])?;
+                    if let Packet::Oack(_, extra) = &packet {
+                        if let Some(multicast) = Multicast::find(extra)? {
+                            self.master.store(multicast.master, Ordering::Relaxed);
+                            continue;
+                        }
+                    }
+                    return Ok((packet, from));
+                }
+                Err(err) if err.kind() == ErrorKind::WouldBlock => (),
+                Err(err) => return Err(err.into()),
+            }
+
+            match self.group.recv_from(&mut buf) {
+                Ok((amt, from)) => return Ok((Packet::deserialize(&buf[..amt])?, from)),
+                Err(err) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => (),
+                Err(err) => return Err(err.into()),
+            }
+
+            if self.nonblocking || deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                return Err(IoError::from(ErrorKind::WouldBlock).into());
+            }
+        }
+    }
+}
+
+impl Socket for MulticastSocket {
+    fn send(&self, packet: &Packet) -> Result<(), Box<dyn Error>> {
+        if matches!(packet, Packet::Ack(_) | Packet::Sack { .. }) && !self.is_master() {
+            return Ok(());
+        }
+        with_serialized(packet, |buf| self.unicast.send(buf))?;
+
+        Ok(())
+    }
+
+    fn send_to(&self, packet: &Packet, to: &SocketAddr) -> Result<(), Box<dyn Error>> {
+        with_serialized(packet, |buf| self.unicast.send_to(buf, to))?;
+
+        Ok(())
+    }
+
+    fn recv_with_size(&self, size: usize) -> Result<Packet, Box<dyn Error>> {
+        Ok(self.recv_any(size)?.0)
+    }
+
+    fn recv_from_with_size(&self, size: usize) -> Result<(Packet, SocketAddr), Box<dyn Error>> {
+        self.recv_any(size)
+    }
+
+    fn remote_addr(&self) -> Result<SocketAddr, Box<dyn Error>> {
+        Ok(self.unicast.peer_addr()?)
+    }
+
+    fn set_read_timeout(&mut self, dur: Duration) -> Result<(), Box<dyn Error>> {
+        self.timeout = Some(dur);
+
+        Ok(())
+    }
+
+    fn set_write_timeout(&mut self, dur: Duration) -> Result<(), Box<dyn Error>> {
+        self.unicast.set_write_timeout(Some(dur))?;
+
+        Ok(())
+    }
+
+    fn set_nonblocking(&mut self, nonblocking: bool) -> Result<(), Box<dyn Error>> {
+        self.nonblocking = nonblocking;
+        self.group.set_nonblocking(nonblocking)?;
+
+        Ok(())
+    }
+}
+
+impl Drop for MulticastSocket {
+    fn drop(&mut self) {
+        let _ = match self.group_addr {
+            IpAddr::V4(ip) => self.group.leave_multicast_v4(&ip, &Ipv4Addr::UNSPECIFIED),
+            IpAddr::V6(ip) => self.group.leave_multicast_v6(&ip, 0),
+        };
+    }
+}
+
+/// StrictTidSocket `struct` is a [`Socket`] talking to a single peer through
+/// an unconnected [`UdpSocket`], checking the source of each datagram itself
+/// instead of leaving it to the OS. The datagrams from another transfer ID
+/// (address and port) are refused with an error and a warning, so that a peer
+/// changing its port during a transfer is reported instead of stalling it.
+///
+/// # Example
+///
+/// ```rust
+/// use std::net::UdpSocket;
+/// use tftpd::{Packet, Socket, StrictTidSocket};
+///
+/// let peer = UdpSocket::bind("127.0.0.1:0").unwrap();
+/// let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+/// let local = socket.local_addr().unwrap();
+///
+/// let socket = StrictTidSocket::new(socket, peer.local_addr().unwrap());
+/// Socket::send_to(&peer, &Packet::Ack(1), &local).unwrap();
+/// assert_eq!(socket.recv().unwrap(), Packet::Ack(1));
+/// ```
+pub struct StrictTidSocket {
+    socket: UdpSocket,
+    peer: SocketAddr,
+    timeout: Option<Duration>,
+}
+
+impl StrictTidSocket {
+    /// Creates a new [`StrictTidSocket`] accepting the datagrams of `peer`
+    /// only, `socket` being left unconnected.
+    pub fn new(socket: UdpSocket, peer: SocketAddr) -> Self {
+        Self {
+            socket,
+            peer,
+            timeout: None,
+        }
+    }
+
+    fn recv_peer(&self, mut buf: Vec<u8>, size: usize) -> Result<Packet, Box<dyn Error>> {
+        let deadline = self.timeout.map(|timeout| Instant::now() + timeout);
+        let mut shortened = false;
+        let result = loop {
+            buf.resize(size + 4, 0);
+            let (amt, from) = match self.socket.recv_from(&mut buf) {
+                Ok(received) => received,
+                Err(err) => break Err(err.into()),
+            };
+            if from == self.peer {
+                buf.truncate(amt);
+                break Packet::deserialize_owned(buf);
+            }
+
+            log_warn!(
+                "  Refused datagram from {from}, transfer ID is {}",
+                self.peer
+            );
+            let _ = Socket::send_to(
+                &self.socket,
+                &Packet::Error {
+                    code: ErrorCode::UnknownId,
+                    msg: "unknown transfer ID".to_string(),
+                },
+                &from,
+            );
+
+            // The wait for the peer is not extended by the refused datagrams
+            if let Some(deadline) = deadline {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                if remaining.is_zero() {
+                    break Err(IoError::from(ErrorKind::WouldBlock).into());
+                }
+                self.socket.set_read_timeout(Some(remaining))?;
+                shortened = true;
+            }
+        };
+
+        if shortened {
+            self.socket.set_read_timeout(self.timeout)?;
+        }
+
+        result
+    }
+}
+
+impl Socket for StrictTidSocket {
+    fn send(&self, packet: &Packet) -> Result<(), Box<dyn Error>> {
+        with_serialized(packet, |buf| self.socket.send_to(buf, self.peer))?;
+
+        Ok(())
+    }
+
+    fn send_to(&self, packet: &Packet, to: &SocketAddr) -> Result<(), Box<dyn Error>> {
+        with_serialized(packet, |buf| self.socket.send_to(buf, to))?;
+
+        Ok(())
+    }
+
+    fn recv_with_size(&self, size: usize) -> Result<Packet, Box<dyn Error>> {
+        self.recv_peer(vec![], size)
+    }
+
+    fn recv_into(&self, buf: Vec<u8>, size: usize) -> Result<Packet, Box<dyn Error>> {
+        self.recv_peer(buf, size)
+    }
+
+    fn recv_from_with_size(&self, size: usize) -> Result<(Packet, SocketAddr), Box<dyn Error>> {
+        Ok((self.recv_peer(vec![], size)?, self.peer))
+    }
+
+    fn remote_addr(&self) -> Result<SocketAddr, Box<dyn Error>> {
+        Ok(self.peer)
+    }
+
+    fn set_read_timeout(&mut self, dur: Duration) -> Result<(), Box<dyn Error>> {
+        self.timeout = Some(dur);
+        self.socket.set_read_timeout(self.timeout)?;
+
+        Ok(())
+    }
+
+    fn set_write_timeout(&mut self, dur: Duration) -> Result<(), Box<dyn Error>> {
+        self.socket.set_write_timeout(Some(dur))?;
+
+        Ok(())
+    }
+
+    fn set_nonblocking(&mut self, nonblocking: bool) -> Result<(), Box<dyn Error>> {
+        self.socket.set_nonblocking(nonblocking)?;
+
+        Ok(())
+    }
+}
+
+/// MeteredSocket `struct` wraps a [`Socket`] to count the datagrams and
+/// bytes it sends and receives, and the retransmissions: data blocks which are
+/// not after the last one sent, and acknowledgements equal to the previous
+/// one. The counters are relaxed atomics, cheap enough to stay enabled.
+///
+/// # Example
+///
+/// ```rust
+/// use std::net::UdpSocket;
+/// use tftpd::{MeteredSocket, Packet, Socket};
+///
+/// let peer = UdpSocket::bind("127.0.0.1:0").unwrap();
+/// let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+/// socket.connect(peer.local_addr().unwrap()).unwrap();
+///
+/// let socket = MeteredSocket::new(socket);
+/// let meter = socket.meter();
+/// socket.send(&Packet::Ack(1)).unwrap();
+/// socket.send(&Packet::Ack(1)).unwrap();
+///
+/// let metrics = meter.snapshot();
+/// assert_eq!(metrics.datagrams_sent, 2);
+/// assert_eq!(metrics.bytes_sent, 8);
+/// assert_eq!(metrics.retransmits, 1);
+/// ```
+pub struct MeteredSocket<S: Socket> {
+    inner: S,
+    meter: Meter,
+}
+
+/// Meter `struct` is a handle on the counters of a [`MeteredSocket`], still
+/// readable once the socket moved to the thread of the transfer.
+#[derive(Clone, Default)]
+pub struct Meter {
+    counters: Arc<Counters>,
+}
+
+struct Counters {
+    datagrams_sent: AtomicU64,
+    datagrams_received: AtomicU64,
+    bytes_sent: AtomicU64,
+    bytes_received: AtomicU64,
+    retransmits: AtomicU64,
+    last_data: AtomicU32,
+    last_ack: AtomicU32,
+}
+
+impl Default for Counters {
+    fn default() -> Self {
+        Self {
+            datagrams_sent: AtomicU64::new(0),
+            datagrams_received: AtomicU64::new(0),
+            bytes_sent: AtomicU64::new(0),
+            bytes_received: AtomicU64::new(0),
+            retransmits: AtomicU64::new(0),
+            last_data: AtomicU32::new(NO_BLOCK),
+            last_ack: AtomicU32::new(NO_BLOCK),
+        }
+    }
+}
+
+impl Meter {
+    /// Returns the current values of the counters.
+    pub fn snapshot(&self) -> SocketMetrics {
+        let counters = &self.counters;
+        SocketMetrics {
+            datagrams_sent: counters.datagrams_sent.load(Ordering::Relaxed),
+            datagrams_received: counters.datagrams_received.load(Ordering::Relaxed),
+            bytes_sent: counters.bytes_sent.load(Ordering::Relaxed),
+            bytes_received: counters.bytes_received.load(Ordering::Relaxed),
+            retransmits: counters.retransmits.load(Ordering::Relaxed),
+        }
+    }
+
+    fn sent(&self, packet: &Packet, bytes: usize) {
+        let counters = &self.counters;
+        counters.datagrams_sent.fetch_add(1, Ordering::Relaxed);
+        counters
+            .bytes_sent
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+
+        let retransmit = match packet {
+            Packet::Data { block_num, .. } => {
+                let last = counters.last_data.load(Ordering::Relaxed);
+                // Block numbers wrap, a block up to half the range behind the
+                // last one is sent again
+                let again = last != NO_BLOCK && (last as u16).wrapping_sub(*block_num) < 0x8000;
+                if !again {
+                    counters
+                        .last_data
+                        .store(*block_num as u32, Ordering::Relaxed);
+                }
+                again
+            }
+            Packet::Ack(block_num) | Packet::Sack { block_num, .. } => {
+                counters.last_ack.swap(*block_num as u32, Ordering::Relaxed) == *block_num as u32
+            }
+            _ => false,
+        };
+        if retransmit {
+            counters.retransmits.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn received(&self, packet: &Packet) -> Result<(), Box<dyn Error>> {
+        let bytes = wire_len(packet)?;
+        let counters = &self.counters;
+        counters.datagrams_received.fetch_add(1, Ordering::Relaxed);
+        counters
+            .bytes_received
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+
+        Ok(())
+    }
+}
+
+impl<S: Socket> MeteredSocket<S> {
+    /// Creates a new [`MeteredSocket`] counting the datagrams through `inner`.
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner,
+            meter: Meter::default(),
+        }
+    }
+
+    /// Returns a [`Meter`] reading the counters of the [`MeteredSocket`].
+    pub fn meter(&self) -> Meter {
+        self.meter.clone()
+    }
+}
+
+impl<S: Socket> Socket for MeteredSocket<S> {
+    fn send(&self, packet: &Packet) -> Result<(), Box<dyn Error>> {
+        self.inner.send(packet)?;
+        self.meter.sent(packet, wire_len(packet)?);
+
+        Ok(())
+    }
+
+    fn send_to(&self, packet: &Packet, to: &SocketAddr) -> Result<(), Box<dyn Error>> {
+        self.inner.send_to(packet, to)?;
+        self.meter.sent(packet, wire_len(packet)?);
+
+        Ok(())
+    }
+
+    fn recv_with_size(&self, size: usize) -> Result<Packet, Box<dyn Error>> {
+        let packet = self.inner.recv_with_size(size)?;
+        self.meter.received(&packet)?;
+
+        Ok(packet)
+    }
+
+    fn recv_into(&self, buf: Vec<u8>, size: usize) -> Result<Packet, Box<dyn Error>> {
+        let packet = self.inner.recv_into(buf, size)?;
+        self.meter.received(&packet)?;
+
+        Ok(packet)
+    }
+
+    fn recv_from_with_size(&self, size: usize) -> Result<(Packet, SocketAddr), Box<dyn Error>> {
+        let (packet, from) = self.inner.recv_from_with_size(size)?;
+        self.meter.received(&packet)?;
+
+        Ok((packet, from))
+    }
+
+    fn remote_addr(&self) -> Result<SocketAddr, Box<dyn Error>> {
+        self.inner.remote_addr()
+    }
+
+    fn set_read_timeout(&mut self, dur: Duration) -> Result<(), Box<dyn Error>> {
+        self.inner.set_read_timeout(dur)
+    }
+
+    fn set_write_timeout(&mut self, dur: Duration) -> Result<(), Box<dyn Error>> {
+        self.inner.set_write_timeout(dur)
+    }
+
+    fn set_nonblocking(&mut self, nonblocking: bool) -> Result<(), Box<dyn Error>> {
+        self.inner.set_nonblocking(nonblocking)
+    }
+}
+
 impl<T: Socket + ?Sized> Socket for Box<T> {
     fn send(&self, packet: &Packet) -> Result<(), Box<dyn Error>> {
         (**self).send(packet)
@@ -223,6 +854,10 @@ impl<T: Socket + ?Sized> Socket for Box<T> {
         (**self).recv_with_size(size)
     }
 
+    fn recv_into(&self, buf: Vec<u8>, size: usize) -> Result<Packet, Box<dyn Error>> {
+        (**self).recv_into(buf, size)
+    }
+
     fn recv_from_with_size(&self, size: usize) -> Result<(Packet, SocketAddr), Box<dyn Error>> {
         (**self).recv_from_with_size(size)
     }
@@ -244,12 +879,173 @@ impl<T: Socket + ?Sized> Socket for Box<T> {
     }
 }
 
+/// Returns the size of a buffer of `socket`, as reported by the OS.
+#[cfg(all(unix, feature = "client"))]
+pub(crate) fn buffer_size(socket: &UdpSocket, buffer: Buffer) -> Result<usize, Box<dyn Error>> {
+    use std::os::fd::AsRawFd;
+
+    let mut size: libc::c_int = 0;
+    let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+    let ret = unsafe {
+        libc::getsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_SOCKET,
+            buffer.option(),
+            &mut size as *mut libc::c_int as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if ret != 0 {
+        return Err(IoError::last_os_error().into());
+    }
+
+    Ok(size as usize)
+}
+
+/// Sets the size of a buffer of `socket`, and returns the size applied by
+/// the OS, which may clamp it to its limits or, like Linux, double it for
+/// its own bookkeeping.
+#[cfg(all(unix, feature = "client"))]
+pub(crate) fn set_buffer_size(
+    socket: &UdpSocket,
+    buffer: Buffer,
+    size: usize,
+) -> Result<usize, Box<dyn Error>> {
+    use std::os::fd::AsRawFd;
+
+    let size =
+        libc::c_int::try_from(size).map_err(|_| format!("Buffer size {size} is too large"))?;
+    let ret = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_SOCKET,
+            buffer.option(),
+            &size as *const libc::c_int as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if ret != 0 {
+        return Err(IoError::last_os_error().into());
+    }
+
+    buffer_size(socket, buffer)
+}
+
+#[cfg(all(not(unix), feature = "client"))]
+pub(crate) fn buffer_size(_socket: &UdpSocket, _buffer: Buffer) -> Result<usize, Box<dyn Error>> {
+    Err("Socket buffer sizes are only supported on Unix".into())
+}
+
+#[cfg(all(not(unix), feature = "client"))]
+pub(crate) fn set_buffer_size(
+    _socket: &UdpSocket,
+    _buffer: Buffer,
+    _size: usize,
+) -> Result<usize, Box<dyn Error>> {
+    Err("Socket buffer sizes are only supported on Unix".into())
+}
+
+#[cfg(all(unix, feature = "client"))]
+impl Buffer {
+    fn option(self) -> libc::c_int {
+        match self {
+            Buffer::Receive => libc::SO_RCVBUF,
+            Buffer::Send => libc::SO_SNDBUF,
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    use crate::WindowWrite;
     use std::str::FromStr;
 
+    #[test]
+    #[cfg(all(unix, feature = "client"))]
+    fn sets_buffer_sizes() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        for buffer in [Buffer::Receive, Buffer::Send] {
+            assert!(set_buffer_size(&socket, buffer, 8192).unwrap() >= 8192);
+            // Large sizes may be clamped, but are not refused
+            let applied = set_buffer_size(&socket, buffer, 4 * 1024 * 1024).unwrap();
+            assert_eq!(buffer_size(&socket, buffer).unwrap(), applied);
+        }
+    }
+
+    #[test]
+    fn refuses_datagrams_from_other_tid() {
+        let peer = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let intruder = UdpSocket::bind("127.0.0.1:0").unwrap();
+        intruder
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .unwrap();
+        let local = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let local_addr = local.local_addr().unwrap();
+        let mut socket = StrictTidSocket::new(local, peer.local_addr().unwrap());
+        Socket::set_read_timeout(&mut socket, Duration::from_millis(200)).unwrap();
+
+        Socket::send_to(&intruder, &Packet::Ack(9), &local_addr).unwrap();
+        Socket::send_to(&peer, &Packet::Ack(1), &local_addr).unwrap();
+        assert_eq!(socket.recv().unwrap(), Packet::Ack(1));
+        assert_eq!(
+            Socket::recv_from(&intruder).unwrap(),
+            (
+                Packet::Error {
+                    code: ErrorCode::UnknownId,
+                    msg: "unknown transfer ID".to_string(),
+                },
+                local_addr
+            )
+        );
+
+        // The datagrams refused do not extend the wait for the peer
+        let start = Instant::now();
+        for _ in 0..3 {
+            Socket::send_to(&intruder, &Packet::Ack(9), &local_addr).unwrap();
+        }
+        assert!(socket.recv().is_err());
+        assert!(start.elapsed() < Duration::from_millis(400));
+    }
+
+    #[test]
+    fn receives_blocks_without_allocating_after_warm_up() {
+        let peer = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket.connect(peer.local_addr().unwrap()).unwrap();
+        peer.connect(socket.local_addr().unwrap()).unwrap();
+        UdpSocket::set_read_timeout(&socket, Some(Duration::from_secs(1))).unwrap();
+
+        const WINDOW: u16 = 4;
+        let mut window = WindowWrite::new(WINDOW, vec![]);
+        let mut warmed_up = vec![];
+        for win in 0..50u16 {
+            for idx in 0..WINDOW {
+                let block_num = win * WINDOW + idx + 1;
+                let data = Packet::Data {
+                    block_num,
+                    data: vec![block_num as u8; 512],
+                };
+                Socket::send(&peer, &data).unwrap();
+
+                let Packet::Data { data, .. } = socket.recv_into(window.buffer(), 512).unwrap()
+                else {
+                    panic!("expected data");
+                };
+                assert_eq!(data, vec![block_num as u8; 512]);
+                if win == 0 {
+                    warmed_up.push(data.as_ptr());
+                } else {
+                    // Once the first window is written, its buffers are the only ones
+                    assert!(warmed_up.contains(&data.as_ptr()));
+                }
+                window.add(data).unwrap();
+            }
+            window.empty().unwrap();
+        }
+    }
+
     #[test]
     fn test_recv() {
         let socket = ServerSocket::new(
@@ -284,4 +1080,75 @@ mod tests {
             }
         );
     }
+
+    #[test]
+    fn counts_datagrams_and_retransmits() {
+        let peer = MeteredSocket::new(UdpSocket::bind("127.0.0.1:0").unwrap());
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket.connect(peer.inner.local_addr().unwrap()).unwrap();
+        let socket = MeteredSocket::new(socket);
+        let (meter, peer_meter) = (socket.meter(), peer.meter());
+
+        let data = |block_num| Packet::Data {
+            block_num,
+            data: vec![0; 100],
+        };
+        let packets = [
+            data(65535),
+            data(0),
+            data(1),
+            data(0),
+            Packet::Ack(2),
+            Packet::Ack(2),
+            Packet::Error {
+                code: ErrorCode::DiskFull,
+                msg: "full".to_string(),
+            },
+        ];
+        for packet in &packets {
+            socket.send(packet).unwrap();
+            assert_eq!(&peer.recv_with_size(512).unwrap(), packet);
+        }
+
+        let bytes = 4 * 104 + 2 * 4 + 9;
+        assert_eq!(
+            meter.snapshot(),
+            SocketMetrics {
+                datagrams_sent: 7,
+                bytes_sent: bytes,
+                retransmits: 2,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            peer_meter.snapshot(),
+            SocketMetrics {
+                datagrams_received: 7,
+                bytes_received: bytes,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn limits_sending_rate() {
+        let peer = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket.connect(peer.local_addr().unwrap()).unwrap();
+        let socket = RateLimitedSocket::new(socket, 100_000);
+
+        // 30 kB with a 10 kB burst at 100 kB/s take at least 200 ms
+        let start = Instant::now();
+        for block_num in 1..=30 {
+            let data = Packet::Data {
+                block_num,
+                data: vec![0; 996],
+            };
+            socket.send(&data).unwrap();
+        }
+        let elapsed = start.elapsed();
+
+        assert!(elapsed >= Duration::from_millis(195), "{elapsed:?}");
+        assert!(elapsed < Duration::from_millis(400), "{elapsed:?}");
+    }
 }
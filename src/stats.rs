@@ -0,0 +1,268 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use crate::OptionsProtocol;
+
+// Span of the rate of a progress report, longer than the bursts of the windows
+const RATE_WINDOW: Duration = Duration::from_secs(3);
+
+/// TransferStats `struct` gathers measurements about a single transfer.
+///
+/// It is filled by the [`Worker`](crate::Worker) during the transfer, and
+/// retrieved from the client with `Client::stats()`.
+///
+/// # Example
+///
+/// ```rust
+/// use tftpd::TransferStats;
+///
+/// let stats = TransferStats::default();
+/// assert_eq!(stats.time_to_first_data, None);
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TransferStats {
+    /// Time from the request to the first data block received, when receiving
+    pub time_to_first_data: Option<Duration>,
+    /// Time from the request to the end of the transfer
+    pub duration: Duration,
+    /// Data bytes transferred, acknowledged ones when sending
+    pub bytes: u64,
+    /// Data blocks transferred, including the final short block
+    pub blocks: u64,
+    /// Block size of the transfer, the smaller one that worked when the
+    /// client fell back after failures
+    pub block_size: u16,
+    /// Data blocks sent again when sending, or received out of sequence when
+    /// receiving
+    pub retransmits: u64,
+    /// Windows sent when sending, or acknowledged when receiving
+    pub windows: u64,
+    /// Histogram of the windows acknowledged when sending, by their count of
+    /// blocks sent again: entry `n` counts the windows with `n` retransmits.
+    /// A window ends when the acknowledgements move past its first block.
+    pub window_retransmits: Vec<u64>,
+    /// Hexadecimal digest of the received file, when a hash algorithm is selected
+    pub digest: Option<String>,
+    /// Datagrams through the socket of the transfer
+    pub socket: SocketMetrics,
+}
+
+impl TransferStats {
+    /// Returns the count of windows which needed at least one retransmit
+    /// when sending. See [`TransferStats::window_retransmits`].
+    pub fn windows_with_retransmits(&self) -> u64 {
+        self.window_retransmits.iter().skip(1).sum()
+    }
+
+    /// Counts a window acknowledged after `retransmits` blocks sent again
+    pub(crate) fn add_window_retransmits(&mut self, retransmits: usize) {
+        if self.window_retransmits.len() <= retransmits {
+            self.window_retransmits.resize(retransmits + 1, 0);
+        }
+        self.window_retransmits[retransmits] += 1;
+    }
+}
+
+/// SocketMetrics `struct` is a snapshot of the counters of a
+/// [`MeteredSocket`](crate::MeteredSocket).
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SocketMetrics {
+    /// Datagrams sent
+    pub datagrams_sent: u64,
+    /// Datagrams received
+    pub datagrams_received: u64,
+    /// Bytes sent, TFTP headers included
+    pub bytes_sent: u64,
+    /// Bytes received, TFTP headers included
+    pub bytes_received: u64,
+    /// Data blocks sent again, and acknowledgements repeated
+    pub retransmits: u64,
+}
+
+/// TransferEvent `enum` represents the steps of a transfer, pulled from the
+/// channel returned by `Client::events()`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum TransferEvent {
+    /// The request was answered, with the options used for the transfer
+    HandshakeComplete {
+        /// Options accepted by the server, or the default ones
+        options: OptionsProtocol,
+    },
+    /// A window was sent, ending with `block`
+    WindowSent {
+        /// Number of the last block of the window
+        block: u16,
+        /// Data bytes of the window
+        bytes: u64,
+    },
+    /// A window was received and acknowledged, ending with `block`
+    WindowReceived {
+        /// Number of the last block of the window
+        block: u16,
+        /// Data bytes of the window
+        bytes: u64,
+    },
+    /// The transfer succeeded
+    Completed {
+        /// Statistics of the transfer
+        stats: TransferStats,
+    },
+}
+
+/// ProgressReport `struct` describes the progress of a transfer, with the
+/// rates computed from the bytes transferred, see [`ProgressMeter`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProgressReport {
+    /// Data bytes transferred so far
+    pub bytes: u64,
+    /// Size of the file, when given by the transfer size option
+    pub total: Option<u64>,
+    /// Time since the start of the transfer
+    pub elapsed: Duration,
+    /// Bytes per second over the last seconds, smoothing the bursts of the windows
+    pub rate: f64,
+    /// Bytes per second since the start of the transfer
+    pub average_rate: f64,
+    /// Time left at the current rate, when the size is known
+    pub eta: Option<Duration>,
+    /// Percentage of the size transferred, when the size is known
+    pub percent: Option<f64>,
+}
+
+/// ProgressMeter `struct` turns the bytes transferred at given times into
+/// [`ProgressReport`]s.
+///
+/// # Example
+///
+/// ```rust
+/// use std::time::{Duration, Instant};
+/// use tftpd::ProgressMeter;
+///
+/// let start = Instant::now();
+/// let mut meter = ProgressMeter::new(start, Some(4000));
+/// let report = meter.update(start + Duration::from_secs(1), 1000);
+/// assert_eq!(report.rate, 1000.0);
+/// assert_eq!(report.eta, Some(Duration::from_secs(3)));
+/// assert_eq!(report.percent, Some(25.0));
+/// ```
+pub struct ProgressMeter {
+    start: Instant,
+    total: Option<u64>,
+    samples: VecDeque<(Instant, u64)>,
+}
+
+impl ProgressMeter {
+    /// Creates a new [`ProgressMeter`] for a transfer started at `start`,
+    /// of `total` bytes when known.
+    pub fn new(start: Instant, total: Option<u64>) -> Self {
+        Self {
+            start,
+            total,
+            samples: VecDeque::from([(start, 0)]),
+        }
+    }
+
+    /// Records that `bytes` were transferred at `now`, and returns the
+    /// resulting [`ProgressReport`].
+    pub fn update(&mut self, now: Instant, bytes: u64) -> ProgressReport {
+        self.samples.push_back((now, bytes));
+        // The oldest sample kept is the last one before the window
+        while self
+            .samples
+            .get(1)
+            .is_some_and(|&(time, _)| now.saturating_duration_since(time) >= RATE_WINDOW)
+        {
+            self.samples.pop_front();
+        }
+
+        let elapsed = now.saturating_duration_since(self.start);
+        let average_rate = rate(bytes, elapsed);
+        let (oldest_time, oldest_bytes) = self.samples[0];
+        let span = now.saturating_duration_since(oldest_time);
+        let rate = match span.is_zero() {
+            true => average_rate,
+            false => rate(bytes.saturating_sub(oldest_bytes), span),
+        };
+
+        let remaining = self.total.map(|total| total.saturating_sub(bytes));
+        ProgressReport {
+            bytes,
+            total: self.total,
+            elapsed,
+            rate,
+            average_rate,
+            eta: remaining.and_then(|remaining| match remaining {
+                0 => Some(Duration::ZERO),
+                _ if rate > 0.0 => Some(Duration::from_secs_f64(remaining as f64 / rate)),
+                _ => None,
+            }),
+            percent: self.total.map(|total| match total {
+                0 => 100.0,
+                total => (bytes.min(total) as f64 * 100.0) / total as f64,
+            }),
+        }
+    }
+}
+
+/// Returns the bytes per second of `bytes` transferred in `duration`
+fn rate(bytes: u64, duration: Duration) -> f64 {
+    match duration.is_zero() {
+        true => 0.0,
+        false => bytes as f64 / duration.as_secs_f64(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_rates_over_sliding_window() {
+        let start = Instant::now();
+        let at = |secs: u64| start + Duration::from_secs(secs);
+        let mut meter = ProgressMeter::new(start, Some(10_000));
+
+        let report = meter.update(at(1), 1000);
+        assert_eq!(report.elapsed, Duration::from_secs(1));
+        assert_eq!((report.rate, report.average_rate), (1000.0, 1000.0));
+        assert_eq!(report.eta, Some(Duration::from_secs(9)));
+        assert_eq!(report.percent, Some(10.0));
+
+        meter.update(at(2), 2000);
+        meter.update(at(3), 3000);
+        // A burst only counts over the last seconds
+        let report = meter.update(at(4), 9000);
+        assert_eq!(report.rate, (9000.0 - 1000.0) / 3.0);
+        assert_eq!(report.average_rate, 2250.0);
+        assert_eq!(
+            report.eta,
+            Some(Duration::from_secs_f64(1000.0 * 3.0 / 8000.0))
+        );
+        assert_eq!(report.percent, Some(90.0));
+
+        // A stall brings the rate down, not the average
+        let report = meter.update(at(8), 9000);
+        assert_eq!(report.rate, 0.0);
+        assert_eq!(report.average_rate, 1125.0);
+        assert_eq!(report.eta, None);
+
+        let report = meter.update(at(10), 10_000);
+        assert_eq!(report.eta, Some(Duration::ZERO));
+        assert_eq!(report.percent, Some(100.0));
+    }
+
+    #[test]
+    fn reports_rates_without_size() {
+        let start = Instant::now();
+        let mut meter = ProgressMeter::new(start, None);
+
+        let report = meter.update(start, 0);
+        assert_eq!((report.rate, report.average_rate), (0.0, 0.0));
+
+        let report = meter.update(start + Duration::from_millis(500), 1000);
+        assert_eq!(report.total, None);
+        assert_eq!(report.rate, 2000.0);
+        assert_eq!(report.eta, None);
+        assert_eq!(report.percent, None);
+    }
+}
@@ -0,0 +1,225 @@
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::{Packet, Socket};
+
+/// Direction `enum` tells whether a traced packet was sent or received.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Direction {
+    /// Packet sent to the peer
+    Sent,
+    /// Packet received from the peer
+    Received,
+}
+
+/// TraceEntry `struct` is a packet recorded by a [`Trace`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct TraceEntry {
+    /// Time elapsed since the trace was created
+    pub time: Duration,
+    /// Whether the packet was sent or received
+    pub direction: Direction,
+    /// Address the packet was sent to or received from, when known
+    pub peer: Option<SocketAddr>,
+    /// The packet, as printed on one line by its [`Display`](fmt::Display)
+    pub packet: String,
+}
+
+impl fmt::Display for TraceEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let arrow = match self.direction {
+            Direction::Sent => "->",
+            Direction::Received => "<-",
+        };
+        write!(f, "{:10.6} {arrow} ", self.time.as_secs_f64())?;
+        if let Some(peer) = self.peer {
+            write!(f, "{peer} ")?;
+        }
+        f.write_str(&self.packet)
+    }
+}
+
+/// Trace `struct` records the packets going through [`TracedSocket`]s in a
+/// ring buffer, the oldest entries being dropped once `capacity` is reached.
+/// Clones share the same buffer.
+///
+/// # Example
+///
+/// ```rust
+/// use tftpd::{Direction, Packet, Trace};
+///
+/// let trace = Trace::new(2);
+/// for block_num in 1..=3 {
+///     trace.record(Direction::Sent, &Packet::Ack(block_num), None);
+/// }
+/// let entries = trace.take();
+/// assert_eq!(entries.len(), 2);
+/// assert_eq!(entries[0].packet, "ACK #2");
+/// ```
+#[derive(Clone)]
+pub struct Trace {
+    ring: Arc<Mutex<Ring>>,
+}
+
+struct Ring {
+    entries: VecDeque<TraceEntry>,
+    capacity: usize,
+    start: Instant,
+}
+
+impl Trace {
+    /// Creates a new [`Trace`] keeping the last `capacity` packets.
+    pub fn new(capacity: usize) -> Trace {
+        Trace {
+            ring: Arc::new(Mutex::new(Ring {
+                entries: VecDeque::with_capacity(capacity.min(1024)),
+                capacity,
+                start: Instant::now(),
+            })),
+        }
+    }
+
+    /// Records a packet sent or received.
+    pub fn record(&self, direction: Direction, packet: &Packet, peer: Option<SocketAddr>) {
+        let mut ring = self.ring.lock().unwrap();
+        if ring.capacity == 0 {
+            return;
+        }
+        if ring.entries.len() == ring.capacity {
+            ring.entries.pop_front();
+        }
+        let time = ring.start.elapsed();
+        ring.entries.push_back(TraceEntry {
+            time,
+            direction,
+            peer,
+            packet: packet.to_string(),
+        });
+    }
+
+    /// Returns the recorded entries, oldest first, and empties the trace.
+    pub fn take(&self) -> Vec<TraceEntry> {
+        self.ring.lock().unwrap().entries.drain(..).collect()
+    }
+}
+
+/// TracedSocket `struct` wraps a [`Socket`] to record the packets it sends
+/// and receives in a [`Trace`].
+pub struct TracedSocket<S: Socket> {
+    inner: S,
+    trace: Trace,
+}
+
+impl<S: Socket> TracedSocket<S> {
+    /// Creates a new [`TracedSocket`] recording the packets of `inner` in `trace`.
+    pub fn new(inner: S, trace: Trace) -> Self {
+        Self { inner, trace }
+    }
+}
+
+impl<S: Socket> Socket for TracedSocket<S> {
+    fn send(&self, packet: &Packet) -> Result<(), Box<dyn Error>> {
+        self.inner.send(packet)?;
+        self.trace.record(Direction::Sent, packet, None);
+
+        Ok(())
+    }
+
+    fn send_to(&self, packet: &Packet, to: &SocketAddr) -> Result<(), Box<dyn Error>> {
+        self.inner.send_to(packet, to)?;
+        self.trace.record(Direction::Sent, packet, Some(*to));
+
+        Ok(())
+    }
+
+    fn recv_with_size(&self, size: usize) -> Result<Packet, Box<dyn Error>> {
+        let packet = self.inner.recv_with_size(size)?;
+        self.trace.record(Direction::Received, &packet, None);
+
+        Ok(packet)
+    }
+
+    fn recv_into(&self, buf: Vec<u8>, size: usize) -> Result<Packet, Box<dyn Error>> {
+        let packet = self.inner.recv_into(buf, size)?;
+        self.trace.record(Direction::Received, &packet, None);
+
+        Ok(packet)
+    }
+
+    fn recv_from_with_size(&self, size: usize) -> Result<(Packet, SocketAddr), Box<dyn Error>> {
+        let (packet, from) = self.inner.recv_from_with_size(size)?;
+        self.trace.record(Direction::Received, &packet, Some(from));
+
+        Ok((packet, from))
+    }
+
+    fn remote_addr(&self) -> Result<SocketAddr, Box<dyn Error>> {
+        self.inner.remote_addr()
+    }
+
+    fn set_read_timeout(&mut self, dur: Duration) -> Result<(), Box<dyn Error>> {
+        self.inner.set_read_timeout(dur)
+    }
+
+    fn set_write_timeout(&mut self, dur: Duration) -> Result<(), Box<dyn Error>> {
+        self.inner.set_write_timeout(dur)
+    }
+
+    fn set_nonblocking(&mut self, nonblocking: bool) -> Result<(), Box<dyn Error>> {
+        self.inner.set_nonblocking(nonblocking)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::UdpSocket;
+
+    #[test]
+    fn records_sent_and_received_packets() {
+        let peer = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket.connect(peer.local_addr().unwrap()).unwrap();
+        peer.connect(socket.local_addr().unwrap()).unwrap();
+        let trace = Trace::new(8);
+        let socket = TracedSocket::new(socket, trace.clone());
+
+        let data = Packet::Data {
+            block_num: 1,
+            data: vec![0; 3],
+        };
+        socket.send(&data).unwrap();
+        Socket::recv(&peer).unwrap();
+        Socket::send(&peer, &Packet::Ack(1)).unwrap();
+        socket.recv().unwrap();
+
+        let entries = trace.take();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].direction, Direction::Sent);
+        assert_eq!(entries[0].packet, "DATA #1 (3 bytes)");
+        assert_eq!(entries[1].direction, Direction::Received);
+        assert_eq!(entries[1].packet, "ACK #1");
+        assert!(entries[0].time <= entries[1].time);
+        assert!(entries[1].to_string().ends_with("<- ACK #1"));
+        assert!(trace.take().is_empty());
+    }
+
+    #[test]
+    fn keeps_last_entries_up_to_capacity() {
+        let trace = Trace::new(3);
+        for block_num in 1..=5 {
+            trace.record(Direction::Received, &Packet::Ack(block_num), None);
+        }
+
+        let packets: Vec<String> = trace.take().into_iter().map(|e| e.packet).collect();
+        assert_eq!(packets, ["ACK #3", "ACK #4", "ACK #5"]);
+
+        let trace = Trace::new(0);
+        trace.record(Direction::Sent, &Packet::Ack(1), None);
+        assert!(trace.take().is_empty());
+    }
+}
@@ -0,0 +1,184 @@
+use std::error::Error;
+
+/// Transform `trait` is used to process the received data before it is
+/// written to the file, e.g. to decrypt or decompress it.
+///
+/// A transform is fed the data in order, chunk after chunk, and may output
+/// more or less bytes than it received, keeping the remainder in its own
+/// state until the next chunk or [`Transform::finish()`].
+///
+/// Closures taking a chunk and returning the transformed one implement this
+/// `trait`, for stateless transforms.
+///
+/// # Example
+///
+/// ```rust
+/// use tftpd::{Pipeline, Transform};
+///
+/// let mut pipeline = Pipeline::new();
+/// pipeline.push(|data: &[u8]| data.to_ascii_uppercase());
+/// assert_eq!(pipeline.transform(b"hello").unwrap(), b"HELLO");
+/// ```
+pub trait Transform: Send {
+    /// Transforms a chunk of data.
+    fn transform(&mut self, data: &[u8]) -> Result<Vec<u8>, Box<dyn Error>>;
+
+    /// Returns the data still buffered once all chunks were transformed.
+    fn finish(&mut self) -> Result<Vec<u8>, Box<dyn Error>> {
+        Ok(vec![])
+    }
+}
+
+impl<F: FnMut(&[u8]) -> Vec<u8> + Send> Transform for F {
+    fn transform(&mut self, data: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+        Ok(self(data))
+    }
+}
+
+/// Pipeline `struct` chains [`Transform`]s, the output of each one being the
+/// input of the next one.
+#[derive(Default)]
+pub struct Pipeline {
+    stages: Vec<Box<dyn Transform>>,
+}
+
+impl Pipeline {
+    /// Creates an empty [`Pipeline`], which leaves data untouched.
+    pub fn new() -> Pipeline {
+        Pipeline { stages: vec![] }
+    }
+
+    /// Appends a [`Transform`] at the end of the [`Pipeline`].
+    pub fn push<T: Transform + 'static>(&mut self, stage: T) {
+        self.stages.push(Box::new(stage));
+    }
+
+    /// Returns `true` if the [`Pipeline`] has no [`Transform`].
+    pub fn is_empty(&self) -> bool {
+        self.stages.is_empty()
+    }
+
+    /// Transforms a chunk of data through all the stages.
+    pub fn transform(&mut self, data: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+        let mut data = data.to_vec();
+        for stage in &mut self.stages {
+            data = stage.transform(&data)?;
+        }
+
+        Ok(data)
+    }
+
+    /// Flushes the data buffered by all the stages. Data flushed by a stage
+    /// still goes through the following ones.
+    pub fn finish(&mut self) -> Result<Vec<u8>, Box<dyn Error>> {
+        let mut data = vec![];
+        for stage in &mut self.stages {
+            data = stage.transform(&data)?;
+            data.extend(stage.finish()?);
+        }
+
+        Ok(data)
+    }
+}
+
+impl Transform for Pipeline {
+    fn transform(&mut self, data: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+        Pipeline::transform(self, data)
+    }
+
+    fn finish(&mut self) -> Result<Vec<u8>, Box<dyn Error>> {
+        Pipeline::finish(self)
+    }
+}
+
+/// Skip `struct` is a [`Transform`] discarding the first bytes of the data,
+/// used to resume a transfer. Finishing before all of them were received is
+/// an error.
+pub(crate) struct Skip {
+    total: u64,
+    remaining: u64,
+}
+
+impl Skip {
+    pub(crate) fn new(len: u64) -> Skip {
+        Skip {
+            total: len,
+            remaining: len,
+        }
+    }
+}
+
+impl Transform for Skip {
+    fn transform(&mut self, data: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+        let skipped = self.remaining.min(data.len() as u64);
+        self.remaining -= skipped;
+
+        Ok(data[skipped as usize..].to_vec())
+    }
+
+    fn finish(&mut self) -> Result<Vec<u8>, Box<dyn Error>> {
+        if self.remaining > 0 {
+            return Err(format!(
+                "Remote file ({} bytes) is smaller than the local partial file ({} bytes)",
+                self.total - self.remaining,
+                self.total
+            )
+            .into());
+        }
+
+        Ok(vec![])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Outputs the data by groups of 3 bytes, the remainder being buffered
+    struct Groups(Vec<u8>);
+
+    impl Transform for Groups {
+        fn transform(&mut self, data: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+            self.0.extend_from_slice(data);
+            let len = self.0.len() - self.0.len() % 3;
+            Ok(self.0.drain(..len).collect())
+        }
+
+        fn finish(&mut self) -> Result<Vec<u8>, Box<dyn Error>> {
+            Ok(self.0.drain(..).collect())
+        }
+    }
+
+    #[test]
+    fn chains_stages_in_order() {
+        let mut pipeline = Pipeline::new();
+        pipeline.push(|data: &[u8]| data.iter().map(|b| b + 1).collect());
+        pipeline.push(|data: &[u8]| data.iter().map(|b| b * 2).collect());
+
+        assert_eq!(pipeline.transform(&[1, 2, 3]).unwrap(), [4, 6, 8]);
+        assert_eq!(pipeline.finish().unwrap(), []);
+    }
+
+    #[test]
+    fn flushes_buffered_data_through_next_stages() {
+        let mut pipeline = Pipeline::new();
+        pipeline.push(Groups(vec![]));
+        pipeline.push(|data: &[u8]| data.to_ascii_uppercase());
+
+        assert_eq!(pipeline.transform(b"ab").unwrap(), b"");
+        assert_eq!(pipeline.transform(b"cde").unwrap(), b"ABC");
+        assert_eq!(pipeline.finish().unwrap(), b"DE");
+    }
+
+    #[test]
+    fn skips_first_bytes() {
+        let mut skip = Skip::new(4);
+        assert_eq!(skip.transform(b"abc").unwrap(), b"");
+        assert_eq!(skip.transform(b"def").unwrap(), b"ef");
+        assert_eq!(skip.finish().unwrap(), b"");
+
+        let mut skip = Skip::new(4);
+        assert_eq!(skip.transform(b"abc").unwrap(), b"");
+        assert!(skip.finish().is_err());
+    }
+}
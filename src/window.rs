@@ -1,13 +1,35 @@
 use std::{
-    collections::VecDeque,
+    cmp::{max, min},
+    collections::{BTreeMap, VecDeque},
     error::Error,
     fs::File,
-    io::{BufRead, BufReader, Read, Write},
+    io::{self, BufRead, BufReader, BufWriter, Read, Write},
+    mem,
+    sync::mpsc::{self, Receiver, RecvTimeoutError},
+    thread::{self, JoinHandle},
+    time::Duration,
 };
 
-/// WindowRead `struct` is used to store chunks of data from a file. It is
-/// used to help store the data that is being sent for the
-/// [RFC 7440](https://www.rfc-editor.org/rfc/rfc7440) Windowsize option.
+use crate::checksum::Crc32;
+use crate::log::*;
+#[cfg(unix)]
+use crate::mmap::Mmap;
+use crate::options::DEFAULT_MAX_MEMORY;
+use crate::Pipeline;
+
+const DEFAULT_WRITE_BUFFER: usize = 64 * 1024;
+// Time given to the prefetch thread to stop before leaving it behind
+const PREFETCH_STOP_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// WindowRead `struct` is used to store chunks of data from a file, or any
+/// other [`Read`] source. It is used to help store the data that is being
+/// sent for the [RFC 7440](https://www.rfc-editor.org/rfc/rfc7440) Windowsize option.
+///
+/// The source is buffered internally, and a chunk is only shorter than the
+/// chunk size at the end of the data, even if the source returns partial reads.
+/// The data always ends with a short chunk, empty when the data is empty or a
+/// multiple of the chunk size.
+/// `WindowRead` alone stands for `WindowRead<File>`, as before it was generic.
 ///
 /// # Example
 /// ```rust
@@ -23,45 +45,174 @@ use std::{
 /// window.fill().unwrap();
 /// fs::remove_file("test.txt").unwrap();
 /// ```
-pub struct WindowRead {
+pub struct WindowRead<R: Read = File> {
     elements: VecDeque<Vec<u8>>,
     size: u16,
     chunk_size: u16,
-    bufreader: BufReader<File>,
+    source: Source<R>,
+    crc: Option<Crc32>,
+    pad: bool,
+}
+
+/// Where the chunks are read from
+enum Source<R: Read> {
+    Buffered(BufReader<R>),
+    #[cfg(unix)]
+    Mapped(Mmap),
+    Prefetched(Prefetch),
+    Detached,
+}
+
+/// Background thread reading chunks ahead of the transfer
+struct Prefetch {
+    receiver: Option<Receiver<io::Result<Vec<u8>>>>,
+    handle: Option<JoinHandle<()>>,
+    // Disconnected when the thread ends
+    done: Receiver<()>,
+}
+
+impl Prefetch {
+    fn spawn(mut reader: Box<dyn Read + Send>, chunk_size: u16, size: u16) -> Prefetch {
+        let (sender, receiver) = mpsc::sync_channel(size as usize);
+        let (done_sender, done) = mpsc::channel::<()>();
+
+        let handle = thread::spawn(move || {
+            let _done_sender = done_sender;
+            loop {
+                let chunk = read_chunk(&mut reader, chunk_size);
+                let last = !matches!(&chunk, Ok(chunk) if chunk.len() == chunk_size as usize);
+                // Fails once the receiver is dropped, ending the thread
+                if sender.send(chunk).is_err() || last {
+                    break;
+                }
+            }
+        });
+
+        Prefetch {
+            receiver: Some(receiver),
+            handle: Some(handle),
+            done,
+        }
+    }
+
+    fn read(&mut self) -> io::Result<Vec<u8>> {
+        match self.receiver.as_ref().map(|receiver| receiver.recv()) {
+            Some(Ok(chunk)) => chunk,
+            // Thread is done after the last chunk
+            _ => Ok(vec![]),
+        }
+    }
+}
+
+impl Drop for Prefetch {
+    fn drop(&mut self) {
+        // Dropping the receiver first unblocks a thread waiting to send
+        self.receiver.take();
+
+        // A thread blocked in a read is left behind, it ends after the read
+        match self.done.recv_timeout(PREFETCH_STOP_TIMEOUT) {
+            Err(RecvTimeoutError::Disconnected) => {
+                if let Some(handle) = self.handle.take() {
+                    let _ = handle.join();
+                }
+            }
+            _ => log_warn!("  Prefetch thread blocked in a read, detaching it"),
+        }
+    }
+}
+
+/// Reads a full chunk, or less at the end of the data.
+fn read_chunk(reader: &mut impl Read, chunk_size: u16) -> io::Result<Vec<u8>> {
+    let mut chunk = Vec::with_capacity(chunk_size as usize);
+    reader.take(chunk_size as u64).read_to_end(&mut chunk)?;
+    Ok(chunk)
 }
 
-impl WindowRead {
-    /// Creates a new `Window` with the supplied size and chunk size.
-    pub fn new(size: u16, chunk_size: u16, file: File) -> WindowRead {
-        WindowRead {
+impl<R: Read> WindowRead<R> {
+    /// Creates a new `Window` with the supplied size and chunk size, clamped
+    /// to the default memory limit of 16 MiB. See [`WindowRead::with_memory_limit()`].
+    pub fn new(size: u16, chunk_size: u16, reader: R) -> WindowRead<R> {
+        WindowRead::with_memory_limit(DEFAULT_MAX_MEMORY, size, chunk_size, reader).0
+    }
+
+    /// Creates a new `Window` with the supplied size and chunk size, the size
+    /// being reduced so that a full window fits in `max_bytes` (at least one
+    /// chunk is kept). The read buffer takes the rest of the budget, up to two
+    /// windows. Returns the `Window` and its effective size.
+    pub fn with_memory_limit(
+        max_bytes: usize,
+        size: u16,
+        chunk_size: u16,
+        reader: R,
+    ) -> (WindowRead<R>, u16) {
+        let size = clamp_size(max_bytes, size, chunk_size);
+        let window_bytes = size as usize * chunk_size as usize;
+
+        let window = WindowRead {
             elements: VecDeque::new(),
             size,
             chunk_size,
-            bufreader: BufReader::with_capacity(2 * size as usize * chunk_size as usize, file),
-        }
+            source: Source::Buffered(BufReader::with_capacity(
+                min(2 * window_bytes, max_bytes.saturating_sub(window_bytes)),
+                reader,
+            )),
+            crc: None,
+            pad: false,
+        };
+
+        (window, size)
     }
 
     /// Fills the `Window` with chunks of data from the file.
     /// Returns `true` if the `Window` is full.
     pub fn fill(&mut self) -> Result<bool, Box<dyn Error>> {
         for _ in self.len()..self.size {
-            let mut chunk = vec![0; self.chunk_size as usize];
-            let size = self.bufreader.read(&mut chunk)?;
+            let mut chunk = match &mut self.source {
+                Source::Buffered(bufreader) => read_chunk(bufreader, self.chunk_size)?,
+                #[cfg(unix)]
+                Source::Mapped(mmap) => read_chunk(mmap, self.chunk_size)?,
+                Source::Prefetched(prefetch) => prefetch.read()?,
+                Source::Detached => vec![],
+            };
+            if self.pad && !chunk.is_empty() {
+                chunk.resize(self.chunk_size as usize, 0);
+            }
+            let size = chunk.len();
+            if let Some(crc) = &mut self.crc {
+                crc.update(&chunk);
+            }
+            self.elements.push_back(chunk);
+
             if size != self.chunk_size as usize {
-                chunk.truncate(size);
-                self.elements.push_back(chunk);
                 return Ok(false);
             }
-
-            self.elements.push_back(chunk);
         }
 
         Ok(true)
     }
 
+    /// Pads the last chunk with zeros up to the chunk size, an empty chunk
+    /// then ends the data. To be called before the first fill.
+    pub fn enable_padding(&mut self) {
+        self.pad = true;
+    }
+
+    /// Starts computing a CRC-32 of the data read, see [`WindowRead::checksum()`].
+    /// To be called before the first fill.
+    pub fn enable_checksum(&mut self) {
+        self.crc = Some(Crc32::new());
+    }
+
+    /// Returns the CRC-32 of all the data read so far, if enabled.
+    pub fn checksum(&self) -> Option<u32> {
+        self.crc.map(|crc| crc.value())
+    }
+
     /// Fill the read buffer to speed up next window fill
     pub fn prefill(&mut self) -> Result<(), Box<dyn Error>> {
-        self.bufreader.fill_buf()?;
+        if let Source::Buffered(bufreader) = &mut self.source {
+            bufreader.fill_buf()?;
+        }
         Ok(())
     }
 
@@ -81,6 +232,18 @@ impl WindowRead {
         &self.elements
     }
 
+    /// Returns the first chunk of the `Window` without removing it.
+    pub fn peek_front(&self) -> Option<&[u8]> {
+        self.elements.front().map(Vec::as_slice)
+    }
+
+    /// Returns `true` if the first chunk of the `Window` ends the data,
+    /// being shorter than the chunk size.
+    pub fn next_is_final(&self) -> bool {
+        self.peek_front()
+            .is_some_and(|chunk| chunk.len() < self.chunk_size as usize)
+    }
+
     /// Returns the length of the `Window`.
     pub fn len(&self) -> u16 {
         self.elements.len() as u16
@@ -92,9 +255,58 @@ impl WindowRead {
     }
 }
 
-/// WindowWrite `struct` is used to store data and write them in a file.
-/// It is used to help store the data that is being received for the
-/// [RFC 7440](https://www.rfc-editor.org/rfc/rfc7440) Windowsize option.
+impl WindowRead<File> {
+    /// Creates a new `Window` with the supplied size and chunk size, reading
+    /// the chunks from a memory mapping of the file rather than through a
+    /// buffer. This avoids extra copies when serving large files repeatedly.
+    /// The size is clamped as with [`WindowRead::new()`].
+    ///
+    /// If the file is truncated during the transfer, [`WindowRead::fill()`]
+    /// returns an error, the process is never killed by SIGBUS.
+    #[cfg(unix)]
+    pub fn mmap(size: u16, chunk_size: u16, file: File) -> Result<WindowRead, Box<dyn Error>> {
+        Ok(WindowRead {
+            elements: VecDeque::new(),
+            size: clamp_size(DEFAULT_MAX_MEMORY, size, chunk_size),
+            chunk_size,
+            source: Source::Mapped(Mmap::new(file)?),
+            crc: None,
+            pad: false,
+        })
+    }
+}
+
+impl<R: Read + Send + 'static> WindowRead<R> {
+    /// Moves the reading to a background thread, which reads up to one window
+    /// of chunks ahead while the current one is being sent. [`WindowRead::fill()`]
+    /// then takes the chunks already read instead of waiting for the file.
+    ///
+    /// The thread is stopped when the `Window` is dropped. If it is blocked in
+    /// a read, it is not waited for and ends once the read returns.
+    pub fn spawn_prefetch(&mut self) {
+        let reader: Box<dyn Read + Send> = match mem::replace(&mut self.source, Source::Detached) {
+            Source::Buffered(bufreader) => Box::new(bufreader),
+            #[cfg(unix)]
+            Source::Mapped(mmap) => Box::new(mmap),
+            source => {
+                self.source = source;
+                return;
+            }
+        };
+
+        self.source = Source::Prefetched(Prefetch::spawn(reader, self.chunk_size, self.size));
+    }
+}
+
+/// Returns the largest window size up to `size` fitting in `max_bytes`, at least 1.
+fn clamp_size(max_bytes: usize, size: u16, chunk_size: u16) -> u16 {
+    let max_size = (max_bytes / max(chunk_size as usize, 1)).clamp(1, u16::MAX as usize);
+    min(size as usize, max_size) as u16
+}
+
+/// WindowWrite `struct` is used to store data and write them in a file, or
+/// any other [`Sink`]. It is used to help store the data that is being
+/// received for the [RFC 7440](https://www.rfc-editor.org/rfc/rfc7440) Windowsize option.
 ///
 /// # Example
 /// ```rust
@@ -106,30 +318,221 @@ impl WindowRead {
 /// window.add(vec![0x1, 0x2, 0x3]).unwrap();
 /// window.add(vec![0x4, 0x5, 0x6]).unwrap();
 /// window.empty().unwrap();
+/// window.flush().unwrap();
 /// ```
-pub struct WindowWrite {
+pub struct WindowWrite<W: Sink = File> {
     elements: VecDeque<Vec<u8>>,
+    pending: BTreeMap<u16, Vec<u8>>,
+    // Buffers of the blocks written, reused for the next ones
+    spare: Vec<Vec<u8>>,
     size: u16,
-    file: File,
+    file: BufWriter<W>,
+    pipeline: Pipeline,
+    received: u64,
+    crc: Option<Crc32>,
+    window_crcs: Option<WindowCrcs>,
+}
+
+/// CRC-32 of each run of `run_len` bytes written
+struct WindowCrcs {
+    run_len: u64,
+    written: u64,
+    crc: Crc32,
+    done: Vec<u32>,
+}
+
+impl WindowCrcs {
+    fn update(&mut self, data: &[u8]) {
+        self.crc.update(data);
+        self.written += data.len() as u64;
+        // Blocks never straddle runs, which are made of whole blocks
+        if self.written >= self.run_len {
+            self.end();
+        }
+    }
+
+    fn end(&mut self) {
+        if self.written > 0 {
+            self.done.push(self.crc.value());
+            self.crc = Crc32::new();
+            self.written = 0;
+        }
+    }
 }
 
-impl WindowWrite {
-    /// Creates a new `Window` with the supplied size and chunk size.
-    pub fn new(size: u16, file: File) -> WindowWrite {
+impl<W: Sink> WindowWrite<W> {
+    /// Creates a new `Window` with the supplied size, buffering the file
+    /// output with a default capacity of 64 KiB.
+    pub fn new(size: u16, file: W) -> WindowWrite<W> {
+        WindowWrite::with_buffer(size, file, DEFAULT_WRITE_BUFFER)
+    }
+
+    /// Creates a new `Window` with the supplied size, buffering the file
+    /// output with the supplied capacity. Data is only guaranteed to be in the
+    /// file after [`WindowWrite::flush()`].
+    pub fn with_buffer(size: u16, file: W, buf_capacity: usize) -> WindowWrite<W> {
         WindowWrite {
             elements: VecDeque::new(),
+            pending: BTreeMap::new(),
+            spare: vec![],
             size,
-            file,
+            file: BufWriter::with_capacity(buf_capacity, file),
+            pipeline: Pipeline::new(),
+            received: 0,
+            crc: None,
+            window_crcs: None,
         }
     }
 
-    /// Empties the `Window` by writing the data to the file.
+    /// Sets the [`Pipeline`] the data goes through before being written.
+    pub fn set_pipeline(&mut self, pipeline: Pipeline) {
+        self.pipeline = pipeline;
+    }
+
+    /// Empties the `Window` by writing the data to the file. Fails without
+    /// writing anything if some blocks are missing, see [`WindowWrite::is_complete()`].
     pub fn empty(&mut self) -> Result<(), Box<dyn Error>> {
+        if !self.is_complete() {
+            return Err(
+                format!("cannot write a window missing blocks {:?}", self.missing()).into(),
+            );
+        }
+
+        self.write_elements()
+    }
+
+    /// Empties the `Window` of the blocks received in order, keeping the ones
+    /// received after a gap, whose offsets then start from the first missing block.
+    pub fn empty_received(&mut self) -> Result<(), Box<dyn Error>> {
+        self.empty_received_with(|| Ok(()))
+    }
+
+    /// Same as [`WindowWrite::empty_received()`], calling `after_block`
+    /// after each block written, e.g. to keep the peer waiting while the
+    /// file is slow to write.
+    pub fn empty_received_with(
+        &mut self,
+        after_block: impl FnMut() -> Result<(), Box<dyn Error>>,
+    ) -> Result<(), Box<dyn Error>> {
+        let written = self.len();
+        self.write_elements_with(after_block)?;
+        self.pending = std::mem::take(&mut self.pending)
+            .into_iter()
+            .map(|(offset, data)| (offset - written, data))
+            .collect();
+
+        Ok(())
+    }
+
+    /// Writes the blocks received in order and flushes the file, dropping
+    /// the ones received after a gap, so that a failed transfer keeps its
+    /// longest contiguous data. Returns the count of bytes written, before
+    /// any transform.
+    pub fn flush_partial(&mut self) -> Result<u64, Box<dyn Error>> {
+        let committed = self.elements.iter().map(|data| data.len() as u64).sum();
+        self.write_elements()?;
+        self.pending.clear();
+        self.flush()?;
+
+        Ok(committed)
+    }
+
+    fn write_elements(&mut self) -> Result<(), Box<dyn Error>> {
+        self.write_elements_with(|| Ok(()))
+    }
+
+    fn write_elements_with(
+        &mut self,
+        mut after_block: impl FnMut() -> Result<(), Box<dyn Error>>,
+    ) -> Result<(), Box<dyn Error>> {
         for data in &self.elements {
-            self.file.write_all(data)?;
+            if let Some(crc) = &mut self.crc {
+                crc.update(data);
+            }
+            if let Some(crcs) = &mut self.window_crcs {
+                crcs.update(data);
+            }
+            if self.pipeline.is_empty() {
+                self.file.write_all(data)?;
+            } else {
+                self.file.write_all(&self.pipeline.transform(data)?)?;
+            }
+            after_block()?;
+        }
+
+        for mut data in self.elements.drain(..) {
+            if self.spare.len() < self.size as usize {
+                data.clear();
+                self.spare.push(data);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns an empty buffer to receive the next block in, reusing the ones
+    /// of the blocks already written, so that a steady reception does not
+    /// allocate, see [`Socket::recv_into()`](crate::Socket::recv_into).
+    pub fn buffer(&mut self) -> Vec<u8> {
+        self.spare.pop().unwrap_or_default()
+    }
+
+    /// Starts computing a CRC-32 of the data written, before any transform,
+    /// see [`WindowWrite::checksum()`]. To be called before the first write.
+    pub fn enable_checksum(&mut self) {
+        self.crc = Some(Crc32::new());
+    }
+
+    /// Returns the CRC-32 of all the data written so far, if enabled.
+    pub fn checksum(&self) -> Option<u32> {
+        self.crc.map(|crc| crc.value())
+    }
+
+    /// Starts computing a CRC-32 of each run of `run_len` bytes written,
+    /// before any transform, see [`WindowWrite::take_window_checksums()`].
+    /// `run_len` should be a multiple of the block size. To be called before
+    /// the first write.
+    pub fn enable_window_checksums(&mut self, run_len: u64) {
+        self.window_crcs = Some(WindowCrcs {
+            run_len,
+            written: 0,
+            crc: Crc32::new(),
+            done: vec![],
+        });
+    }
+
+    /// Returns the CRC-32 of the runs completed since the previous call, the
+    /// last run being shorter once the `Window` is finished.
+    pub fn take_window_checksums(&mut self) -> Vec<u32> {
+        self.window_crcs
+            .as_mut()
+            .map(|crcs| mem::take(&mut crcs.done))
+            .unwrap_or_default()
+    }
+
+    /// Empties the `Window`, writes the data still buffered by the
+    /// [`Pipeline`] and flushes the file. To be called once all the data was added.
+    pub fn finish(&mut self) -> Result<(), Box<dyn Error>> {
+        self.empty()?;
+        if let Some(crcs) = &mut self.window_crcs {
+            crcs.end();
         }
+        self.file.write_all(&self.pipeline.finish()?)?;
+        self.flush()
+    }
 
-        self.elements.clear();
+    /// Writes the buffered data to the file.
+    pub fn flush(&mut self) -> Result<(), Box<dyn Error>> {
+        self.file.flush()?;
+
+        Ok(())
+    }
+
+    /// Writes the buffered data to the file and waits for it to reach the
+    /// disk, see [`Sink::sync()`].
+    pub fn sync(&mut self) -> Result<(), Box<dyn Error>> {
+        self.flush()?;
+        self.file.get_ref().sync()?;
 
         Ok(())
     }
@@ -140,11 +543,62 @@ impl WindowWrite {
             return Err("cannot add to a full window");
         }
 
-        self.elements.push_back(data);
+        self.add_at(self.len(), data)
+    }
+
+    /// Adds a data `Vec<u8>` to the `Window` at `block_offset` from the start of
+    /// the `Window`, so that blocks received out of order are written in order.
+    /// Data already present at this offset is kept.
+    pub fn add_at(&mut self, block_offset: u16, data: Vec<u8>) -> Result<(), &'static str> {
+        if block_offset >= self.size {
+            return Err("cannot add beyond the window size");
+        }
+
+        if block_offset < self.len() || self.pending.contains_key(&block_offset) {
+            return Ok(());
+        }
+
+        self.received += data.len() as u64;
+        self.pending.insert(block_offset, data);
+        while let Some(data) = self.pending.remove(&self.len()) {
+            self.elements.push_back(data);
+        }
 
         Ok(())
     }
 
+    /// Returns `true` if there is no gap between the blocks of the `Window`.
+    pub fn is_complete(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// Returns `true` if a block shorter than `block_size`, which ends the
+    /// data, is followed by another one in the `Window`.
+    pub fn has_short_block_before_end(&self, block_size: usize) -> bool {
+        let blocks: Vec<&Vec<u8>> = self.elements.iter().chain(self.pending.values()).collect();
+        blocks
+            .split_last()
+            .is_some_and(|(_, before)| before.iter().any(|block| block.len() < block_size))
+    }
+
+    /// Returns the offsets of the blocks missing before the last one added.
+    pub fn missing(&self) -> Vec<u16> {
+        match self.pending.keys().next_back() {
+            Some(&last) => (self.len()..last)
+                .filter(|offset| !self.pending.contains_key(offset))
+                .collect(),
+            None => vec![],
+        }
+    }
+
+    /// Returns the offsets of all the blocks missing up to the end of the
+    /// `Window`, including those after the last one added.
+    pub fn unreceived(&self) -> Vec<u16> {
+        (self.len()..self.size)
+            .filter(|offset| !self.pending.contains_key(offset))
+            .collect()
+    }
+
     /// Returns the length of the `Window`.
     pub fn len(&self) -> u16 {
         self.elements.len() as u16
@@ -160,18 +614,46 @@ impl WindowWrite {
         self.elements.len() as u16 == self.size
     }
 
-    /// Returns the length of the file
+    /// Returns the count of bytes added, before any transform
+    pub fn received_len(&self) -> u64 {
+        self.received
+    }
+}
+
+impl WindowWrite<File> {
+    /// Returns the length of the file, including the data not flushed yet
     pub fn file_len(&self) -> Result<u64, Box<dyn Error>> {
-        Ok(self.file.metadata()?.len())
+        Ok(self.file.get_ref().metadata()?.len() + self.file.buffer().len() as u64)
     }
 }
 
+/// Sink `trait` is implemented by the destinations a [`WindowWrite`] writes
+/// to. Files are synced to the disk by [`WindowWrite::sync()`], other
+/// destinations are only flushed.
+pub trait Sink: Write {
+    /// Waits for the data written to reach its final destination.
+    fn sync(&self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Sink for File {
+    fn sync(&self) -> io::Result<()> {
+        self.sync_all()
+    }
+}
+
+impl Sink for Vec<u8> {}
+
+impl Sink for Box<dyn Write + Send> {}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use std::{
         fs::{self, OpenOptions},
-        io::Write,
+        io::{Cursor, Write},
+        time::Instant,
     };
 
     const DIR_NAME: &str = "target/test";
@@ -205,6 +687,387 @@ mod tests {
         clean(FILENAME);
     }
 
+    #[test]
+    fn clamps_window_to_memory_limit() {
+        const FILENAME: &str = "clamps_window_to_memory_limit.txt";
+
+        let file = initialize(FILENAME);
+        let (window, size) = WindowRead::with_memory_limit(1024 * 1024, 1000, 65464, file);
+        assert!(size <= 16);
+        assert_eq!(window.size, size);
+        let Source::Buffered(reader) = &window.source else {
+            panic!("expected a buffered reader");
+        };
+        assert!(size as usize * 65464 + reader.capacity() <= 1024 * 1024);
+
+        let (_, size) = WindowRead::with_memory_limit(1024, 8, 512, open(FILENAME));
+        assert_eq!(size, 2);
+
+        let (_, size) = WindowRead::with_memory_limit(100, 8, 512, open(FILENAME));
+        assert_eq!(size, 1);
+
+        let window = WindowRead::new(65535, 65464, open(FILENAME));
+        assert_eq!(window.size, 256);
+
+        clean(FILENAME);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn fills_window_from_mapped_file() {
+        const FILENAME: &str = "fills_window_from_mapped_file.txt";
+
+        let mut file = initialize(FILENAME);
+        file.write_all(b"Hello, world!").unwrap();
+        drop(file);
+
+        let mut window = WindowRead::mmap(2, 5, open(FILENAME)).unwrap();
+        assert!(window.fill().unwrap());
+        assert_eq!(window.elements[0], b"Hello"[..]);
+        assert_eq!(window.elements[1], b", wor"[..]);
+
+        window.remove(2).unwrap();
+        assert!(!window.fill().unwrap());
+        assert_eq!(window.elements[0], b"ld!"[..]);
+
+        let mut window = WindowRead::mmap(2, 5, initialize(FILENAME)).unwrap();
+        assert!(!window.fill().unwrap());
+        assert!(window.elements[0].is_empty());
+
+        clean(FILENAME);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn returns_error_on_truncated_mapped_file() {
+        const FILENAME: &str = "returns_error_on_truncated_mapped_file.txt";
+
+        let mut file = initialize(FILENAME);
+        file.write_all(&[0x42; 3 * 4096]).unwrap();
+        drop(file);
+
+        let mut window = WindowRead::mmap(1, 4096, open(FILENAME)).unwrap();
+        assert!(window.fill().unwrap());
+        window.remove(1).unwrap();
+
+        // Next chunk now in the partial page after the end of the file
+        open(FILENAME).set_len(4096 + 100).unwrap();
+        assert!(window.fill().is_err());
+
+        // And beyond, where SIGBUS would be raised
+        open(FILENAME).set_len(100).unwrap();
+        assert!(window.fill().is_err());
+
+        clean(FILENAME);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn prefetches_mapped_file() {
+        const FILENAME: &str = "prefetches_mapped_file.txt";
+
+        let mut file = initialize(FILENAME);
+        file.write_all(b"Hello, world!").unwrap();
+        drop(file);
+
+        let mut window = WindowRead::mmap(2, 5, open(FILENAME)).unwrap();
+        window.spawn_prefetch();
+        assert!(window.fill().unwrap());
+        window.remove(2).unwrap();
+        assert!(!window.fill().unwrap());
+        assert_eq!(window.elements[0], b"ld!"[..]);
+
+        clean(FILENAME);
+    }
+
+    #[test]
+    fn fills_window_from_any_reader() {
+        let mut window = WindowRead::new(2, 5, Cursor::new(b"Hello, world!".to_vec()));
+        assert!(window.fill().unwrap());
+        assert_eq!(window.elements[0], b"Hello"[..]);
+        assert_eq!(window.elements[1], b", wor"[..]);
+
+        window.remove(2).unwrap();
+        assert!(!window.fill().unwrap());
+        assert_eq!(window.elements[0], b"ld!"[..]);
+    }
+
+    #[test]
+    fn peeks_front_chunk() {
+        let mut window = WindowRead::new(2, 5, Cursor::new(b"Hello, world!".to_vec()));
+        assert_eq!(window.peek_front(), None);
+        assert!(!window.next_is_final());
+
+        window.fill().unwrap();
+        assert_eq!(window.peek_front(), Some(&b"Hello"[..]));
+        assert!(!window.next_is_final());
+        assert_eq!(window.len(), 2);
+
+        window.remove(2).unwrap();
+        window.fill().unwrap();
+        assert_eq!(window.peek_front(), Some(&b"ld!"[..]));
+        assert!(window.next_is_final());
+
+        // Aligned data ends with an empty chunk
+        let mut window = WindowRead::new(2, 5, Cursor::new(b"Hello".to_vec()));
+        window.fill().unwrap();
+        assert!(!window.next_is_final());
+        window.remove(1).unwrap();
+        assert_eq!(window.peek_front(), Some(&b""[..]));
+        assert!(window.next_is_final());
+    }
+
+    #[test]
+    fn ends_aligned_data_with_empty_chunk() {
+        let mut window = WindowRead::new(4, 5, Cursor::new(b"Hello".to_vec()));
+        assert!(!window.fill().unwrap());
+        assert_eq!(window.elements, [b"Hello".to_vec(), vec![]]);
+
+        let mut window = WindowRead::new(1, 5, Cursor::new(b"Hello".to_vec()));
+        assert!(window.fill().unwrap());
+        window.remove(1).unwrap();
+        assert!(!window.fill().unwrap());
+        assert_eq!(window.elements, [vec![]]);
+    }
+
+    #[test]
+    fn fills_single_empty_chunk_from_empty_data() {
+        let mut window = WindowRead::new(4, 5, Cursor::new(vec![]));
+        assert!(!window.fill().unwrap());
+        assert_eq!(window.elements, [vec![]]);
+
+        window.enable_padding();
+        window.remove(1).unwrap();
+        assert!(!window.fill().unwrap());
+        assert_eq!(window.elements, [vec![]]);
+    }
+
+    #[test]
+    fn pads_last_chunk() {
+        let mut window = WindowRead::new(4, 5, Cursor::new(b"Hello, world!".to_vec()));
+        window.enable_padding();
+        assert!(!window.fill().unwrap());
+        assert_eq!(
+            window.elements,
+            [
+                b"Hello".to_vec(),
+                b", wor".to_vec(),
+                b"ld!\0\0".to_vec(),
+                vec![]
+            ]
+        );
+    }
+
+    #[test]
+    fn fills_window_from_prefetch_thread() {
+        let mut window = WindowRead::new(2, 5, Cursor::new(b"Hello, world!".to_vec()));
+        window.spawn_prefetch();
+
+        assert!(window.fill().unwrap());
+        assert_eq!(window.elements[0], b"Hello"[..]);
+        assert_eq!(window.elements[1], b", wor"[..]);
+
+        window.remove(2).unwrap();
+        assert!(!window.fill().unwrap());
+        assert_eq!(window.elements[0], b"ld!"[..]);
+
+        window.remove(1).unwrap();
+        assert!(!window.fill().unwrap());
+        assert_eq!(window.elements[0], b""[..]);
+    }
+
+    #[test]
+    fn stops_prefetch_thread_waiting_to_send() {
+        let mut window = WindowRead::new(2, 5, Cursor::new(vec![0; 1000]));
+        window.spawn_prefetch();
+        window.fill().unwrap();
+
+        // Let the thread fill the channel and block on the next chunk
+        thread::sleep(Duration::from_millis(50));
+        drop(window);
+    }
+
+    #[test]
+    fn stops_prefetch_thread_while_reading() {
+        /// Endless reader signaling each read, and slow to return
+        struct SlowReader(mpsc::Sender<()>);
+
+        impl Read for SlowReader {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                let _ = self.0.send(());
+                thread::sleep(Duration::from_millis(50));
+                buf.fill(0);
+                Ok(buf.len())
+            }
+        }
+
+        let (sender, receiver) = mpsc::channel();
+        let mut window = WindowRead::new(2, 5, SlowReader(sender));
+        window.spawn_prefetch();
+
+        receiver.recv().unwrap();
+        drop(window);
+    }
+
+    #[test]
+    fn stops_prefetch_thread_blocked_in_read() {
+        /// Reader blocked until its sender is dropped
+        struct BlockedReader(mpsc::Receiver<()>);
+
+        impl Read for BlockedReader {
+            fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+                let _ = self.0.recv();
+                Ok(0)
+            }
+        }
+
+        let (sender, receiver) = mpsc::channel();
+        let mut window = WindowRead::new(2, 5, BlockedReader(receiver));
+        window.spawn_prefetch();
+
+        let start = Instant::now();
+        drop(window);
+        assert!(start.elapsed() < Duration::from_secs(1));
+        drop(sender);
+    }
+
+    #[test]
+    fn computes_window_checksums() {
+        let data: Vec<u8> = (0..2600u32).map(|i| (i * 7) as u8).collect();
+        let crc = |data: &[u8]| {
+            let mut crc = Crc32::new();
+            crc.update(data);
+            crc.value()
+        };
+
+        let mut write = WindowWrite::new(2, vec![]);
+        write.enable_window_checksums(1024);
+        let mut crcs = vec![];
+        for block in data.chunks(512) {
+            write.add(block.to_vec()).unwrap();
+            if write.is_full() {
+                write.empty().unwrap();
+                crcs.extend(write.take_window_checksums());
+            }
+        }
+        // The last run is only complete once finished
+        assert_eq!(crcs.len(), 2);
+        write.finish().unwrap();
+        crcs.extend(write.take_window_checksums());
+
+        assert_eq!(
+            crcs,
+            [
+                crc(&data[..1024]),
+                crc(&data[1024..2048]),
+                crc(&data[2048..])
+            ]
+        );
+        assert!(WindowWrite::new(2, vec![])
+            .take_window_checksums()
+            .is_empty());
+    }
+
+    #[test]
+    fn reuses_buffers_of_written_blocks() {
+        let mut window = WindowWrite::new(2, vec![]);
+        assert_eq!(window.buffer().capacity(), 0);
+
+        let blocks = [vec![1; 512], vec![2; 512], vec![3; 512]];
+        let ptrs: Vec<_> = blocks.iter().map(|block| block.as_ptr()).collect();
+        for block in blocks {
+            window.add(block).unwrap();
+            if window.is_full() {
+                window.empty().unwrap();
+            }
+        }
+        window.empty().unwrap();
+
+        // Only a window of buffers is kept, cleared
+        let reused = [window.buffer(), window.buffer()];
+        assert!(reused
+            .iter()
+            .all(|buffer| buffer.is_empty() && buffer.capacity() == 512));
+        assert!(reused.iter().all(|buffer| ptrs.contains(&buffer.as_ptr())));
+        assert_eq!(window.buffer().capacity(), 0);
+        window.flush().unwrap();
+        assert_eq!(window.file.get_ref().len(), 3 * 512);
+    }
+
+    #[test]
+    fn finds_short_block_before_end() {
+        let mut window = WindowWrite::new(4, vec![]);
+        window.add(vec![1; 4]).unwrap();
+        window.add_at(2, vec![3; 2]).unwrap();
+        assert!(!window.has_short_block_before_end(4));
+
+        window.add_at(1, vec![2; 4]).unwrap();
+        assert!(!window.has_short_block_before_end(4));
+
+        window.add_at(3, vec![4; 4]).unwrap();
+        assert!(window.has_short_block_before_end(4));
+    }
+
+    #[test]
+    fn computes_checksum_once_per_byte() {
+        const FILENAME: &str = "computes_checksum_once_per_byte.txt";
+
+        // CRC-32 of the data as computed by crc32fast (or zlib)
+        const REFERENCE: u32 = 0xB641_F5C8;
+
+        let data: Vec<u8> = (0..5000).map(|i| (i * 7) as u8).collect();
+
+        let mut read = WindowRead::new(3, 512, Cursor::new(data.clone()));
+        read.enable_checksum();
+        let mut write = WindowWrite::new(3, initialize(FILENAME));
+        write.enable_checksum();
+
+        // Acknowledge one block at a time, refilling the window in between
+        // the transfer ends with the first short chunk
+        loop {
+            read.fill().unwrap();
+            let last = read.next_is_final();
+            write.add(read.peek_front().unwrap().to_vec()).unwrap();
+            read.remove(1).unwrap();
+            if write.is_full() {
+                write.empty().unwrap();
+            }
+            if last {
+                break;
+            }
+        }
+        write.empty().unwrap();
+
+        assert_eq!(read.checksum(), Some(REFERENCE));
+        assert_eq!(write.checksum(), Some(REFERENCE));
+        assert_eq!(WindowRead::new(3, 512, Cursor::new(data)).checksum(), None);
+
+        clean(FILENAME);
+    }
+
+    #[test]
+    fn fills_full_chunks_from_partial_reads() {
+        /// Returns at most 3 bytes per read, like a pipe
+        struct Trickle(Cursor<Vec<u8>>);
+
+        impl Read for Trickle {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                let len = buf.len().min(3);
+                self.0.read(&mut buf[..len])
+            }
+        }
+
+        let reader = Trickle(Cursor::new(b"Hello, world!".to_vec()));
+        let mut window = WindowRead::with_memory_limit(10, 2, 5, reader).0;
+        assert!(window.fill().unwrap());
+        assert_eq!(window.elements[0], b"Hello"[..]);
+        assert_eq!(window.elements[1], b", wor"[..]);
+
+        window.remove(2).unwrap();
+        assert!(!window.fill().unwrap());
+        assert_eq!(window.elements[0], b"ld!"[..]);
+    }
+
     #[test]
     fn adds_to_and_empties_window() {
         const FILENAME: &str = "adds_to_and_empties_window.txt";
@@ -229,6 +1092,8 @@ mod tests {
 
         window.empty().unwrap();
         assert_eq!(window.elements.len(), 0);
+        assert_eq!(window.file_len().unwrap(), 13);
+        window.flush().unwrap();
 
         let mut contents = Default::default();
         File::read_to_string(
@@ -241,6 +1106,118 @@ mod tests {
         clean(FILENAME);
     }
 
+    #[test]
+    fn adds_out_of_order_blocks() {
+        const FILENAME: &str = "adds_out_of_order_blocks.txt";
+
+        let file = initialize(FILENAME);
+
+        let mut window = WindowWrite::new(4, file);
+        window.add_at(2, b"ld".to_vec()).unwrap();
+        window.add_at(0, b"Hello".to_vec()).unwrap();
+        assert_eq!(window.len(), 1);
+        assert!(!window.is_complete());
+        assert_eq!(window.missing(), vec![1]);
+        assert!(window.empty().is_err());
+
+        window.add_at(3, b"!".to_vec()).unwrap();
+        window.add_at(1, b", wor".to_vec()).unwrap();
+        assert!(window.is_complete());
+        assert!(window.is_full());
+        assert!(window.add_at(4, b"?".to_vec()).is_err());
+
+        window.empty().unwrap();
+        window.flush().unwrap();
+        assert_eq!(
+            fs::read_to_string(DIR_NAME.to_string() + "/" + FILENAME).unwrap(),
+            "Hello, world!"
+        );
+
+        clean(FILENAME);
+    }
+
+    #[test]
+    fn keeps_out_of_order_blocks_when_emptied() {
+        const FILENAME: &str = "keeps_out_of_order_blocks_when_emptied.txt";
+
+        let file = initialize(FILENAME);
+
+        let mut window = WindowWrite::new(6, file);
+        for (offset, data) in [(0, "He"), (2, "o,"), (4, "wo"), (5, "rl")] {
+            window.add_at(offset, data.as_bytes().to_vec()).unwrap();
+        }
+        assert_eq!(window.unreceived(), vec![1, 3]);
+
+        window.empty_received().unwrap();
+        assert!(window.is_empty());
+        assert_eq!(window.unreceived(), vec![0, 2, 5]);
+
+        window.add(b"ll".to_vec()).unwrap();
+        window.add(b" ".to_vec()).unwrap();
+        assert_eq!(window.len(), 5);
+        window.add(b"d!".to_vec()).unwrap();
+        assert!(window.is_complete());
+
+        window.empty_received().unwrap();
+        window.flush().unwrap();
+        assert_eq!(
+            fs::read_to_string(DIR_NAME.to_string() + "/" + FILENAME).unwrap(),
+            "Hello, world!"
+        );
+
+        clean(FILENAME);
+    }
+
+    #[test]
+    fn flushes_partial_window() {
+        const FILENAME: &str = "flushes_partial_window.txt";
+
+        let file = initialize(FILENAME);
+
+        let mut window = WindowWrite::new(5, file);
+        for (offset, data) in [(0, "Hel"), (1, "lo,"), (2, " wo"), (4, "!")] {
+            window.add_at(offset, data.as_bytes().to_vec()).unwrap();
+        }
+        assert_eq!(window.len(), 3);
+
+        assert_eq!(window.flush_partial().unwrap(), 9);
+        assert!(window.is_empty());
+        assert!(window.is_complete());
+        assert_eq!(
+            fs::read_to_string(DIR_NAME.to_string() + "/" + FILENAME).unwrap(),
+            "Hello, wo"
+        );
+
+        clean(FILENAME);
+    }
+
+    #[test]
+    fn buffers_writes_until_flush() {
+        const FILENAME: &str = "buffers_writes_until_flush.txt";
+
+        let file = initialize(FILENAME);
+        let path = DIR_NAME.to_string() + "/" + FILENAME;
+
+        let mut window = WindowWrite::with_buffer(2, file, 1024);
+        window.add(b"Hello".to_vec()).unwrap();
+        window.add(b", world!".to_vec()).unwrap();
+        window.empty().unwrap();
+
+        assert_eq!(fs::metadata(&path).unwrap().len(), 0);
+        assert_eq!(window.file_len().unwrap(), 13);
+
+        window.flush().unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().len(), 13);
+        assert_eq!(window.file_len().unwrap(), 13);
+
+        window.add(b"!".to_vec()).unwrap();
+        window.empty().unwrap();
+        window.sync().unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().len(), 14);
+
+        clean(FILENAME);
+    }
+
     fn initialize(filename: &str) -> File {
         let filename = DIR_NAME.to_string() + "/" + filename;
 
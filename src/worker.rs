@@ -1,16 +1,27 @@
 use std::{
     error::Error,
-    fs::{self, File},
-    io::ErrorKind,
+    fs::{self, File, OpenOptions},
+    io::{self, ErrorKind, Read, Write},
     path::PathBuf,
-    sync::{atomic::AtomicBool, Arc},
+    sync::{
+        atomic::{AtomicBool, AtomicU16, Ordering},
+        mpsc::{Sender, SyncSender},
+        Arc, Mutex,
+    },
     thread,
-    time::{Duration, Instant},
+    time::{Duration, Instant, SystemTime},
 };
 
+use crate::checksum::{crc32_file, crc32_read};
 use crate::log::*;
-use crate::options::{OptionsPrivate, OptionsProtocol, Rollover};
-use crate::{ErrorCode, Packet, Socket, WindowRead, WindowWrite};
+use crate::options::{OptionsPrivate, OptionsProtocol, Rollover, TrailingData, DEFAULT_MAX_MEMORY};
+use crate::transform::Skip;
+use crate::{
+    Compression, Compressor, Decompressor, ErrorCode, NetasciiDecoder, NetasciiEncoder, OptionType,
+    Packet, Pipeline, Socket, TftpError, TransferEvent, TransferMode, TransferOption,
+    TransferStats,
+};
+use crate::{Sink, WindowRead, WindowWrite};
 
 #[cfg(feature = "debug_drop")]
 use crate::drop::drop_check;
@@ -18,6 +29,12 @@ use crate::drop::drop_check;
 const DEFAULT_DUPLICATE_DELAY: Duration = Duration::from_millis(1);
 // Chosen arbitrarily because not specified in RFC
 const MAX_ERROR_PACKET_SIZE: usize = 128;
+// Largest block fitting in a 1500 bytes Ethernet frame, with IPv4 headers
+const ETHERNET_MAX_BLOCK_SIZE: u16 = 1468;
+// Timeouts in a row after which large blocks are suspected to be fragmented
+const FRAGMENTATION_RETRIES: usize = 2;
+// Doublings of the timeout on consecutive losses of a block, up to 4 times the negotiated one
+const MAX_BACKOFF_SHIFT: usize = 2;
 
 /// Worker `struct` is used for multithreaded file sending and receiving.
 /// It creates a new socket using the Server's IP and a random port
@@ -52,45 +69,230 @@ pub struct Worker<T: Socket + ?Sized> {
     opt_local: OptionsPrivate,
     opt_common: OptionsProtocol,
     abort: Arc<AtomicBool>,
+    start: Instant,
+    stats: Arc<Mutex<TransferStats>>,
+    pipeline: Pipeline,
+    oack: Option<Vec<TransferOption>>,
+    effective_window: Arc<AtomicU16>,
+    progress: Option<Sender<u64>>,
+    events: Option<SyncSender<TransferEvent>>,
+    mode: TransferMode,
+    compression: Option<Compression>,
+    resume: u64,
+    append: bool,
+    manifest: Option<Vec<u32>>,
+    first_packet: Option<Packet>,
+    reader: Option<Box<dyn Read + Send>>,
+    writer: Option<Box<dyn Write + Send>>,
+    source: Option<(u64, Option<SystemTime>)>,
 }
 
 impl<T: Socket + ?Sized> Worker<T> {
-    /// Creates a new [`Worker`] with the supplied options.
+    /// Creates a new [`Worker`] with the supplied options. A negotiated
+    /// rollover value overrides the local roll-over policy.
     pub fn new(
         socket: Box<T>,
         file_path: PathBuf,
-        opt_local: OptionsPrivate,
+        mut opt_local: OptionsPrivate,
         opt_common: OptionsProtocol,
         abort: Arc<AtomicBool>,
     ) -> Worker<T> {
+        match opt_common.rollover {
+            Some(0) => opt_local.rollover = Rollover::Enforce0,
+            Some(_) => opt_local.rollover = Rollover::Enforce1,
+            None => (),
+        }
+        let effective_window = Arc::new(AtomicU16::new(opt_common.window_size));
         Worker {
             socket,
             file_path,
             opt_local,
             opt_common,
             abort,
+            start: Instant::now(),
+            stats: Default::default(),
+            pipeline: Pipeline::new(),
+            oack: None,
+            effective_window,
+            progress: None,
+            events: None,
+            mode: TransferMode::Octet,
+            compression: None,
+            resume: 0,
+            append: false,
+            manifest: None,
+            first_packet: None,
+            reader: None,
+            writer: None,
+            source: None,
         }
     }
 
+    /// Creates a new [`Worker`] sending the data of `reader` instead of a
+    /// file, the transfer being named `-`. See [`Worker::set_reader()`].
+    pub fn with_reader(
+        socket: Box<T>,
+        reader: Box<dyn Read + Send>,
+        opt_local: OptionsPrivate,
+        opt_common: OptionsProtocol,
+        abort: Arc<AtomicBool>,
+    ) -> Worker<T> {
+        let mut worker = Worker::new(socket, PathBuf::from("-"), opt_local, opt_common, abort);
+        worker.set_reader(reader);
+        worker
+    }
+
+    /// Creates a new [`Worker`] writing the data received to `writer` instead
+    /// of a file, the transfer being named `-`. See [`Worker::set_writer()`].
+    pub fn with_writer(
+        socket: Box<T>,
+        writer: Box<dyn Write + Send>,
+        opt_local: OptionsPrivate,
+        opt_common: OptionsProtocol,
+        abort: Arc<AtomicBool>,
+    ) -> Worker<T> {
+        let mut worker = Worker::new(socket, PathBuf::from("-"), opt_local, opt_common, abort);
+        worker.set_writer(writer);
+        worker
+    }
+
+    /// Sets the [`TransferMode`], netascii data being translated from and to
+    /// local text on the fly. (default: octet)
+    pub fn set_mode(&mut self, mode: TransferMode) {
+        self.mode = mode;
+    }
+
+    /// Sets the [`Pipeline`] the received data goes through before being
+    /// written. A checksum is verified against the data before the [`Pipeline`].
+    pub fn set_pipeline(&mut self, pipeline: Pipeline) {
+        self.pipeline = pipeline;
+    }
+
+    /// Sets the options to acknowledge before sending the file, from the
+    /// [`Worker`] thread. The checksum option value is replaced by the file one.
+    pub fn set_oack(&mut self, options: Vec<TransferOption>) {
+        self.oack = Some(options);
+    }
+
+    /// Sets the instant the transfer was requested, from which durations are
+    /// measured. (default: creation of the [`Worker`])
+    pub fn set_start(&mut self, start: Instant) {
+        self.start = start;
+    }
+
+    /// Sets the channel the number of bytes transferred so far is sent to,
+    /// once per completed window.
+    pub fn set_progress(&mut self, progress: Sender<u64>) {
+        self.progress = Some(progress);
+    }
+
+    /// Sets the channel each window sent or received is reported to. A full
+    /// channel blocks the transfer until the events are consumed.
+    pub fn set_events(&mut self, events: SyncSender<TransferEvent>) {
+        self.events = Some(events);
+    }
+
+    /// Compresses the data sent, after its netascii translation, or
+    /// decompresses the data received, the sizes and checksums of the
+    /// transfer being those of the compressed data. (default: none)
+    pub fn set_compression(&mut self, compression: Option<Compression>) {
+        self.compression = compression;
+    }
+
+    /// Resumes a reception after the first `offset` bytes, which are already
+    /// in the file: the data received up to there is discarded, and the rest
+    /// is appended to the file, which is kept on error. (default: 0)
+    pub fn set_resume(&mut self, offset: u64) {
+        self.resume = offset;
+    }
+
+    /// Appends the received data to the file instead of replacing it, the
+    /// file being kept on error. (default: false)
+    pub fn set_append(&mut self, append: bool) {
+        self.append = append;
+    }
+
+    /// Verifies the data received against a manifest of the CRC-32 of each
+    /// window, i.e. of each run of windowsize x blocksize bytes of the file,
+    /// the last one being possibly shorter. The reception is aborted at the
+    /// first window not matching its CRC-32.
+    pub fn set_manifest(&mut self, manifest: Vec<u32>) {
+        self.manifest = Some(manifest);
+    }
+
+    /// Sets a packet already received from the remote, handled by the
+    /// [`Worker`] as the first one when receiving, e.g. the first data block
+    /// sent by a server answering a request without acknowledging options.
+    pub fn set_first_packet(&mut self, packet: Packet) {
+        self.first_packet = Some(packet);
+    }
+
+    /// Sets the source of the data to send instead of the file, which then
+    /// only names the transfer. The data size and checksum cannot be known in
+    /// advance.
+    pub fn set_reader(&mut self, reader: Box<dyn Read + Send>) {
+        self.reader = Some(reader);
+    }
+
+    /// Sets the destination of the data received instead of the file, which
+    /// then only names the transfer.
+    pub fn set_writer(&mut self, writer: Box<dyn Write + Send>) {
+        self.writer = Some(writer);
+    }
+
+    /// Sets the size and modification time of the file to send when the
+    /// transfer was negotiated. The transfer is aborted if the file then
+    /// differs, rather than sending inconsistent data.
+    pub fn set_source_snapshot(&mut self, len: u64, modified: Option<SystemTime>) {
+        self.source = Some((len, modified));
+    }
+
+    /// Retrieve a ref to the statistics, filled during the transfer
+    pub fn stats(&self) -> Arc<Mutex<TransferStats>> {
+        self.stats.clone()
+    }
+
+    /// Retrieve a ref to the effective window size when sending, which is
+    /// adapted to losses during the transfer when enabled
+    pub fn effective_window(&self) -> Arc<AtomicU16> {
+        self.effective_window.clone()
+    }
+
     /// Sends a file to the remote [`SocketAddr`] that has sent a read request using
     /// a random port, asynchronously.
-    pub fn send(self, check_response: bool) -> Result<thread::JoinHandle<bool>, Box<dyn Error>> {
+    pub fn send(
+        mut self,
+        check_response: bool,
+    ) -> Result<thread::JoinHandle<Result<(), TftpError>>, Box<dyn Error>> {
         let file_path = self.file_path.clone();
         let remote_addr = self.socket.remote_addr().unwrap();
 
+        let start = self.start;
+        let stats = self.stats.clone();
+
         let handle = thread::spawn(move || {
             let handle_send = || -> Result<(), Box<dyn Error>> {
-                self.send_file(File::open(&file_path)?, check_response)
+                let reader: Box<dyn Read + Send> = match (self.reader.take(), self.source) {
+                    (Some(reader), _) => reader,
+                    (None, Some((len, modified))) => {
+                        Box::new(CheckedSource::new(File::open(&file_path)?, len, modified))
+                    }
+                    (None, None) => Box::new(File::open(&file_path)?),
+                };
+                let reader = self.encode(reader);
+                self.send_file(reader, check_response)
             };
+            let result = handle_send();
+            stats.lock().unwrap().duration = start.elapsed();
 
-            match handle_send() {
+            match result {
                 Ok(_) => {
                     log_info!(
                         "Sent {} to {}",
                         &file_path.file_name().unwrap().to_string_lossy(),
                         &remote_addr
                     );
-                    true
+                    Ok(())
                 }
                 Err(err) => {
                     log_err!(
@@ -98,7 +300,7 @@ impl<T: Socket + ?Sized> Worker<T> {
                         &file_path.file_name().unwrap().to_string_lossy(),
                         &remote_addr
                     );
-                    false
+                    Err(err.into())
                 }
             }
         });
@@ -108,22 +310,43 @@ impl<T: Socket + ?Sized> Worker<T> {
 
     /// Receives a file from the remote [`SocketAddr`] (client or server) using
     /// the supplied socket, asynchronously.
-    pub fn receive(self) -> Result<thread::JoinHandle<bool>, Box<dyn Error>> {
-        let clean_on_error = self.opt_local.clean_on_error;
+    pub fn receive(mut self) -> Result<thread::JoinHandle<Result<(), TftpError>>, Box<dyn Error>> {
+        let clean_on_error = self.opt_local.clean_on_error
+            && self.resume == 0
+            && !self.append
+            && self.writer.is_none();
         let file_path = self.file_path.clone();
         let remote_addr = self.socket.remote_addr().unwrap();
         let opt_tsize = self.opt_common.transfer_size;
+        let start = self.start;
+        let stats = self.stats.clone();
 
         let handle = thread::spawn(move || {
-            let handle_receive =
-                || -> Result<u64, Box<dyn Error>> { self.receive_file(File::create(&file_path)?) };
+            let handle_receive = || -> Result<u64, Box<dyn Error>> {
+                if let Some(writer) = self.writer.take() {
+                    return self.receive_file(writer);
+                }
+                let file = if self.resume > 0 || self.append {
+                    OpenOptions::new()
+                        .append(true)
+                        .create(true)
+                        .open(&file_path)?
+                } else {
+                    File::create(&file_path)?
+                };
+                self.receive_file(file)
+            };
+            let result = handle_receive();
+            stats.lock().unwrap().duration = start.elapsed();
 
-            match handle_receive() {
+            match result {
                 Ok(size) => {
                     if let Some(tsize) = opt_tsize {
                         if tsize != size {
-                            log_err!("Size mismatch, negotiated: {tsize}, transferred: {size}");
-                            return false;
+                            let msg =
+                                format!("Size mismatch, negotiated: {tsize}, transferred: {size}");
+                            log_err!("{msg}");
+                            return Err(TftpError::Transfer(msg.into()));
                         }
                     }
 
@@ -133,7 +356,7 @@ impl<T: Socket + ?Sized> Worker<T> {
                         size,
                         remote_addr
                     );
-                    true
+                    Ok(())
                 }
                 Err(err) => {
                     log_err!(
@@ -144,7 +367,7 @@ impl<T: Socket + ?Sized> Worker<T> {
                     if clean_on_error && fs::remove_file(&file_path).is_err() {
                         log_err!("Error while cleaning {}", &file_path.to_str().unwrap());
                     }
-                    false
+                    Err(err.into())
                 }
             }
         });
@@ -152,18 +375,43 @@ impl<T: Socket + ?Sized> Worker<T> {
         Ok(handle)
     }
 
-    fn send_file(mut self, file: File, check_response: bool) -> Result<(), Box<dyn Error>> {
+    fn send_file<R: Read>(mut self, file: R, check_response: bool) -> Result<(), Box<dyn Error>> {
         let mut block_seq_win: u16 = 0;
         let mut win_idx: u16 = 0;
-        let mut window = WindowRead::new(
+        // Number of blocks of the window already sent once
+        let mut win_sent: u16 = 0;
+        // Blocks of the window the receiver reported as received, not sent again
+        let mut sacked: Vec<bool> = vec![];
+        let (mut window, window_size) = WindowRead::with_memory_limit(
+            self.opt_local.max_memory.unwrap_or(DEFAULT_MAX_MEMORY),
             self.opt_common.window_size,
             self.opt_common.block_size,
             file,
         );
-        let mut more = window.fill()?;
+        if window_size < self.opt_common.window_size {
+            log_warn!(
+                "  Window size {} exceeds memory budget. Sending windows of {window_size}.",
+                self.opt_common.window_size
+            );
+        }
+        if self.opt_local.pad_final_block {
+            window.enable_padding();
+        }
+        let mut more = window
+            .fill()
+            .map_err(|err| self.read_error(err, block_seq_win, window.len()))?;
+        let mut adaptive = AdaptiveWindow::new(
+            self.opt_common.window_size,
+            self.opt_local.adaptive_window,
+            self.effective_window.clone(),
+        );
 
         let mut timeout_end = Instant::now() + self.opt_common.timeout;
         let mut retry_cnt = 0;
+        // Timeouts extended by a receiver slow to write, counted per block
+        let mut keepalives = 0;
+        // Blocks sent again since the acknowledgements last moved
+        let mut win_retransmits = 0;
 
         if cfg!(windows) {
             // On Windows, recv can return up to 15ms before timeout
@@ -173,6 +421,20 @@ impl<T: Socket + ?Sized> Worker<T> {
             self.socket.set_read_timeout(self.opt_common.timeout)?;
         }
 
+        if let Some(mut options) = self.oack.take() {
+            for option in options.iter_mut() {
+                match option.option {
+                    OptionType::Checksum => option.value = self.file_checksum()? as u64,
+                    OptionType::TransferSize if self.compression.is_some() => {
+                        option.value = io::copy(&mut self.encoded_file()?, &mut io::sink())?;
+                    }
+                    _ => (),
+                }
+            }
+            let extra = self.compression.iter().map(Compression::option).collect();
+            self.send_packet(&Packet::Oack(options, extra))?;
+        }
+
         if check_response {
             self.check_response()?;
         }
@@ -180,7 +442,9 @@ impl<T: Socket + ?Sized> Worker<T> {
         self.socket.set_nonblocking(true)?;
 
         loop {
-            if let Some(frame) = window.get_elements().get(win_idx as usize) {
+            let win_limit = window.len().min(adaptive.size());
+            if win_idx < win_limit {
+                let frame = &window.get_elements()[win_idx as usize];
                 let mut block_seq_tx = block_seq_win.wrapping_add(win_idx + 1);
                 if block_seq_tx < block_seq_win {
                     match self.opt_local.rollover {
@@ -194,21 +458,60 @@ impl<T: Socket + ?Sized> Worker<T> {
                     block_num: block_seq_tx,
                     data: frame.to_vec(),
                 })?;
-                win_idx += 1;
+                let mut next_idx = win_idx + 1;
+                while next_idx < win_limit && sacked.get(next_idx as usize) == Some(&true) {
+                    next_idx += 1;
+                }
+                {
+                    let mut stats = self.stats.lock().unwrap();
+                    if win_idx < win_sent {
+                        stats.retransmits += 1;
+                        win_retransmits += 1;
+                    }
+                    if next_idx == win_limit {
+                        stats.windows += 1;
+                    }
+                }
+                if next_idx == win_limit {
+                    let bytes = window
+                        .get_elements()
+                        .iter()
+                        .take(win_limit as usize)
+                        .map(|frame| frame.len() as u64)
+                        .sum();
+                    log_trace!("  Window sent up to block {block_seq_tx} ({bytes} bytes)");
+                    self.report_event(TransferEvent::WindowSent {
+                        block: block_seq_tx,
+                        bytes,
+                    });
+                }
+                win_idx = next_idx;
+                win_sent = win_sent.max(win_idx);
 
-                if win_idx < window.len() {
+                let mut wait = self.retransmit_timeout(retry_cnt);
+                if win_idx < win_limit {
                     if !self.opt_common.window_wait.is_zero() {
                         thread::sleep(self.opt_common.window_wait);
                     }
                 } else {
-                    window.prefill()?;
+                    window
+                        .prefill()
+                        .map_err(|err| self.read_error(err, block_seq_win, window.len()))?;
                     self.socket.set_nonblocking(false)?;
+
+                    // The receiver only acknowledges a window smaller than the
+                    // negotiated one after its own timeout, do not take it for a loss
+                    if win_limit < self.opt_common.window_size && (more || win_limit < window.len())
+                    {
+                        wait *= 2;
+                    }
                 }
 
-                timeout_end = Instant::now() + self.opt_common.timeout;
+                timeout_end = Instant::now() + wait;
             }
 
             let mut last_ack: Option<u16> = None;
+            let mut last_missing: Option<Vec<u16>> = None;
             loop {
                 self.check_abort()?;
 
@@ -218,11 +521,21 @@ impl<T: Socket + ?Sized> Worker<T> {
                             self.socket.set_nonblocking(true)?;
                         }
                         last_ack = Some(block_seq_rx);
+                        last_missing = None;
+                        continue;
+                    }
+
+                    Ok(Packet::Sack { block_num, missing }) => {
+                        if last_ack.is_none() {
+                            self.socket.set_nonblocking(true)?;
+                        }
+                        last_ack = Some(block_num);
+                        last_missing = Some(missing);
                         continue;
                     }
 
                     Ok(Packet::Error { code, msg }) => {
-                        return Err(format!("Received error code {code}: {msg}").into())
+                        return Err(TftpError::from_packet(code, msg).into())
                     }
 
                     Ok(_) => log_info!("  Received unexpected packet"),
@@ -240,27 +553,85 @@ impl<T: Socket + ?Sized> Worker<T> {
                                             diff -= 1;
                                         }
 
-                                        if diff == 0 {
+                                        if diff == 0 && last_missing.is_none() {
+                                            // Repeated by a receiver slow to write the previous window
+                                            if win_idx == win_limit
+                                                && keepalives < self.opt_local.max_retries
+                                            {
+                                                log_dbg!(
+                                                    "  Receiver busy, waiting for window again"
+                                                );
+                                                keepalives += 1;
+                                                timeout_end = timeout_end
+                                                    .max(Instant::now() + self.opt_common.timeout);
+                                                self.socket.set_nonblocking(false)?;
+                                            }
                                             break;
                                         } else if diff <= self.opt_common.window_size {
+                                            if diff >= win_idx {
+                                                adaptive.on_clean_window();
+                                            }
+                                            if diff > 0 {
+                                                // The retries are counted per block
+                                                retry_cnt = 0;
+                                                keepalives = 0;
+                                            }
                                             block_seq_win = ack;
+                                            {
+                                                let mut stats = self.stats.lock().unwrap();
+                                                if diff > 0 {
+                                                    stats.add_window_retransmits(std::mem::take(
+                                                        &mut win_retransmits,
+                                                    ));
+                                                }
+                                                stats.blocks += diff as u64;
+                                                stats.bytes += window
+                                                    .get_elements()
+                                                    .iter()
+                                                    .take(diff as usize)
+                                                    .map(|frame| frame.len() as u64)
+                                                    .sum::<u64>();
+                                                self.report_progress(stats.bytes);
+                                            }
+                                            win_sent = win_sent.saturating_sub(diff);
                                             window.remove(diff)?;
                                             if !more && window.is_empty() {
                                                 return Ok(());
                                             }
-                                            more = more && window.fill()?;
+                                            more = more
+                                                && window.fill().map_err(|err| {
+                                                    self.read_error(
+                                                        err,
+                                                        block_seq_win,
+                                                        window.len(),
+                                                    )
+                                                })?;
+                                            sacked = match last_missing.take() {
+                                                // Only the missing blocks are sent again
+                                                Some(missing) => (0..window.len())
+                                                    .map(|idx| {
+                                                        let block = block_at(
+                                                            ack,
+                                                            idx + 1,
+                                                            self.opt_local.rollover,
+                                                        );
+                                                        !missing.contains(&block)
+                                                    })
+                                                    .collect(),
+                                                None => vec![],
+                                            };
                                             win_idx = 0;
                                             break;
                                         } else {
                                             log_dbg!("      Received Ack with unexpected seq {ack} (prev {block_seq_win})");
                                         }
                                     }
-                                    if win_idx < window.len() && Instant::now() < timeout_end {
+                                    if win_idx < win_limit && Instant::now() < timeout_end {
                                         break;
                                     }
                                 }
-                                ErrorKind::ConnectionReset => {
-                                    log_info!("  Cnx reset during reception {io_e:?}")
+                                ErrorKind::ConnectionReset | ErrorKind::ConnectionRefused => {
+                                    return Err(port_closed(io_e));
                                 }
                                 _ => log_warn!("  IO error during reception {io_e:?}"),
                             }
@@ -280,7 +651,9 @@ impl<T: Socket + ?Sized> Worker<T> {
                         .into());
                     }
                     retry_cnt += 1;
-                    timeout_end = Instant::now() + self.opt_common.timeout;
+                    self.warn_fragmentation(retry_cnt);
+                    timeout_end = Instant::now() + self.retransmit_timeout(retry_cnt);
+                    adaptive.on_loss();
                     win_idx = 0;
                     self.socket.set_nonblocking(true)?;
                     break;
@@ -289,24 +662,85 @@ impl<T: Socket + ?Sized> Worker<T> {
         }
     }
 
+    /// Returns the wait for an acknowledgement, doubled on each consecutive
+    /// loss of the same block so as not to flood a congested link
+    fn retransmit_timeout(&self, retry_cnt: usize) -> Duration {
+        self.opt_common.timeout * (1 << retry_cnt.min(MAX_BACKOFF_SHIFT)) as u32
+    }
+
     fn send_rollover_error(&self) -> Box<dyn Error> {
+        self.send_protocol_error("Block counter rollover error".to_string())
+    }
+
+    fn send_protocol_error(&self, msg: String) -> Box<dyn Error> {
         self.send_packet(&Packet::Error {
             code: ErrorCode::IllegalOperation,
-            msg: "Block counter rollover error".to_string(),
+            msg: msg.clone(),
         })
         .unwrap_or_else(|err| {
             log_err!("Error: error '{err:?}' while sending error code");
         });
-        "Block counter rollover error".into()
+        msg.into()
     }
 
-    fn receive_file(mut self, file: File) -> Result<u64, Box<dyn Error>> {
-        // rx socket size for data and error packets
-        let max_pkt_size: usize =
-            std::cmp::max(MAX_ERROR_PACKET_SIZE, self.opt_common.block_size as usize);
+    fn receive_file<W: Sink>(mut self, file: W) -> Result<u64, Box<dyn Error>> {
+        // rx socket size for data and error packets, one byte larger than
+        // a block to detect the oversized ones instead of truncating them
+        let max_pkt_size: usize = std::cmp::max(
+            MAX_ERROR_PACKET_SIZE,
+            self.opt_common.block_size as usize + 1,
+        );
+        let block_size = self.opt_common.block_size as usize;
         let mut block_number: u16 = 0;
         let mut window = WindowWrite::new(self.opt_common.window_size, file);
+        let mut pipeline = std::mem::take(&mut self.pipeline);
+        if self.mode == TransferMode::Netascii {
+            // Local text is decoded first, the pipeline gets the same data as in octet mode
+            let mut netascii = Pipeline::new();
+            netascii.push(NetasciiDecoder::new());
+            if !pipeline.is_empty() {
+                netascii.push(pipeline);
+            }
+            pipeline = netascii;
+        }
+        if let Some(compression) = self.compression {
+            // Decompressed first, the sender compressing the translated text
+            let mut decompress = Pipeline::new();
+            decompress.push(Decompressor::with_memory_limit(
+                compression,
+                self.opt_local.max_memory.unwrap_or(DEFAULT_MAX_MEMORY),
+            ));
+            if !pipeline.is_empty() {
+                decompress.push(pipeline);
+            }
+            pipeline = decompress;
+        }
+        if self.resume > 0 {
+            // Skips the data as it would be written to the file
+            let mut resume = Pipeline::new();
+            if !pipeline.is_empty() {
+                resume.push(pipeline);
+            }
+            resume.push(Skip::new(self.resume));
+            pipeline = resume;
+        }
+        window.set_pipeline(pipeline);
+        if self.opt_common.checksum.is_some() {
+            window.enable_checksum();
+        }
+        if self.manifest.is_some() {
+            window.enable_window_checksums(
+                self.opt_common.window_size as u64 * self.opt_common.block_size as u64,
+            );
+        }
+        // Count of windows matching the manifest
+        let mut verified = 0;
         let mut retry_cnt = 0;
+        let mut win_bytes: u64 = 0;
+        // Final block received after a gap, with selective acknowledgements
+        let mut final_block: Option<u16> = None;
+        // Last block acknowledged at the end of a window
+        let mut acked_block = block_number;
 
         let mut last = false;
         let mut listen_all = false;
@@ -314,11 +748,21 @@ impl<T: Socket + ?Sized> Worker<T> {
 
         while !last {
             while !send_ack {
-                match self.socket.recv_with_size(max_pkt_size) {
+                let received = match self.first_packet.take() {
+                    Some(packet) => Ok(packet),
+                    None => self.socket.recv_into(window.buffer(), max_pkt_size),
+                };
+                match received {
                     Ok(Packet::Data {
                         block_num: received_block_number,
                         data,
                     }) => {
+                        if data.len() > block_size {
+                            return Err(self.send_protocol_error(format!(
+                                "Data block {received_block_number} of {} bytes exceeds the blocksize {block_size}",
+                                data.len()
+                            )));
+                        }
                         let mut new_block_number = block_number.wrapping_add(1);
                         if new_block_number == 0 {
                             match self.opt_local.rollover {
@@ -345,12 +789,61 @@ impl<T: Socket + ?Sized> Worker<T> {
                         }
 
                         if received_block_number == new_block_number {
-                            block_number = received_block_number;
-                            last = data.len() < self.opt_common.block_size as usize;
+                            {
+                                let mut stats = self.stats.lock().unwrap();
+                                stats.time_to_first_data.get_or_insert(self.start.elapsed());
+                                stats.blocks += 1;
+                                stats.bytes += data.len() as u64;
+                            }
+                            win_bytes += data.len() as u64;
+                            // Only consecutive timeouts count, as a sender with a
+                            // reduced window relies on them
+                            retry_cnt = 0;
+                            last = data.len() < block_size;
+                            let len = window.len();
                             window.add(data)?;
-                            send_ack = window.is_full() || last;
+                            if last && window.has_short_block_before_end(block_size) {
+                                return Err(self.send_protocol_error(format!(
+                                    "Data block {received_block_number} is short but not the last"
+                                )));
+                            }
+                            // Blocks received after a gap may now follow this one
+                            block_number = block_at(
+                                received_block_number,
+                                window.len() - len - 1,
+                                self.opt_local.rollover,
+                            );
+                            last = last || final_block == Some(block_number);
+                            send_ack = window.is_full() || last || self.opt_local.ack_per_block;
+                        } else if let Some(offset) =
+                            self.sack_offset(&window, block_number, received_block_number)
+                        {
+                            {
+                                let mut stats = self.stats.lock().unwrap();
+                                stats.time_to_first_data.get_or_insert(self.start.elapsed());
+                                stats.blocks += 1;
+                                stats.bytes += data.len() as u64;
+                            }
+                            win_bytes += data.len() as u64;
+                            retry_cnt = 0;
+                            let short = data.len() < block_size;
+                            if short {
+                                final_block = Some(received_block_number);
+                            }
+                            window.add_at(offset, data)?;
+                            if final_block.is_some()
+                                && window.has_short_block_before_end(block_size)
+                            {
+                                return Err(self.send_protocol_error(format!(
+                                    "Data received after the short block {}",
+                                    final_block.unwrap_or_default()
+                                )));
+                            }
+                            // The sender waits for the acknowledgement once its window is sent
+                            send_ack = short || offset + 1 == self.opt_common.window_size;
                         } else {
                             log_dbg!("  Data packet mismatch. Received {received_block_number} instead of {new_block_number}.");
+                            self.stats.lock().unwrap().retransmits += 1;
                             send_ack = true;
                         }
 
@@ -358,7 +851,7 @@ impl<T: Socket + ?Sized> Worker<T> {
                         listen_all = true;
                     }
                     Ok(Packet::Error { code, msg }) => {
-                        return Err(format!("Received error '{code}': {msg}").into());
+                        return Err(TftpError::from_packet(code, msg).into());
                     }
                     Ok(_) => log_info!("  Received unexpected packet"),
 
@@ -376,6 +869,11 @@ impl<T: Socket + ?Sized> Worker<T> {
                                             self.opt_local.max_retries
                                         );
                                         if retry_cnt == self.opt_local.max_retries {
+                                            // Keeps the data received in order in the file
+                                            let committed = window
+                                                .flush_partial()
+                                                .map_err(|err| self.write_error(err))?;
+                                            log_dbg!("  Wrote {committed} bytes of the incomplete window");
                                             return Err(format!(
                                                 "Transfer timed out after {} tries",
                                                 self.opt_local.max_retries
@@ -383,12 +881,12 @@ impl<T: Socket + ?Sized> Worker<T> {
                                             .into());
                                         }
                                         retry_cnt += 1;
+                                        self.warn_fragmentation(retry_cnt);
                                         send_ack = true;
                                     }
                                 }
-                                ErrorKind::ConnectionReset => {
-                                    log_info!("  Cnx reset during reception {io_e:?}");
-                                    self.socket.set_nonblocking(false)?;
+                                ErrorKind::ConnectionReset | ErrorKind::ConnectionRefused => {
+                                    return Err(port_closed(io_e));
                                 }
                                 _ => log_warn!("  IO error during reception {io_e:?}"),
                             }
@@ -401,15 +899,201 @@ impl<T: Socket + ?Sized> Worker<T> {
                 self.check_abort()?;
             }
 
-            self.send_packet(&Packet::Ack(block_number))?;
+            if last {
+                // the data must be in the file once the final block is acknowledged
+                let mut acked = Instant::now();
+                window
+                    .empty_received_with(|| self.keep_alive(acked_block, &mut acked))
+                    .map_err(|err| self.write_error(err))?;
+                window.finish().map_err(|err| self.write_error(err))?;
+                self.check_manifest(&mut window, &mut verified, true)?;
+                if self.opt_local.sync {
+                    window.sync().map_err(|err| self.write_error(err))?;
+                }
+            }
+            acked_block = block_number;
+            if window.is_complete() {
+                self.send_packet(&Packet::Ack(block_number))?;
+            } else {
+                let missing = window
+                    .unreceived()
+                    .into_iter()
+                    .map(|offset| {
+                        block_at(
+                            block_number,
+                            offset - window.len() + 1,
+                            self.opt_local.rollover,
+                        )
+                    })
+                    .collect();
+                self.send_packet(&Packet::Sack {
+                    block_num: block_number,
+                    missing,
+                })?;
+            }
+            {
+                let mut stats = self.stats.lock().unwrap();
+                stats.windows += 1;
+                self.report_progress(stats.bytes);
+            }
+            // Acknowledgements sent again after a timeout do not end a window
+            if win_bytes > 0 || last {
+                log_trace!("  Window received up to block {block_number} ({win_bytes} bytes)");
+                self.report_event(TransferEvent::WindowReceived {
+                    block: block_number,
+                    bytes: std::mem::take(&mut win_bytes),
+                });
+            }
             send_ack = false;
 
-            window.empty()?;
+            let mut acked = Instant::now();
+            if window.is_complete() {
+                window.empty_received_with(|| self.keep_alive(block_number, &mut acked))
+            } else {
+                window.empty_received()
+            }
+            .map_err(|err| self.write_error(err))?;
+            self.check_manifest(&mut window, &mut verified, last)?;
         }
 
         // we should wait and listen a bit more as per RFC 1350 section 6
+        self.check_trailing_data(block_number, max_pkt_size)?;
+
+        if let (Some(checksum), Some(received)) = (self.opt_common.checksum, window.checksum()) {
+            if received != checksum {
+                return Err(format!(
+                    "Checksum mismatch, negotiated: {checksum:08x}, transferred: {received:08x}"
+                )
+                .into());
+            }
+            log_dbg!("  Checksum {checksum:08x} verified");
+        }
+
+        Ok(window.received_len())
+    }
+
+    /// Compares the CRC-32 of the windows written since the previous call to
+    /// the manifest, if any, `verified` counting the matching ones. Once the
+    /// file is `finished`, the manifest should not list more windows.
+    fn check_manifest<W: Sink>(
+        &self,
+        window: &mut WindowWrite<W>,
+        verified: &mut usize,
+        finished: bool,
+    ) -> Result<(), Box<dyn Error>> {
+        let Some(manifest) = &self.manifest else {
+            return Ok(());
+        };
+
+        let mut error = None;
+        for crc in window.take_window_checksums() {
+            match manifest.get(*verified) {
+                Some(&expected) if expected == crc => *verified += 1,
+                Some(&expected) => {
+                    error = Some(format!(
+                        "Window {} does not match the manifest, expected CRC-32: {expected:08x}, received: {crc:08x}",
+                        *verified + 1
+                    ));
+                    break;
+                }
+                None => {
+                    error = Some(format!("Manifest only lists {} windows", manifest.len()));
+                    break;
+                }
+            }
+        }
+        if error.is_none() && finished && *verified < manifest.len() {
+            error = Some(format!(
+                "Received {} windows, manifest lists {}",
+                *verified,
+                manifest.len()
+            ));
+        }
+
+        match error {
+            Some(msg) => {
+                let _ = self.socket.send(&Packet::Error {
+                    code: ErrorCode::NotDefined,
+                    msg: "manifest mismatch".to_string(),
+                });
+                Err(msg.into())
+            }
+            None => Ok(()),
+        }
+    }
 
-        window.file_len()
+    /// Acknowledges `block_number` again when the file has been written for
+    /// half the timeout since `acked`, so that the sender waits for the next
+    /// window instead of timing out. Sent at most once per half timeout.
+    fn keep_alive(&self, block_number: u16, acked: &mut Instant) -> Result<(), Box<dyn Error>> {
+        if acked.elapsed() >= self.opt_common.timeout / 2 {
+            log_dbg!("  Slow write, acknowledging block {block_number} again");
+            self.send_packet(&Packet::Ack(block_number))?;
+            *acked = Instant::now();
+        }
+
+        Ok(())
+    }
+
+    /// Returns the offset in the window of a block received after a gap, when
+    /// selective acknowledgements were negotiated and the block belongs to the
+    /// window of the sender.
+    fn sack_offset<W: Sink>(
+        &self,
+        window: &WindowWrite<W>,
+        block_number: u16,
+        received_block_number: u16,
+    ) -> Option<u16> {
+        if !self.opt_common.sack {
+            return None;
+        }
+
+        let mut distance = received_block_number.wrapping_sub(block_number);
+        if received_block_number < block_number && self.opt_local.rollover == Rollover::Enforce1 {
+            distance -= 1;
+        }
+        (distance > 1 && distance <= self.opt_common.window_size - window.len())
+            .then(|| window.len() + distance - 1)
+    }
+
+    /// Handles the packets already received after the final block, without
+    /// waiting for more: duplicates of the final block are acknowledged again,
+    /// and following data blocks are handled as per the trailing data policy.
+    fn check_trailing_data(
+        &mut self,
+        block_number: u16,
+        max_pkt_size: usize,
+    ) -> Result<(), Box<dyn Error>> {
+        self.socket.set_nonblocking(true)?;
+
+        while let Ok(packet) = self.socket.recv_with_size(max_pkt_size) {
+            if let Packet::Data { block_num, .. } = packet {
+                let diff = block_num.wrapping_sub(block_number);
+                if diff == 0 {
+                    self.send_packet(&Packet::Ack(block_number))?;
+                } else if diff < 0x8000 {
+                    match self.opt_local.trailing_data {
+                        TrailingData::Ignore => {
+                            log_warn!(
+                                "  Ignoring data block {block_num} received after final block"
+                            )
+                        }
+                        TrailingData::Abort => {
+                            self.send_packet(&Packet::Error {
+                                code: ErrorCode::IllegalOperation,
+                                msg: "Data received after final block".to_string(),
+                            })?;
+                            return Err(format!(
+                                "Data block {block_num} received after final block {block_number}"
+                            )
+                            .into());
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(())
     }
 
     fn send_packet(&self, packet: &Packet) -> Result<(), Box<dyn Error>> {
@@ -454,11 +1138,119 @@ impl<T: Socket + ?Sized> Worker<T> {
             msg: "invalid oack response".to_string(),
         })?;
 
-        Err(format!("Unexpected packet received instead of Ack(0): {pkt:#?}").into())
+        Err(format!("Unexpected packet received instead of Ack(0): {pkt}").into())
+    }
+
+    /// Returns the CRC-32 of the file as sent, after translation and compression
+    fn file_checksum(&self) -> io::Result<u32> {
+        match (self.mode, self.compression) {
+            (TransferMode::Octet, None) => crc32_file(&self.file_path),
+            _ => crc32_read(self.encoded_file()?),
+        }
+    }
+
+    /// Opens the file, read as sent
+    fn encoded_file(&self) -> io::Result<Box<dyn Read + Send>> {
+        Ok(self.encode(Box::new(File::open(&self.file_path)?)))
+    }
+
+    /// Translates then compresses the data read, as configured
+    fn encode(&self, reader: Box<dyn Read + Send>) -> Box<dyn Read + Send> {
+        let reader: Box<dyn Read + Send> = match self.mode {
+            TransferMode::Octet => reader,
+            TransferMode::Netascii => Box::new(NetasciiEncoder::new(reader)),
+        };
+        match self.compression {
+            Some(compression) => Box::new(Compressor::new(reader, compression)),
+            None => reader,
+        }
+    }
+
+    fn report_progress(&self, bytes: u64) {
+        if let Some(progress) = &self.progress {
+            // The receiving side may be gone, the transfer goes on regardless
+            let _ = progress.send(bytes);
+        }
+    }
+
+    /// Warns once when the transfer keeps timing out with blocks larger than
+    /// an Ethernet frame, which are fragmented on some paths
+    fn warn_fragmentation(&self, retry_cnt: usize) {
+        if retry_cnt == FRAGMENTATION_RETRIES
+            && self.opt_common.block_size > ETHERNET_MAX_BLOCK_SIZE
+        {
+            log_warn!(
+                "  Blocks of {} bytes keep timing out, they may be fragmented: try a smaller MTU or blocksize",
+                self.opt_common.block_size
+            );
+        }
+    }
+
+    fn report_event(&self, event: TransferEvent) {
+        if let Some(events) = &self.events {
+            // Blocks while the channel is full, fails once the receiver is dropped
+            let _ = events.send(event);
+        }
+    }
+
+    /// Adds the file name and the number of the block being read to a read
+    /// error, keeping its kind, and tells the peer the transfer is aborted.
+    fn read_error(&self, err: Box<dyn Error>, block_seq_win: u16, read: u16) -> Box<dyn Error> {
+        let block = block_at(block_seq_win, read + 1, self.opt_local.rollover);
+        let msg = format!(
+            "read error on '{}' at block {block}: {err}",
+            self.file_path
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+        );
+        let _ = self.socket.send(&Packet::Error {
+            code: ErrorCode::NotDefined,
+            msg: "read error".to_string(),
+        });
+
+        match err.downcast::<io::Error>() {
+            Ok(err) => Box::new(io::Error::new(err.kind(), msg)),
+            Err(_) => msg.into(),
+        }
+    }
+
+    /// Adds the file name to a write error, keeping its kind, and tells the
+    /// peer the transfer is aborted with the matching TFTP error code.
+    fn write_error(&self, err: Box<dyn Error>) -> Box<dyn Error> {
+        let (code, reason) = write_error_code(err.downcast_ref::<io::Error>().map(io::Error::kind));
+        let _ = self.socket.send(&Packet::Error {
+            code,
+            msg: reason.to_string(),
+        });
+        let msg = format!(
+            "write error on '{}': {err}",
+            self.file_path
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+        );
+
+        match err.downcast::<io::Error>() {
+            Ok(err) => Box::new(io::Error::new(err.kind(), msg)),
+            Err(_) => msg.into(),
+        }
     }
 
     fn check_abort(&self) -> Result<(), Box<dyn Error>> {
-        if self.abort.load(std::sync::atomic::Ordering::Relaxed) {
+        if self
+            .opt_local
+            .deadline
+            .is_some_and(|deadline| self.start.elapsed() >= deadline)
+        {
+            log_warn!("  Transfer deadline exceeded, aborting");
+            self.socket.send(&Packet::Error {
+                code: ErrorCode::NotDefined,
+                msg: "transfer deadline exceeded".to_string(),
+            })?;
+
+            Err("transfer deadline exceeded".into())
+        } else if self.abort.load(Ordering::Relaxed) {
             self.socket.send(&Packet::Error {
                 code: ErrorCode::NotDefined,
                 msg: "Transfert aborted by user".to_string(),
@@ -470,3 +1262,1410 @@ impl<T: Socket + ?Sized> Worker<T> {
         }
     }
 }
+
+/// Returns the number of the block `offset` blocks after `block`, skipping 0
+/// when rolling over to 1.
+fn block_at(block: u16, offset: u16, rollover: Rollover) -> u16 {
+    let next = block.wrapping_add(offset);
+    if next < block && rollover == Rollover::Enforce1 {
+        next + 1
+    } else {
+        next
+    }
+}
+
+/// Returns the TFTP error code and message sent to the peer when the data
+/// received cannot be written, from the kind of the I/O error if any.
+fn write_error_code(kind: Option<ErrorKind>) -> (ErrorCode, &'static str) {
+    match kind {
+        Some(ErrorKind::StorageFull | ErrorKind::QuotaExceeded) => {
+            (ErrorCode::DiskFull, "disk full")
+        }
+        Some(ErrorKind::PermissionDenied | ErrorKind::ReadOnlyFilesystem) => {
+            (ErrorCode::AccessViolation, "access violation")
+        }
+        _ => (ErrorCode::NotDefined, "write error"),
+    }
+}
+
+/// File sent, failing to read once it differs from its snapshot: more bytes
+/// than its size, or at its end, another size or modification time. The file
+/// is checked through its handle, a file replaced by another one being still
+/// read consistently.
+struct CheckedSource {
+    file: File,
+    len: u64,
+    modified: Option<SystemTime>,
+    read: u64,
+}
+
+impl CheckedSource {
+    fn new(file: File, len: u64, modified: Option<SystemTime>) -> CheckedSource {
+        CheckedSource {
+            file,
+            len,
+            modified,
+            read: 0,
+        }
+    }
+
+    fn is_modified(&self) -> io::Result<bool> {
+        let metadata = self.file.metadata()?;
+        Ok(self.read != self.len
+            || metadata.len() != self.len
+            || self
+                .modified
+                .is_some_and(|modified| metadata.modified().ok() != Some(modified)))
+    }
+}
+
+impl Read for CheckedSource {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.file.read(buf)?;
+        self.read += read as u64;
+        // Checked at the end before the final block is sent, the peer never
+        // completing the file
+        if self.read > self.len || (read == 0 && !buf.is_empty() && self.is_modified()?) {
+            return Err(io::Error::other("source file modified during transfer"));
+        }
+
+        Ok(read)
+    }
+}
+
+/// Error for an ICMP error reported on reception, the peer port being most
+/// likely closed, waiting for the retries would be useless.
+fn port_closed(err: &std::io::Error) -> Box<dyn Error> {
+    format!("Peer port is closed, transfer aborted ({err})").into()
+}
+
+/// Effective count of blocks sent before waiting for an acknowledgement,
+/// adapted to losses similarly to TCP congestion control: halved on each loss
+/// and increased by one after each fully acknowledged window. The negotiated
+/// window size is the upper bound.
+///
+/// As RFC 7440 receivers only acknowledge full windows or after a timeout, a
+/// reduced window relies on the receiver timeout, so this is opt-in. Only the
+/// sender timeouts are taken for losses, as partial acknowledgements are also
+/// the receiver answer to a reduced window.
+#[derive(Debug)]
+struct AdaptiveWindow {
+    max: u16,
+    size: Arc<AtomicU16>,
+    enabled: bool,
+}
+
+impl AdaptiveWindow {
+    fn new(max: u16, enabled: bool, size: Arc<AtomicU16>) -> AdaptiveWindow {
+        size.store(max, Ordering::Relaxed);
+        AdaptiveWindow { max, size, enabled }
+    }
+
+    /// Returns the current effective window size
+    fn size(&self) -> u16 {
+        self.size.load(Ordering::Relaxed)
+    }
+
+    fn on_loss(&mut self) {
+        let size = self.size();
+        if self.enabled && size > 1 {
+            self.size.store(size / 2, Ordering::Relaxed);
+            log_dbg!("  Window size reduced to {}", size / 2);
+        }
+    }
+
+    fn on_clean_window(&mut self) {
+        let size = self.size();
+        if self.enabled && size < self.max {
+            self.size.store(size + 1, Ordering::Relaxed);
+            log_dbg!("  Window size increased to {}", size + 1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checksum::Crc32;
+    use std::net::UdpSocket;
+
+    const DIR_NAME: &str = "target/test/worker";
+
+    #[test]
+    fn ignores_data_after_final_block() {
+        const FILENAME: &str = "ignores_data_after_final_block.txt";
+
+        let opt_local = OptionsPrivate {
+            trailing_data: TrailingData::Ignore,
+            ..Default::default()
+        };
+        let (peer, result) = receive_with_trailing_data(FILENAME, opt_local);
+
+        assert!(result.is_ok());
+        assert_eq!(fs::read(file_path(FILENAME)).unwrap(), b"short");
+        assert_eq!(recv(&peer), Packet::Ack(1));
+
+        clean(FILENAME);
+    }
+
+    #[test]
+    fn aborts_on_data_after_final_block() {
+        const FILENAME: &str = "aborts_on_data_after_final_block.txt";
+
+        let (peer, result) = receive_with_trailing_data(FILENAME, Default::default());
+
+        assert!(result.is_err());
+        assert!(!file_path(FILENAME).exists());
+        assert_eq!(recv(&peer), Packet::Ack(1));
+        assert!(matches!(
+            recv(&peer),
+            Packet::Error {
+                code: ErrorCode::IllegalOperation,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn verifies_matching_checksum() {
+        const FILENAME: &str = "verifies_matching_checksum.txt";
+
+        let (_, result) = receive_with_checksum(FILENAME, 0x8F28_90A2);
+
+        assert!(result.is_ok());
+        assert_eq!(fs::read(file_path(FILENAME)).unwrap(), b"short");
+
+        clean(FILENAME);
+    }
+
+    #[test]
+    fn fails_on_checksum_mismatch() {
+        const FILENAME: &str = "fails_on_checksum_mismatch.txt";
+
+        let (_, result) = receive_with_checksum(FILENAME, 0x1234_5678);
+
+        assert!(result.is_err());
+        assert!(!file_path(FILENAME).exists());
+    }
+
+    #[test]
+    fn adapts_window_to_losses() {
+        let mut adaptive = AdaptiveWindow::new(8, true, Default::default());
+        assert_eq!(adaptive.size(), 8);
+
+        adaptive.on_clean_window();
+        assert_eq!(adaptive.size(), 8);
+
+        adaptive.on_loss();
+        assert_eq!(adaptive.size(), 4);
+        adaptive.on_loss();
+        adaptive.on_loss();
+        adaptive.on_loss();
+        assert_eq!(adaptive.size(), 1);
+
+        for size in 2..=8 {
+            adaptive.on_clean_window();
+            assert_eq!(adaptive.size(), size);
+        }
+        adaptive.on_clean_window();
+        assert_eq!(adaptive.size(), 8);
+    }
+
+    #[test]
+    fn recovers_window_after_loss() {
+        const FILENAME: &str = "recovers_window_after_loss.txt";
+        const TIMEOUT: Duration = Duration::from_millis(200);
+
+        let _ = fs::create_dir_all(DIR_NAME);
+        // 12 full blocks and a short one
+        fs::write(
+            file_path(FILENAME),
+            b"0123456789abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMN",
+        )
+        .unwrap();
+        let (socket, peer) = socket_pair();
+        socket.set_read_timeout(Some(TIMEOUT)).unwrap();
+        peer.set_read_timeout(Some(TIMEOUT)).unwrap();
+
+        let opt_local = OptionsPrivate {
+            adaptive_window: true,
+            ..Default::default()
+        };
+        let opt_common = OptionsProtocol {
+            block_size: 4,
+            window_size: 4,
+            timeout: TIMEOUT,
+            ..Default::default()
+        };
+        let worker = Worker::new(
+            Box::new(socket),
+            file_path(FILENAME),
+            opt_local,
+            opt_common,
+            Default::default(),
+        );
+        let effective_window = worker.effective_window();
+        let handle = worker.send(false).unwrap();
+
+        // First window is lost
+        for block_num in 1..=4 {
+            assert!(matches!(recv(&peer), Packet::Data { block_num: n, .. } if n == block_num));
+        }
+
+        // Then behave as a RFC 7440 receiver, recording the acknowledged windows
+        let mut windows = vec![];
+        let mut expected = 1;
+        let mut received = 0;
+        loop {
+            match Socket::recv(&peer) {
+                Ok(Packet::Data { block_num, data }) if block_num == expected => {
+                    if expected == 1 {
+                        assert_eq!(effective_window.load(Ordering::Relaxed), 2);
+                    }
+                    expected += 1;
+                    received += 1;
+                    let last = data.len() < 4;
+                    if received == 4 || last {
+                        Socket::send(&peer, &Packet::Ack(block_num)).unwrap();
+                        windows.push(received);
+                        received = 0;
+                    }
+                    if last {
+                        break;
+                    }
+                }
+                Ok(_) => (),
+                Err(_) => {
+                    Socket::send(&peer, &Packet::Ack(expected - 1)).unwrap();
+                    if received > 0 {
+                        windows.push(received);
+                    }
+                    received = 0;
+                }
+            }
+        }
+
+        assert!(handle.join().unwrap().is_ok());
+        assert_eq!(windows, [2, 3, 4, 4]);
+        assert_eq!(effective_window.load(Ordering::Relaxed), 4);
+
+        clean(FILENAME);
+    }
+
+    #[test]
+    fn sends_again_only_missing_blocks() {
+        const FILENAME: &str = "sends_again_only_missing_blocks.txt";
+
+        let _ = fs::create_dir_all(DIR_NAME);
+        // 5 full blocks and a short one
+        fs::write(file_path(FILENAME), b"0123456789abcdefghij!").unwrap();
+        let (socket, peer) = socket_pair();
+
+        let opt_common = OptionsProtocol {
+            block_size: 4,
+            window_size: 6,
+            timeout: Duration::from_millis(500),
+            sack: true,
+            ..Default::default()
+        };
+        let worker = Worker::new(
+            Box::new(socket),
+            file_path(FILENAME),
+            Default::default(),
+            opt_common,
+            Default::default(),
+        );
+        let stats = worker.stats();
+        let handle = worker.send(false).unwrap();
+
+        for block_num in 1..=6 {
+            assert!(matches!(recv(&peer), Packet::Data { block_num: n, .. } if n == block_num));
+        }
+        // Blocks 2 and 4 are lost
+        let sack = Packet::Sack {
+            block_num: 1,
+            missing: vec![2, 4],
+        };
+        Socket::send(&peer, &sack).unwrap();
+
+        assert_eq!(
+            recv(&peer),
+            Packet::Data {
+                block_num: 2,
+                data: b"4567".to_vec()
+            }
+        );
+        assert_eq!(
+            recv(&peer),
+            Packet::Data {
+                block_num: 4,
+                data: b"cdef".to_vec()
+            }
+        );
+        Socket::send(&peer, &Packet::Ack(6)).unwrap();
+
+        assert!(handle.join().unwrap().is_ok());
+        assert_eq!(stats.lock().unwrap().retransmits, 2);
+        assert!(Socket::recv(&peer).is_err());
+
+        clean(FILENAME);
+    }
+
+    #[test]
+    fn reports_missing_blocks() {
+        const FILENAME: &str = "reports_missing_blocks.txt";
+
+        let _ = fs::create_dir_all(DIR_NAME);
+        let (socket, peer) = socket_pair();
+        let opt_common = OptionsProtocol {
+            block_size: 4,
+            window_size: 6,
+            timeout: Duration::from_millis(500),
+            sack: true,
+            ..Default::default()
+        };
+        let worker = Worker::new(
+            Box::new(socket),
+            file_path(FILENAME),
+            Default::default(),
+            opt_common,
+            Default::default(),
+        );
+        let handle = worker.receive().unwrap();
+
+        let blocks: Vec<&[u8]> = vec![b"0123", b"4567", b"89ab", b"cdef", b"ghij", b"!"];
+        let send_block = |block_num: u16| {
+            let data = Packet::Data {
+                block_num,
+                data: blocks[block_num as usize - 1].to_vec(),
+            };
+            Socket::send(&peer, &data).unwrap();
+        };
+
+        for block_num in [1, 3, 5, 6] {
+            send_block(block_num);
+        }
+        assert_eq!(
+            recv(&peer),
+            Packet::Sack {
+                block_num: 1,
+                missing: vec![2, 4]
+            }
+        );
+
+        for block_num in [2, 4] {
+            send_block(block_num);
+        }
+        assert_eq!(recv(&peer), Packet::Ack(6));
+
+        assert!(handle.join().unwrap().is_ok());
+        assert_eq!(
+            fs::read(file_path(FILENAME)).unwrap(),
+            b"0123456789abcdefghij!"
+        );
+
+        clean(FILENAME);
+    }
+
+    #[test]
+    fn refuses_oversized_data_block() {
+        const FILENAME: &str = "refuses_oversized_data_block.txt";
+
+        let _ = fs::create_dir_all(DIR_NAME);
+        let (socket, peer) = socket_pair();
+        let opt_common = OptionsProtocol {
+            block_size: 4,
+            timeout: Duration::from_millis(500),
+            ..Default::default()
+        };
+        let worker = Worker::new(
+            Box::new(socket),
+            file_path(FILENAME),
+            Default::default(),
+            opt_common,
+            Default::default(),
+        );
+        let handle = worker.receive().unwrap();
+
+        let data = Packet::Data {
+            block_num: 1,
+            data: b"01234".to_vec(),
+        };
+        Socket::send(&peer, &data).unwrap();
+        assert!(matches!(
+            recv(&peer),
+            Packet::Error {
+                code: ErrorCode::IllegalOperation,
+                ..
+            }
+        ));
+        let err = handle.join().unwrap().unwrap_err();
+        assert!(err.to_string().contains("exceeds the blocksize 4"));
+        assert!(!file_path(FILENAME).exists());
+    }
+
+    #[test]
+    fn refuses_premature_short_data_block() {
+        const FILENAME: &str = "refuses_premature_short_data_block.txt";
+
+        let _ = fs::create_dir_all(DIR_NAME);
+        // The short block 2 arrives after the one following it, in order or not
+        for order in [vec![1, 3, 2], vec![3, 2]] {
+            let (socket, peer) = socket_pair();
+            let opt_common = OptionsProtocol {
+                block_size: 4,
+                window_size: 4,
+                timeout: Duration::from_millis(500),
+                sack: true,
+                ..Default::default()
+            };
+            let worker = Worker::new(
+                Box::new(socket),
+                file_path(FILENAME),
+                Default::default(),
+                opt_common,
+                Default::default(),
+            );
+            let handle = worker.receive().unwrap();
+
+            let blocks: Vec<&[u8]> = vec![b"0123", b"45", b"6789"];
+            for block_num in order {
+                let data = Packet::Data {
+                    block_num,
+                    data: blocks[block_num as usize - 1].to_vec(),
+                };
+                Socket::send(&peer, &data).unwrap();
+            }
+            let error = loop {
+                match recv(&peer) {
+                    Packet::Error { code, .. } => break code,
+                    _ => continue,
+                }
+            };
+            assert_eq!(error, ErrorCode::IllegalOperation);
+            assert!(handle.join().unwrap().is_err());
+            assert!(!file_path(FILENAME).exists());
+        }
+    }
+
+    #[test]
+    fn counts_retransmits_per_window() {
+        let (socket, peer) = socket_pair();
+        let opt_common = OptionsProtocol {
+            block_size: 4,
+            window_size: 2,
+            timeout: Duration::from_millis(200),
+            ..Default::default()
+        };
+        let worker = Worker::with_reader(
+            Box::new(socket),
+            Box::new(io::Cursor::new(b"0123456789abcdefghijxy".to_vec())),
+            Default::default(),
+            opt_common,
+            Default::default(),
+        );
+        let stats = worker.stats();
+        let handle = worker.send(false).unwrap();
+
+        let expect = |blocks: &[u16], ack: u16| {
+            for &block in blocks {
+                assert!(
+                    matches!(recv(&peer), Packet::Data { block_num, .. } if block_num == block)
+                );
+            }
+            Socket::send(&peer, &Packet::Ack(ack)).unwrap();
+        };
+        expect(&[1, 2], 2);
+        // Block 4 is lost, then sent again with block 5
+        expect(&[3, 4], 3);
+        expect(&[4, 5], 5);
+        // The final block is lost twice, sent again after each timeout
+        expect(&[6, 6, 6], 6);
+
+        assert!(handle.join().unwrap().is_ok());
+        let stats = stats.lock().unwrap();
+        assert_eq!(stats.retransmits, 3);
+        assert_eq!(stats.window_retransmits, [2, 1, 1]);
+        assert_eq!(stats.windows_with_retransmits(), 2);
+    }
+
+    #[test]
+    fn keeps_window_when_not_adaptive() {
+        let mut adaptive = AdaptiveWindow::new(8, false, Default::default());
+        adaptive.on_loss();
+        assert_eq!(adaptive.size(), 8);
+    }
+
+    #[test]
+    fn writes_through_pipeline() {
+        const FILENAME: &str = "writes_through_pipeline.txt";
+
+        let _ = fs::create_dir_all(DIR_NAME);
+        let (socket, peer) = socket_pair();
+        for (block_num, data) in [(1, "Hell"), (2, "o, w"), (3, "orld"), (4, "!")] {
+            let packet = Packet::Data {
+                block_num,
+                data: data.as_bytes().to_vec(),
+            };
+            Socket::send(&peer, &packet).unwrap();
+        }
+        thread::sleep(Duration::from_millis(50));
+
+        let rot13 = |data: &[u8]| -> Vec<u8> {
+            data.iter()
+                .map(|&b| match b {
+                    b'a'..=b'z' => (b - b'a' + 13) % 26 + b'a',
+                    b'A'..=b'Z' => (b - b'A' + 13) % 26 + b'A',
+                    _ => b,
+                })
+                .collect()
+        };
+        let mut pipeline = Pipeline::new();
+        pipeline.push(rot13);
+        pipeline.push(|data: &[u8]| data.to_ascii_uppercase());
+
+        let opt_common = OptionsProtocol {
+            block_size: 4,
+            window_size: 2,
+            // CRC-32 of the data before the pipeline
+            checksum: Some(0xEBE6_C6E6),
+            ..Default::default()
+        };
+        let mut worker = Worker::new(
+            Box::new(socket),
+            file_path(FILENAME),
+            Default::default(),
+            opt_common,
+            Default::default(),
+        );
+        worker.set_pipeline(pipeline);
+
+        assert!(worker.receive().unwrap().join().unwrap().is_ok());
+        assert_eq!(fs::read(file_path(FILENAME)).unwrap(), b"URYYB, JBEYQ!");
+
+        clean(FILENAME);
+    }
+
+    #[test]
+    fn rolls_over_to_0() {
+        transfer_past_rollover("rolls_over_to_0.bin", Rollover::Enforce0);
+    }
+
+    #[test]
+    fn rolls_over_to_1() {
+        transfer_past_rollover("rolls_over_to_1.bin", Rollover::Enforce1);
+    }
+
+    #[test]
+    fn rolls_over_as_negotiated() {
+        // Forbidden locally, but negotiated with the rollover option
+        for rollover in [0, 1] {
+            let filename = format!("rolls_over_as_negotiated_{rollover}.bin");
+            let opt_local = OptionsPrivate {
+                rollover: Rollover::None,
+                ..Default::default()
+            };
+            let opt_common = OptionsProtocol {
+                rollover: Some(rollover),
+                ..Default::default()
+            };
+            let worker: Worker<UdpSocket> = Worker::new(
+                Box::new(UdpSocket::bind("127.0.0.1:0").unwrap()),
+                file_path(&filename),
+                opt_local.clone(),
+                opt_common.clone(),
+                Default::default(),
+            );
+            let expected = [Rollover::Enforce0, Rollover::Enforce1][rollover as usize];
+            assert_eq!(worker.opt_local.rollover, expected);
+
+            let (content, result) =
+                transfer_worker_pair_with(&filename, 0x10400 * 512, opt_local, opt_common);
+            assert!(result.is_ok());
+            assert!(fs::read(file_path(&filename)).unwrap() == content);
+            clean(&filename);
+            fs::remove_file(file_path(&format!("{filename}.src"))).unwrap();
+        }
+    }
+
+    #[test]
+    fn refuses_rollover() {
+        const FILENAME: &str = "refuses_rollover.bin";
+
+        let opt_local = OptionsPrivate {
+            rollover: Rollover::None,
+            ..Default::default()
+        };
+        let (_, result) = transfer_worker_pair(FILENAME, 0x10000 * 512, opt_local);
+
+        // The sender cannot send block 65536
+        assert_eq!(
+            result,
+            Err(TftpError::IllegalOperation(
+                "Block counter rollover error".to_string()
+            ))
+        );
+        fs::remove_file(file_path(&format!("{FILENAME}.src"))).unwrap();
+    }
+
+    /// Transfers a 40 MiB file of 81920 blocks of 512 bytes, so that the
+    /// block counter rolls over once
+    fn transfer_past_rollover(filename: &str, rollover: Rollover) {
+        let opt_local = OptionsPrivate {
+            rollover,
+            ..Default::default()
+        };
+        let (content, result) = transfer_worker_pair(filename, 40 * 1024 * 1024, opt_local);
+
+        assert!(result.is_ok());
+        assert!(fs::read(file_path(filename)).unwrap() == content);
+        clean(filename);
+        fs::remove_file(file_path(&format!("{filename}.src"))).unwrap();
+    }
+
+    #[test]
+    fn keeps_sender_waiting_during_slow_write() {
+        /// Writer taking 60 ms per call, i.e. per block of 65464 bytes as
+        /// they do not fit twice in the write buffer
+        struct SlowWriter(Arc<Mutex<Vec<u8>>>);
+
+        impl Write for SlowWriter {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                thread::sleep(Duration::from_millis(60));
+                self.0.lock().unwrap().extend_from_slice(buf);
+                Ok(buf.len())
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let (socket, peer) = socket_pair();
+        let content: Vec<u8> = (0..4 * 65464 + 40).map(|i| (i % 251) as u8).collect();
+        // Writing a window takes longer than the timeout
+        let opt_common = OptionsProtocol {
+            block_size: 65464,
+            window_size: 2,
+            timeout: Duration::from_millis(100),
+            ..Default::default()
+        };
+
+        let written = Arc::new(Mutex::new(vec![]));
+        let mut receiver = Worker::new(
+            Box::new(socket),
+            PathBuf::from("-"),
+            Default::default(),
+            opt_common.clone(),
+            Default::default(),
+        );
+        receiver.set_writer(Box::new(SlowWriter(written.clone())));
+        let mut sender = Worker::new(
+            Box::new(peer),
+            PathBuf::from("-"),
+            Default::default(),
+            opt_common,
+            Default::default(),
+        );
+        sender.set_reader(Box::new(io::Cursor::new(content.clone())));
+        let stats = sender.stats();
+
+        let receiving = receiver.receive().unwrap();
+        assert!(sender.send(false).unwrap().join().unwrap().is_ok());
+        assert!(receiving.join().unwrap().is_ok());
+        assert_eq!(*written.lock().unwrap(), content);
+        assert_eq!(stats.lock().unwrap().retransmits, 0);
+    }
+
+    #[test]
+    fn acknowledges_at_chosen_cadence() {
+        for (ack_per_block, acks) in [(false, vec![5, 10]), (true, (1..=10).collect())] {
+            let (socket, peer) = socket_pair();
+            peer.set_read_timeout(Some(Duration::from_millis(300)))
+                .unwrap();
+            let opt_local = OptionsPrivate {
+                ack_per_block,
+                ..Default::default()
+            };
+            let opt_common = OptionsProtocol {
+                window_size: 5,
+                timeout: Duration::from_millis(100),
+                ..Default::default()
+            };
+            let mut worker = Worker::new(
+                Box::new(socket),
+                PathBuf::from("-"),
+                opt_local,
+                opt_common,
+                Default::default(),
+            );
+            worker.set_writer(Box::new(io::sink()));
+            let handle = worker.receive().unwrap();
+
+            for block_num in 1..=10 {
+                let len = if block_num == 10 { 100 } else { 512 };
+                let data = Packet::Data {
+                    block_num,
+                    data: vec![0; len],
+                };
+                Socket::send(&peer, &data).unwrap();
+            }
+            let mut received = vec![];
+            while let Ok(Packet::Ack(block_num)) = Socket::recv(&peer) {
+                received.push(block_num);
+            }
+
+            assert!(handle.join().unwrap().is_ok());
+            assert_eq!(received, acks, "ack per block: {ack_per_block}");
+        }
+    }
+
+    #[test]
+    fn aborts_at_window_not_matching_manifest() {
+        let (socket, peer) = socket_pair();
+        peer.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+        let content: Vec<u8> = (0..2600u32).map(|i| (i * 7) as u8).collect();
+        let manifest = content
+            .chunks(1024)
+            .map(|run| {
+                let mut crc = Crc32::new();
+                crc.update(run);
+                crc.value()
+            })
+            .collect();
+        let opt_common = OptionsProtocol {
+            window_size: 2,
+            ..Default::default()
+        };
+
+        let mut worker = Worker::new(
+            Box::new(socket),
+            PathBuf::from("-"),
+            Default::default(),
+            opt_common,
+            Default::default(),
+        );
+        worker.set_writer(Box::new(io::sink()));
+        worker.set_manifest(manifest);
+        let handle = worker.receive().unwrap();
+
+        for (block_num, chunk) in (1..=4).zip(content.chunks(512)) {
+            let mut data = chunk.to_vec();
+            // Corrupts the second window
+            if block_num == 4 {
+                data[100] ^= 0x01;
+            }
+            Socket::send(&peer, &Packet::Data { block_num, data }).unwrap();
+            if block_num % 2 == 0 {
+                assert_eq!(recv(&peer), Packet::Ack(block_num));
+            }
+        }
+        assert!(matches!(
+            recv(&peer),
+            Packet::Error {
+                code: ErrorCode::NotDefined,
+                ..
+            }
+        ));
+
+        let err = handle.join().unwrap().unwrap_err();
+        assert!(
+            err.to_string()
+                .starts_with("Window 2 does not match the manifest"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn keeps_received_blocks_on_timeout() {
+        let _ = fs::create_dir_all(DIR_NAME);
+        let path = file_path("keeps_received_blocks_on_timeout");
+        let (socket, peer) = socket_pair();
+        socket
+            .set_read_timeout(Some(Duration::from_millis(100)))
+            .unwrap();
+        let opt_local = OptionsPrivate {
+            max_retries: 0,
+            clean_on_error: false,
+            ..Default::default()
+        };
+        let opt_common = OptionsProtocol {
+            window_size: 5,
+            timeout: Duration::from_millis(100),
+            ..Default::default()
+        };
+
+        let worker = Worker::new(
+            Box::new(socket),
+            path.clone(),
+            opt_local,
+            opt_common,
+            Default::default(),
+        );
+        let handle = worker.receive().unwrap();
+        for block_num in 1..=3 {
+            let data = Packet::Data {
+                block_num,
+                data: vec![block_num as u8; 512],
+            };
+            Socket::send(&peer, &data).unwrap();
+        }
+
+        let err = handle.join().unwrap().unwrap_err();
+        assert!(err.to_string().contains("timed out"));
+        let expected: Vec<u8> = (1..=3).flat_map(|n| vec![n as u8; 512]).collect();
+        assert_eq!(fs::read(&path).unwrap(), expected);
+    }
+
+    /// Sends a file of `len` bytes from a worker to another one with the same
+    /// options, returns its content and the result of the receiving worker
+    fn transfer_worker_pair(
+        filename: &str,
+        len: usize,
+        opt_local: OptionsPrivate,
+    ) -> (Vec<u8>, Result<(), TftpError>) {
+        transfer_worker_pair_with(filename, len, opt_local, Default::default())
+    }
+
+    /// Same as [`transfer_worker_pair`], with other negotiated options than
+    /// the window size and timeout
+    fn transfer_worker_pair_with(
+        filename: &str,
+        len: usize,
+        opt_local: OptionsPrivate,
+        opt_common: OptionsProtocol,
+    ) -> (Vec<u8>, Result<(), TftpError>) {
+        let _ = fs::create_dir_all(DIR_NAME);
+        // Blocks differ over the rollover period so that a wrong order shows
+        let content: Vec<u8> = (0..len).map(|i| (i / 512 % 251) as u8).collect();
+        let source = file_path(&format!("{filename}.src"));
+        fs::write(&source, &content).unwrap();
+
+        let (socket, peer) = socket_pair();
+        let opt_common = OptionsProtocol {
+            window_size: 16,
+            timeout: Duration::from_millis(500),
+            ..opt_common
+        };
+        let receiver = Worker::new(
+            Box::new(socket),
+            file_path(filename),
+            opt_local.clone(),
+            opt_common.clone(),
+            Default::default(),
+        );
+        let sender = Worker::new(
+            Box::new(peer),
+            source,
+            opt_local,
+            opt_common,
+            Default::default(),
+        );
+
+        let receiving = receiver.receive().unwrap();
+        let _ = sender.send(false).unwrap().join().unwrap();
+
+        (content, receiving.join().unwrap())
+    }
+
+    #[test]
+    fn sends_from_reader() {
+        let (socket, peer) = socket_pair();
+        let content: Vec<u8> = (0..600u32).map(|i| i as u8).collect();
+
+        let worker = Worker::with_reader(
+            Box::new(socket),
+            Box::new(io::Cursor::new(content.clone())),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+        );
+        let handle = worker.send(false).unwrap();
+
+        let mut received = vec![];
+        for block_num in 1..=2 {
+            let Packet::Data {
+                block_num: num,
+                data,
+            } = recv(&peer)
+            else {
+                panic!("expected data");
+            };
+            assert_eq!(num, block_num);
+            received.extend(data);
+            Socket::send(&peer, &Packet::Ack(block_num)).unwrap();
+        }
+
+        assert!(handle.join().unwrap().is_ok());
+        assert_eq!(received, content);
+    }
+
+    #[test]
+    fn receives_into_writer() {
+        /// Buffer kept by the test while the worker writes into it
+        struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+        impl Write for SharedBuffer {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.0.lock().unwrap().write(buf)
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let (socket, peer) = socket_pair();
+        let content: Vec<u8> = (0..600u32).map(|i| (i * 3) as u8).collect();
+
+        let written = Arc::new(Mutex::new(vec![]));
+        let worker = Worker::with_writer(
+            Box::new(socket),
+            Box::new(SharedBuffer(written.clone())),
+            Default::default(),
+            OptionsProtocol {
+                timeout: Duration::from_millis(500),
+                ..Default::default()
+            },
+            Default::default(),
+        );
+        let handle = worker.receive().unwrap();
+
+        for (block_num, data) in (1..).zip(content.chunks(512)) {
+            let data = Packet::Data {
+                block_num,
+                data: data.to_vec(),
+            };
+            Socket::send(&peer, &data).unwrap();
+            assert_eq!(recv(&peer), Packet::Ack(block_num));
+        }
+
+        assert!(handle.join().unwrap().is_ok());
+        assert_eq!(*written.lock().unwrap(), content);
+    }
+
+    #[test]
+    fn reports_read_error_with_context() {
+        /// Reader failing once 3 blocks were read
+        struct FailingReader(usize);
+
+        impl Read for FailingReader {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                if self.0 == 0 {
+                    return Err(io::Error::new(ErrorKind::BrokenPipe, "device gone"));
+                }
+                let len = buf.len().min(self.0);
+                buf[..len].fill(0x5A);
+                self.0 -= len;
+                Ok(len)
+            }
+        }
+
+        let (socket, peer) = socket_pair();
+        let mut worker = Worker::new(
+            Box::new(socket),
+            PathBuf::from("foo.bin"),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+        );
+        worker.set_reader(Box::new(FailingReader(3 * 512)));
+        let handle = worker.send(false).unwrap();
+
+        for block_num in 1..=3 {
+            assert!(matches!(recv(&peer), Packet::Data { block_num: num, .. } if num == block_num));
+            Socket::send(&peer, &Packet::Ack(block_num)).unwrap();
+        }
+        assert!(matches!(
+            recv(&peer),
+            Packet::Error {
+                code: ErrorCode::NotDefined,
+                ..
+            }
+        ));
+
+        let Err(TftpError::Transfer(err)) = handle.join().unwrap() else {
+            panic!("expected a local error");
+        };
+        assert_eq!(
+            err.to_string(),
+            "read error on 'foo.bin' at block 4: device gone"
+        );
+        assert_eq!(
+            err.downcast_ref::<io::Error>().unwrap().kind(),
+            ErrorKind::BrokenPipe
+        );
+    }
+
+    #[test]
+    fn reports_write_error_to_peer() {
+        /// Writer of a full disk
+        struct FullDisk;
+
+        impl Write for FullDisk {
+            fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+                Err(io::Error::new(ErrorKind::StorageFull, "no space left"))
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let (socket, peer) = socket_pair();
+        let opt_common = OptionsProtocol {
+            timeout: Duration::from_millis(500),
+            ..Default::default()
+        };
+        let worker = Worker::with_writer(
+            Box::new(socket),
+            Box::new(FullDisk),
+            Default::default(),
+            opt_common,
+            Default::default(),
+        );
+        let handle = worker.receive().unwrap();
+
+        let data = Packet::Data {
+            block_num: 1,
+            data: vec![0x5A; 100],
+        };
+        Socket::send(&peer, &data).unwrap();
+        assert_eq!(
+            recv(&peer),
+            Packet::Error {
+                code: ErrorCode::DiskFull,
+                msg: "disk full".to_string(),
+            }
+        );
+
+        let Err(TftpError::Transfer(err)) = handle.join().unwrap() else {
+            panic!("expected a local error");
+        };
+        assert_eq!(err.to_string(), "write error on '-': no space left");
+        assert_eq!(
+            err.downcast_ref::<io::Error>().unwrap().kind(),
+            ErrorKind::StorageFull
+        );
+    }
+
+    #[test]
+    fn maps_write_errors_to_codes() {
+        assert_eq!(
+            write_error_code(Some(ErrorKind::StorageFull)).0,
+            ErrorCode::DiskFull
+        );
+        assert_eq!(
+            write_error_code(Some(ErrorKind::PermissionDenied)).0,
+            ErrorCode::AccessViolation
+        );
+        assert_eq!(
+            write_error_code(Some(ErrorKind::InvalidData)).0,
+            ErrorCode::NotDefined
+        );
+        assert_eq!(write_error_code(None).0, ErrorCode::NotDefined);
+    }
+
+    #[test]
+    fn aborts_when_source_modified() {
+        const FILENAME: &str = "aborts_when_source_modified.bin";
+
+        let _ = fs::create_dir_all(DIR_NAME);
+        fs::write(file_path(FILENAME), vec![0x5A; 64 * 512]).unwrap();
+        let metadata = fs::metadata(file_path(FILENAME)).unwrap();
+
+        let (socket, peer) = socket_pair();
+        let mut worker = Worker::new(
+            Box::new(socket),
+            file_path(FILENAME),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+        );
+        worker.set_source_snapshot(metadata.len(), metadata.modified().ok());
+        let handle = worker.send(false).unwrap();
+
+        assert!(matches!(recv(&peer), Packet::Data { block_num: 1, .. }));
+        let mut file = OpenOptions::new()
+            .append(true)
+            .open(file_path(FILENAME))
+            .unwrap();
+        file.write_all(b"appended").unwrap();
+        Socket::send(&peer, &Packet::Ack(1)).unwrap();
+
+        // The blocks read ahead are sent, not the final one
+        loop {
+            match recv(&peer) {
+                Packet::Data { block_num, data } => {
+                    assert_eq!(data.len(), 512);
+                    Socket::send(&peer, &Packet::Ack(block_num)).unwrap();
+                }
+                Packet::Error { .. } => break,
+                packet => panic!("unexpected {packet:?}"),
+            }
+        }
+        let err = handle.join().unwrap().unwrap_err();
+        assert!(err
+            .to_string()
+            .ends_with("source file modified during transfer"));
+
+        clean(FILENAME);
+    }
+
+    #[test]
+    fn ends_aligned_file_with_empty_block() {
+        let (socket, peer) = socket_pair();
+
+        let mut worker = Worker::new(
+            Box::new(socket),
+            PathBuf::from("-"),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+        );
+        worker.set_reader(Box::new(io::Cursor::new(vec![0x5A; 512])));
+        let handle = worker.send(false).unwrap();
+
+        assert_eq!(
+            recv(&peer),
+            Packet::Data {
+                block_num: 1,
+                data: vec![0x5A; 512]
+            }
+        );
+        Socket::send(&peer, &Packet::Ack(1)).unwrap();
+        assert_eq!(
+            recv(&peer),
+            Packet::Data {
+                block_num: 2,
+                data: vec![]
+            }
+        );
+        Socket::send(&peer, &Packet::Ack(2)).unwrap();
+
+        assert!(handle.join().unwrap().is_ok());
+    }
+
+    #[test]
+    fn aborts_after_max_retries_per_block() {
+        let (socket, peer) = socket_pair();
+        let opt_local = OptionsPrivate {
+            max_retries: 3,
+            ..Default::default()
+        };
+        let opt_common = OptionsProtocol {
+            timeout: Duration::from_millis(100),
+            ..Default::default()
+        };
+
+        let mut worker = Worker::new(
+            Box::new(socket),
+            PathBuf::from("-"),
+            opt_local,
+            opt_common,
+            Default::default(),
+        );
+        worker.set_reader(Box::new(io::Cursor::new(vec![0; 600])));
+        let handle = worker.send(false).unwrap();
+
+        // Retries of the first block do not count for the second one
+        for _ in 0..3 {
+            assert!(matches!(recv(&peer), Packet::Data { block_num: 1, .. }));
+        }
+        Socket::send(&peer, &Packet::Ack(1)).unwrap();
+
+        let mut sent = 0;
+        while let Ok(Packet::Data { block_num: 2, .. }) = Socket::recv(&peer) {
+            sent += 1;
+        }
+        assert_eq!(sent, 1 + 3);
+        assert!(handle
+            .join()
+            .unwrap()
+            .unwrap_err()
+            .to_string()
+            .contains("timed out after 3 tries"));
+    }
+
+    #[test]
+    fn backs_off_on_consecutive_losses() {
+        let (socket, peer) = socket_pair();
+        let timeout = Duration::from_millis(100);
+        let opt_common = OptionsProtocol {
+            timeout,
+            ..Default::default()
+        };
+
+        let mut worker = Worker::new(
+            Box::new(socket),
+            PathBuf::from("-"),
+            Default::default(),
+            opt_common,
+            Default::default(),
+        );
+        worker.set_reader(Box::new(io::Cursor::new(vec![0; 600])));
+        let handle = worker.send(false).unwrap();
+
+        // First block sent, then sent again after 3 losses
+        let mut sent = vec![];
+        for _ in 0..4 {
+            assert!(matches!(recv(&peer), Packet::Data { block_num: 1, .. }));
+            sent.push(Instant::now());
+        }
+        let intervals: Vec<Duration> = sent.windows(2).map(|w| w[1] - w[0]).collect();
+        assert!(intervals[0] >= timeout && intervals[0] < timeout * 2);
+        assert!(intervals[1] >= timeout * 2 && intervals[1] < timeout * 3);
+        assert!(intervals[2] >= timeout * 4 && intervals[2] < timeout * 5);
+
+        // The wait is back to the negotiated timeout for the next block
+        Socket::send(&peer, &Packet::Ack(1)).unwrap();
+        assert!(matches!(recv(&peer), Packet::Data { block_num: 2, .. }));
+        let start = Instant::now();
+        assert!(matches!(recv(&peer), Packet::Data { block_num: 2, .. }));
+        assert!(start.elapsed() < timeout * 2);
+        Socket::send(&peer, &Packet::Ack(2)).unwrap();
+
+        assert!(handle.join().unwrap().is_ok());
+    }
+
+    #[test]
+    fn aborts_when_peer_port_closed() {
+        let (socket, peer) = socket_pair();
+        drop(peer);
+
+        let mut worker = Worker::new(
+            Box::new(socket),
+            PathBuf::from("-"),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+        );
+        worker.set_reader(Box::new(io::Cursor::new(vec![0; 600])));
+        let start = Instant::now();
+        let result = worker.send(false).unwrap().join().unwrap();
+
+        assert!(result.unwrap_err().to_string().contains("port is closed"));
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn reports_illegal_operation() {
+        const FILENAME: &str = "reports_illegal_operation.txt";
+
+        let (_, result) = receive_error(FILENAME, ErrorCode::IllegalOperation);
+
+        assert_eq!(
+            result,
+            Err(TftpError::IllegalOperation("refused".to_string()))
+        );
+    }
+
+    #[test]
+    fn reports_no_such_user() {
+        const FILENAME: &str = "reports_no_such_user.txt";
+
+        let (_, result) = receive_error(FILENAME, ErrorCode::NoSuchUser);
+
+        assert_eq!(result, Err(TftpError::NoSuchUser("refused".to_string())));
+    }
+
+    fn receive_error(filename: &str, code: ErrorCode) -> (UdpSocket, Result<(), TftpError>) {
+        receive_packets(
+            filename,
+            Default::default(),
+            Default::default(),
+            &[Packet::Error {
+                code,
+                msg: "refused".to_string(),
+            }],
+        )
+    }
+
+    fn receive_with_checksum(filename: &str, checksum: u32) -> (UdpSocket, Result<(), TftpError>) {
+        let opt_common = OptionsProtocol {
+            checksum: Some(checksum),
+            ..Default::default()
+        };
+        receive_packets(
+            filename,
+            Default::default(),
+            opt_common,
+            &[Packet::Data {
+                block_num: 1,
+                data: b"short".to_vec(),
+            }],
+        )
+    }
+
+    /// Sends a final block, then another one shortly after its acknowledgement
+    /// Receives a final block followed by another one, both already queued
+    fn receive_with_trailing_data(
+        filename: &str,
+        opt_local: OptionsPrivate,
+    ) -> (UdpSocket, Result<(), TftpError>) {
+        receive_packets(
+            filename,
+            opt_local,
+            Default::default(),
+            &[
+                Packet::Data {
+                    block_num: 1,
+                    data: b"short".to_vec(),
+                },
+                Packet::Data {
+                    block_num: 2,
+                    data: b"trailing".to_vec(),
+                },
+            ],
+        )
+    }
+
+    fn receive_packets(
+        filename: &str,
+        opt_local: OptionsPrivate,
+        opt_common: OptionsProtocol,
+        packets: &[Packet],
+    ) -> (UdpSocket, Result<(), TftpError>) {
+        let _ = fs::create_dir_all(DIR_NAME);
+        let (socket, peer) = socket_pair();
+
+        for packet in packets {
+            Socket::send(&peer, packet).unwrap();
+        }
+        // Let all packets reach the worker socket before it starts
+        thread::sleep(Duration::from_millis(50));
+
+        let worker = Worker::new(
+            Box::new(socket),
+            file_path(filename),
+            opt_local,
+            opt_common,
+            Default::default(),
+        );
+        let result = worker.receive().unwrap().join().unwrap();
+
+        (peer, result)
+    }
+
+    fn socket_pair() -> (UdpSocket, UdpSocket) {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let peer = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket.connect(peer.local_addr().unwrap()).unwrap();
+        peer.connect(socket.local_addr().unwrap()).unwrap();
+        peer.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+
+        (socket, peer)
+    }
+
+    fn recv(peer: &UdpSocket) -> Packet {
+        Socket::recv(peer).unwrap()
+    }
+
+    fn file_path(filename: &str) -> PathBuf {
+        PathBuf::from(DIR_NAME).join(filename)
+    }
+
+    fn clean(filename: &str) {
+        fs::remove_file(file_path(filename)).unwrap();
+    }
+}
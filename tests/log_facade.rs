@@ -0,0 +1,45 @@
+#![cfg(feature = "log")]
+
+use std::sync::Mutex;
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use tftpd::{log_err, log_info, log_warn};
+
+static RECORDED: Mutex<Vec<(Level, String)>> = Mutex::new(vec![]);
+
+struct Recorder;
+
+impl Log for Recorder {
+    fn enabled(&self, _metadata: &Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &Record) {
+        RECORDED
+            .lock()
+            .unwrap()
+            .push((record.level(), record.args().to_string()));
+    }
+
+    fn flush(&self) {}
+}
+
+// The only test of this binary, the logger being global to the process
+#[test]
+fn forwards_messages_to_log_crate() {
+    log::set_logger(&Recorder).unwrap();
+    log::set_max_level(LevelFilter::Trace);
+
+    log_err!("error {}", 1);
+    log_warn!("warning {}", 2);
+    log_info!("info {}", 3);
+
+    assert_eq!(
+        *RECORDED.lock().unwrap(),
+        [
+            (Level::Error, "error 1".to_string()),
+            (Level::Warn, "warning 2".to_string()),
+            (Level::Info, "info 3".to_string()),
+        ]
+    );
+}